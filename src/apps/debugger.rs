@@ -0,0 +1,641 @@
+use std::io::{self, BufRead, Write};
+
+use itertools::Itertools;
+use colored::Colorize;
+
+use crate::ir::structures::*;
+use crate::utils::display_helper::*;
+use super::executor::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Stop when entering the function.
+    Function(String),
+    /// Stop before the first instruction of the basic block,
+    /// optionally restricted to the given function.
+    Label(Option<String>, String),
+    /// Stop before the instruction defining the named value,
+    /// optionally restricted to the given function.
+    Value(Option<String>, String),
+}
+
+/// Execution position of an active function.
+/// The executor runs function calls recursively,
+/// the debugger instead keeps its own call stack to be able to suspend anywhere.
+#[derive(Debug, Clone)]
+pub struct DebugFrame {
+    pub function: FunctionRef,
+    pub block: BlockRef,
+    /// Index of the next instruction to execute,
+    /// equals to the number of instructions when stopping at the terminator.
+    pub index: usize,
+    /// The call instruction in the caller frame receiving the return value.
+    pub call_site: Option<ValueRef>,
+}
+
+#[derive(Debug, Clone)]
+pub enum StopReason {
+    Stepped,
+    Breakpoint(usize),
+    Exited(Val),
+    /// Boxed to keep stop reasons small, since execution errors carry the whole source context.
+    Error(Box<ExecutionError>),
+}
+
+enum StepEvent {
+    Executed,
+    Entered,
+    Returned,
+    Exited(Val),
+}
+
+pub struct Debugger<'m> {
+    module: &'m Module,
+    pub env: ProgramEnv,
+    pub frames: Vec<DebugFrame>,
+    pub breakpoints: Vec<(usize, Breakpoint)>,
+    next_breakpoint_id: usize,
+    /// Set after the entry function returns or execution fails,
+    /// frames are kept on failure for inspection.
+    pub terminated: bool,
+}
+
+const HELP_MESSAGE: &str = "\
+commands:
+  break <@fn | %label | %value> [in @fn]   set a breakpoint (alias: b)
+  delete [id]                              delete one or all breakpoints (alias: d)
+  info                                     list breakpoints (alias: i)
+  step                                     execute one instruction, stepping into calls (alias: s)
+  next                                     execute one instruction, stepping over calls (alias: n)
+  finish                                   run until the current function returns
+  continue                                 run until a breakpoint or program exit (alias: c)
+  print <%value | #arg | @global>          print the runtime value (alias: p)
+  memory <pointer> [count]                 print the memory region a pointer refers to (alias: x)
+  list                                     print the current basic block (alias: l)
+  backtrace                                print the call stack (alias: bt)
+  quit                                     exit the debugger (alias: q)";
+
+impl<'m> Debugger<'m> {
+    // execution errors are returned unboxed, the same as `run_on_module`.
+    #[allow(clippy::result_large_err)]
+    pub fn new(module: &'m Module, entry_fn: &str, args: Vec<Val>) -> Result<Debugger<'m>, ExecutionError> {
+        let func_ref = *module.string_func_map
+            .get(entry_fn)
+            .ok_or_else(| | ExecutionError {
+                function: "<global frame>".to_string(),
+                value: "<entry function>".to_string(),
+                error: ExecutionErrorInternal::SymbolNotFound(format!("@{}", entry_fn)),
+                span: None,
+                source: None
+            })?;
+        let mut env = ProgramEnv::new();
+        initialize_module(&mut env, module);
+        prepare_function_frame(&mut env, module, func_ref, args)?;
+        let entry_bb = module.get_function(func_ref).blocks[0];
+        env.position = Some(entry_bb);
+        Ok(Debugger {
+            module,
+            env,
+            frames: vec![DebugFrame { function: func_ref, block: entry_bb, index: 0, call_site: None }],
+            breakpoints: Vec::new(),
+            next_breakpoint_id: 1,
+            terminated: false,
+        })
+    }
+
+    fn current_frame(&self) -> &DebugFrame {
+        self.frames.last().expect("no active debug frame")
+    }
+
+    fn current_function(&self) -> &'m Function {
+        self.module.get_function(self.current_frame().function)
+    }
+
+    /// Execute exactly one instruction or terminator of the top frame.
+    #[allow(clippy::result_large_err)]
+    fn step_once(&mut self) -> Result<StepEvent, ExecutionError> {
+        let module = self.module;
+        let frame = self.current_frame().clone();
+        let function = module.get_function(frame.function);
+        let block = function.get_basic_block(frame.block);
+
+        if let Some(instr) = block.instrs.get(frame.index).cloned() {
+            if let ValueKind::FnCall(call) = &module.get_value(instr).kind {
+                if !is_runtime_function(&call.callee) {
+                    let args = call.args
+                        .iter().cloned()
+                        .map(| arg | self.env.get_val(arg).clone())
+                        .collect::<Vec<_>>();
+                    let callee = module.get_function_ref(&call.callee);
+                    if let Err(err) = prepare_function_frame(&mut self.env, module, callee, args) {
+                        // keep the frames in sync for inspection after the error.
+                        self.env.epilogue();
+                        return Err(err);
+                    }
+                    let entry_bb = module.get_function(callee).blocks[0];
+                    self.env.position = Some(entry_bb);
+                    self.frames.push(DebugFrame { function: callee, block: entry_bb, index: 0, call_site: Some(instr) });
+                    return Ok(StepEvent::Entered);
+                }
+            }
+            self.env.program_counter = Some(instr);
+            let val = single_step(&mut self.env, module, function, instr)?;
+            self.env.set_value_binding(instr, val);
+            self.frames.last_mut().unwrap().index += 1;
+            return Ok(StepEvent::Executed);
+        }
+
+        self.env.position = Some(frame.block);
+        let val = single_step_terminator(&mut self.env, module, function, &block.terminator)?;
+        match self.env.position {
+            Some(next_bb) => {
+                let top = self.frames.last_mut().unwrap();
+                top.block = next_bb;
+                top.index = 0;
+                Ok(StepEvent::Executed)
+            },
+            None => {
+                self.env.epilogue();
+                self.frames.pop();
+                match (frame.call_site, self.frames.last_mut()) {
+                    (Some(call_site), Some(caller)) => {
+                        // the caller stays at the call instruction until the callee returns.
+                        caller.index += 1;
+                        self.env.position = Some(caller.block);
+                        self.env.set_value_binding(call_site, val);
+                        Ok(StepEvent::Returned)
+                    },
+                    _ => Ok(StepEvent::Exited(val))
+                }
+            }
+        }
+    }
+
+    /// Find the breakpoint hit at the current position.
+    fn hit_breakpoint(&self, entered: bool) -> Option<usize> {
+        let frame = self.current_frame();
+        let function = self.current_function();
+        let block = function.get_basic_block(frame.block);
+        let in_scope = | scope: &Option<String> | scope.as_ref().is_none_or(| name | name == &function.name);
+        self.breakpoints
+            .iter()
+            .find(| (_, breakpoint) | match breakpoint {
+                Breakpoint::Function(name) => entered && name == &function.name,
+                Breakpoint::Label(scope, label) =>
+                    frame.index == 0 && in_scope(scope) && block.name.as_ref() == Some(label),
+                Breakpoint::Value(scope, value) =>
+                    in_scope(scope) && block.instrs
+                        .get(frame.index)
+                        .is_some_and(| instr | self.module.get_value(*instr).name.as_ref() == Some(value)),
+            })
+            .map(| (id, _) | *id)
+    }
+
+    /// Keep stepping while the call stack is deeper than `depth`, or forever if `depth` is `None`.
+    /// Always executes at least one step, stops at breakpoints.
+    fn run_until(&mut self, depth: Option<usize>) -> StopReason {
+        loop {
+            let event = match self.step_once() {
+                Ok(event) => event,
+                Err(err) => {
+                    self.terminated = true;
+                    return StopReason::Error(Box::new(err));
+                }
+            };
+            let entered = match event {
+                StepEvent::Exited(val) => {
+                    self.terminated = true;
+                    return StopReason::Exited(val);
+                },
+                StepEvent::Entered => true,
+                StepEvent::Executed | StepEvent::Returned => false
+            };
+            if let Some(id) = self.hit_breakpoint(entered) {
+                return StopReason::Breakpoint(id);
+            }
+            if depth.is_some_and(| depth | self.frames.len() <= depth) {
+                return StopReason::Stepped;
+            }
+        }
+    }
+
+    pub fn step(&mut self) -> StopReason {
+        let depth = self.frames.len();
+        // stepping into a function stops at its entry.
+        self.run_until(Some(depth + 1))
+    }
+
+    pub fn step_over(&mut self) -> StopReason {
+        let depth = self.frames.len();
+        self.run_until(Some(depth))
+    }
+
+    pub fn finish(&mut self) -> StopReason {
+        let depth = self.frames.len();
+        self.run_until(Some(depth - 1))
+    }
+
+    pub fn cont(&mut self) -> StopReason {
+        self.run_until(None)
+    }
+
+    /// Resolve `@fn`, `%label` or `%value`, optionally scoped by `in @fn`.
+    pub fn add_breakpoint(&mut self, target: &str, scope: Option<&str>) -> Result<usize, String> {
+        let scope = match scope {
+            Some(scope) => {
+                let name = scope.strip_prefix('@')
+                    .ok_or_else(| | format!("expect a function '@<name>', but found '{}'", scope))?;
+                if !self.module.string_func_map.contains_key(name) {
+                    return Err(format!("function '@{}' not found", name));
+                }
+                Some(name.to_string())
+            },
+            None => None
+        };
+        let breakpoint = if let Some(name) = target.strip_prefix('@') {
+            if !self.module.string_func_map.contains_key(name) {
+                return Err(format!("function '@{}' not found", name));
+            }
+            Breakpoint::Function(name.to_string())
+        } else if let Some(name) = target.strip_prefix('%') {
+            let functions = self.module.funcs
+                .iter()
+                .map(| func_ref | self.module.get_function(*func_ref))
+                .filter(| function | scope.as_ref().is_none_or(| scope | scope == &function.name))
+                .collect::<Vec<_>>();
+            let is_label = functions
+                .iter()
+                .flat_map(| function | function.blocks.iter().map(| bb | function.get_basic_block(*bb)))
+                .any(| block | block.name.as_deref() == Some(name));
+            let is_value = functions
+                .iter()
+                .flat_map(| function | function.blocks.iter().map(| bb | function.get_basic_block(*bb)))
+                .flat_map(| block | block.instrs.iter())
+                .any(| instr | self.module.get_value(*instr).name.as_deref() == Some(name));
+            if is_label {
+                Breakpoint::Label(scope, name.to_string())
+            } else if is_value {
+                Breakpoint::Value(scope, name.to_string())
+            } else {
+                return Err(format!("no basic block or value named '%{}'", name));
+            }
+        } else {
+            return Err(format!("expect '@<function>', '%<label>' or '%<value>', but found '{}'", target));
+        };
+        let id = self.next_breakpoint_id;
+        self.next_breakpoint_id += 1;
+        self.breakpoints.push((id, breakpoint));
+        Ok(id)
+    }
+
+    /// Find a symbol visible from the current frame.
+    fn lookup_value(&self, symbol: &str) -> Option<ValueRef> {
+        let module = self.module;
+        let function = self.current_function();
+        let matches = | value: &ValueRef, name: &str | module.get_value(*value).name.as_deref() == Some(name);
+        if let Some(name) = symbol.strip_prefix('@') {
+            module.globals.iter().find(| value | matches(value, name)).cloned()
+        } else if let Some(name) = symbol.strip_prefix('#') {
+            function.args.iter().find(| value | matches(value, name)).cloned()
+        } else if let Some(name) = symbol.strip_prefix('%') {
+            function.blocks
+                .iter()
+                .flat_map(| bb | function.get_basic_block(*bb).instrs.iter())
+                .find(| value | matches(value, name))
+                .cloned()
+        } else {
+            None
+        }
+    }
+
+    fn lookup_val(&self, value: ValueRef) -> Option<&Val> {
+        self.env
+            .get_top_frame()
+            .and_then(| frame | frame.get_local_val(value))
+            .or_else(| | self.env.global_val.get(value))
+    }
+
+    fn format_val(&self, val: &Val) -> String {
+        match val {
+            Val::Pointer(object) => {
+                let base = self.module.get_value(object.base);
+                // global regions live in frame 0, which has no real function.
+                let scope = if object.frame_index == 0 {
+                    "<global>".to_string()
+                } else {
+                    format!("@{}", self.module.get_function(object.function).name)
+                };
+                format!("<pointer to {} in {} frame {}, offset {} of {}>",
+                        base, scope, object.frame_index, object.offset_within, object.size)
+            },
+            _ => val.wrap_context(self.module).to_string()
+        }
+    }
+
+    fn write_location<W: Write>(&self, out: &mut W, frame: &DebugFrame) -> io::Result<()> {
+        let module = self.module;
+        let function = module.get_function(frame.function);
+        let block = function.get_basic_block(frame.block);
+        let label = block.name.clone().unwrap_or("<unknown_label>".to_string());
+        writeln!(out, "{} %{}:", format!("@{}", function.name).bold(), label)?;
+        match block.instrs.get(frame.index) {
+            Some(instr) => write!(out, "{}", module.get_value(*instr).wrap_context(&(module, function))),
+            None => write!(out, "{}", block.terminator.wrap_context(&(module, function)))
+        }
+    }
+
+    fn report<W: Write>(&self, out: &mut W, reason: StopReason) -> io::Result<()> {
+        match reason {
+            StopReason::Stepped => (),
+            StopReason::Breakpoint(id) => writeln!(out, "{} {}", "breakpoint".yellow().bold(), id)?,
+            StopReason::Exited(val) => {
+                writeln!(out, "program exited with {}", self.format_val(&val).bold())?;
+                return Ok(());
+            },
+            StopReason::Error(err) => {
+                writeln!(out, "{}", err)?;
+                return Ok(());
+            }
+        }
+        self.write_location(out, self.current_frame())
+    }
+
+    fn execute_memory<W: Write>(&self, out: &mut W, symbol: &str, count: Option<&str>) -> io::Result<()> {
+        let val = match self.lookup_value(symbol).and_then(| value | self.lookup_val(value)) {
+            Some(val) => val,
+            None => return writeln!(out, "'{}' is not defined or not evaluated yet", symbol),
+        };
+        let object = match val {
+            Val::Pointer(object) => object,
+            _ => return writeln!(out, "'{}' is not a pointer, but {}", symbol, self.format_val(val)),
+        };
+        let region = match self.env.memory.get(&(object.base, object.frame_index)) {
+            Some(region) => region,
+            None => return writeln!(out, "memory region of '{}' is already released", symbol),
+        };
+        let remaining = region.len().saturating_sub(object.offset_within);
+        let count = match count.map(str::parse::<usize>) {
+            Some(Ok(count)) => count.min(remaining),
+            Some(Err(_)) => return writeln!(out, "invalid element count"),
+            None => remaining
+        };
+        writeln!(out, "{}", self.format_val(val))?;
+        for (i, elem) in region.iter().enumerate().skip(object.offset_within).take(count) {
+            writeln!(out, "  [{}] {}", i, self.format_val(elem))?;
+        }
+        Ok(())
+    }
+
+    /// Run one line of debugger command, returns `false` if user quits.
+    pub fn execute_command<W: Write>(&mut self, out: &mut W, line: &str) -> io::Result<bool> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let (command, operands) = match words.split_first() {
+            Some((command, operands)) => (*command, operands),
+            None => return Ok(true)
+        };
+        let running_commands = ["s", "step", "n", "next", "finish", "c", "continue"];
+        // frames are gone once the program exits, but kept after a runtime error.
+        let inspecting_commands = ["l", "list", "bt", "backtrace", "p", "print", "x", "memory"];
+        if (self.terminated && running_commands.contains(&command))
+            || (self.frames.is_empty() && inspecting_commands.contains(&command)) {
+            writeln!(out, "the program is not running")?;
+            return Ok(true);
+        }
+        match (command, operands) {
+            ("s" | "step", []) => {
+                let reason = self.step();
+                self.report(out, reason)?;
+            },
+            ("n" | "next", []) => {
+                let reason = self.step_over();
+                self.report(out, reason)?;
+            },
+            ("finish", []) => {
+                let reason = self.finish();
+                self.report(out, reason)?;
+            },
+            ("c" | "continue", []) => {
+                let reason = self.cont();
+                self.report(out, reason)?;
+            },
+            ("b" | "break", [target]) | ("b" | "break", [target, "in", _]) => {
+                match self.add_breakpoint(target, operands.get(2).cloned()) {
+                    Ok(id) => writeln!(out, "breakpoint {} at '{}'", id, operands.iter().format(" "))?,
+                    Err(msg) => writeln!(out, "{}", msg)?
+                }
+            },
+            ("d" | "delete", []) => self.breakpoints.clear(),
+            ("d" | "delete", [id]) => {
+                let before = self.breakpoints.len();
+                self.breakpoints.retain(| (bp_id, _) | Ok(*bp_id) != id.parse::<usize>());
+                if before == self.breakpoints.len() {
+                    writeln!(out, "no breakpoint '{}'", id)?;
+                }
+            },
+            ("i" | "info", []) => {
+                for (id, breakpoint) in self.breakpoints.iter() {
+                    let scope = | scope: &Option<String> | scope
+                        .as_ref()
+                        .map_or(String::new(), | name | format!(" in @{}", name));
+                    match breakpoint {
+                        Breakpoint::Function(name) => writeln!(out, "{}: function @{}", id, name)?,
+                        Breakpoint::Label(func, label) => writeln!(out, "{}: label %{}{}", id, label, scope(func))?,
+                        Breakpoint::Value(func, value) => writeln!(out, "{}: value %{}{}", id, value, scope(func))?,
+                    }
+                }
+            },
+            ("p" | "print", [symbol]) => {
+                match self.lookup_value(symbol) {
+                    Some(value) => match self.lookup_val(value) {
+                        Some(val) => writeln!(out, "{} = {}", symbol, self.format_val(val))?,
+                        None => writeln!(out, "{} = <not evaluated>", symbol)?
+                    },
+                    None => writeln!(out, "symbol '{}' not found in current scope", symbol)?
+                }
+            },
+            ("x" | "memory", [symbol]) => self.execute_memory(out, symbol, None)?,
+            ("x" | "memory", [symbol, count]) => self.execute_memory(out, symbol, Some(count))?,
+            ("l" | "list", []) => {
+                let frame = self.current_frame();
+                let function = self.current_function();
+                let block = function.get_basic_block(frame.block);
+                writeln!(out, "%{}:", block.name.clone().unwrap_or("<unknown_label>".to_string()))?;
+                for (i, instr) in block.instrs.iter().enumerate() {
+                    let marker = if i == frame.index { "=>" } else { "  " };
+                    write!(out, "{}{}", marker, self.module.get_value(*instr).wrap_context(&(self.module, function)))?;
+                }
+                let marker = if frame.index == block.instrs.len() { "=>" } else { "  " };
+                write!(out, "{}{}", marker, block.terminator.wrap_context(&(self.module, function)))?;
+            },
+            ("bt" | "backtrace", []) => {
+                // frames in `ProgramEnv` are always in sync with the debugger frames.
+                for (i, (env_frame, frame)) in self.env.frames.iter().zip(self.frames.iter()).rev().enumerate() {
+                    write!(out, "#{} ", i)?;
+                    debug_assert!(env_frame.working_function == frame.function);
+                    self.write_location(out, frame)?;
+                }
+            },
+            ("h" | "help", []) => writeln!(out, "{}", HELP_MESSAGE)?,
+            ("q" | "quit", []) => return Ok(false),
+            _ => writeln!(out, "invalid command '{}', try 'help'", line.trim())?
+        }
+        Ok(true)
+    }
+
+    pub fn run_repl<R: BufRead, W: Write>(&mut self, input: R, mut out: W) -> io::Result<()> {
+        self.write_location(&mut out, self.current_frame())?;
+        let mut lines = input.lines();
+        loop {
+            write!(out, "(accipit) ")?;
+            out.flush()?;
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(())
+            };
+            if !self.execute_command(&mut out, &line)? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontend::new_parser::parse_module;
+
+    /// Run the commands on `factorial(3)` of `examples/factorial.acc`, returning the transcript.
+    fn run_script(commands: &[&str]) -> String {
+        run_script_on(include_str!("../../examples/factorial.acc"), "factorial", vec![Val::Integer(3)], commands)
+    }
+
+    fn run_script_on(src: &str, entry_fn: &str, args: Vec<Val>, commands: &[&str]) -> String {
+        colored::control::set_override(false);
+        let module = parse_module(src).unwrap();
+        let mut debugger = Debugger::new(&module, entry_fn, args).unwrap();
+        let mut out = Vec::new();
+        debugger.run_repl(commands.join("\n").as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_debugger_session() {
+        let transcript = run_script(&[
+            "break %res in @factorial",
+            "continue",
+            "print #n",
+            "step",
+            "backtrace",
+            "print #n",
+            "delete 1",
+            "finish",
+            "print %res",
+            "next",
+            "memory %n.addr",
+            "continue",
+            "step",
+        ]);
+        let expected = "\
+@factorial %Lentry:
+  let %ret.addr: i32* = alloca i32, 1
+(accipit) breakpoint 1 at '%res in @factorial'
+(accipit) breakpoint 1
+@factorial %Lfalse:
+  let %res: i32 = call @factorial, %14: i32
+(accipit) #n = 3
+(accipit) @factorial %Lentry:
+  let %ret.addr: i32* = alloca i32, 1
+(accipit) #0 @factorial %Lentry:
+  let %ret.addr: i32* = alloca i32, 1
+#1 @factorial %Lfalse:
+  let %res: i32 = call @factorial, %14: i32
+(accipit) #n = 2
+(accipit) (accipit) @factorial %Lfalse:
+  let %16: i32 = load %n.addr: i32*
+(accipit) %res = 2
+(accipit) @factorial %Lfalse:
+  let %17: i32 = mul %16: i32, %res: i32
+(accipit) <pointer to %n.addr: i32* in @factorial frame 1, offset 0 of 1>
+  [0] 3
+(accipit) program exited with 6
+(accipit) the program is not running
+(accipit) ";
+        assert_eq!(transcript, expected);
+    }
+
+    #[test]
+    fn test_debugger_errors() {
+        let transcript = run_script(&[
+            "break @missing",
+            "break %nowhere",
+            "break @factorial",
+            "info",
+            "print %res",
+            "print %undefined",
+            "memory #n",
+            "jump",
+            "quit",
+            "step",
+        ]);
+        let lines = transcript.lines().skip(2).collect::<Vec<_>>();
+        assert_eq!(lines, [
+            "(accipit) function '@missing' not found",
+            "(accipit) no basic block or value named '%nowhere'",
+            "(accipit) breakpoint 1 at '@factorial'",
+            "(accipit) 1: function @factorial",
+            "(accipit) %res = <not evaluated>",
+            "(accipit) symbol '%undefined' not found in current scope",
+            "(accipit) '#n' is not a pointer, but 3",
+            "(accipit) invalid command 'jump', try 'help'",
+            "(accipit) ",
+        ]);
+    }
+
+    #[test]
+    fn test_debugger_runtime_error() {
+        let src = "\
+fn @get(#i: i32) -> i32 {
+%entry:
+    let %a = alloca i32, 4
+    let %p = offset i32, %a, [#i < 4]
+    let %v = load %p
+    ret %v
+}
+
+fn @main() -> i32 {
+%entry:
+    let %i = add 2, 3
+    let %r = call @get, %i
+    ret %r
+}
+";
+        let transcript = run_script_on(src, "main", vec![], &[
+            "continue",
+            "backtrace",
+            "print #i",
+            "print %p",
+            "step",
+            "continue",
+        ]);
+        let expected = "\
+@main %entry:
+  let %i: i32 = add 2, 3
+(accipit) error:  in function 'get' with value 'p'
+in offset '#i: i32', index ['5' < '4'] is invalid
+(accipit) #0 @get %entry:
+  let %p: i32* = offset i32, %a: i32*, [#i: i32 < 4]
+#1 @main %entry:
+  let %r: i32 = call @get, %i: i32
+(accipit) #i = 5
+(accipit) %p = <not evaluated>
+(accipit) the program is not running
+(accipit) the program is not running
+(accipit) ";
+        assert_eq!(transcript, expected);
+
+        let module = parse_module(src).unwrap();
+        let err = Debugger::new(&module, "nosuch", vec![]).err().unwrap();
+        assert!(matches!(err.error, ExecutionErrorInternal::SymbolNotFound(_)), "{}", err);
+    }
+}
@@ -3,7 +3,10 @@ use std::char;
 use std::str::FromStr;
 use itertools::Itertools;
 use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
+use crate::backend::regalloc::{Allocation, Location};
+use crate::frontend::{SourceFile, Span};
 use crate::ir::{
     types::TypeKind,
     values,
@@ -17,6 +20,7 @@ use crate::utils::{
 use slotmap::SecondaryMap;
 use libc::*;
 use colored::Colorize;
+use ariadne::{Color, Label, Report, ReportKind, Source};
 
 #[derive(Debug, Clone)]
 pub enum ExecutionErrorInternal {
@@ -33,6 +37,8 @@ pub enum ExecutionErrorInternal {
     InternalError(String),
     FunctionNumArgumentMismatch(String, Vec<Val>),
     ReturnDanglingPointer(Value),
+    NoIncomingValue(String),
+    ClobberedLocation(Value, Location),
     LexerError,
     ParseError
 }
@@ -43,7 +49,10 @@ pub struct ExecutionError {
     pub function: String,
     pub value: String,
     // error internal.
-    pub error: ExecutionErrorInternal
+    pub error: ExecutionErrorInternal,
+    // source location of the value where error occurs.
+    pub span: Option<Span>,
+    pub source: Option<Rc<SourceFile>>
 }
 
 macro_rules! exec_error {
@@ -51,7 +60,9 @@ macro_rules! exec_error {
         ExecutionError {
             function: $function,
             value: $value,
-            error: $error
+            error: $error,
+            span: None,
+            source: None
         }
     }
 }
@@ -66,12 +77,21 @@ macro_rules! insepct_exec_error {
 
 }
 
-impl fmt::Display for ExecutionError {
+impl ExecutionError {
+    /// Attach the source location where the error occurs, unless it is already located.
+    pub fn located_at(mut self, module: &Module, span: Option<Span>) -> ExecutionError {
+        if self.span.is_none() && span.is_some() {
+            self.span = span;
+            self.source = module.source.clone();
+        }
+        self
+    }
+}
+
+impl fmt::Display for ExecutionErrorInternal {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} in function '{}' with value '{}'\n",
-                    "error: ".red().bold(), self.function.bold(), self.value.bold())?;
         use ExecutionErrorInternal::*;
-        match &self.error {
+        match self {
             SymbolNotFound(s) =>
                 write!(f, "'{}' symbol not found", s.bold()),
             TypeMismatch(value, val) =>
@@ -102,6 +122,11 @@ impl fmt::Display for ExecutionError {
             },
             UnexpectedIncompatibleVal(val) =>
                 write!(f, "unexpected incompatible value '{}'", val.to_string().bold()),
+            NoIncomingValue(label) =>
+                write!(f, "phi has no incoming value from predecessor '{}'", label.bold()),
+            ClobberedLocation(value, location) =>
+                write!(f, "value '{}' in '{}' is overwritten before its use, the register assignment is invalid",
+                        value.to_string().bold(), location.to_string().bold()),
             
             LexerError => write!(f, "lexing error"),
             ParseError => write!(f, "parsing error"),
@@ -110,6 +135,32 @@ impl fmt::Display for ExecutionError {
     }
 }
 
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.span, &self.source) {
+            (Some(span), Some(source)) => {
+                // print the offending IR line with a caret.
+                let mut buffer = Vec::new();
+                Report::build(ReportKind::Error, source.name.clone(), span.start)
+                    .with_message(format!("in function '{}' with value '{}'",
+                                    self.function.bold(), self.value.bold()))
+                    .with_label(Label::new((source.name.clone(), span.into_range()))
+                        .with_message(self.error.to_string())
+                        .with_color(Color::Red))
+                    .finish()
+                    .write((source.name.clone(), Source::from(source.text.as_str())), &mut buffer)
+                    .map_err(| _ | fmt::Error)?;
+                write!(f, "{}", String::from_utf8_lossy(&buffer).trim_end())
+            },
+            _ => {
+                write!(f, "{} in function '{}' with value '{}'\n",
+                            "error: ".red().bold(), self.function.bold(), self.value.bold())?;
+                write!(f, "{}", self.error)
+            }
+        }
+    }
+}
+
 /// Trace the source of pointer values,
 /// including function parameters, local allocas.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -322,11 +373,50 @@ impl Val {
     }
 }
 
+/// Registers and spill slots of a frame, simulating a register assignment of the working function.
+#[derive(Debug, Clone)]
+pub struct SimulatedLocations {
+    pub allocation: Rc<Allocation>,
+    pub registers: Vec<Val>,
+    pub spill_slots: Vec<Val>,
+}
+
+impl SimulatedLocations {
+    pub fn new(allocation: Rc<Allocation>) -> SimulatedLocations {
+        SimulatedLocations {
+            registers: vec![Val::Undefined; allocation.num_registers],
+            spill_slots: vec![Val::Undefined; allocation.num_spill_slots],
+            allocation
+        }
+    }
+
+    fn slot(&self, location: Location) -> &Val {
+        match location {
+            Location::Register(index) => &self.registers[index],
+            Location::Spill(index) => &self.spill_slots[index],
+        }
+    }
+
+    fn slot_mut(&mut self, location: Location) -> &mut Val {
+        match location {
+            Location::Register(index) => &mut self.registers[index],
+            Location::Spill(index) => &mut self.spill_slots[index],
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Frame {
+    /// values of the frame, also kept for values with a simulated location to detect clobbered reads.
     pub frame_val_env: SecondaryMap<ValueRef, Val>,
     pub local_allocas: HashSet<ValueRef>,
-    pub working_function: FunctionRef
+    pub working_function: FunctionRef,
+    /// the basic block control comes from.
+    pub predecessor: Option<BlockRef>,
+    /// values of phi in the current basic block, all read when entering the block.
+    pub incoming_vals: SecondaryMap<ValueRef, Val>,
+    /// simulated register assignment, values with a location are read from it.
+    pub locations: Option<SimulatedLocations>
 }
 
 impl Frame {
@@ -334,16 +424,36 @@ impl Frame {
         Frame {
             frame_val_env: SecondaryMap::new(),
             local_allocas: HashSet::new(),
-            working_function
+            working_function,
+            predecessor: None,
+            incoming_vals: SecondaryMap::new(),
+            locations: None
         }
     }
 
+    fn location(&self, value: ValueRef) -> Option<Location> {
+        self.locations.as_ref()?.allocation.location(value)
+    }
+
     pub fn set_local_val(&mut self, value: ValueRef, val: Val) -> Option<Val> {
+        if let Some(location) = self.location(value) {
+            *self.locations.as_mut().unwrap().slot_mut(location) = val.clone();
+        }
         self.frame_val_env.insert(value, val) 
     }
 
     pub fn get_local_val(&self, value: ValueRef) -> Option<&Val> {
-        self.frame_val_env.get(value)
+        match self.location(value) {
+            Some(location) => Some(self.locations.as_ref().unwrap().slot(location)),
+            None => self.frame_val_env.get(value)
+        }
+    }
+
+    /// The location of `value` if it no longer holds the value, overwritten by another value sharing it.
+    pub fn clobbered_location(&self, value: ValueRef) -> Option<Location> {
+        let location = self.location(value)?;
+        let simulated = self.locations.as_ref().unwrap().slot(location);
+        (self.frame_val_env.get(value) != Some(simulated)).then_some(location)
     }
 }
 
@@ -358,7 +468,9 @@ pub struct ProgramEnv {
     /// global values.
     pub global_val: SecondaryMap<ValueRef, Val>,
     /// function frames.
-    pub frames: Vec<Frame>
+    pub frames: Vec<Frame>,
+    /// register assignments simulated by the frames of these functions.
+    pub allocations: HashMap<FunctionRef, Rc<Allocation>>
 }
 
 impl ProgramEnv {
@@ -371,7 +483,18 @@ impl ProgramEnv {
     }
 
     pub fn prologue(&mut self, function: FunctionRef) {
-        self.frames.push(Frame::new(function))
+        let mut frame = Frame::new(function);
+        frame.locations = self.allocations
+            .get(&function)
+            .map(| allocation | SimulatedLocations::new(allocation.clone()));
+        self.frames.push(frame)
+    }
+
+    /// First of the values about to be read which no longer holds in its simulated location.
+    pub fn clobbered_operand(&self, mut values: impl Iterator<Item = ValueRef>) -> Option<(ValueRef, Location)> {
+        let frame = self.get_top_frame()
+            .expect("no active function frame");
+        values.find_map(| value | frame.clobbered_location(value).map(| location | (value, location)))
     }
 
     pub fn epilogue(&mut self) {
@@ -403,6 +526,31 @@ impl ProgramEnv {
             .expect("value does not exist")
     }
 
+    /// Transfer control to `dest` from the current basic block,
+    /// phi at the beginning of `dest` are evaluated simultaneously.
+    pub fn enter_basic_block(&mut self, module: &Module, function: &Function, dest: BlockRef) {
+        let predecessor = self.position;
+        let incoming_vals = function
+            .get_basic_block(dest)
+            .instrs
+            .iter().cloned()
+            .map_while(| instr | match &module.get_value(instr).kind {
+                ValueKind::Phi(inner) => Some((instr, inner)),
+                _ => None
+            })
+            .filter_map(| (instr, inner) | {
+                let incoming = inner.incoming_value(predecessor?)?;
+                Some((instr, self.get_val(incoming).clone()))
+            })
+            .collect();
+        let top_frame = self.frames
+            .last_mut()
+            .expect("no active function frame");
+        top_frame.predecessor = predecessor;
+        top_frame.incoming_vals = incoming_vals;
+        self.position = Some(dest);
+    }
+
     pub fn get_memory(&self, ptr: ValueRef, frame_index: usize) -> &Vec<Val> {
         let key_info = (ptr, frame_index);
         self.memory
@@ -437,17 +585,43 @@ impl ProgramEnv {
             program_counter: None,
             memory: HashMap::new(),
             global_val: SecondaryMap::new(),
-            frames: Vec::new()
+            frames: Vec::new(),
+            allocations: HashMap::new()
         }
     }
 }
 
 
+fn clobbered_error(module: &Module, function: &Function, value: ValueRef, location: Location) -> ExecutionError {
+    let value_data = module.get_value(value);
+    exec_error!(ExecutionErrorInternal::ClobberedLocation(value_data.clone(), location),
+                function.name.clone(), value_data.name.clone().unwrap_or("<anonymous>".to_string()))
+}
+
 pub fn single_step(
     env: &mut ProgramEnv,
     module: &Module,
     function: &Function,
     value: ValueRef,
+) -> Result<Val, ExecutionError> {
+    // phis read their incoming values when entering the block.
+    let operands = match &module.get_value(value).kind {
+        ValueKind::Phi(_) => vec![],
+        kind => kind.operands().collect()
+    };
+    if let Some((operand, location)) = env.clobbered_operand(operands.into_iter()) {
+        return Err(clobbered_error(module, function, operand, location)
+            .located_at(module, module.get_value(value).span));
+    }
+    step_value(env, module, function, value)
+        .map_err(| err | err.located_at(module, module.get_value(value).span))
+}
+
+fn step_value(
+    env: &mut ProgramEnv,
+    module: &Module,
+    function: &Function,
+    value: ValueRef,
 ) -> Result<Val, ExecutionError> {
     // println!("single step on `{}`", value_data);
     let value_data = module.get_value(value);
@@ -641,11 +815,15 @@ pub fn single_step(
                 },
                 _ => {
                     let func_ref = module.get_function_ref(&inner.callee);
-                    run_on_function(
+                    // the callee clobbers the position, restore it for the terminator of the caller.
+                    let position = env.position;
+                    let ret_val = run_on_function(
                         env,
                         module,
                         func_ref,
-                        args_val)
+                        args_val);
+                    env.position = position;
+                    ret_val
                 }
             }
         },
@@ -687,6 +865,20 @@ pub fn single_step(
             region[ptr.offset_within] = value_stored;
             Ok(Val::Unit)
         }
+        ValueKind::Phi(_) => {
+            let frame = env.get_top_frame()
+                .expect("no active function frame");
+            match frame.incoming_vals.get(value) {
+                Some(val) => Ok(val.clone()),
+                None => {
+                    let predecessor = frame.predecessor
+                        .map(| bb | format!("%{}", function.get_basic_block(bb).name.clone().unwrap_or("<unknown_label>".to_string())))
+                        .unwrap_or("<function entry>".to_string());
+                    Err(exec_error!(ExecutionErrorInternal::NoIncomingValue(predecessor),
+                                    function.name.clone(), value_data_name))
+                }
+            }
+        },
         _ => Err(exec_error!(ExecutionErrorInternal::NotImplemented(String::from("Expected Instruction")),
                             function.name.clone(), value_data_name))
     }
@@ -697,25 +889,52 @@ pub fn single_step_terminator(
     module: &Module,
     function: &Function,
     term: &Terminator
+) -> Result<Val, ExecutionError> {
+    // values flowing into phis of successors are read when leaving the block.
+    let phi_inputs = match env.position {
+        Some(bb) => term
+            .successors()
+            .flat_map(| succ | function.get_basic_block(succ).instrs.iter().cloned())
+            .map_while(| instr | match &module.get_value(instr).kind {
+                ValueKind::Phi(inner) => Some(inner.incoming_value(bb)),
+                _ => None
+            })
+            .flatten()
+            .collect(),
+        None => vec![]
+    };
+    if let Some((operand, location)) = env.clobbered_operand(term.operands().chain(phi_inputs)) {
+        return Err(clobbered_error(module, function, operand, location)
+            .located_at(module, term.span()));
+    }
+    step_terminator(env, module, function, term)
+        .map_err(| err | err.located_at(module, term.span()))
+}
+
+fn step_terminator(
+    env: &mut ProgramEnv,
+    module: &Module,
+    function: &Function,
+    term: &Terminator
 ) -> Result<Val, ExecutionError> {
     match &term {
         Terminator::Branch(inner) => {
             let cond = env.get_val(inner.cond);
             match cond {
                 Val::Bool(true) => {
-                    env.position = Some(inner.true_label);
+                    env.enter_basic_block(module, function, inner.true_label);
                     Ok(Val::Unit)
                 },
                 Val::Bool(false) => {
-                    env.position = Some(inner.false_label);
+                    env.enter_basic_block(module, function, inner.false_label);
                     Ok(Val::Unit)
                 },
                 Val::Integer(num) if num.clone() != 0 => {
-                    env.position = Some(inner.true_label);
+                    env.enter_basic_block(module, function, inner.true_label);
                     Ok(Val::Unit)
                 },
                 Val::Integer(num) if num.clone() == 0 => {
-                    env.position = Some(inner.false_label);
+                    env.enter_basic_block(module, function, inner.false_label);
                     Ok(Val::Unit)
                 },
                 _ => Err(exec_error!(ExecutionErrorInternal::UnexpectedIncompatibleVal(cond.clone()),
@@ -723,7 +942,7 @@ pub fn single_step_terminator(
             }
         },
         Terminator::Jump(inner) => {
-            env.position = Some(inner.dest);
+            env.enter_basic_block(module, function, inner.dest);
             Ok(Val::Unit)
         },
         Terminator::Return(inner) => {
@@ -761,12 +980,22 @@ pub fn run_on_basicblock(
     single_step_terminator(env, module, function, &block.terminator)
 }
 
-pub fn run_on_function(
+/// Runtime IO functions handled by the executor itself,
+/// they need no declaration or definition in the module.
+pub fn is_runtime_function(name: &str) -> bool {
+    matches!(name,
+        "getint" | "getch" | "getarray" |
+        "putint" | "putch" | "putarray" |
+        "starttime" | "stoptime")
+}
+
+/// Push a new frame for `function` and bind the input arguments to its parameters.
+pub fn prepare_function_frame(
     env: &mut ProgramEnv,
     module: &Module,
     function: FunctionRef,
     args: Vec<Val>
-) -> Result<Val, ExecutionError> {
+) -> Result<(), ExecutionError> {
     env.prologue(function);
     let function = module.get_function(function);
     // set args values
@@ -787,6 +1016,17 @@ pub fn run_on_function(
     params
         .into_iter().zip(function.args.iter().cloned())
         .for_each(| (val, value) | { env.set_value_binding(value, val); } );
+    Ok(())
+}
+
+pub fn run_on_function(
+    env: &mut ProgramEnv,
+    module: &Module,
+    function: FunctionRef,
+    args: Vec<Val>
+) -> Result<Val, ExecutionError> {
+    prepare_function_frame(env, module, function, args)?;
+    let function = module.get_function(function);
 
     let entry_bb = function.blocks[0];
     env.position = Some(entry_bb);
@@ -803,12 +1043,11 @@ pub fn run_on_function(
 }
 
 
-pub fn run_on_module(
+/// Bind constants and allocate global regions before running any function.
+pub fn initialize_module(
     env: &mut ProgramEnv,
-    module: &Module,
-    entry_fn: &str,
-    args: Vec<Val>
-) -> Result<Val, ExecutionError> {
+    module: &Module
+) {
     // FIXME, insert a phantom function as the global 'frame'.
     use crate::ir::types::Type;
     use slotmap::SlotMap;
@@ -863,7 +1102,58 @@ pub fn run_on_module(
                 _ => (),
             };
         });
+}
 
+pub fn run_on_module(
+    env: &mut ProgramEnv,
+    module: &Module,
+    entry_fn: &str,
+    args: Vec<Val>
+) -> Result<Val, ExecutionError> {
+    initialize_module(env, module);
     let function = module.get_function_ref(entry_fn);
     run_on_function(env, module, function, args)
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontend::new_parser::parse_module;
+
+    #[test]
+    fn test_phi_reads_predecessor() {
+        let src = std::fs::read_to_string(
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/phi.acc")).unwrap();
+        let module = parse_module(&src).unwrap();
+        let sum = run_on_module(&mut ProgramEnv::new(), &module, "sum", vec![Val::Integer(10)]).unwrap();
+        assert_eq!(sum, Val::Integer(55));
+        // phi are evaluated simultaneously, `%a` and `%b` swap each iteration.
+        let swapped = run_on_module(&mut ProgramEnv::new(), &module, "swap", vec![Val::Integer(2)]).unwrap();
+        assert_eq!(swapped, Val::Integer(2));
+    }
+
+    #[test]
+    fn test_error_source_location() {
+        colored::control::set_override(false);
+        let src = "\
+fn @main() -> i32 {
+%entry:
+    let %a = alloca i32, 4
+    let %p = offset i32, %a, [5 < 4]
+    let %v = load %p
+    ret %v
+}
+";
+        let mut module = parse_module(src).unwrap();
+        module.source = Some(Rc::new(SourceFile::new("oob.acc".to_string(), src.to_string())));
+        let err = run_on_module(&mut ProgramEnv::new(), &module, "main", vec![]).unwrap_err();
+        assert_eq!(&src[err.span.unwrap().into_range()], "let %p = offset i32, %a, [5 < 4]");
+        let rendered = err.to_string();
+        assert!(rendered.contains("oob.acc:4:5"), "{}", rendered);
+        assert!(rendered.contains(" 4 │     let %p = offset i32, %a, [5 < 4]\n"), "{}", rendered);
+        // the label points at the offending line.
+        assert!(rendered.lines().any(| line | line.contains('╰') && line.ends_with("in offset '5', index ['5' < '4'] is invalid")),
+                "{}", rendered);
+    }
+}
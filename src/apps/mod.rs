@@ -1,2 +1,3 @@
 pub mod executor;
-pub mod debugger;
\ No newline at end of file
+pub mod debugger;
+pub mod rvsim;
\ No newline at end of file
@@ -0,0 +1,1055 @@
+//! Assembler and simulator for RV64IM assembly, to run generated code without qemu.
+//!
+//! The assembler accepts GNU assembler syntax with `.text`, `.data`, `.rodata` and `.bss` sections,
+//! the usual data directives and the common pseudo instructions, and checks immediate ranges.
+//! Instructions are kept decoded, each taking 4 bytes of the text address space,
+//! so code addresses are only meaningful as jump targets and cannot be read as data.
+//!
+//! Calls to the SysY runtime functions land in stubs running the hooks of the IR executor,
+//! reading from and writing to the streams given to `Machine::run`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, Write};
+
+use colored::Colorize;
+
+use super::executor::is_runtime_function;
+
+/// Address of the first instruction.
+pub const TEXT_BASE: u64 = 0x1_0000;
+/// Address of the first byte of data, followed by the bss section and the stack.
+pub const DATA_BASE: u64 = 0x1000_0000;
+/// Size of the stack in bytes.
+pub const STACK_SIZE: usize = 8 << 20;
+
+const REGISTER_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2",
+    "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7",
+    "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+const RA: usize = 1;
+const SP: usize = 2;
+const A0: usize = 10;
+const A1: usize = 11;
+const A7: usize = 17;
+
+/// Symbols of the SysY runtime provided by the simulator unless the program defines them,
+/// `starttime` and `stoptime` are macros over `_sysy_starttime` and `_sysy_stoptime` in the runtime.
+fn runtime_symbols() -> impl Iterator<Item = &'static str> {
+    ["getint", "getch", "getarray", "putint", "putch", "putarray", "starttime", "stoptime"]
+        .into_iter()
+        .filter(| name | is_runtime_function(name))
+        .chain(["_sysy_starttime", "_sysy_stoptime"])
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RvsimError {
+    /// Malformed or unsupported assembly, at a line counting from one.
+    Syntax(usize, String),
+    UndefinedSymbol(usize, String),
+    DuplicatedSymbol(usize, String),
+    /// The entry function is not a label in the text section.
+    MissingEntry(String),
+    /// Control reached an address without instruction, after the instruction at the line.
+    InvalidJump(usize, u64),
+    /// Load or store outside the data sections and the stack.
+    InvalidAccess(usize, u64),
+    /// `unimp`, `ebreak` or an `ecall` other than exit was executed.
+    Trap(usize, String),
+    /// A runtime function failed, such as on malformed input.
+    Runtime(String, String),
+}
+
+impl fmt::Display for RvsimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", "rvsim error: ".red().bold())?;
+        match self {
+            RvsimError::Syntax(line, message) =>
+                write!(f, "line {}: {}", line, message),
+            RvsimError::UndefinedSymbol(line, symbol) =>
+                write!(f, "line {}: undefined symbol '{}'", line, symbol.bold()),
+            RvsimError::DuplicatedSymbol(line, symbol) =>
+                write!(f, "line {}: symbol '{}' is already defined", line, symbol.bold()),
+            RvsimError::MissingEntry(entry) =>
+                write!(f, "entry function '{}' is not defined in the text section", entry.bold()),
+            RvsimError::InvalidJump(line, target) =>
+                write!(f, "line {}: no instruction at address {:#x}", line, target),
+            RvsimError::InvalidAccess(line, addr) =>
+                write!(f, "line {}: memory access out of bounds at address {:#x}", line, addr),
+            RvsimError::Trap(line, reason) =>
+                write!(f, "line {}: {}", line, reason),
+            RvsimError::Runtime(function, message) =>
+                write!(f, "runtime function '{}' failed, {}", function.bold(), message),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AluOp {
+    Add, Sub, Sll, Slt, Sltu, Xor, Srl, Sra, Or, And,
+    Mul, Mulh, Mulhsu, Mulhu, Div, Divu, Rem, Remu,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BranchOp {
+    Eq, Ne, Lt, Ge, Ltu, Geu,
+}
+
+/// A decoded instruction, pseudo instructions are expanded to these.
+///
+/// Word operations compute on the low 32 bits of their operands and sign extend the result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    Op { op: AluOp, word: bool, rd: usize, rs1: usize, rs2: usize },
+    OpImm { op: AluOp, word: bool, rd: usize, rs1: usize, imm: i64 },
+    /// `lui` and `auipc`, with the immediate already shifted.
+    Lui { rd: usize, imm: i64 },
+    Auipc { rd: usize, imm: i64 },
+    Load { width: usize, signed: bool, rd: usize, rs1: usize, offset: i64 },
+    Store { width: usize, rs2: usize, rs1: usize, offset: i64 },
+    Branch { op: BranchOp, rs1: usize, rs2: usize, target: u64 },
+    Jal { rd: usize, target: u64 },
+    Jalr { rd: usize, rs1: usize, offset: i64 },
+    Ecall,
+    Ebreak,
+    Fence,
+    Unimp,
+    /// Stub of a runtime function, returning to `ra`.
+    Runtime(&'static str),
+    /// Return address of the entry function, stops the simulation.
+    Halt,
+}
+
+/// An assembled program, with instructions at `TEXT_BASE` and the data image at `DATA_BASE`.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+    /// Source line of each instruction, zero for stubs.
+    pub lines: Vec<usize>,
+    pub data: Vec<u8>,
+    pub symbols: HashMap<String, u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Section {
+    Text,
+    Data,
+    Bss,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Symbol {
+    Text(usize),
+    Data(Section, usize),
+}
+
+struct Statement {
+    line: usize,
+    mnemonic: String,
+    operands: Vec<String>,
+}
+
+/// Data word holding the address of a symbol, resolved once all symbols are laid out.
+struct Fixup {
+    line: usize,
+    section: Section,
+    offset: usize,
+    width: usize,
+    expr: String,
+}
+
+fn align_to(value: usize, align: usize) -> usize {
+    value.div_ceil(align) * align
+}
+
+fn parse_register(operand: &str) -> Option<usize> {
+    match operand {
+        "fp" => Some(8),
+        _ => REGISTER_NAMES
+            .iter()
+            .position(| name | *name == operand)
+            .or_else(|| operand.strip_prefix('x')?.parse().ok().filter(| index | *index < 32))
+    }
+}
+
+fn parse_integer(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let magnitude = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<u64>().ok()?,
+    };
+    Some(if negative { (magnitude as i64).wrapping_neg() } else { magnitude as i64 })
+}
+
+/// Split the operands of a statement at top level commas, keeping string literals whole.
+fn split_operands(text: &str) -> Vec<String> {
+    let mut operands = Vec::new();
+    let mut current = String::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in text.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                operands.push(current.trim().to_string());
+                current.clear();
+                continue;
+            },
+            _ => ()
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() || !operands.is_empty() {
+        operands.push(current.trim().to_string());
+    }
+    operands
+}
+
+/// Strip a `#` comment which is not inside a string literal.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => ()
+        }
+    }
+    line
+}
+
+fn parse_string(line: usize, literal: &str) -> Result<Vec<u8>, RvsimError> {
+    let inner = literal
+        .strip_prefix('"')
+        .and_then(| rest | rest.strip_suffix('"'))
+        .ok_or_else(|| RvsimError::Syntax(line, format!("expect a string literal, but found '{}'", literal)))?;
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            bytes.extend(c.to_string().bytes());
+            continue;
+        }
+        bytes.push(match chars.next() {
+            Some('n') => b'\n',
+            Some('t') => b'\t',
+            Some('0') => 0,
+            Some('\\') => b'\\',
+            Some('"') => b'"',
+            other => return Err(RvsimError::Syntax(line, format!("unsupported escape sequence '\\{}'", other.unwrap_or(' ')))),
+        });
+    }
+    Ok(bytes)
+}
+
+fn alu_op(mnemonic: &str) -> Option<(AluOp, bool)> {
+    let (base, word) = match mnemonic.strip_suffix('w') {
+        Some(base) if matches!(base, "add" | "sub" | "sll" | "srl" | "sra" | "mul" | "div" | "divu" | "rem" | "remu") => (base, true),
+        _ => (mnemonic, false),
+    };
+    let op = match base {
+        "add" => AluOp::Add,
+        "sub" => AluOp::Sub,
+        "sll" => AluOp::Sll,
+        "slt" => AluOp::Slt,
+        "sltu" => AluOp::Sltu,
+        "xor" => AluOp::Xor,
+        "srl" => AluOp::Srl,
+        "sra" => AluOp::Sra,
+        "or" => AluOp::Or,
+        "and" => AluOp::And,
+        "mul" => AluOp::Mul,
+        "mulh" => AluOp::Mulh,
+        "mulhsu" => AluOp::Mulhsu,
+        "mulhu" => AluOp::Mulhu,
+        "div" => AluOp::Div,
+        "divu" => AluOp::Divu,
+        "rem" => AluOp::Rem,
+        "remu" => AluOp::Remu,
+        _ => return None
+    };
+    Some((op, word))
+}
+
+fn alu_imm_op(mnemonic: &str) -> Option<(AluOp, bool)> {
+    let op = match mnemonic {
+        "addi" | "addiw" => AluOp::Add,
+        "slti" => AluOp::Slt,
+        "sltiu" => AluOp::Sltu,
+        "xori" => AluOp::Xor,
+        "ori" => AluOp::Or,
+        "andi" => AluOp::And,
+        "slli" | "slliw" => AluOp::Sll,
+        "srli" | "srliw" => AluOp::Srl,
+        "srai" | "sraiw" => AluOp::Sra,
+        _ => return None
+    };
+    Some((op, mnemonic.ends_with('w')))
+}
+
+fn branch_op(mnemonic: &str) -> Option<BranchOp> {
+    match mnemonic {
+        "beq" => Some(BranchOp::Eq),
+        "bne" => Some(BranchOp::Ne),
+        "blt" => Some(BranchOp::Lt),
+        "bge" => Some(BranchOp::Ge),
+        "bltu" => Some(BranchOp::Ltu),
+        "bgeu" => Some(BranchOp::Geu),
+        _ => None
+    }
+}
+
+/// Width in bytes and signedness of a load.
+fn load_op(mnemonic: &str) -> Option<(usize, bool)> {
+    match mnemonic {
+        "lb" => Some((1, true)),
+        "lh" => Some((2, true)),
+        "lw" => Some((4, true)),
+        "ld" => Some((8, true)),
+        "lbu" => Some((1, false)),
+        "lhu" => Some((2, false)),
+        "lwu" => Some((4, false)),
+        _ => None
+    }
+}
+
+fn store_op(mnemonic: &str) -> Option<usize> {
+    match mnemonic {
+        "sb" => Some(1),
+        "sh" => Some(2),
+        "sw" => Some(4),
+        "sd" => Some(8),
+        _ => None
+    }
+}
+
+struct Assembler {
+    statements: Vec<Statement>,
+    sections: HashMap<Section, Vec<u8>>,
+    fixups: Vec<Fixup>,
+    labels: HashMap<String, Symbol>,
+    /// Addresses of all symbols, filled once sections are laid out.
+    symbols: HashMap<String, u64>,
+}
+
+impl Assembler {
+    fn define(&mut self, line: usize, name: &str, symbol: Symbol) -> Result<(), RvsimError> {
+        match self.labels.insert(name.to_string(), symbol) {
+            Some(_) => Err(RvsimError::DuplicatedSymbol(line, name.to_string())),
+            None => Ok(())
+        }
+    }
+
+    /// Read the statements of the source, emitting data directly into the sections.
+    fn read(&mut self, src: &str) -> Result<(), RvsimError> {
+        let mut section = Section::Text;
+        for (index, text) in src.lines().enumerate() {
+            let line = index + 1;
+            let mut rest = strip_comment(text).trim();
+            // any number of labels may precede a statement.
+            while let Some((label, after)) = rest.split_once(':') {
+                let label = label.trim();
+                if label.is_empty() || label.contains(| c: char | c.is_whitespace() || c == '"') {
+                    break;
+                }
+                let symbol = match section {
+                    Section::Text => Symbol::Text(self.statements.len()),
+                    _ => Symbol::Data(section, self.sections.entry(section).or_default().len()),
+                };
+                self.define(line, label, symbol)?;
+                rest = after.trim();
+            }
+            if rest.is_empty() {
+                continue;
+            }
+            let (mnemonic, operands) = match rest.split_once(char::is_whitespace) {
+                Some((mnemonic, operands)) => (mnemonic, split_operands(operands)),
+                None => (rest, vec![]),
+            };
+            if mnemonic.starts_with('.') {
+                self.directive(line, &mut section, mnemonic, &operands)?;
+            } else if section == Section::Text {
+                self.statements.push(Statement { line, mnemonic: mnemonic.to_string(), operands });
+            } else {
+                return Err(RvsimError::Syntax(line, format!("instruction '{}' outside the text section", mnemonic)));
+            }
+        }
+        Ok(())
+    }
+
+    fn directive(&mut self, line: usize, section: &mut Section, directive: &str, operands: &[String]) -> Result<(), RvsimError> {
+        let integer = | operand: &String | parse_integer(operand)
+            .ok_or_else(|| RvsimError::Syntax(line, format!("expect an integer, but found '{}'", operand)));
+        let switch_to = | name: &str | match name {
+            ".text" => Some(Section::Text),
+            ".data" | ".rodata" | ".sdata" | ".srodata" => Some(Section::Data),
+            ".bss" | ".sbss" => Some(Section::Bss),
+            _ if name.starts_with(".rodata.") || name.starts_with(".data.") || name.starts_with(".srodata.") => Some(Section::Data),
+            _ if name.starts_with(".bss.") => Some(Section::Bss),
+            _ if name.starts_with(".text.") => Some(Section::Text),
+            _ => None
+        };
+        match directive {
+            ".text" | ".data" | ".rodata" | ".bss" => *section = switch_to(directive).unwrap(),
+            ".section" => {
+                let name = operands.first().map(String::as_str).unwrap_or("");
+                *section = switch_to(name)
+                    .ok_or_else(|| RvsimError::Syntax(line, format!("unsupported section '{}'", name)))?;
+            },
+            ".globl" | ".global" | ".local" | ".type" | ".size" | ".file" | ".ident" | ".option" | ".attribute" => (),
+            ".align" | ".p2align" | ".balign" => {
+                let amount = integer(operands.first().ok_or_else(|| RvsimError::Syntax(line, format!("'{}' expects an alignment", directive)))?)?;
+                let align = if directive == ".balign" { amount as usize } else { 1 << amount };
+                // instructions are always aligned, only data needs padding.
+                if *section != Section::Text {
+                    let bytes = self.sections.entry(*section).or_default();
+                    bytes.resize(align_to(bytes.len(), align.max(1)), 0);
+                }
+            },
+            ".byte" | ".half" | ".short" | ".2byte" | ".word" | ".long" | ".4byte" | ".dword" | ".quad" | ".8byte" => {
+                let width = match directive {
+                    ".byte" => 1,
+                    ".half" | ".short" | ".2byte" => 2,
+                    ".word" | ".long" | ".4byte" => 4,
+                    _ => 8,
+                };
+                if *section == Section::Text {
+                    return Err(RvsimError::Syntax(line, format!("data directive '{}' in the text section", directive)));
+                }
+                for operand in operands {
+                    let bytes = self.sections.entry(*section).or_default();
+                    let value = match parse_integer(operand) {
+                        Some(value) => value,
+                        None => {
+                            self.fixups.push(Fixup { line, section: *section, offset: bytes.len(), width, expr: operand.clone() });
+                            0
+                        }
+                    };
+                    bytes.extend_from_slice(&value.to_le_bytes()[..width]);
+                }
+            },
+            ".zero" | ".space" | ".skip" => {
+                let size = integer(operands.first().ok_or_else(|| RvsimError::Syntax(line, format!("'{}' expects a size", directive)))?)?;
+                if *section == Section::Text {
+                    return Err(RvsimError::Syntax(line, format!("data directive '{}' in the text section", directive)));
+                }
+                let bytes = self.sections.entry(*section).or_default();
+                bytes.resize(bytes.len() + size as usize, 0);
+            },
+            ".string" | ".asciz" | ".ascii" => {
+                if *section == Section::Text {
+                    return Err(RvsimError::Syntax(line, format!("data directive '{}' in the text section", directive)));
+                }
+                for operand in operands {
+                    let mut string = parse_string(line, operand)?;
+                    if directive != ".ascii" {
+                        string.push(0);
+                    }
+                    self.sections.entry(*section).or_default().extend(string);
+                }
+            },
+            _ => return Err(RvsimError::Syntax(line, format!("unsupported directive '{}'", directive)))
+        }
+        Ok(())
+    }
+
+    /// Lay out the sections and runtime stubs, and compute the address of every symbol.
+    /// Returns the data image and the instructions preceding the program.
+    fn layout(&mut self) -> (Vec<u8>, Vec<Instruction>) {
+        let mut data = self.sections.remove(&Section::Data).unwrap_or_default();
+        let bss_start = align_to(data.len(), 16);
+        let bss = self.sections.remove(&Section::Bss).unwrap_or_default();
+        data.resize(bss_start + bss.len(), 0);
+
+        let mut stubs = vec![Instruction::Halt];
+        for name in runtime_symbols() {
+            if !self.labels.contains_key(name) {
+                self.symbols.insert(name.to_string(), TEXT_BASE + 4 * stubs.len() as u64);
+                stubs.push(Instruction::Runtime(name));
+            }
+        }
+        for (name, symbol) in self.labels.iter() {
+            let address = match symbol {
+                Symbol::Text(index) => TEXT_BASE + 4 * (stubs.len() + index) as u64,
+                Symbol::Data(Section::Bss, offset) => DATA_BASE + (bss_start + offset) as u64,
+                Symbol::Data(_, offset) => DATA_BASE + *offset as u64,
+            };
+            self.symbols.insert(name.clone(), address);
+        }
+        for fixup in self.fixups.iter_mut() {
+            if fixup.section == Section::Bss {
+                fixup.offset += bss_start;
+            }
+        }
+        (data, stubs)
+    }
+
+    /// Value of `symbol`, `symbol+offset`, `symbol-offset` or an integer.
+    fn expression(&self, line: usize, expr: &str) -> Result<i64, RvsimError> {
+        if let Some(value) = parse_integer(expr) {
+            return Ok(value);
+        }
+        let (symbol, offset) = match expr.rfind(['+', '-']).filter(| index | *index > 0) {
+            Some(index) => (expr[..index].trim(), parse_integer(&expr[index..].replace(' ', ""))
+                .ok_or_else(|| RvsimError::Syntax(line, format!("invalid expression '{}'", expr)))?),
+            None => (expr, 0)
+        };
+        self.symbols
+            .get(symbol)
+            .map(| address | *address as i64 + offset)
+            .ok_or_else(|| RvsimError::UndefinedSymbol(line, symbol.to_string()))
+    }
+
+    /// Immediate operand, possibly a `%hi` or `%lo` relocation, checked to fit in `bits` signed bits.
+    fn immediate(&self, line: usize, operand: &str, bits: u32) -> Result<i64, RvsimError> {
+        let relocation = | name: &str | operand
+            .strip_prefix(name)
+            .and_then(| rest | rest.strip_prefix('('))
+            .and_then(| rest | rest.strip_suffix(')'));
+        let value = if let Some(expr) = relocation("%hi") {
+            (self.expression(line, expr)? + 0x800) >> 12
+        } else if let Some(expr) = relocation("%lo") {
+            (self.expression(line, expr)? << 52) >> 52
+        } else if operand.starts_with('%') {
+            return Err(RvsimError::Syntax(line, format!("unsupported relocation '{}'", operand)));
+        } else {
+            parse_integer(operand)
+                .ok_or_else(|| RvsimError::Syntax(line, format!("expect an immediate, but found '{}'", operand)))?
+        };
+        let range = -(1i64 << (bits - 1))..(1i64 << (bits - 1));
+        if range.contains(&value) {
+            Ok(value)
+        } else {
+            Err(RvsimError::Syntax(line, format!("immediate '{}' does not fit in {} bits", operand, bits)))
+        }
+    }
+
+    fn register(&self, line: usize, operand: &str) -> Result<usize, RvsimError> {
+        parse_register(operand).ok_or_else(|| RvsimError::Syntax(line, format!("expect a register, but found '{}'", operand)))
+    }
+
+    /// Address operand `offset(register)`, the offset may be omitted or a `%lo` relocation.
+    fn memory(&self, line: usize, operand: &str) -> Result<(i64, usize), RvsimError> {
+        let (offset, register) = operand
+            .strip_suffix(')')
+            .and_then(| rest | rest.rsplit_once('('))
+            .ok_or_else(|| RvsimError::Syntax(line, format!("expect a memory operand, but found '{}'", operand)))?;
+        let offset = match offset.trim() {
+            "" => 0,
+            offset => self.immediate(line, offset, 12)?,
+        };
+        Ok((offset, self.register(line, register.trim())?))
+    }
+
+    fn target(&self, line: usize, operand: &str) -> Result<u64, RvsimError> {
+        self.expression(line, operand).map(| address | address as u64)
+    }
+
+    fn shift_amount(&self, line: usize, operand: &str, word: bool) -> Result<i64, RvsimError> {
+        let amount = self.immediate(line, operand, 12)?;
+        match (amount, word) {
+            (0..=31, true) | (0..=63, false) => Ok(amount),
+            _ => Err(RvsimError::Syntax(line, format!("shift amount '{}' out of range", operand)))
+        }
+    }
+
+    fn instruction(&self, statement: &Statement) -> Result<Instruction, RvsimError> {
+        let line = statement.line;
+        let mnemonic = statement.mnemonic.as_str();
+        let ops = statement.operands.iter().map(String::as_str).collect::<Vec<_>>();
+        let reg = | index: usize | self.register(line, ops[index]);
+        let imm12 = | index: usize | self.immediate(line, ops[index], 12);
+        let target = | index: usize | self.target(line, ops[index]);
+        let expect = | count: usize | if ops.len() == count {
+            Ok(())
+        } else {
+            Err(RvsimError::Syntax(line, format!("'{}' expects {} operands, but found {}", mnemonic, count, ops.len())))
+        };
+
+        if let Some((op, word)) = alu_op(mnemonic) {
+            expect(3)?;
+            return Ok(Instruction::Op { op, word, rd: reg(0)?, rs1: reg(1)?, rs2: reg(2)? });
+        }
+        if let Some((op, word)) = alu_imm_op(mnemonic) {
+            expect(3)?;
+            let imm = match op {
+                AluOp::Sll | AluOp::Srl | AluOp::Sra => self.shift_amount(line, ops[2], word)?,
+                _ => imm12(2)?,
+            };
+            return Ok(Instruction::OpImm { op, word, rd: reg(0)?, rs1: reg(1)?, imm });
+        }
+        if let Some((width, signed)) = load_op(mnemonic) {
+            expect(2)?;
+            let (offset, rs1) = self.memory(line, ops[1])?;
+            return Ok(Instruction::Load { width, signed, rd: reg(0)?, rs1, offset });
+        }
+        if let Some(width) = store_op(mnemonic) {
+            expect(2)?;
+            let (offset, rs1) = self.memory(line, ops[1])?;
+            return Ok(Instruction::Store { width, rs2: reg(0)?, rs1, offset });
+        }
+        if let Some(op) = branch_op(mnemonic) {
+            expect(3)?;
+            return Ok(Instruction::Branch { op, rs1: reg(0)?, rs2: reg(1)?, target: target(2)? });
+        }
+        let mv = | rd: usize, rs1: usize | Instruction::OpImm { op: AluOp::Add, word: false, rd, rs1, imm: 0 };
+        let instruction = match mnemonic {
+            "lui" | "auipc" => {
+                expect(2)?;
+                let imm = self.immediate(line, ops[1], 21)?;
+                if !(0..1 << 20).contains(&imm) && !(-(1 << 19)..0).contains(&imm) {
+                    return Err(RvsimError::Syntax(line, format!("immediate '{}' does not fit in 20 bits", ops[1])));
+                }
+                // the upper immediate is sign extended from bit 31.
+                let imm = ((imm << 12) as i32) as i64;
+                if mnemonic == "lui" { Instruction::Lui { rd: reg(0)?, imm } } else { Instruction::Auipc { rd: reg(0)?, imm } }
+            },
+            "jal" => match ops.len() {
+                1 => Instruction::Jal { rd: RA, target: target(0)? },
+                _ => {
+                    expect(2)?;
+                    Instruction::Jal { rd: reg(0)?, target: target(1)? }
+                }
+            },
+            "jalr" => match ops.len() {
+                1 => Instruction::Jalr { rd: RA, rs1: reg(0)?, offset: 0 },
+                2 => {
+                    let (offset, rs1) = self.memory(line, ops[1])?;
+                    Instruction::Jalr { rd: reg(0)?, rs1, offset }
+                },
+                _ => {
+                    expect(3)?;
+                    Instruction::Jalr { rd: reg(0)?, rs1: reg(1)?, offset: imm12(2)? }
+                }
+            },
+            "ecall" | "ebreak" | "unimp" | "nop" | "ret" => {
+                expect(0)?;
+                match mnemonic {
+                    "ecall" => Instruction::Ecall,
+                    "ebreak" => Instruction::Ebreak,
+                    "unimp" => Instruction::Unimp,
+                    "nop" => mv(0, 0),
+                    _ => Instruction::Jalr { rd: 0, rs1: RA, offset: 0 },
+                }
+            },
+            "fence" | "fence.i" => Instruction::Fence,
+            "li" => {
+                expect(2)?;
+                let imm = parse_integer(ops[1])
+                    .ok_or_else(|| RvsimError::Syntax(line, format!("expect an immediate, but found '{}'", ops[1])))?;
+                Instruction::OpImm { op: AluOp::Add, word: false, rd: reg(0)?, rs1: 0, imm }
+            },
+            "la" | "lla" => {
+                expect(2)?;
+                Instruction::OpImm { op: AluOp::Add, word: false, rd: reg(0)?, rs1: 0, imm: self.expression(line, ops[1])? }
+            },
+            "mv" => {
+                expect(2)?;
+                mv(reg(0)?, reg(1)?)
+            },
+            "not" | "neg" | "negw" | "sext.w" | "seqz" | "snez" | "sltz" | "sgtz" => {
+                expect(2)?;
+                let (rd, rs) = (reg(0)?, reg(1)?);
+                match mnemonic {
+                    "not" => Instruction::OpImm { op: AluOp::Xor, word: false, rd, rs1: rs, imm: -1 },
+                    "neg" => Instruction::Op { op: AluOp::Sub, word: false, rd, rs1: 0, rs2: rs },
+                    "negw" => Instruction::Op { op: AluOp::Sub, word: true, rd, rs1: 0, rs2: rs },
+                    "sext.w" => Instruction::OpImm { op: AluOp::Add, word: true, rd, rs1: rs, imm: 0 },
+                    "seqz" => Instruction::OpImm { op: AluOp::Sltu, word: false, rd, rs1: rs, imm: 1 },
+                    "snez" => Instruction::Op { op: AluOp::Sltu, word: false, rd, rs1: 0, rs2: rs },
+                    "sltz" => Instruction::Op { op: AluOp::Slt, word: false, rd, rs1: rs, rs2: 0 },
+                    _ => Instruction::Op { op: AluOp::Slt, word: false, rd, rs1: 0, rs2: rs },
+                }
+            },
+            "beqz" | "bnez" | "blez" | "bgez" | "bltz" | "bgtz" => {
+                expect(2)?;
+                let (rs, target) = (reg(0)?, target(1)?);
+                let (op, rs1, rs2) = match mnemonic {
+                    "beqz" => (BranchOp::Eq, rs, 0),
+                    "bnez" => (BranchOp::Ne, rs, 0),
+                    "blez" => (BranchOp::Ge, 0, rs),
+                    "bgez" => (BranchOp::Ge, rs, 0),
+                    "bltz" => (BranchOp::Lt, rs, 0),
+                    _ => (BranchOp::Lt, 0, rs),
+                };
+                Instruction::Branch { op, rs1, rs2, target }
+            },
+            "bgt" | "ble" | "bgtu" | "bleu" => {
+                expect(3)?;
+                let op = match mnemonic {
+                    "bgt" => BranchOp::Lt,
+                    "ble" => BranchOp::Ge,
+                    "bgtu" => BranchOp::Ltu,
+                    _ => BranchOp::Geu,
+                };
+                Instruction::Branch { op, rs1: reg(1)?, rs2: reg(0)?, target: target(2)? }
+            },
+            "j" | "call" | "tail" => {
+                expect(1)?;
+                let rd = if mnemonic == "call" { RA } else { 0 };
+                Instruction::Jal { rd, target: target(0)? }
+            },
+            "jr" => {
+                expect(1)?;
+                Instruction::Jalr { rd: 0, rs1: reg(0)?, offset: 0 }
+            },
+            _ => return Err(RvsimError::Syntax(line, format!("unsupported instruction '{}'", mnemonic)))
+        };
+        Ok(instruction)
+    }
+}
+
+/// Assemble a program from RV64IM assembly in GNU assembler syntax.
+pub fn assemble(src: &str) -> Result<Program, RvsimError> {
+    let mut assembler = Assembler {
+        statements: Vec::new(),
+        sections: HashMap::new(),
+        fixups: Vec::new(),
+        labels: HashMap::new(),
+        symbols: HashMap::new(),
+    };
+    assembler.read(src)?;
+    let (mut data, mut instructions) = assembler.layout();
+    let mut lines = vec![0; instructions.len()];
+    for fixup in assembler.fixups.iter() {
+        let value = assembler.expression(fixup.line, &fixup.expr)?;
+        data[fixup.offset..fixup.offset + fixup.width].copy_from_slice(&value.to_le_bytes()[..fixup.width]);
+    }
+    for statement in assembler.statements.iter() {
+        instructions.push(assembler.instruction(statement)?);
+        lines.push(statement.line);
+    }
+    Ok(Program { instructions, lines, data, symbols: assembler.symbols })
+}
+
+fn alu(op: AluOp, word: bool, lhs: u64, rhs: u64) -> u64 {
+    if word {
+        let (lhs, rhs) = (lhs as i32, rhs as i32);
+        let shift = (rhs & 31) as u32;
+        let result = match op {
+            AluOp::Add => lhs.wrapping_add(rhs),
+            AluOp::Sub => lhs.wrapping_sub(rhs),
+            AluOp::Sll => lhs << shift,
+            AluOp::Srl => ((lhs as u32) >> shift) as i32,
+            AluOp::Sra => lhs >> shift,
+            AluOp::Mul => lhs.wrapping_mul(rhs),
+            AluOp::Div if rhs == 0 => -1,
+            AluOp::Div => lhs.wrapping_div(rhs),
+            AluOp::Divu if rhs == 0 => -1,
+            AluOp::Divu => ((lhs as u32) / (rhs as u32)) as i32,
+            AluOp::Rem if rhs == 0 => lhs,
+            AluOp::Rem => lhs.wrapping_rem(rhs),
+            AluOp::Remu if rhs == 0 => lhs,
+            AluOp::Remu => ((lhs as u32) % (rhs as u32)) as i32,
+            _ => unreachable!("no word variant of {:?}", op)
+        };
+        return result as i64 as u64;
+    }
+    let (signed_lhs, signed_rhs) = (lhs as i64, rhs as i64);
+    let shift = (rhs & 63) as u32;
+    match op {
+        AluOp::Add => lhs.wrapping_add(rhs),
+        AluOp::Sub => lhs.wrapping_sub(rhs),
+        AluOp::Sll => lhs << shift,
+        AluOp::Slt => (signed_lhs < signed_rhs) as u64,
+        AluOp::Sltu => (lhs < rhs) as u64,
+        AluOp::Xor => lhs ^ rhs,
+        AluOp::Srl => lhs >> shift,
+        AluOp::Sra => (signed_lhs >> shift) as u64,
+        AluOp::Or => lhs | rhs,
+        AluOp::And => lhs & rhs,
+        AluOp::Mul => lhs.wrapping_mul(rhs),
+        AluOp::Mulh => ((signed_lhs as i128 * signed_rhs as i128) >> 64) as u64,
+        AluOp::Mulhsu => ((signed_lhs as i128 * rhs as i128) >> 64) as u64,
+        AluOp::Mulhu => ((lhs as u128 * rhs as u128) >> 64) as u64,
+        AluOp::Div if rhs == 0 => u64::MAX,
+        AluOp::Div => signed_lhs.wrapping_div(signed_rhs) as u64,
+        AluOp::Divu if rhs == 0 => u64::MAX,
+        AluOp::Divu => lhs / rhs,
+        AluOp::Rem if rhs == 0 => lhs,
+        AluOp::Rem => signed_lhs.wrapping_rem(signed_rhs) as u64,
+        AluOp::Remu if rhs == 0 => lhs,
+        AluOp::Remu => lhs % rhs,
+    }
+}
+
+fn peek_byte(input: &mut impl BufRead) -> Option<u8> {
+    input.fill_buf().ok()?.first().cloned()
+}
+
+fn next_byte(input: &mut impl BufRead) -> Option<u8> {
+    let byte = peek_byte(input)?;
+    input.consume(1);
+    Some(byte)
+}
+
+/// Read a decimal integer after any whitespace, as `scanf("%d")` does.
+fn read_int(input: &mut impl BufRead) -> Option<i32> {
+    while peek_byte(input).is_some_and(| byte | byte.is_ascii_whitespace()) {
+        input.consume(1);
+    }
+    let mut text = String::new();
+    if let Some(sign @ (b'+' | b'-')) = peek_byte(input) {
+        text.push(sign as char);
+        input.consume(1);
+    }
+    while let Some(digit) = peek_byte(input).filter(u8::is_ascii_digit) {
+        text.push(digit as char);
+        input.consume(1);
+    }
+    text.parse().ok()
+}
+
+/// State of a running program, registers and the memory holding data and the stack.
+pub struct Machine<'a> {
+    program: &'a Program,
+    pub registers: [u64; 32],
+    pub pc: u64,
+    memory: Vec<u8>,
+    /// Source line of the last executed instruction.
+    line: usize,
+    /// Number of executed instructions.
+    pub steps: u64,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new(program: &'a Program) -> Machine<'a> {
+        let mut memory = program.data.clone();
+        memory.resize(align_to(memory.len(), 16) + STACK_SIZE, 0);
+        Machine { program, registers: [0; 32], pc: TEXT_BASE, memory, line: 0, steps: 0 }
+    }
+
+    fn access(&self, addr: u64, width: usize) -> Result<usize, RvsimError> {
+        addr.checked_sub(DATA_BASE)
+            .map(| offset | offset as usize)
+            .filter(| offset | offset.checked_add(width).is_some_and(| end | end <= self.memory.len()))
+            .ok_or(RvsimError::InvalidAccess(self.line, addr))
+    }
+
+    fn load(&self, addr: u64, width: usize, signed: bool) -> Result<u64, RvsimError> {
+        let offset = self.access(addr, width)?;
+        let mut bytes = [0; 8];
+        bytes[..width].copy_from_slice(&self.memory[offset..offset + width]);
+        let value = u64::from_le_bytes(bytes);
+        let unused = 64 - 8 * width as u32;
+        Ok(if signed { (((value << unused) as i64) >> unused) as u64 } else { value })
+    }
+
+    fn store(&mut self, addr: u64, width: usize, value: u64) -> Result<(), RvsimError> {
+        let offset = self.access(addr, width)?;
+        self.memory[offset..offset + width].copy_from_slice(&value.to_le_bytes()[..width]);
+        Ok(())
+    }
+
+    /// Run a runtime function on the argument registers, returning its result.
+    fn call_runtime(&mut self, name: &str, input: &mut impl BufRead, output: &mut impl Write) -> Result<u64, RvsimError> {
+        let failed = | message: &str | RvsimError::Runtime(name.to_string(), message.to_string());
+        let written = | result: std::io::Result<()> | result.map_err(| err | failed(&err.to_string()));
+        let (arg0, arg1) = (self.registers[A0], self.registers[A1]);
+        match name {
+            "getint" => read_int(input)
+                .map(| value | value as i64 as u64)
+                .ok_or_else(|| failed("expect a 'int' input")),
+            "getch" => Ok(next_byte(input).map_or(u64::MAX, | byte | byte as u64)),
+            "getarray" => {
+                let n = read_int(input).ok_or_else(|| failed("expect a 'int' input as array size"))?;
+                if n < 0 {
+                    return Err(failed("expect a non-negative array size"));
+                }
+                for i in 0..n as u64 {
+                    let value = read_int(input).ok_or_else(|| failed("expect a 'int' input as array element"))?;
+                    self.store(arg0.wrapping_add(4 * i), 4, value as u64)?;
+                }
+                Ok(n as u64)
+            },
+            "putint" => written(write!(output, "{}", arg0 as i32)).map(| _ | 0),
+            "putch" => {
+                let c = char::from_u32(arg0 as u32).ok_or_else(|| failed("ilegal char value"))?;
+                written(write!(output, "{}", c)).map(| _ | 0)
+            },
+            "putarray" => {
+                let n = arg0 as i32;
+                if n < 0 {
+                    return Err(failed("expect a non-negative array size"));
+                }
+                written(write!(output, "{}:", n))?;
+                for i in 0..n as u64 {
+                    let value = self.load(arg1.wrapping_add(4 * i), 4, true)?;
+                    written(write!(output, " {}", value as i32))?;
+                }
+                Ok(0)
+            },
+            // timing is meaningless in the simulator.
+            _ => Ok(0)
+        }
+    }
+
+    /// Run the program from the entry function until it returns, giving the value returned in `a0`.
+    /// Runtime functions read from `input` and write to `output`.
+    pub fn run(&mut self, entry: &str, input: &mut impl BufRead, output: &mut impl Write) -> Result<i32, RvsimError> {
+        self.pc = self.program.symbols
+            .get(entry)
+            .cloned()
+            .filter(| addr | *addr >= TEXT_BASE && *addr < TEXT_BASE + 4 * self.program.instructions.len() as u64)
+            .ok_or_else(|| RvsimError::MissingEntry(entry.to_string()))?;
+        // the entry returns to the halting stub placed first.
+        self.registers[RA] = TEXT_BASE;
+        self.registers[SP] = DATA_BASE + self.memory.len() as u64;
+        loop {
+            let index = self.pc
+                .checked_sub(TEXT_BASE)
+                .filter(| offset | offset % 4 == 0)
+                .map(| offset | (offset / 4) as usize)
+                .filter(| index | *index < self.program.instructions.len())
+                .ok_or(RvsimError::InvalidJump(self.line, self.pc))?;
+            self.line = self.program.lines[index];
+            self.steps += 1;
+            let regs = self.registers;
+            let mut next = self.pc + 4;
+            match self.program.instructions[index].clone() {
+                Instruction::Op { op, word, rd, rs1, rs2 } => self.registers[rd] = alu(op, word, regs[rs1], regs[rs2]),
+                Instruction::OpImm { op, word, rd, rs1, imm } => self.registers[rd] = alu(op, word, regs[rs1], imm as u64),
+                Instruction::Lui { rd, imm } => self.registers[rd] = imm as u64,
+                Instruction::Auipc { rd, imm } => self.registers[rd] = self.pc.wrapping_add(imm as u64),
+                Instruction::Load { width, signed, rd, rs1, offset } =>
+                    self.registers[rd] = self.load(regs[rs1].wrapping_add(offset as u64), width, signed)?,
+                Instruction::Store { width, rs2, rs1, offset } =>
+                    self.store(regs[rs1].wrapping_add(offset as u64), width, regs[rs2])?,
+                Instruction::Branch { op, rs1, rs2, target } => {
+                    let (lhs, rhs) = (regs[rs1], regs[rs2]);
+                    let taken = match op {
+                        BranchOp::Eq => lhs == rhs,
+                        BranchOp::Ne => lhs != rhs,
+                        BranchOp::Lt => (lhs as i64) < (rhs as i64),
+                        BranchOp::Ge => (lhs as i64) >= (rhs as i64),
+                        BranchOp::Ltu => lhs < rhs,
+                        BranchOp::Geu => lhs >= rhs,
+                    };
+                    if taken {
+                        next = target;
+                    }
+                },
+                Instruction::Jal { rd, target } => {
+                    self.registers[rd] = next;
+                    next = target;
+                },
+                Instruction::Jalr { rd, rs1, offset } => {
+                    self.registers[rd] = next;
+                    next = regs[rs1].wrapping_add(offset as u64) & !1;
+                },
+                // linux exit and exit_group.
+                Instruction::Ecall if matches!(regs[A7], 93 | 94) => return Ok(regs[A0] as i32),
+                Instruction::Ecall => return Err(RvsimError::Trap(self.line, format!("unsupported system call {}", regs[A7] as i64))),
+                Instruction::Ebreak => return Err(RvsimError::Trap(self.line, "breakpoint".to_string())),
+                Instruction::Unimp => return Err(RvsimError::Trap(self.line, "illegal instruction".to_string())),
+                Instruction::Fence => (),
+                Instruction::Runtime(name) => {
+                    self.registers[A0] = self.call_runtime(name, input, output)?;
+                    next = regs[RA];
+                },
+                Instruction::Halt => return Ok(regs[A0] as i32),
+            }
+            self.registers[0] = 0;
+            self.pc = next;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::riscv::emit_module;
+    use crate::frontend::new_parser::parse_module;
+
+    fn run_assembly(src: &str, input: &str) -> Result<(i32, String), RvsimError> {
+        let program = assemble(src)?;
+        let mut output = Vec::new();
+        let result = Machine::new(&program).run("main", &mut input.as_bytes(), &mut output)?;
+        Ok((result, String::from_utf8(output).unwrap()))
+    }
+
+    #[test]
+    fn test_run_assembly() {
+        let src = r#"
+            .data
+            table: .word 3, -4
+            .align 3
+            ptr:   .dword table+4
+            .section .bss
+            buf:   .zero 16
+            .text
+            .globl main
+        main:
+            addi sp, sp, -16
+            sd ra, 8(sp)
+            lui t0, %hi(table)
+            lw a0, %lo(table)(t0)   # 3
+            la t1, ptr
+            ld t1, 0(t1)
+            lw t1, (t1)             # -4
+            mulw a0, a0, t1         # -12
+            li t2, 0
+            divw t3, a0, t2         # division by zero gives -1
+            add a0, a0, t3          # -13
+            call putint
+            li a0, 10
+            call putch
+            la a0, buf
+            call getarray
+            mv a1, a0
+            la a0, buf
+            lw a0, 4(a0)
+            bgt a0, a1, .Lskip
+            neg a0, a0
+        .Lskip: la a1, buf
+            li a0, 2
+            call putarray
+            ld ra, 8(sp)
+            addi sp, sp, 16
+            li a0, 7
+            ret
+        "#;
+        assert_eq!(run_assembly(src, "2 5 9").unwrap(), (7, "-13\n2: 5 9".to_string()));
+
+        assert!(matches!(assemble("main: addi a0, a0, 4096"), Err(RvsimError::Syntax(1, _))));
+        assert!(matches!(assemble("main:\n  j missing"), Err(RvsimError::UndefinedSymbol(2, _))));
+        assert!(matches!(run_assembly("main:\n  li t0, 8\n  ld a0, 0(t0)", ""), Err(RvsimError::InvalidAccess(3, 8))));
+    }
+
+    #[test]
+    fn test_run_generated() {
+        let module = parse_module(r"
+            @matrix: region i32, 12
+            fn @sum(#a: i32, #b: i32, #c: i32, #d: i32, #e: i32, #f: i32, #g: i32, #h: i32, #i: i32, #j: i32) -> i32 {
+            %entry:
+                let %0 = add #a, #j
+                let %1 = mul %0, #i
+                ret %1
+            }
+            fn @main() -> i32 {
+            %entry:
+                let %i = call @getint
+                let %addr = offset i32, @matrix, [%i < 3], [2 < 4]
+                let %0 = store 7, %addr
+                let %row = offset i32, @matrix, [%i < 3], [0 < 4]
+                let %1 = call @putarray, 4, %row
+                let %2 = call @sum, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10
+                ret %2
+            }
+        ").unwrap();
+        let (result, output) = run_assembly(&emit_module(&module), "1").unwrap();
+        assert_eq!(output, "4: 0 0 7 0");
+        assert_eq!(result, 99);
+    }
+}
@@ -0,0 +1,385 @@
+//! C99 generation, giving a differential testing oracle for the interpreter.
+//!
+//! Every instruction result becomes a local variable, `alloca` a local array and `region` globals
+//! zero initialized static arrays, `offset` is row-major index arithmetic over the bounds,
+//! and blocks are labels jumped to by `goto`. Phis are lowered by assigning their incoming value
+//! to a staging variable on each edge, copied into the phi variable when entering the block.
+//! Addition, subtraction and multiplication wrap through unsigned arithmetic instead of overflowing.
+//!
+//! Functions without body, and runtime functions called without declaration, become extern declarations.
+//! `RUNTIME_SOURCE` implements the runtime functions with the output format of the interpreter,
+//! so that a program built with the host C compiler prints the same as `run_on_module`.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::apps::executor::is_runtime_function;
+use crate::ir::structures::*;
+use crate::ir::types::{Type, TypeKind};
+use crate::ir::values::BinaryOp;
+use crate::utils::unique_name::UniqueName;
+
+/// C source of the SysY runtime functions, printing like the interpreter.
+pub const RUNTIME_SOURCE: &str = include_str!("sysy_runtime.c");
+
+fn c_type(ty: &Type) -> String {
+    match &**ty {
+        TypeKind::Int32 | TypeKind::Int1 => "int32_t".to_string(),
+        TypeKind::Unit => "void".to_string(),
+        TypeKind::Pointer(base) => format!("{} *", c_type(base)),
+        TypeKind::OpaquePtr | TypeKind::Function(..) => "void *".to_string(),
+    }
+}
+
+/// Declaration of `name` with the C type `ty`, keeping the `*` of pointer types next to the name.
+fn declare(ty: &str, name: &str) -> String {
+    if ty.ends_with('*') {
+        format!("{}{}", ty, name)
+    } else {
+        format!("{} {}", ty, name)
+    }
+}
+
+/// IR names may contain `.` and `-`, which are replaced in C identifiers.
+fn sanitize(name: &str) -> String {
+    name.replace(| c: char | !c.is_ascii_alphanumeric() && c != '_', "_")
+}
+
+/// C identifier based on an IR name, different from all identifiers returned by `used`.
+fn fresh_identifier(used: &mut UniqueName, prefix: &str, name: &str) -> String {
+    used.next_unused_name(&format!("{}{}", prefix, sanitize(name)))
+}
+
+/// Prototype of a function, naming its parameters if `param_names` is given.
+fn prototype(name: &str, params: &[Type], ret: &Type, param_names: Option<&[String]>) -> String {
+    let params = match params {
+        [] => "void".to_string(),
+        _ => params
+            .iter()
+            .enumerate()
+            .map(| (index, ty) | match param_names {
+                Some(names) => declare(&c_type(ty), &names[index]),
+                None => c_type(ty),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let ret = if name == "main" { "int".to_string() } else { c_type(ret) };
+    declare(&ret, &format!("{}({})", sanitize(name), params))
+}
+
+struct FunctionEmitter<'a> {
+    module: &'a Module,
+    function: &'a Function,
+    globals: &'a HashMap<ValueRef, String>,
+    /// Variables of arguments and instruction results.
+    names: HashMap<ValueRef, String>,
+    /// Staging variables of phis.
+    staging: HashMap<ValueRef, String>,
+    labels: HashMap<BlockRef, String>,
+    out: &'a mut String,
+}
+
+impl<'a> FunctionEmitter<'a> {
+    fn new(module: &'a Module, function: &'a Function, globals: &'a HashMap<ValueRef, String>, out: &'a mut String) -> Self {
+        let mut used = UniqueName::with_separator("_");
+        let mut names = HashMap::new();
+        let mut staging = HashMap::new();
+        let values = function.args
+            .iter()
+            .chain(function.blocks.iter().flat_map(| bb | function.get_basic_block(*bb).instrs.iter()))
+            .cloned();
+        for value in values {
+            let data = module.get_value(value);
+            let name = fresh_identifier(&mut used, "v_", data.name.as_deref().unwrap_or("t"));
+            if data.is_phi() {
+                staging.insert(value, fresh_identifier(&mut used, &name, "_in"));
+            }
+            names.insert(value, name);
+        }
+        let mut used_labels = UniqueName::with_separator("_");
+        let labels = function.blocks
+            .iter()
+            .enumerate()
+            .map(| (index, bb) | {
+                let name = function.get_basic_block(*bb).name.clone().unwrap_or_else(|| index.to_string());
+                (*bb, fresh_identifier(&mut used_labels, "bb_", &name))
+            })
+            .collect();
+        FunctionEmitter { module, function, globals, names, staging, labels, out }
+    }
+
+    fn emit(&mut self, line: &str) {
+        self.out.push_str("    ");
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    /// C expression of an operand.
+    fn operand(&self, value: ValueRef) -> String {
+        let data = self.module.get_value(value);
+        match &data.kind {
+            // the literal `-2147483648` is the negation of a `long` constant.
+            ValueKind::ConstantInt(inner) if inner.value == i32::MIN => "(-2147483647 - 1)".to_string(),
+            ValueKind::ConstantInt(inner) => inner.value.to_string(),
+            ValueKind::ConstantBool(inner) => (inner.value as i32).to_string(),
+            ValueKind::ConstantNullPtr(_) | ValueKind::ConstantUnit(_) => "0".to_string(),
+            ValueKind::GlobalVar(_) => self.globals[&value].clone(),
+            _ => self.names[&value].clone(),
+        }
+    }
+
+    /// Pointer operand of type pointer to `elem_ty`, casting other pointers.
+    fn typed_pointer(&self, value: ValueRef, elem_ty: &Type) -> String {
+        match self.module.get_value_type(value).get_pointer_base_type() {
+            Some(base) if base == *elem_ty => self.operand(value),
+            _ => format!("({}){}", declare(&c_type(elem_ty), "*"), self.operand(value)),
+        }
+    }
+
+    fn binary(&self, op: &BinaryOp, lhs: &str, rhs: &str) -> String {
+        let c_op = match op {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::And => "&",
+            BinaryOp::Or => "|",
+            BinaryOp::Xor => "^",
+            BinaryOp::Lt => "<",
+            BinaryOp::Gt => ">",
+            BinaryOp::Le => "<=",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+        };
+        match op {
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul =>
+                format!("(int32_t)((uint32_t){} {} (uint32_t){})", lhs, c_op, rhs),
+            _ => format!("{} {} {}", lhs, c_op, rhs),
+        }
+    }
+
+    fn emit_instruction(&mut self, instr: ValueRef) {
+        let module = self.module;
+        let data = module.get_value(instr);
+        let statement = match &data.kind {
+            ValueKind::Binary(inner) => self.binary(&inner.op, &self.operand(inner.lhs), &self.operand(inner.rhs)),
+            ValueKind::Offset(inner) => {
+                // `(i * bound + j) * bound + k` for indices `i`, `j` and `k`.
+                let index = inner.index
+                    .iter()
+                    .zip(inner.bounds.iter())
+                    .skip(1)
+                    .fold(self.operand(inner.index[0]), | acc, (index, bound) | format!(
+                        "({} * {} + {})",
+                        acc, bound.expect("only the first dimension of an offset may be unbounded"), self.operand(*index)));
+                format!("{} + {}", self.typed_pointer(inner.base_addr, &inner.elem_type), index)
+            },
+            ValueKind::FnCall(inner) => format!(
+                "{}({})",
+                sanitize(&inner.callee),
+                inner.args.iter().map(| arg | self.operand(*arg)).collect::<Vec<_>>().join(", ")),
+            ValueKind::Load(inner) => format!("*{}", self.typed_pointer(inner.addr, &data.ty)),
+            ValueKind::Store(inner) => {
+                let value_ty = module.get_value_type(inner.value);
+                let line = format!("*{} = {};", self.typed_pointer(inner.addr, &value_ty), self.operand(inner.value));
+                self.emit(&line);
+                return;
+            },
+            // allocas are declared as arrays, phis are assigned when entering the block.
+            ValueKind::Alloca(_) | ValueKind::Phi(_) => return,
+            _ => unreachable!("not an instruction")
+        };
+        if data.ty.is_unit_type() {
+            self.emit(&format!("{};", statement));
+        } else {
+            let line = format!("{} = {};", self.names[&instr], statement);
+            self.emit(&line);
+        }
+    }
+
+    /// Statements assigning the phis of `succ` on the edge from `bb`, then jumping to `succ`.
+    fn edge(&self, bb: BlockRef, succ: BlockRef) -> String {
+        let mut statements = self.function
+            .get_basic_block(succ)
+            .instrs
+            .iter()
+            .map_while(| instr | match &self.module.get_value(*instr).kind {
+                ValueKind::Phi(inner) => Some((*instr, inner.incoming_value(bb))),
+                _ => None
+            })
+            .filter_map(| (phi, incoming) | incoming.map(| value | format!("{} = {}; ", self.staging[&phi], self.operand(value))))
+            .collect::<String>();
+        statements.push_str(&format!("goto {};", self.labels[&succ]));
+        statements
+    }
+
+    fn emit_terminator(&mut self, bb: BlockRef) {
+        let line = match &self.function.get_basic_block(bb).terminator {
+            Terminator::Jump(inner) => self.edge(bb, inner.dest),
+            Terminator::Branch(inner) => format!(
+                "if ({}) {{ {} }} else {{ {} }}",
+                self.operand(inner.cond), self.edge(bb, inner.true_label), self.edge(bb, inner.false_label)),
+            Terminator::Return(inner) => {
+                let ty = self.module.get_value_type(inner.value);
+                match (ty.is_unit_type(), self.function.name == "main") {
+                    (true, true) => "return 0;".to_string(),
+                    (true, false) => "return;".to_string(),
+                    (false, _) => format!("return {};", self.operand(inner.value)),
+                }
+            },
+            Terminator::Panic => "abort();".to_string(),
+        };
+        self.emit(&line);
+    }
+
+    fn emit_function(&mut self) {
+        let module = self.module;
+        let function = self.function;
+        let params = function.ty.get_function_params_type().unwrap_or_default();
+        let ret = function.ty.get_function_ret_type().unwrap_or(Type::get_unit());
+        let param_names = function.args.iter().map(| arg | self.names[arg].clone()).collect::<Vec<_>>();
+        self.out.push_str(&format!("{} {{\n", prototype(&function.name, &params, &ret, Some(&param_names))));
+
+        for instr in function.blocks.iter().flat_map(| bb | function.get_basic_block(*bb).instrs.iter()).cloned() {
+            let data = module.get_value(instr);
+            let name = self.names[&instr].clone();
+            match &data.kind {
+                ValueKind::Alloca(inner) =>
+                    self.emit(&format!("{}[{}];", declare(&c_type(&inner.elem_type), &name), inner.num_elements.max(1))),
+                _ if data.ty.is_unit_type() => (),
+                ValueKind::Phi(_) =>
+                    self.emit(&format!("{}, {};", declare(&c_type(&data.ty), &name), self.staging[&instr])),
+                _ => self.emit(&format!("{};", declare(&c_type(&data.ty), &name))),
+            }
+        }
+
+        let targets = function.blocks
+            .iter()
+            .flat_map(| bb | function.get_basic_block(*bb).terminator.successors())
+            .collect::<HashSet<_>>();
+        for bb in function.blocks.iter().cloned() {
+            if targets.contains(&bb) {
+                self.out.push_str(&format!("{}:;\n", self.labels[&bb]));
+            }
+            let block = function.get_basic_block(bb);
+            for instr in block.instrs.iter().cloned() {
+                if self.module.get_value(instr).is_phi() {
+                    let line = format!("{} = {};", self.names[&instr], self.staging[&instr]);
+                    self.emit(&line);
+                }
+            }
+            for instr in block.instrs.iter().cloned() {
+                self.emit_instruction(instr);
+            }
+            self.emit_terminator(bb);
+        }
+        self.out.push_str("}\n\n");
+    }
+}
+
+/// Lower a verified module to a C99 translation unit.
+///
+/// The program is linked with `RUNTIME_SOURCE` or another implementation of the runtime functions it calls.
+/// A `main` function returning unit returns zero in C.
+pub fn emit_module(module: &Module) -> String {
+    let mut out = String::from("#include <stdint.h>\n\nextern void abort(void);\n");
+
+    // external functions, and runtime functions with the signature of their first call.
+    let mut declared = HashSet::new();
+    for function in module.funcs.iter().cloned().map(| function | module.get_function(function)) {
+        declared.insert(function.name.clone());
+        let params = function.ty.get_function_params_type().unwrap_or_default();
+        let ret = function.ty.get_function_ret_type().unwrap_or(Type::get_unit());
+        let extern_prefix = if function.is_external { "extern " } else { "" };
+        out.push_str(&format!("{}{};\n", extern_prefix, prototype(&function.name, &params, &ret, None)));
+    }
+    let calls = module.funcs
+        .iter()
+        .map(| function | module.get_function(*function))
+        .flat_map(| function | function.blocks.iter().flat_map(| bb | function.get_basic_block(*bb).instrs.iter()))
+        .filter_map(| instr | match &module.get_value(*instr).kind {
+            ValueKind::FnCall(inner) => Some((inner, module.get_value_type(*instr))),
+            _ => None
+        });
+    for (call, ret) in calls {
+        if is_runtime_function(&call.callee) && declared.insert(call.callee.clone()) {
+            let params = call.args.iter().map(| arg | module.get_value_type(*arg)).collect::<Vec<_>>();
+            out.push_str(&format!("extern {};\n", prototype(&call.callee, &params, &ret, None)));
+        }
+    }
+    out.push('\n');
+
+    let mut used = UniqueName::with_separator("_");
+    let mut globals = HashMap::new();
+    for global in module.globals.iter().cloned() {
+        let data = module.get_value(global);
+        if let ValueKind::GlobalVar(inner) = &data.kind {
+            let name = fresh_identifier(&mut used, "g_", data.name.as_deref().unwrap_or("global"));
+            out.push_str(&format!("static {}[{}];\n", declare(&c_type(&inner.elem_ty), &name), inner.size.max(1)));
+            globals.insert(global, name);
+        }
+    }
+    if !globals.is_empty() {
+        out.push('\n');
+    }
+
+    for function in module.funcs.iter().cloned() {
+        let function = module.get_function(function);
+        if !function.is_external {
+            FunctionEmitter::new(module, function, &globals, &mut out).emit_function();
+        }
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontend::new_parser::parse_module;
+
+    #[test]
+    fn test_emit_module() {
+        let module = parse_module(r"
+            @matrix: region i32, 12
+            fn @sum(#n: i32) -> i32 {
+            %entry:
+                jmp label %loop
+            %loop:
+                let %i = phi [0, label %entry], [%i.next, label %loop]
+                let %acc = phi [0, label %entry], [%acc.next, label %loop]
+                let %addr = offset i32, @matrix, [%i < 3], [2 < 4]
+                let %x = load %addr
+                let %acc.next = add %acc, %x
+                let %i.next = add %i, 1
+                let %c = lt %i.next, #n
+                br %c, label %loop, label %exit
+            %exit:
+                ret %acc
+            }
+            fn @main() -> () {
+            %entry:
+                let %a = alloca i32, 4
+                let %n = call @getarray, %a
+                let %0 = call @sum, %n
+                let %1 = call @putint, %0
+                ret ()
+            }
+        ").unwrap();
+        let c = emit_module(&module);
+        let lines = c.lines().map(str::trim).collect::<Vec<_>>();
+        let contains = | fragment: &[&str] | lines.windows(fragment.len()).any(| window | window == fragment);
+
+        assert!(contains(&["int32_t sum(int32_t);", "int main(void);", "extern int32_t getarray(int32_t *);", "extern void putint(int32_t);"]));
+        assert!(contains(&["static int32_t g_matrix[12];"]));
+        assert!(contains(&["int32_t sum(int32_t v_n) {", "int32_t v_i, v_i_in;", "int32_t v_acc, v_acc_in;", "int32_t *v_addr;"]));
+        // the entry block is never jumped to, and phis take their staged incoming values.
+        assert!(contains(&["v_i_in = 0; v_acc_in = 0; goto bb_loop;", "bb_loop:;", "v_i = v_i_in;", "v_acc = v_acc_in;", "v_addr = g_matrix + (v_i * 4 + 2);"]));
+        assert!(contains(&["v_acc_next = (int32_t)((uint32_t)v_acc + (uint32_t)v_x);"]));
+        assert!(contains(&["if (v_c) { v_i_in = v_i_next; v_acc_in = v_acc_next; goto bb_loop; } else { goto bb_exit; }"]));
+        assert!(contains(&["int main(void) {", "int32_t v_a[4];", "int32_t v_n;", "int32_t v_0;"]));
+        assert!(contains(&["v_n = getarray(v_a);", "v_0 = sum(v_n);", "putint(v_0);", "return 0;"]));
+    }
+}
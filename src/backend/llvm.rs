@@ -0,0 +1,365 @@
+//! LLVM textual IR generation, with opaque pointers.
+//!
+//! Instructions map one to one, except comparisons, producing `i32` in Accipit, which are an `icmp`
+//! extended to `i32`, and branches on `i32` conditions, compared to zero first.
+//! `offset` is a `getelementptr` over the nested array type of its bounds,
+//! whose first index steps over whole rows like the first dimension of the offset.
+//! Names are kept, quoted when starting with a digit, and renamed apart when a block and a value share one.
+
+use std::collections::HashSet;
+use std::collections::HashMap;
+
+use crate::apps::executor::is_runtime_function;
+use crate::ir::structures::*;
+use crate::ir::types::{Type, TypeKind};
+use crate::ir::values::BinaryOp;
+use crate::utils::unique_name::UniqueName;
+
+fn llvm_type(ty: &Type) -> &'static str {
+    match &**ty {
+        TypeKind::Int32 => "i32",
+        TypeKind::Int1 => "i1",
+        TypeKind::Unit => "void",
+        TypeKind::Pointer(_) | TypeKind::OpaquePtr | TypeKind::Function(..) => "ptr",
+    }
+}
+
+/// Name as written in LLVM, quoted unless it is a valid unquoted identifier.
+fn quoted(name: &str) -> String {
+    let valid = name.chars().all(| c | c.is_ascii_alphanumeric() || matches!(c, '-' | '$' | '.' | '_'))
+        && !name.starts_with(| c: char | c.is_ascii_digit());
+    if valid {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name)
+    }
+}
+
+fn identifier(sigil: char, name: &str) -> String {
+    format!("{}{}", sigil, quoted(name))
+}
+
+fn declaration(name: &str, params: &[Type], ret: &Type) -> String {
+    let params = params.iter().map(llvm_type).collect::<Vec<_>>().join(", ");
+    format!("declare {} {}({})\n", llvm_type(ret), identifier('@', name), params)
+}
+
+struct FunctionEmitter<'a> {
+    module: &'a Module,
+    function: &'a Function,
+    /// Local names of arguments, instruction results and blocks, which share a namespace in LLVM.
+    names: HashMap<ValueRef, String>,
+    labels: HashMap<BlockRef, String>,
+    used: UniqueName,
+    out: &'a mut String,
+}
+
+impl<'a> FunctionEmitter<'a> {
+    fn new(module: &'a Module, function: &'a Function, out: &'a mut String) -> Self {
+        let mut used = UniqueName::new();
+        let values = function.args
+            .iter()
+            .chain(function.blocks.iter().flat_map(| bb | function.get_basic_block(*bb).instrs.iter()))
+            .cloned()
+            .filter(| value | !module.get_value_type(*value).is_unit_type())
+            .collect::<Vec<_>>();
+        let names = values
+            .into_iter()
+            .map(| value | (value, used.next_unused_name(module.get_value(value).name.as_deref().unwrap_or("t"))))
+            .collect();
+        let labels = function.blocks
+            .iter()
+            .enumerate()
+            .map(| (index, bb) | {
+                let name = function.get_basic_block(*bb).name.clone().unwrap_or_else(|| format!("bb{}", index));
+                (*bb, used.next_unused_name(&name))
+            })
+            .collect();
+        FunctionEmitter { module, function, names, labels, used, out }
+    }
+
+    fn emit(&mut self, line: &str) {
+        self.out.push_str("  ");
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    fn temporary(&mut self, base: &str) -> String {
+        identifier('%', &self.used.next_unused_name(base))
+    }
+
+    fn operand(&self, value: ValueRef) -> String {
+        let data = self.module.get_value(value);
+        match &data.kind {
+            ValueKind::ConstantInt(inner) => inner.value.to_string(),
+            ValueKind::ConstantBool(inner) => inner.value.to_string(),
+            ValueKind::ConstantNullPtr(_) => "null".to_string(),
+            ValueKind::ConstantUnit(_) => "void".to_string(),
+            ValueKind::GlobalVar(_) => identifier('@', data.name.as_deref().expect("global variables are named")),
+            _ => identifier('%', &self.names[&value]),
+        }
+    }
+
+    fn typed_operand(&self, value: ValueRef) -> String {
+        format!("{} {}", llvm_type(&self.module.get_value_type(value)), self.operand(value))
+    }
+
+    fn label(&self, bb: BlockRef) -> String {
+        identifier('%', &self.labels[&bb])
+    }
+
+    fn emit_instruction(&mut self, instr: ValueRef) {
+        let module = self.module;
+        let data = module.get_value(instr);
+        let ty = llvm_type(&data.ty);
+        let rhs = match &data.kind {
+            ValueKind::Binary(inner) => {
+                let op = match inner.op {
+                    BinaryOp::Add => "add",
+                    BinaryOp::Sub => "sub",
+                    BinaryOp::Mul => "mul",
+                    BinaryOp::Div => "sdiv",
+                    BinaryOp::Rem => "srem",
+                    BinaryOp::And => "and",
+                    BinaryOp::Or => "or",
+                    BinaryOp::Xor => "xor",
+                    BinaryOp::Lt => "icmp slt",
+                    BinaryOp::Gt => "icmp sgt",
+                    BinaryOp::Le => "icmp sle",
+                    BinaryOp::Ge => "icmp sge",
+                    BinaryOp::Eq => "icmp eq",
+                    BinaryOp::Ne => "icmp ne",
+                };
+                let operands = format!("{}, {}", self.typed_operand(inner.lhs), self.operand(inner.rhs));
+                if op.starts_with("icmp") {
+                    let cmp = self.temporary(&format!("{}.cmp", self.names[&instr]));
+                    self.emit(&format!("{} = {} {}", cmp, op, operands));
+                    format!("zext i1 {} to {}", cmp, ty)
+                } else {
+                    format!("{} {}", op, operands)
+                }
+            },
+            ValueKind::Offset(inner) => {
+                let elem_ty = llvm_type(&inner.elem_type).to_string();
+                let source_ty = inner.bounds
+                    .iter()
+                    .skip(1)
+                    .rev()
+                    .fold(elem_ty, | ty, bound | format!("[{} x {}]", bound.expect("only the first dimension of an offset may be unbounded"), ty));
+                let mut indices = Vec::new();
+                for index in inner.index.iter().cloned() {
+                    if module.get_value_type(index).is_i1_type() {
+                        let extended = self.temporary(&format!("{}.idx", self.names[&instr]));
+                        self.emit(&format!("{} = zext i1 {} to i32", extended, self.operand(index)));
+                        indices.push(format!("i32 {}", extended));
+                    } else {
+                        indices.push(self.typed_operand(index));
+                    }
+                }
+                format!("getelementptr {}, {}, {}", source_ty, self.typed_operand(inner.base_addr), indices.join(", "))
+            },
+            ValueKind::FnCall(inner) => format!(
+                "call {} {}({})",
+                ty,
+                identifier('@', &inner.callee),
+                inner.args.iter().map(| arg | self.typed_operand(*arg)).collect::<Vec<_>>().join(", ")),
+            ValueKind::Alloca(inner) => match inner.num_elements {
+                1 => format!("alloca {}", llvm_type(&inner.elem_type)),
+                n => format!("alloca [{} x {}]", n, llvm_type(&inner.elem_type)),
+            },
+            ValueKind::Load(inner) => format!("load {}, {}", ty, self.typed_operand(inner.addr)),
+            ValueKind::Store(inner) => format!("store {}, {}", self.typed_operand(inner.value), self.typed_operand(inner.addr)),
+            ValueKind::Phi(inner) => format!(
+                "phi {} {}",
+                ty,
+                inner.incoming.iter().map(| (value, bb) | format!("[ {}, {} ]", self.operand(*value), self.label(*bb))).collect::<Vec<_>>().join(", ")),
+            _ => unreachable!("not an instruction")
+        };
+        if data.ty.is_unit_type() {
+            self.emit(&rhs);
+        } else {
+            let line = format!("{} = {}", self.operand(instr), rhs);
+            self.emit(&line);
+        }
+    }
+
+    fn emit_terminator(&mut self, bb: BlockRef) {
+        let line = match &self.function.get_basic_block(bb).terminator {
+            Terminator::Jump(inner) => format!("br label {}", self.label(inner.dest)),
+            Terminator::Branch(inner) => {
+                let cond = if self.module.get_value_type(inner.cond).is_i1_type() {
+                    self.operand(inner.cond)
+                } else {
+                    let base = match self.names.get(&inner.cond) {
+                        Some(name) => format!("{}.cond", name),
+                        None => "cond".to_string(),
+                    };
+                    let cond = self.temporary(&base);
+                    self.emit(&format!("{} = icmp ne {}, 0", cond, self.typed_operand(inner.cond)));
+                    cond
+                };
+                format!("br i1 {}, label {}, label {}", cond, self.label(inner.true_label), self.label(inner.false_label))
+            },
+            Terminator::Return(inner) if self.module.get_value_type(inner.value).is_unit_type() => "ret void".to_string(),
+            Terminator::Return(inner) => format!("ret {}", self.typed_operand(inner.value)),
+            Terminator::Panic => "unreachable".to_string(),
+        };
+        self.emit(&line);
+    }
+
+    fn emit_function(&mut self) {
+        let function = self.function;
+        let ret = function.ty.get_function_ret_type().unwrap_or(Type::get_unit());
+        let params = function.args.iter().map(| arg | self.typed_operand(*arg)).collect::<Vec<_>>().join(", ");
+        self.out.push_str(&format!("define {} {}({}) {{\n", llvm_type(&ret), identifier('@', &function.name), params));
+        // the entry block of LLVM functions cannot be jumped to.
+        let entry = function.blocks[0];
+        let entry_is_target = function.blocks
+            .iter()
+            .any(| bb | function.get_basic_block(*bb).terminator.successors().any(| succ | succ == entry));
+        if entry_is_target {
+            let start = self.used.next_unused_name("start");
+            self.out.push_str(&format!("{}:\n", quoted(&start)));
+            let line = format!("br label {}", self.label(entry));
+            self.emit(&line);
+        }
+        for bb in function.blocks.iter().cloned() {
+            self.out.push_str(&format!("{}:\n", quoted(&self.labels[&bb])));
+            for instr in function.get_basic_block(bb).instrs.iter().cloned() {
+                self.emit_instruction(instr);
+            }
+            self.emit_terminator(bb);
+        }
+        self.out.push_str("}\n");
+    }
+}
+
+/// Lower a verified module to LLVM textual IR.
+///
+/// `region` globals are zero initialized arrays, functions without body and runtime functions called
+/// without declaration are declared, the latter with the signature of their first call.
+pub fn emit_module(module: &Module) -> String {
+    let mut out = String::new();
+    for global in module.globals.iter().cloned() {
+        let data = module.get_value(global);
+        if let ValueKind::GlobalVar(inner) = &data.kind {
+            out.push_str(&format!(
+                "{} = global [{} x {}] zeroinitializer\n",
+                identifier('@', data.name.as_deref().expect("global variables are named")), inner.size, llvm_type(&inner.elem_ty)));
+        }
+    }
+    if !module.globals.is_empty() {
+        out.push('\n');
+    }
+
+    let mut declared = HashSet::new();
+    let mut declarations = String::new();
+    for function in module.funcs.iter().cloned().map(| function | module.get_function(function)) {
+        declared.insert(function.name.clone());
+        if function.is_external {
+            let params = function.ty.get_function_params_type().unwrap_or_default();
+            let ret = function.ty.get_function_ret_type().unwrap_or(Type::get_unit());
+            declarations.push_str(&declaration(&function.name, &params, &ret));
+        }
+    }
+    let calls = module.funcs
+        .iter()
+        .map(| function | module.get_function(*function))
+        .flat_map(| function | function.blocks.iter().flat_map(| bb | function.get_basic_block(*bb).instrs.iter()))
+        .filter_map(| instr | match &module.get_value(*instr).kind {
+            ValueKind::FnCall(inner) => Some((inner, module.get_value_type(*instr))),
+            _ => None
+        });
+    for (call, ret) in calls {
+        if is_runtime_function(&call.callee) && declared.insert(call.callee.clone()) {
+            let params = call.args.iter().map(| arg | module.get_value_type(*arg)).collect::<Vec<_>>();
+            declarations.push_str(&declaration(&call.callee, &params, &ret));
+        }
+    }
+    if !declarations.is_empty() {
+        out.push_str(&declarations);
+        out.push('\n');
+    }
+
+    let definitions = module.funcs
+        .iter()
+        .map(| function | module.get_function(*function))
+        .filter(| function | !function.is_external)
+        .map(| function | {
+            let mut definition = String::new();
+            FunctionEmitter::new(module, function, &mut definition).emit_function();
+            definition
+        })
+        .collect::<Vec<_>>();
+    out.push_str(&definitions.join("\n"));
+    out
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontend::new_parser::parse_module;
+
+    #[test]
+    fn test_emit_module() {
+        let module = parse_module(r"
+            @matrix: region i32, 12
+            fn @sum(#n: i32) -> i32 {
+            %entry:
+                jmp label %loop
+            %loop:
+                let %i = phi [0, label %entry], [%i.next, label %loop]
+                let %acc = phi [0, label %entry], [%acc.next, label %loop]
+                let %addr = offset i32, @matrix, [%i < 3], [2 < 4]
+                let %x = load %addr
+                let %acc.next = add %acc, %x
+                let %i.next = add %i, 1
+                let %c = lt %i.next, #n
+                br %c, label %loop, label %exit
+            %exit:
+                ret %acc
+            }
+            fn @main() -> () {
+            %entry:
+                let %a = alloca i32, 4
+                let %0 = call @getarray, %a
+                let %1 = call @sum, %0
+                let %2 = call @putint, %1
+                ret ()
+            }
+        ").unwrap();
+        assert_eq!(emit_module(&module), r#"@matrix = global [12 x i32] zeroinitializer
+
+declare i32 @getarray(ptr)
+declare void @putint(i32)
+
+define i32 @sum(i32 %n) {
+entry:
+  br label %loop
+loop:
+  %i = phi i32 [ 0, %entry ], [ %i.next, %loop ]
+  %acc = phi i32 [ 0, %entry ], [ %acc.next, %loop ]
+  %addr = getelementptr [4 x i32], ptr @matrix, i32 %i, i32 2
+  %x = load i32, ptr %addr
+  %acc.next = add i32 %acc, %x
+  %i.next = add i32 %i, 1
+  %c.cmp = icmp slt i32 %i.next, %n
+  %c = zext i1 %c.cmp to i32
+  %c.cond = icmp ne i32 %c, 0
+  br i1 %c.cond, label %loop, label %exit
+exit:
+  ret i32 %acc
+}
+
+define void @main() {
+entry:
+  %a = alloca [4 x i32]
+  %"0" = call i32 @getarray(ptr %a)
+  %"1" = call i32 @sum(i32 %"0")
+  call void @putint(i32 %"1")
+  ret void
+}
+"#);
+    }
+}
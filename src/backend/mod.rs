@@ -0,0 +1,8 @@
+//! Code generation from the IR to target assembly and other languages.
+
+pub mod c;
+pub mod llvm;
+pub mod riscv;
+pub mod regalloc;
+pub mod wasm;
+pub mod x86_64;
@@ -0,0 +1,306 @@
+//! Linear scan register allocation (Poletto and Sarkar, TOPLAS 1999) over IR functions.
+//!
+//! Blocks are laid out in the order of `Function::blocks`, numbering instructions and terminators.
+//! Instruction `n` reads its operands at position `2n` and writes its result at `2n + 1`,
+//! so that a value last read by an instruction may share a register with the instruction result.
+//! The live interval of a value is the smallest range of positions covering its definition,
+//! its uses and every block where it is live in or live out, computed by backward dataflow.
+//! A phi reads its incoming value at the end of the predecessor, and is defined at its own position.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ir::structures::*;
+
+/// Where a value is kept, one of `k` registers or a stack slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Location {
+    Register(usize),
+    Spill(usize),
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Register(index) => write!(f, "r{}", index),
+            Location::Spill(index) => write!(f, "spill{}", index),
+        }
+    }
+}
+
+/// Positions of the linearized function where a value is live, both inclusive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiveInterval {
+    pub value: ValueRef,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocationStrategy {
+    /// Linear scan, spilling the interval ending last when registers run out.
+    LinearScan,
+    /// Baseline keeping every value in its own stack slot.
+    SpillEverything,
+}
+
+/// Assignment of the instruction-defined values of a function to locations.
+///
+/// Values of the unit type are never read and get no location.
+#[derive(Debug, Clone)]
+pub struct Allocation {
+    pub num_registers: usize,
+    pub num_spill_slots: usize,
+    pub intervals: Vec<LiveInterval>,
+    locations: HashMap<ValueRef, Location>,
+}
+
+impl Allocation {
+    pub fn location(&self, value: ValueRef) -> Option<Location> {
+        self.locations.get(&value).cloned()
+    }
+
+    pub fn locations(&self) -> &HashMap<ValueRef, Location> {
+        &self.locations
+    }
+}
+
+/// Index of each instruction and terminator, in layout order.
+struct Linearization {
+    indices: HashMap<ValueRef, usize>,
+    /// First index and terminator index of each block.
+    ranges: HashMap<BlockRef, (usize, usize)>,
+}
+
+impl Linearization {
+    fn new(function: &Function) -> Linearization {
+        let mut indices = HashMap::new();
+        let mut ranges = HashMap::new();
+        let mut index = 0;
+        for bb in function.blocks.iter().cloned() {
+            let start = index;
+            for instr in function.get_basic_block(bb).instrs.iter().cloned() {
+                indices.insert(instr, index);
+                index += 1;
+            }
+            ranges.insert(bb, (start, index));
+            index += 1;
+        }
+        Linearization { indices, ranges }
+    }
+}
+
+/// Values flowing from `bb` into the phis of its successors.
+fn phi_inputs(module: &Module, function: &Function, bb: BlockRef) -> Vec<ValueRef> {
+    function.get_basic_block(bb).terminator
+        .successors()
+        .flat_map(| succ | function.get_basic_block(succ).instrs.iter().cloned())
+        .map_while(| instr | match &module.get_value(instr).kind {
+            ValueKind::Phi(inner) => Some(inner.incoming.clone()),
+            _ => None
+        })
+        .flatten()
+        .filter(| (_, pred) | *pred == bb)
+        .map(| (value, _) | value)
+        .collect()
+}
+
+/// Live intervals of the values with a location, sorted by start position.
+pub fn live_intervals(module: &Module, function: &Function) -> Vec<LiveInterval> {
+    let linear = Linearization::new(function);
+    let is_allocated = | value: &ValueRef | linear.indices.contains_key(value)
+        && !module.get_value_type(*value).is_unit_type();
+
+    // uses before any definition in the block, and definitions of each block.
+    let mut uses: HashMap<BlockRef, HashSet<ValueRef>> = HashMap::new();
+    let mut defs: HashMap<BlockRef, HashSet<ValueRef>> = HashMap::new();
+    for bb in function.blocks.iter().cloned() {
+        let block = function.get_basic_block(bb);
+        let (block_uses, block_defs) = (uses.entry(bb).or_default(), defs.entry(bb).or_default());
+        for instr in block.instrs.iter().cloned() {
+            let value = module.get_value(instr);
+            if !value.is_phi() {
+                block_uses.extend(value.kind.operands().filter(| operand | is_allocated(operand) && !block_defs.contains(operand)));
+            }
+            block_defs.insert(instr);
+        }
+        let live_at_end = block.terminator.operands().chain(phi_inputs(module, function, bb));
+        block_uses.extend(live_at_end.filter(| operand | is_allocated(operand) && !block_defs.contains(operand)));
+    }
+
+    let mut live_in: HashMap<BlockRef, HashSet<ValueRef>> = HashMap::new();
+    let mut live_out: HashMap<BlockRef, HashSet<ValueRef>> = HashMap::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for bb in function.blocks.iter().rev().cloned() {
+            let mut out: HashSet<ValueRef> = phi_inputs(module, function, bb).into_iter().filter(is_allocated).collect();
+            for succ in function.get_basic_block(bb).terminator.successors() {
+                out.extend(live_in.get(&succ).into_iter().flatten().cloned());
+            }
+            let mut new_in = uses[&bb].clone();
+            new_in.extend(out.iter().filter(| value | !defs[&bb].contains(value)).cloned());
+            live_out.insert(bb, out);
+            if live_in.get(&bb) != Some(&new_in) {
+                live_in.insert(bb, new_in);
+                changed = true;
+            }
+        }
+    }
+
+    let mut bounds: HashMap<ValueRef, (usize, usize)> = HashMap::new();
+    let mut cover = | value: ValueRef, position: usize | {
+        let (start, end) = bounds.entry(value).or_insert((position, position));
+        *start = (*start).min(position);
+        *end = (*end).max(position);
+    };
+    for bb in function.blocks.iter().cloned() {
+        let block = function.get_basic_block(bb);
+        let (first, terminator) = linear.ranges[&bb];
+        for instr in block.instrs.iter().cloned() {
+            let index = linear.indices[&instr];
+            if is_allocated(&instr) {
+                cover(instr, 2 * index + 1);
+            }
+            if !module.get_value(instr).is_phi() {
+                module.get_value(instr).kind.operands().filter(is_allocated).for_each(| operand | cover(operand, 2 * index));
+            }
+        }
+        // live out values survive the terminator, until phis of the successor are written.
+        live_in[&bb].iter().for_each(| value | cover(*value, 2 * first));
+        live_out[&bb].iter().for_each(| value | cover(*value, 2 * terminator + 1));
+        block.terminator.operands().filter(is_allocated).for_each(| operand | cover(operand, 2 * terminator));
+    }
+
+    let mut intervals = bounds
+        .into_iter()
+        .map(| (value, (start, end)) | LiveInterval { value, start, end })
+        .collect::<Vec<_>>();
+    intervals.sort_by_key(| interval | (interval.start, interval.end));
+    intervals
+}
+
+/// Assign the values of `function` to `num_registers` registers and stack slots.
+pub fn allocate_registers(module: &Module, function: &Function, num_registers: usize, strategy: AllocationStrategy) -> Allocation {
+    let intervals = live_intervals(module, function);
+    let mut locations = HashMap::new();
+    let mut num_spill_slots = 0;
+    let mut spill = | locations: &mut HashMap<ValueRef, Location>, value: ValueRef | {
+        locations.insert(value, Location::Spill(num_spill_slots));
+        num_spill_slots += 1;
+    };
+    match strategy {
+        AllocationStrategy::SpillEverything => intervals
+            .iter()
+            .for_each(| interval | spill(&mut locations, interval.value)),
+        AllocationStrategy::LinearScan => {
+            // active intervals holding registers, sorted by end position.
+            let mut active: Vec<(LiveInterval, usize)> = Vec::new();
+            let mut free = (0..num_registers).rev().collect::<Vec<_>>();
+            for interval in intervals.iter().cloned() {
+                active.retain(| (other, register) | {
+                    let expired = other.end < interval.start;
+                    if expired {
+                        free.push(*register);
+                    }
+                    !expired
+                });
+                free.sort_by(| lhs, rhs | rhs.cmp(lhs));
+                let register = match free.pop() {
+                    Some(register) => register,
+                    None => match active.last() {
+                        Some((last, register)) if last.end > interval.end => {
+                            let (last, register) = (last.value, *register);
+                            spill(&mut locations, last);
+                            active.pop();
+                            register
+                        },
+                        _ => {
+                            spill(&mut locations, interval.value);
+                            continue
+                        }
+                    }
+                };
+                locations.insert(interval.value, Location::Register(register));
+                let position = active.partition_point(| (other, _) | other.end <= interval.end);
+                active.insert(position, (interval, register));
+            }
+        }
+    }
+    Allocation { num_registers, num_spill_slots, intervals, locations }
+}
+
+/// Allocate registers for every function defined in the module.
+pub fn allocate_module(module: &Module, num_registers: usize, strategy: AllocationStrategy) -> HashMap<FunctionRef, Allocation> {
+    module.funcs
+        .iter()
+        .cloned()
+        .filter(| function | !module.get_function(*function).is_external)
+        .map(| function | (function, allocate_registers(module, module.get_function(function), num_registers, strategy)))
+        .collect()
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+    use itertools::Itertools;
+
+    use super::*;
+    use crate::apps::executor::{run_on_module, ExecutionErrorInternal, ProgramEnv, Val};
+    use crate::frontend::new_parser::parse_module;
+
+    #[test]
+    fn test_linear_scan() {
+        let module = parse_module(r"
+            fn @swap(#n: i32) -> i32 {
+            %entry:
+                jmp label %loop
+            %loop:
+                let %i = phi [0, label %entry], [%i.next, label %loop]
+                let %a = phi [1, label %entry], [%b, label %loop]
+                let %b = phi [2, label %entry], [%a, label %loop]
+                let %i.next = add %i, 1
+                let %c = lt %i.next, #n
+                br %c, label %loop, label %exit
+            %exit:
+                let %d = mul %a, 10
+                let %r = add %d, %b
+                ret %r
+            }
+        ").unwrap();
+        let function = module.get_function_ref("swap");
+        let run = | allocation: Allocation | {
+            let mut env = ProgramEnv::new();
+            env.allocations.insert(function, Rc::new(allocation));
+            run_on_module(&mut env, &module, "swap", vec![Val::Integer(3)]).map_err(Box::new)
+        };
+
+        let allocation = allocate_registers(&module, module.get_function(function), 3, AllocationStrategy::LinearScan);
+        // `%i`, `%a` and `%b` are live around the loop, `%c` is spilled as the interval ending last.
+        assert_eq!(allocation.num_spill_slots, 1);
+        for (lhs, rhs) in allocation.intervals.iter().tuple_combinations() {
+            let overlap = lhs.start <= rhs.end && rhs.start <= lhs.end;
+            let same_register = matches!(allocation.location(lhs.value), Some(Location::Register(_)))
+                && allocation.location(lhs.value) == allocation.location(rhs.value);
+            assert!(!(overlap && same_register));
+        }
+        assert_eq!(run(allocation.clone()).unwrap(), Val::Integer(12));
+        let spilled = allocate_registers(&module, module.get_function(function), 3, AllocationStrategy::SpillEverything);
+        assert_eq!(spilled.num_spill_slots, spilled.intervals.len());
+        assert_eq!(run(spilled).unwrap(), Val::Integer(12));
+
+        // sharing a register between the swapped phis loses one of them.
+        let mut broken = allocation;
+        let func = module.get_function(function);
+        let named = | name: &str | func.blocks
+            .iter()
+            .flat_map(| bb | func.get_basic_block(*bb).instrs.iter().cloned())
+            .find(| instr | module.get_value(*instr).name.as_deref() == Some(name))
+            .unwrap();
+        let (a, b) = (named("a"), named("b"));
+        broken.locations.insert(b, broken.locations[&a]);
+        let error = run(broken).unwrap_err();
+        assert!(matches!(error.error, ExecutionErrorInternal::ClobberedLocation(..)));
+    }
+}
@@ -0,0 +1,425 @@
+//! RV64IM assembly generation in GNU assembler syntax.
+//!
+//! Instruction selection is a macro expansion of each instruction, without register allocation:
+//! every argument and instruction result lives in an 8 byte stack slot addressed from the frame pointer `s0`,
+//! operands are loaded into temporary registers before each instruction and results stored back.
+//!
+//! The frame of a function, from the incoming stack pointer downwards, holds the return address,
+//! the saved frame pointer, value slots, a staging slot for each phi, alloca regions and
+//! the outgoing stack arguments of calls at the bottom, as required by the LP64 calling convention.
+//!
+//! Phis are lowered by copying incoming values into their staging slots on each edge,
+//! then into the phi slots when entering the block, so that phis reading each other see old values.
+
+use std::collections::HashMap;
+
+use crate::ir::structures::*;
+use crate::ir::types::{Type, TypeKind};
+use crate::ir::values::BinaryOp;
+
+/// Number of arguments passed in registers `a0` to `a7`.
+const NUM_ARG_REGISTERS: usize = 8;
+
+/// Size in bytes of a value of the type in memory, booleans are stored as words.
+/// Shared by the x86-64 backend, which has the same 64 bit pointers.
+pub fn size_of(ty: &Type) -> usize {
+    match &**ty {
+        TypeKind::Int32 | TypeKind::Int1 => 4,
+        TypeKind::Pointer(_) | TypeKind::OpaquePtr | TypeKind::Function(..) => 8,
+        TypeKind::Unit => 0,
+    }
+}
+
+/// Symbol of a callee, `starttime` and `stoptime` are macros over these symbols in the SysY runtime.
+fn callee_symbol(callee: &str) -> &str {
+    match callee {
+        "starttime" => "_sysy_starttime",
+        "stoptime" => "_sysy_stoptime",
+        _ => callee
+    }
+}
+
+fn load_instr(ty: &Type) -> &'static str {
+    if size_of(ty) == 8 { "ld" } else { "lw" }
+}
+
+fn store_instr(ty: &Type) -> &'static str {
+    if size_of(ty) == 8 { "sd" } else { "sw" }
+}
+
+fn fits_imm12(imm: i64) -> bool {
+    (-2048..2048).contains(&imm)
+}
+
+/// Stack layout of a function, offsets are relative to the frame pointer `s0`.
+struct Frame {
+    slots: HashMap<ValueRef, i64>,
+    staging: HashMap<ValueRef, i64>,
+    allocas: HashMap<ValueRef, i64>,
+    /// Frame size excluding the 16 bytes of return address and saved frame pointer.
+    size: i64,
+}
+
+impl Frame {
+    fn new(module: &Module, function: &Function) -> Frame {
+        let mut offset = 16;
+        let mut slot = | bytes: usize | {
+            offset += (bytes as i64 + 7) / 8 * 8;
+            -offset
+        };
+        let (mut slots, mut staging, mut allocas) = (HashMap::new(), HashMap::new(), HashMap::new());
+        let mut outgoing = 0;
+        for arg in function.args.iter().cloned() {
+            slots.insert(arg, slot(8));
+        }
+        for bb in function.blocks.iter().cloned() {
+            for instr in function.get_basic_block(bb).instrs.iter().cloned() {
+                match &module.get_value(instr).kind {
+                    ValueKind::Alloca(inner) => {
+                        allocas.insert(instr, slot(size_of(&inner.elem_type) * inner.num_elements));
+                    },
+                    ValueKind::Phi(_) => {
+                        slots.insert(instr, slot(8));
+                        staging.insert(instr, slot(8));
+                    },
+                    ValueKind::FnCall(inner) => {
+                        outgoing = outgoing.max(inner.args.len().saturating_sub(NUM_ARG_REGISTERS) as i64 * 8);
+                        slots.insert(instr, slot(8));
+                    },
+                    _ => {
+                        slots.insert(instr, slot(8));
+                    }
+                }
+            }
+        }
+        let size = (offset + outgoing + 15) / 16 * 16 - 16;
+        Frame { slots, staging, allocas, size }
+    }
+}
+
+struct FunctionEmitter<'a> {
+    module: &'a Module,
+    function: &'a Function,
+    frame: Frame,
+    out: &'a mut String,
+}
+
+impl<'a> FunctionEmitter<'a> {
+    fn emit(&mut self, line: &str) {
+        self.out.push_str("  ");
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    fn label(&self, bb: BlockRef) -> String {
+        let index = self.function.blocks.iter().position(| other | *other == bb).unwrap();
+        format!(".L{}_{}", self.function.name, index)
+    }
+
+    /// Load or store `reg` at `offset` from the frame pointer, through `t6` for large offsets.
+    fn frame_access(&mut self, op: &str, reg: &str, offset: i64) {
+        if fits_imm12(offset) {
+            self.emit(&format!("{} {}, {}(s0)", op, reg, offset));
+        } else {
+            self.emit(&format!("li t6, {}", offset));
+            self.emit("add t6, t6, s0");
+            self.emit(&format!("{} {}, 0(t6)", op, reg));
+        }
+    }
+
+    fn add_immediate(&mut self, rd: &str, rs: &str, imm: i64) {
+        if fits_imm12(imm) {
+            self.emit(&format!("addi {}, {}, {}", rd, rs, imm));
+        } else {
+            self.emit(&format!("li t6, {}", imm));
+            self.emit(&format!("add {}, {}, t6", rd, rs));
+        }
+    }
+
+    /// Materialize the operand `value` in `reg`.
+    fn load_operand(&mut self, reg: &str, value: ValueRef) {
+        let data = self.module.get_value(value);
+        match &data.kind {
+            ValueKind::ConstantInt(inner) => self.emit(&format!("li {}, {}", reg, inner.value)),
+            ValueKind::ConstantBool(inner) => self.emit(&format!("li {}, {}", reg, inner.value as i32)),
+            ValueKind::ConstantNullPtr(_) | ValueKind::ConstantUnit(_) => self.emit(&format!("li {}, 0", reg)),
+            ValueKind::GlobalVar(_) => {
+                let name = data.name.clone().expect("global variables are named");
+                self.emit(&format!("la {}, {}", reg, name));
+            },
+            ValueKind::Alloca(_) => {
+                let offset = self.frame.allocas[&value];
+                self.add_immediate(reg, "s0", offset);
+            },
+            _ => {
+                let offset = self.frame.slots[&value];
+                self.frame_access(load_instr(&data.ty), reg, offset);
+            }
+        }
+    }
+
+    fn store_result(&mut self, reg: &str, value: ValueRef) {
+        let ty = self.module.get_value_type(value);
+        if !ty.is_unit_type() {
+            let offset = self.frame.slots[&value];
+            self.frame_access(store_instr(&ty), reg, offset);
+        }
+    }
+
+    fn emit_prologue(&mut self) {
+        self.emit("addi sp, sp, -16");
+        self.emit("sd ra, 8(sp)");
+        self.emit("sd s0, 0(sp)");
+        self.emit("addi s0, sp, 16");
+        let size = self.frame.size;
+        self.add_immediate("sp", "sp", -size);
+        for (index, arg) in self.function.args.iter().cloned().enumerate() {
+            let offset = self.frame.slots[&arg];
+            if index < NUM_ARG_REGISTERS {
+                self.frame_access("sd", &format!("a{}", index), offset);
+            } else {
+                // stack arguments are at the bottom of the caller frame, where `s0` points to.
+                self.emit(&format!("ld t0, {}(s0)", (index - NUM_ARG_REGISTERS) * 8));
+                self.frame_access("sd", "t0", offset);
+            }
+        }
+    }
+
+    fn emit_epilogue(&mut self) {
+        self.emit("addi sp, s0, -16");
+        self.emit("ld ra, 8(sp)");
+        self.emit("ld s0, 0(sp)");
+        self.emit("addi sp, sp, 16");
+        self.emit("ret");
+    }
+
+    fn emit_binary(&mut self, op: &BinaryOp) {
+        let lines: &[&str] = match op {
+            BinaryOp::Add => &["addw t0, t0, t1"],
+            BinaryOp::Sub => &["subw t0, t0, t1"],
+            BinaryOp::Mul => &["mulw t0, t0, t1"],
+            BinaryOp::Div => &["divw t0, t0, t1"],
+            BinaryOp::Rem => &["remw t0, t0, t1"],
+            BinaryOp::And => &["and t0, t0, t1"],
+            BinaryOp::Or => &["or t0, t0, t1"],
+            BinaryOp::Xor => &["xor t0, t0, t1"],
+            BinaryOp::Lt => &["slt t0, t0, t1"],
+            BinaryOp::Gt => &["slt t0, t1, t0"],
+            BinaryOp::Le => &["slt t0, t1, t0", "xori t0, t0, 1"],
+            BinaryOp::Ge => &["slt t0, t0, t1", "xori t0, t0, 1"],
+            BinaryOp::Eq => &["xor t0, t0, t1", "seqz t0, t0"],
+            BinaryOp::Ne => &["xor t0, t0, t1", "snez t0, t0"],
+        };
+        for line in lines {
+            self.emit(line);
+        }
+    }
+
+    fn emit_instruction(&mut self, instr: ValueRef) {
+        let module = self.module;
+        match &module.get_value(instr).kind {
+            ValueKind::Binary(inner) => {
+                self.load_operand("t0", inner.lhs);
+                self.load_operand("t1", inner.rhs);
+                self.emit_binary(&inner.op);
+                self.store_result("t0", instr);
+            },
+            ValueKind::Offset(inner) => {
+                // byte offset of each index is the product of the following bounds and the element size.
+                self.load_operand("t0", inner.base_addr);
+                let mut stride = size_of(&inner.elem_type) as i64;
+                let mut constant_offset = 0;
+                for (position, index) in inner.index.iter().cloned().enumerate().rev() {
+                    match &module.get_value(index).kind {
+                        ValueKind::ConstantInt(constant) => constant_offset += constant.value as i64 * stride,
+                        _ => {
+                            self.load_operand("t1", index);
+                            self.emit(&format!("li t2, {}", stride));
+                            self.emit("mul t1, t1, t2");
+                            self.emit("add t0, t0, t1");
+                        }
+                    }
+                    if position > 0 {
+                        stride *= inner.bounds[position].expect("only the first dimension of an offset may be unbounded") as i64;
+                    }
+                }
+                if constant_offset != 0 {
+                    self.add_immediate("t0", "t0", constant_offset);
+                }
+                self.store_result("t0", instr);
+            },
+            ValueKind::FnCall(inner) => {
+                for (index, arg) in inner.args.iter().cloned().enumerate().skip(NUM_ARG_REGISTERS) {
+                    self.load_operand("t0", arg);
+                    self.emit(&format!("sd t0, {}(sp)", (index - NUM_ARG_REGISTERS) * 8));
+                }
+                for (index, arg) in inner.args.iter().cloned().enumerate().take(NUM_ARG_REGISTERS) {
+                    self.load_operand(&format!("a{}", index), arg);
+                }
+                if matches!(inner.callee.as_str(), "starttime" | "stoptime") {
+                    // the line number argument of the runtime timers.
+                    self.emit("li a0, 0");
+                }
+                self.emit(&format!("call {}", callee_symbol(&inner.callee)));
+                self.store_result("a0", instr);
+            },
+            ValueKind::Load(inner) => {
+                self.load_operand("t0", inner.addr);
+                let ty = module.get_value_type(instr);
+                self.emit(&format!("{} t0, 0(t0)", load_instr(&ty)));
+                self.store_result("t0", instr);
+            },
+            ValueKind::Store(inner) => {
+                let ty = module.get_value_type(inner.value);
+                if !ty.is_unit_type() {
+                    self.load_operand("t0", inner.value);
+                    self.load_operand("t1", inner.addr);
+                    self.emit(&format!("{} t0, 0(t1)", store_instr(&ty)));
+                }
+            },
+            // allocas are addressed from the frame pointer, phis are copied on edges.
+            ValueKind::Alloca(_) | ValueKind::Phi(_) => (),
+            _ => panic!("unexpected value in instruction position")
+        }
+    }
+
+    /// Copy the values flowing along the edge `from -> to` into the staging slots of the phis of `to`.
+    fn emit_phi_copies(&mut self, from: BlockRef, to: BlockRef) {
+        for phi in self.function.get_basic_block(to).instrs.iter().cloned() {
+            let incoming = match &self.module.get_value(phi).kind {
+                ValueKind::Phi(inner) => inner.incoming.iter().find(| (_, pred) | *pred == from).map(| (value, _) | *value),
+                _ => break
+            };
+            let value = incoming.expect("phi has no incoming value for a predecessor");
+            let ty = self.module.get_value_type(phi);
+            self.load_operand("t0", value);
+            let offset = self.frame.staging[&phi];
+            self.frame_access(store_instr(&ty), "t0", offset);
+        }
+    }
+
+    fn emit_terminator(&mut self, bb: BlockRef) {
+        match &self.function.get_basic_block(bb).terminator {
+            Terminator::Jump(inner) => {
+                self.emit_phi_copies(bb, inner.dest);
+                let dest = self.label(inner.dest);
+                self.emit(&format!("j {}", dest));
+            },
+            Terminator::Branch(inner) => {
+                // branch only over the copies of the true edge, since conditional branches have a short range.
+                let false_edge = format!("{}_false", self.label(bb));
+                self.load_operand("t0", inner.cond);
+                self.emit(&format!("beqz t0, {}", false_edge));
+                self.emit_phi_copies(bb, inner.true_label);
+                let true_label = self.label(inner.true_label);
+                self.emit(&format!("j {}", true_label));
+                self.out.push_str(&format!("{}:\n", false_edge));
+                self.emit_phi_copies(bb, inner.false_label);
+                let false_label = self.label(inner.false_label);
+                self.emit(&format!("j {}", false_label));
+            },
+            Terminator::Return(inner) => {
+                if !self.module.get_value_type(inner.value).is_unit_type() {
+                    self.load_operand("a0", inner.value);
+                }
+                self.emit_epilogue();
+            },
+            Terminator::Panic => self.emit("unimp"),
+        }
+    }
+
+    fn emit_function(&mut self) {
+        let name = self.function.name.clone();
+        self.out.push_str(&format!("  .globl {}\n  .p2align 2\n  .type {}, @function\n{}:\n", name, name, name));
+        self.emit_prologue();
+        for bb in self.function.blocks.iter().cloned() {
+            let block = self.function.get_basic_block(bb);
+            let comment = block.name.as_ref().map_or(String::new(), | name | format!("  # %{}", name));
+            self.out.push_str(&format!("{}:{}\n", self.label(bb), comment));
+            for phi in block.instrs.iter().cloned().take_while(| instr | self.module.get_value(*instr).is_phi()) {
+                let ty = self.module.get_value_type(phi);
+                let (staging, slot) = (self.frame.staging[&phi], self.frame.slots[&phi]);
+                self.frame_access(load_instr(&ty), "t0", staging);
+                self.frame_access(store_instr(&ty), "t0", slot);
+            }
+            for instr in block.instrs.iter().cloned() {
+                self.emit_instruction(instr);
+            }
+            self.emit_terminator(bb);
+        }
+        self.out.push_str(&format!("  .size {}, .-{}\n\n", name, name));
+    }
+}
+
+/// Lower a verified module to RV64IM assembly for the LP64 ABI.
+///
+/// Function declarations are expected to be provided by the SysY runtime or other objects,
+/// global regions are zero initialized in `.bss`.
+pub fn emit_module(module: &Module) -> String {
+    let mut out = String::new();
+    let globals = module.globals
+        .iter()
+        .filter_map(| global | match &module.get_value(*global).kind {
+            ValueKind::GlobalVar(inner) => Some((module.get_value(*global).name.clone().expect("global variables are named"), inner)),
+            _ => None
+        })
+        .collect::<Vec<_>>();
+    if !globals.is_empty() {
+        out.push_str("  .bss\n");
+        for (name, global) in globals {
+            let size = (size_of(&global.elem_ty) * global.size).max(1);
+            out.push_str(&format!("  .globl {}\n  .p2align 3\n  .type {}, @object\n  .size {}, {}\n{}:\n  .zero {}\n\n", name, name, name, size, name, size));
+        }
+    }
+    out.push_str("  .text\n");
+    for function in module.funcs.iter().cloned() {
+        let function = module.get_function(function);
+        if function.is_external {
+            continue;
+        }
+        let mut emitter = FunctionEmitter { module, function, frame: Frame::new(module, function), out: &mut out };
+        emitter.emit_function();
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontend::new_parser::parse_module;
+
+    #[test]
+    fn test_emit_module() {
+        let module = parse_module(r"
+            @matrix: region i32, 12
+            fn @sum(#a: i32, #b: i32, #c: i32, #d: i32, #e: i32, #f: i32, #g: i32, #h: i32, #i: i32, #j: i32) -> i32 {
+            %entry:
+                let %0 = add #a, #j
+                ret %0
+            }
+            fn @main() -> i32 {
+            %entry:
+                let %i = call @getint
+                let %addr = offset i32, @matrix, [%i < 3], [2 < 4]
+                let %0 = store 7, %addr
+                let %1 = call @starttime
+                let %2 = call @sum, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10
+                ret %2
+            }
+        ").unwrap();
+        let asm = emit_module(&module);
+        let lines = asm.lines().map(str::trim).collect::<Vec<_>>();
+        let contains = | fragment: &[&str] | lines.windows(fragment.len()).any(| window | window == fragment);
+
+        assert!(contains(&[".bss", ".globl matrix"]));
+        assert!(contains(&[".size matrix, 48", "matrix:", ".zero 48"]));
+        // the row stride is 4 elements of 4 bytes, the constant column is folded into one addition.
+        assert!(contains(&["la t0, matrix", "lw t1, -24(s0)", "li t2, 16", "mul t1, t1, t2", "add t0, t0, t1", "addi t0, t0, 8"]));
+        assert!(contains(&["li a0, 0", "call _sysy_starttime"]));
+        // the ninth and tenth arguments are passed on the stack, read from the caller frame by the callee.
+        assert!(contains(&["li t0, 9", "sd t0, 0(sp)", "li t0, 10", "sd t0, 8(sp)"]));
+        assert!(contains(&["ld t0, 8(s0)", "sd t0, -96(s0)"]));
+        assert!(contains(&["lw a0, -104(s0)", "addi sp, s0, -16"]));
+    }
+}
@@ -0,0 +1,56 @@
+/*
+ * SysY runtime functions for programs emitted by `backend::c` and `backend::x86_64`,
+ * printing in the same format as the accipit interpreter so that outputs can be compared.
+ */
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+static void expect_input(int matched, const char *message) {
+    if (matched != 1) {
+        fprintf(stderr, "%s\n", message);
+        exit(1);
+    }
+}
+
+int32_t getint(void) {
+    int value;
+    expect_input(scanf("%d", &value), "'getint' expect a 'int' input");
+    return value;
+}
+
+int32_t getch(void) {
+    char character;
+    expect_input(scanf("%c", &character), "'getch' expect a 'char' input");
+    return character;
+}
+
+int32_t getarray(int32_t *array) {
+    int n;
+    expect_input(scanf("%d", &n), "'getarray' expect a 'int' input as array size");
+    for (int i = 0; i < n; i++) {
+        int value;
+        expect_input(scanf("%d", &value), "expect a 'int' input as array element");
+        array[i] = value;
+    }
+    return n;
+}
+
+void putint(int32_t value) {
+    printf("%d", (int)value);
+}
+
+void putch(int32_t character) {
+    putchar(character);
+}
+
+void putarray(int32_t n, int32_t *array) {
+    printf("%d:", (int)n);
+    for (int32_t i = 0; i < n; i++) {
+        printf(" %d", (int)array[i]);
+    }
+}
+
+void starttime(void) {}
+
+void stoptime(void) {}
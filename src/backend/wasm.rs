@@ -0,0 +1,652 @@
+//! WebAssembly text format generation, for a 32 bit linear memory.
+//!
+//! Every argument and instruction result is an `i32` local, booleans and pointers included.
+//! `region` globals are laid out from `GLOBAL_BASE`, followed by a shadow stack growing down from its top,
+//! whose pointer is the mutable global `$__stack_pointer`. Functions with allocas reserve a frame on it,
+//! addressed from the `$fp` local.
+//!
+//! Structured control flow is recovered from the dominator tree as in "Beyond Relooper" (Ramsey):
+//! a block is emitted within its immediate dominator, loop headers open a `loop`,
+//! and blocks reached by several forward edges (merge blocks) follow a `block` enclosing the code
+//! of their immediate dominator, so that forward edges are `br` out of the `block` and back edges `br` to the `loop`.
+//! Irreducible control flow falls back to a `br_table` dispatch loop over a `$label` local.
+//!
+//! Phis are lowered by setting staging locals on each edge, copied into the phi locals when entering the block.
+//! Runtime and declared functions are imported from the `env` module.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::apps::executor::is_runtime_function;
+use crate::ir::analysis::cfg::ControlFlowGraph;
+use crate::ir::analysis::dominance::DominatorTree;
+use crate::ir::structures::*;
+use crate::ir::types::{Type, TypeKind};
+use crate::ir::values::BinaryOp;
+use crate::utils::unique_name::UniqueName;
+
+/// Address of the first global region, leaving low addresses unused so that null is never a valid object.
+const GLOBAL_BASE: usize = 1024;
+/// Size in bytes of the shadow stack.
+const STACK_SIZE: usize = 1 << 20;
+const PAGE_SIZE: usize = 1 << 16;
+
+/// Size in bytes of a value of the type in memory, booleans are stored as words.
+pub fn size_of(ty: &Type) -> usize {
+    match &**ty {
+        TypeKind::Int32 | TypeKind::Int1 => 4,
+        TypeKind::Pointer(_) | TypeKind::OpaquePtr | TypeKind::Function(..) => 4,
+        TypeKind::Unit => 0,
+    }
+}
+
+fn align_to(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align) * align
+}
+
+/// Parameter and result declarations of a signature.
+fn signature(params: &[Type], ret: &Type) -> String {
+    let mut signature = String::new();
+    if !params.is_empty() {
+        signature.push_str(&format!(" (param{})", " i32".repeat(params.len())));
+    }
+    if !ret.is_unit_type() {
+        signature.push_str(" (result i32)");
+    }
+    signature
+}
+
+fn binary_instr(op: &BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "i32.add",
+        BinaryOp::Sub => "i32.sub",
+        BinaryOp::Mul => "i32.mul",
+        BinaryOp::Div => "i32.div_s",
+        BinaryOp::Rem => "i32.rem_s",
+        BinaryOp::And => "i32.and",
+        BinaryOp::Or => "i32.or",
+        BinaryOp::Xor => "i32.xor",
+        BinaryOp::Lt => "i32.lt_s",
+        BinaryOp::Gt => "i32.gt_s",
+        BinaryOp::Le => "i32.le_s",
+        BinaryOp::Ge => "i32.ge_s",
+        BinaryOp::Eq => "i32.eq",
+        BinaryOp::Ne => "i32.ne",
+    }
+}
+
+/// Loop headers and merge blocks of a function, or `None` if its control flow is irreducible.
+struct Structure {
+    dominators: DominatorTree,
+    /// Reverse postorder number of each reachable block.
+    order: HashMap<BlockRef, usize>,
+    loop_headers: HashSet<BlockRef>,
+    merge_blocks: HashSet<BlockRef>,
+}
+
+impl Structure {
+    fn new(function: &Function) -> Option<Structure> {
+        let cfg = ControlFlowGraph::new(function);
+        let dominators = DominatorTree::new(&cfg);
+        let order = cfg.reverse_postorder()
+            .into_iter()
+            .enumerate()
+            .map(| (index, bb) | (bb, index))
+            .collect::<HashMap<_, _>>();
+        let (mut loop_headers, mut merge_blocks) = (HashSet::new(), HashSet::new());
+        for bb in order.keys().cloned() {
+            let mut forward_edges = 0;
+            for pred in cfg.predecessors(bb).iter().cloned().filter(| pred | order.contains_key(pred)) {
+                if order[&pred] < order[&bb] {
+                    forward_edges += 1;
+                } else if dominators.dominates(bb, pred) {
+                    loop_headers.insert(bb);
+                } else {
+                    return None;
+                }
+            }
+            if forward_edges > 1 {
+                merge_blocks.insert(bb);
+            }
+        }
+        Some(Structure { dominators, order, loop_headers, merge_blocks })
+    }
+
+    fn is_backward(&self, from: BlockRef, to: BlockRef) -> bool {
+        self.order[&to] <= self.order[&from]
+    }
+
+    /// Merge blocks immediately dominated by `bb`, in reverse postorder.
+    fn merge_children(&self, bb: BlockRef) -> Vec<BlockRef> {
+        let mut children = self.dominators
+            .children(bb)
+            .iter()
+            .cloned()
+            .filter(| child | self.merge_blocks.contains(child))
+            .collect::<Vec<_>>();
+        children.sort_by_key(| child | self.order[child]);
+        children
+    }
+}
+
+enum ControlFlow {
+    Structured(Box<Structure>),
+    /// Reachable blocks in reverse postorder, entered by setting the label local to their index.
+    Dispatch { label: String, loop_label: String, blocks: Vec<BlockRef> },
+}
+
+struct FunctionEmitter<'a> {
+    module: &'a Module,
+    function: &'a Function,
+    globals: &'a HashMap<ValueRef, String>,
+    stack_pointer: &'a str,
+    locals: HashMap<ValueRef, String>,
+    staging: HashMap<ValueRef, String>,
+    /// Labels of the `block` and the `loop` targeted by the edges to each block.
+    labels: HashMap<BlockRef, (String, String)>,
+    /// Frame offsets of allocas, the frame size and its frame pointer local.
+    allocas: HashMap<ValueRef, usize>,
+    frame_size: usize,
+    frame_pointer: String,
+    control_flow: ControlFlow,
+    depth: usize,
+    out: &'a mut String,
+}
+
+impl<'a> FunctionEmitter<'a> {
+    fn new(
+        module: &'a Module,
+        function: &'a Function,
+        globals: &'a HashMap<ValueRef, String>,
+        stack_pointer: &'a str,
+        out: &'a mut String
+    ) -> Self {
+        let mut used = UniqueName::new();
+        let name_of = | value: ValueRef, used: &mut UniqueName | {
+            used.next_unused_name(module.get_value(value).name.as_deref().unwrap_or("t"))
+        };
+        let mut locals = HashMap::new();
+        let (mut staging, mut allocas) = (HashMap::new(), HashMap::new());
+        let mut frame_size = 0;
+        for arg in function.args.iter().cloned() {
+            locals.insert(arg, name_of(arg, &mut used));
+        }
+        let instrs = function.blocks
+            .iter()
+            .flat_map(| bb | function.get_basic_block(*bb).instrs.iter())
+            .cloned()
+            .collect::<Vec<_>>();
+        for instr in instrs.iter().cloned() {
+            if !module.get_value_type(instr).is_unit_type() {
+                locals.insert(instr, name_of(instr, &mut used));
+            }
+        }
+        for instr in instrs.iter().cloned() {
+            match &module.get_value(instr).kind {
+                ValueKind::Phi(_) => {
+                    staging.insert(instr, used.next_unused_name(&format!("{}.in", locals[&instr])));
+                },
+                ValueKind::Alloca(inner) => {
+                    allocas.insert(instr, frame_size);
+                    frame_size = align_to(frame_size + size_of(&inner.elem_type) * inner.num_elements, 8);
+                },
+                _ => ()
+            }
+        }
+        let frame_pointer = used.next_unused_name("fp");
+
+        let mut used_labels = UniqueName::new();
+        let labels = function.blocks
+            .iter()
+            .enumerate()
+            .map(| (index, bb) | {
+                let name = function.get_basic_block(*bb).name.clone().unwrap_or_else(|| format!("bb{}", index));
+                (*bb, (used_labels.next_unused_name(&name), used_labels.next_unused_name(&format!("{}.loop", name))))
+            })
+            .collect();
+        let control_flow = match Structure::new(function) {
+            Some(structure) => ControlFlow::Structured(Box::new(structure)),
+            None => ControlFlow::Dispatch {
+                label: used.next_unused_name("label"),
+                loop_label: used_labels.next_unused_name("dispatch"),
+                blocks: ControlFlowGraph::new(function).reverse_postorder(),
+            },
+        };
+        FunctionEmitter {
+            module, function, globals, stack_pointer,
+            locals, staging, labels, allocas,
+            frame_size: align_to(frame_size, 16), frame_pointer,
+            control_flow, depth: 0, out
+        }
+    }
+
+    fn emit(&mut self, line: &str) {
+        self.out.push_str(&"  ".repeat(self.depth + 2));
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    fn push_operand(&mut self, value: ValueRef) {
+        let line = match &self.module.get_value(value).kind {
+            ValueKind::ConstantInt(inner) => format!("i32.const {}", inner.value),
+            ValueKind::ConstantBool(inner) => format!("i32.const {}", inner.value as i32),
+            ValueKind::ConstantNullPtr(_) | ValueKind::ConstantUnit(_) => "i32.const 0".to_string(),
+            ValueKind::GlobalVar(_) => format!("global.get ${}", self.globals[&value]),
+            _ => format!("local.get ${}", self.locals[&value]),
+        };
+        self.emit(&line);
+    }
+
+    fn set_result(&mut self, value: ValueRef) {
+        if let Some(local) = self.locals.get(&value) {
+            let line = format!("local.set ${}", local);
+            self.emit(&line);
+        }
+    }
+
+    fn emit_instruction(&mut self, instr: ValueRef) {
+        let module = self.module;
+        match &module.get_value(instr).kind {
+            ValueKind::Binary(inner) => {
+                self.push_operand(inner.lhs);
+                self.push_operand(inner.rhs);
+                self.emit(binary_instr(&inner.op));
+            },
+            ValueKind::Offset(inner) => {
+                // byte offset of each index is the product of the following bounds and the element size.
+                self.push_operand(inner.base_addr);
+                let mut stride = size_of(&inner.elem_type) as i64;
+                let mut constant_offset = 0;
+                for (position, index) in inner.index.iter().cloned().enumerate().rev() {
+                    match &module.get_value(index).kind {
+                        ValueKind::ConstantInt(constant) => constant_offset += constant.value as i64 * stride,
+                        _ => {
+                            self.push_operand(index);
+                            self.emit(&format!("i32.const {}", stride));
+                            self.emit("i32.mul");
+                            self.emit("i32.add");
+                        }
+                    }
+                    if position > 0 {
+                        stride *= inner.bounds[position].expect("only the first dimension of an offset may be unbounded") as i64;
+                    }
+                }
+                if constant_offset != 0 {
+                    self.emit(&format!("i32.const {}", constant_offset as i32));
+                    self.emit("i32.add");
+                }
+            },
+            ValueKind::FnCall(inner) => {
+                for arg in inner.args.iter().cloned() {
+                    self.push_operand(arg);
+                }
+                self.emit(&format!("call ${}", inner.callee));
+            },
+            ValueKind::Alloca(_) => {
+                let line = format!("local.get ${}", self.frame_pointer);
+                self.emit(&line);
+                let offset = self.allocas[&instr];
+                if offset != 0 {
+                    self.emit(&format!("i32.const {}", offset));
+                    self.emit("i32.add");
+                }
+            },
+            ValueKind::Load(inner) => {
+                self.push_operand(inner.addr);
+                self.emit("i32.load");
+            },
+            ValueKind::Store(inner) => {
+                if !module.get_value_type(inner.value).is_unit_type() {
+                    self.push_operand(inner.addr);
+                    self.push_operand(inner.value);
+                    self.emit("i32.store");
+                }
+            },
+            // phis are set on edges.
+            ValueKind::Phi(_) => return,
+            _ => panic!("unexpected value in instruction position")
+        }
+        self.set_result(instr);
+    }
+
+    fn emit_basic_block(&mut self, bb: BlockRef) {
+        let function = self.function;
+        for instr in function.get_basic_block(bb).instrs.iter().cloned() {
+            if self.module.get_value(instr).is_phi() {
+                let line = format!("local.get ${}", self.staging[&instr]);
+                self.emit(&line);
+                self.set_result(instr);
+            } else {
+                self.emit_instruction(instr);
+            }
+        }
+        self.emit_terminator(bb);
+    }
+
+    /// Set the staging locals of the phis of `to` to the values flowing along the edge `from -> to`, then take it.
+    fn emit_edge(&mut self, from: BlockRef, to: BlockRef) {
+        let function = self.function;
+        for phi in function.get_basic_block(to).instrs.iter().cloned() {
+            let incoming = match &self.module.get_value(phi).kind {
+                ValueKind::Phi(inner) => inner.incoming_value(from),
+                _ => break
+            };
+            self.push_operand(incoming.expect("phi has no incoming value for a predecessor"));
+            let line = format!("local.set ${}", self.staging[&phi]);
+            self.emit(&line);
+        }
+        let (block_label, loop_label) = self.labels[&to].clone();
+        match &self.control_flow {
+            ControlFlow::Structured(structure) => {
+                if structure.is_backward(from, to) {
+                    self.emit(&format!("br ${}", loop_label));
+                } else if structure.merge_blocks.contains(&to) {
+                    self.emit(&format!("br ${}", block_label));
+                } else {
+                    self.emit_tree(to);
+                }
+            },
+            ControlFlow::Dispatch { label, loop_label, blocks } => {
+                let index = blocks.iter().position(| bb | *bb == to).expect("successors of reachable blocks are reachable");
+                let lines = [format!("i32.const {}", index), format!("local.set ${}", label), format!("br ${}", loop_label)];
+                for line in lines {
+                    self.emit(&line);
+                }
+            }
+        }
+    }
+
+    fn emit_terminator(&mut self, bb: BlockRef) {
+        match &self.function.get_basic_block(bb).terminator {
+            Terminator::Jump(inner) => self.emit_edge(bb, inner.dest),
+            Terminator::Branch(inner) if inner.true_label == inner.false_label => self.emit_edge(bb, inner.true_label),
+            Terminator::Branch(inner) => {
+                self.push_operand(inner.cond);
+                self.emit("if");
+                self.depth += 1;
+                self.emit_edge(bb, inner.true_label);
+                self.depth -= 1;
+                self.emit("else");
+                self.depth += 1;
+                self.emit_edge(bb, inner.false_label);
+                self.depth -= 1;
+                self.emit("end");
+            },
+            Terminator::Return(inner) => {
+                if self.frame_size > 0 {
+                    let line = format!("local.get ${}", self.frame_pointer);
+                    self.emit(&line);
+                    self.emit(&format!("i32.const {}", self.frame_size));
+                    self.emit("i32.add");
+                    let line = format!("global.set ${}", self.stack_pointer);
+                    self.emit(&line);
+                }
+                if !self.module.get_value_type(inner.value).is_unit_type() {
+                    self.push_operand(inner.value);
+                }
+                self.emit("return");
+            },
+            Terminator::Panic => self.emit("unreachable"),
+        }
+    }
+
+    /// Code of `bb` and the blocks it immediately dominates, as a `loop` for loop headers.
+    fn emit_tree(&mut self, bb: BlockRef) {
+        let (is_loop_header, merge_children) = match &self.control_flow {
+            ControlFlow::Structured(structure) => (structure.loop_headers.contains(&bb), structure.merge_children(bb)),
+            ControlFlow::Dispatch { .. } => unreachable!("blocks are emitted in dispatch order"),
+        };
+        if is_loop_header {
+            let line = format!("loop ${}", self.labels[&bb].1);
+            self.emit(&line);
+            self.depth += 1;
+            self.emit_within(bb, &merge_children);
+            self.depth -= 1;
+            self.emit("end");
+        } else {
+            self.emit_within(bb, &merge_children);
+        }
+    }
+
+    /// Code of `bb` enclosed in a `block` for each of the merge blocks, followed by them, the latest outermost.
+    fn emit_within(&mut self, bb: BlockRef, merge_blocks: &[BlockRef]) {
+        match merge_blocks.split_last() {
+            Some((last, rest)) => {
+                let line = format!("block ${}", self.labels[last].0);
+                self.emit(&line);
+                self.depth += 1;
+                self.emit_within(bb, rest);
+                self.depth -= 1;
+                self.emit("end");
+                self.emit_tree(*last);
+            },
+            None => self.emit_basic_block(bb),
+        }
+    }
+
+    fn emit_dispatch(&mut self, label: &str, loop_label: &str, blocks: &[BlockRef]) {
+        self.emit("i32.const 0");
+        self.emit(&format!("local.set ${}", label));
+        self.emit(&format!("loop ${}", loop_label));
+        self.depth += 1;
+        for bb in blocks.iter().rev() {
+            let line = format!("block ${}", self.labels[bb].0);
+            self.emit(&line);
+            self.depth += 1;
+        }
+        self.emit(&format!("local.get ${}", label));
+        let targets = blocks.iter().map(| bb | format!("${}", self.labels[bb].0)).collect::<Vec<_>>();
+        self.emit(&format!("br_table {}", targets.join(" ")));
+        for bb in blocks.iter().cloned() {
+            self.depth -= 1;
+            self.emit("end");
+            self.emit_basic_block(bb);
+        }
+        self.depth -= 1;
+        self.emit("end");
+    }
+
+    fn emit_function(&mut self) {
+        let function = self.function;
+        let ret = function.ty.get_function_ret_type().unwrap_or(Type::get_unit());
+        let params = function.args
+            .iter()
+            .filter_map(| arg | self.locals.get(arg))
+            .map(| local | format!(" (param ${} i32)", local))
+            .collect::<String>();
+        let result = if ret.is_unit_type() { "" } else { " (result i32)" };
+        self.out.push_str(&format!("  (func ${} (export \"{}\"){}{}\n", function.name, function.name, params, result));
+
+        let mut locals = function.blocks
+            .iter()
+            .flat_map(| bb | function.get_basic_block(*bb).instrs.iter())
+            .filter_map(| instr | self.locals.get(instr).map(| local | (local, self.staging.get(instr))))
+            .flat_map(| (local, staging) | std::iter::once(local).chain(staging))
+            .map(| local | format!("(local ${} i32)", local))
+            .collect::<Vec<_>>();
+        if self.frame_size > 0 {
+            locals.push(format!("(local ${} i32)", self.frame_pointer));
+        }
+        if let ControlFlow::Dispatch { label, .. } = &self.control_flow {
+            locals.push(format!("(local ${} i32)", label));
+        }
+        if !locals.is_empty() {
+            let line = locals.join(" ");
+            self.emit(&line);
+        }
+
+        if self.frame_size > 0 {
+            let lines = [
+                format!("global.get ${}", self.stack_pointer),
+                format!("i32.const {}", self.frame_size),
+                "i32.sub".to_string(),
+                format!("local.tee ${}", self.frame_pointer),
+                format!("global.set ${}", self.stack_pointer),
+            ];
+            for line in lines {
+                self.emit(&line);
+            }
+        }
+        match &self.control_flow {
+            ControlFlow::Structured(_) => self.emit_tree(function.blocks[0]),
+            ControlFlow::Dispatch { label, loop_label, blocks } => {
+                let (label, loop_label, blocks) = (label.clone(), loop_label.clone(), blocks.clone());
+                self.emit_dispatch(&label, &loop_label, &blocks);
+            }
+        }
+        // the end of a `loop` or `if` closing the function body is reachable for validation.
+        if !ret.is_unit_type() && self.out.ends_with("end\n") {
+            self.emit("unreachable");
+        }
+        self.out.push_str("  )\n");
+    }
+}
+
+/// Lower a verified module to a WebAssembly text format module.
+///
+/// The module exports its memory and defined functions, and imports function declarations
+/// and runtime functions called without declaration from `env`, the latter with the signature of their first call.
+pub fn emit_module(module: &Module) -> String {
+    let mut out = String::from("(module\n");
+
+    let mut imported = HashSet::new();
+    for function in module.funcs.iter().cloned().map(| function | module.get_function(function)) {
+        imported.insert(function.name.clone());
+        if function.is_external {
+            let params = function.ty.get_function_params_type().unwrap_or_default();
+            let ret = function.ty.get_function_ret_type().unwrap_or(Type::get_unit());
+            out.push_str(&format!("  (import \"env\" \"{}\" (func ${}{}))\n", function.name, function.name, signature(&params, &ret)));
+        }
+    }
+    let calls = module.funcs
+        .iter()
+        .map(| function | module.get_function(*function))
+        .flat_map(| function | function.blocks.iter().flat_map(| bb | function.get_basic_block(*bb).instrs.iter()))
+        .filter_map(| instr | match &module.get_value(*instr).kind {
+            ValueKind::FnCall(inner) => Some((inner, module.get_value_type(*instr))),
+            _ => None
+        });
+    for (call, ret) in calls {
+        if is_runtime_function(&call.callee) && imported.insert(call.callee.clone()) {
+            let params = call.args.iter().map(| arg | module.get_value_type(*arg)).collect::<Vec<_>>();
+            out.push_str(&format!("  (import \"env\" \"{}\" (func ${}{}))\n", call.callee, call.callee, signature(&params, &ret)));
+        }
+    }
+
+    let mut used = UniqueName::new();
+    let mut globals = HashMap::new();
+    let mut addresses = Vec::new();
+    let mut offset = GLOBAL_BASE;
+    for global in module.globals.iter().cloned() {
+        let data = module.get_value(global);
+        if let ValueKind::GlobalVar(inner) = &data.kind {
+            let name = used.next_unused_name(data.name.as_deref().expect("global variables are named"));
+            addresses.push((name.clone(), offset));
+            globals.insert(global, name);
+            offset = align_to(offset + size_of(&inner.elem_ty) * inner.size, 8);
+        }
+    }
+    let stack_top = align_to(offset, 16) + STACK_SIZE;
+    let stack_pointer = used.next_unused_name("__stack_pointer");
+    out.push_str(&format!("  (memory (export \"memory\") {})\n", stack_top.div_ceil(PAGE_SIZE)));
+    out.push_str(&format!("  (global ${} (mut i32) (i32.const {}))\n", stack_pointer, stack_top));
+    for (name, address) in addresses {
+        out.push_str(&format!("  (global ${} i32 (i32.const {}))\n", name, address));
+    }
+
+    for function in module.funcs.iter().cloned().map(| function | module.get_function(function)) {
+        if !function.is_external {
+            FunctionEmitter::new(module, function, &globals, &stack_pointer, &mut out).emit_function();
+        }
+    }
+    out.push_str(")\n");
+    out
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontend::new_parser::parse_module;
+
+    fn emit_lines(source: &str) -> Vec<String> {
+        let module = parse_module(source).unwrap();
+        emit_module(&module).lines().map(| line | line.trim().to_string()).collect()
+    }
+
+    fn contains(lines: &[String], fragment: &[&str]) -> bool {
+        lines.windows(fragment.len()).any(| window | window == fragment)
+    }
+
+    #[test]
+    fn test_emit_module() {
+        let lines = emit_lines(r"
+            @matrix: region i32, 12
+            fn @sum(#n: i32) -> i32 {
+            %entry:
+                jmp label %loop
+            %loop:
+                let %i = phi [0, label %entry], [%i.next, label %latch]
+                let %acc = phi [0, label %entry], [%acc.next, label %latch]
+                let %addr = offset i32, @matrix, [%i < 3], [2 < 4]
+                let %x = load %addr
+                let %neg = lt %x, 0
+                br %neg, label %latch, label %add
+            %add:
+                let %y = add %acc, %x
+                jmp label %latch
+            %latch:
+                let %acc.next = phi [%acc, label %loop], [%y, label %add]
+                let %i.next = add %i, 1
+                let %c = lt %i.next, #n
+                br %c, label %loop, label %exit
+            %exit:
+                ret %acc.next
+            }
+            fn @main() -> () {
+            %entry:
+                let %a = alloca i32, 4
+                let %0 = call @getarray, %a
+                let %1 = call @sum, %0
+                let %2 = call @putint, %1
+                ret ()
+            }
+        ");
+        assert!(contains(&lines, &[
+            "(import \"env\" \"getarray\" (func $getarray (param i32) (result i32)))",
+            "(import \"env\" \"putint\" (func $putint (param i32)))",
+            "(memory (export \"memory\") 17)",
+            "(global $__stack_pointer (mut i32) (i32.const 1049648))",
+            "(global $matrix i32 (i32.const 1024))",
+        ]));
+        // the merge block `%latch` follows a `block` inside the loop, which `%exit` returns from.
+        assert!(contains(&lines, &["loop $loop.loop", "block $latch", "local.get $i.in", "local.set $i"]));
+        assert!(contains(&lines, &["global.get $matrix", "local.get $i", "i32.const 16", "i32.mul", "i32.add", "i32.const 8", "i32.add"]));
+        assert!(contains(&lines, &["if", "local.get $acc", "local.set $acc.next.in", "br $latch", "else"]));
+        assert!(contains(&lines, &["end", "end", "local.get $acc.next.in", "local.set $acc.next"]));
+        assert!(contains(&lines, &["br $loop.loop", "else", "local.get $acc.next", "return", "end", "end", "unreachable", ")"]));
+        // the frame of allocas is reserved on the shadow stack and released before returning.
+        assert!(contains(&lines, &["global.get $__stack_pointer", "i32.const 16", "i32.sub", "local.tee $fp", "global.set $__stack_pointer"]));
+        assert!(contains(&lines, &["local.get $fp", "i32.const 16", "i32.add", "global.set $__stack_pointer", "return"]));
+    }
+
+    #[test]
+    fn test_irreducible_dispatch() {
+        let lines = emit_lines(r"
+            fn @main(#n: i32) -> i32 {
+            %entry:
+                br #n, label %a, label %b
+            %a:
+                let %x = phi [#n, label %entry], [%y, label %b]
+                br %x, label %b, label %exit
+            %b:
+                let %y = phi [#n, label %entry], [%x, label %a]
+                br %y, label %a, label %exit
+            %exit:
+                ret 0
+            }
+        ");
+        assert!(contains(&lines, &["loop $dispatch", "block $exit", "block $b", "block $a", "block $entry"]));
+        assert!(contains(&lines, &["local.get $label", "br_table $entry $a $b $exit", "end"]));
+        assert!(contains(&lines, &["local.get $y", "local.set $x.in", "i32.const 1", "local.set $label", "br $dispatch"]));
+    }
+}
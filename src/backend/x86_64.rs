@@ -0,0 +1,396 @@
+//! x86-64 assembly generation in AT&T syntax for the System V ABI.
+//!
+//! Instruction selection is a macro expansion of each instruction, as in `backend::riscv`:
+//! every argument and instruction result lives in an 8 byte stack slot addressed from the frame pointer `%rbp`,
+//! operands are loaded into `%rax` and `%rcx` before each instruction and results stored back from `%rax`.
+//!
+//! The frame of a function, below the saved frame pointer, holds value slots, a staging slot for each phi,
+//! alloca regions and the outgoing stack arguments of calls at the bottom,
+//! keeping the stack pointer 16 byte aligned at calls.
+//!
+//! The SysY runtime functions are expected from `src/backend/sysy_runtime.c`, compiled by the host `gcc`.
+
+use std::collections::HashMap;
+
+use crate::ir::structures::*;
+use crate::ir::types::Type;
+use crate::ir::values::BinaryOp;
+
+use super::riscv::size_of;
+
+/// A general purpose register, by its 64 bit and 32 bit names.
+#[derive(Debug, Clone, Copy)]
+struct Register(&'static str, &'static str);
+
+const RAX: Register = Register("%rax", "%eax");
+const RCX: Register = Register("%rcx", "%ecx");
+
+/// Registers of the first integer arguments, the following are passed on the stack.
+const ARG_REGISTERS: [Register; 6] = [
+    Register("%rdi", "%edi"),
+    Register("%rsi", "%esi"),
+    Register("%rdx", "%edx"),
+    Register("%rcx", "%ecx"),
+    Register("%r8", "%r8d"),
+    Register("%r9", "%r9d"),
+];
+
+impl Register {
+    /// Name of the register holding a value of the type.
+    fn sized(self, ty: &Type) -> &'static str {
+        if size_of(ty) == 8 { self.0 } else { self.1 }
+    }
+}
+
+fn mov_instr(ty: &Type) -> &'static str {
+    if size_of(ty) == 8 { "movq" } else { "movl" }
+}
+
+/// Stack layout of a function, offsets are relative to the frame pointer `%rbp`.
+struct Frame {
+    slots: HashMap<ValueRef, i64>,
+    staging: HashMap<ValueRef, i64>,
+    allocas: HashMap<ValueRef, i64>,
+    /// Frame size below the saved frame pointer.
+    size: i64,
+}
+
+impl Frame {
+    fn new(module: &Module, function: &Function) -> Frame {
+        let mut offset = 0;
+        let mut slot = | bytes: usize | {
+            offset += (bytes as i64 + 7) / 8 * 8;
+            -offset
+        };
+        let (mut slots, mut staging, mut allocas) = (HashMap::new(), HashMap::new(), HashMap::new());
+        let mut outgoing = 0;
+        for arg in function.args.iter().cloned() {
+            slots.insert(arg, slot(8));
+        }
+        for bb in function.blocks.iter().cloned() {
+            for instr in function.get_basic_block(bb).instrs.iter().cloned() {
+                match &module.get_value(instr).kind {
+                    ValueKind::Alloca(inner) => {
+                        allocas.insert(instr, slot(size_of(&inner.elem_type) * inner.num_elements));
+                    },
+                    ValueKind::Phi(_) => {
+                        slots.insert(instr, slot(8));
+                        staging.insert(instr, slot(8));
+                    },
+                    ValueKind::FnCall(inner) => {
+                        outgoing = outgoing.max(inner.args.len().saturating_sub(ARG_REGISTERS.len()) as i64 * 8);
+                        slots.insert(instr, slot(8));
+                    },
+                    _ => {
+                        slots.insert(instr, slot(8));
+                    }
+                }
+            }
+        }
+        let size = (offset + outgoing + 15) / 16 * 16;
+        Frame { slots, staging, allocas, size }
+    }
+}
+
+struct FunctionEmitter<'a> {
+    module: &'a Module,
+    function: &'a Function,
+    frame: Frame,
+    out: &'a mut String,
+}
+
+impl<'a> FunctionEmitter<'a> {
+    fn emit(&mut self, line: &str) {
+        self.out.push_str("  ");
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    fn label(&self, bb: BlockRef) -> String {
+        let index = self.function.blocks.iter().position(| other | *other == bb).unwrap();
+        format!(".L{}_{}", self.function.name, index)
+    }
+
+    /// Materialize the operand `value` in `reg`.
+    fn load_operand(&mut self, reg: Register, value: ValueRef) {
+        let data = self.module.get_value(value);
+        match &data.kind {
+            ValueKind::ConstantInt(inner) => self.emit(&format!("movl ${}, {}", inner.value, reg.1)),
+            ValueKind::ConstantBool(inner) => self.emit(&format!("movl ${}, {}", inner.value as i32, reg.1)),
+            ValueKind::ConstantNullPtr(_) | ValueKind::ConstantUnit(_) => self.emit(&format!("xorl {}, {}", reg.1, reg.1)),
+            ValueKind::GlobalVar(_) => {
+                let name = data.name.clone().expect("global variables are named");
+                self.emit(&format!("leaq {}(%rip), {}", name, reg.0));
+            },
+            ValueKind::Alloca(_) => {
+                let offset = self.frame.allocas[&value];
+                self.emit(&format!("leaq {}(%rbp), {}", offset, reg.0));
+            },
+            _ => {
+                let offset = self.frame.slots[&value];
+                self.emit(&format!("{} {}(%rbp), {}", mov_instr(&data.ty), offset, reg.sized(&data.ty)));
+            }
+        }
+    }
+
+    fn store_result(&mut self, reg: Register, value: ValueRef) {
+        let ty = self.module.get_value_type(value);
+        if !ty.is_unit_type() {
+            let offset = self.frame.slots[&value];
+            self.emit(&format!("{} {}, {}(%rbp)", mov_instr(&ty), reg.sized(&ty), offset));
+        }
+    }
+
+    fn emit_prologue(&mut self) {
+        self.emit("pushq %rbp");
+        self.emit("movq %rsp, %rbp");
+        if self.frame.size > 0 {
+            let size = self.frame.size;
+            self.emit(&format!("subq ${}, %rsp", size));
+        }
+        for (index, arg) in self.function.args.iter().cloned().enumerate() {
+            let offset = self.frame.slots[&arg];
+            match ARG_REGISTERS.get(index) {
+                Some(reg) => self.emit(&format!("movq {}, {}(%rbp)", reg.0, offset)),
+                None => {
+                    // stack arguments are above the return address and the saved frame pointer.
+                    self.emit(&format!("movq {}(%rbp), %rax", 16 + (index - ARG_REGISTERS.len()) * 8));
+                    self.emit(&format!("movq %rax, {}(%rbp)", offset));
+                }
+            }
+        }
+    }
+
+    fn emit_binary(&mut self, op: &BinaryOp) {
+        let lines: &[&str] = match op {
+            BinaryOp::Add => &["addl %ecx, %eax"],
+            BinaryOp::Sub => &["subl %ecx, %eax"],
+            BinaryOp::Mul => &["imull %ecx, %eax"],
+            BinaryOp::Div => &["cltd", "idivl %ecx"],
+            BinaryOp::Rem => &["cltd", "idivl %ecx", "movl %edx, %eax"],
+            BinaryOp::And => &["andl %ecx, %eax"],
+            BinaryOp::Or => &["orl %ecx, %eax"],
+            BinaryOp::Xor => &["xorl %ecx, %eax"],
+            BinaryOp::Lt => &["cmpl %ecx, %eax", "setl %al", "movzbl %al, %eax"],
+            BinaryOp::Gt => &["cmpl %ecx, %eax", "setg %al", "movzbl %al, %eax"],
+            BinaryOp::Le => &["cmpl %ecx, %eax", "setle %al", "movzbl %al, %eax"],
+            BinaryOp::Ge => &["cmpl %ecx, %eax", "setge %al", "movzbl %al, %eax"],
+            BinaryOp::Eq => &["cmpl %ecx, %eax", "sete %al", "movzbl %al, %eax"],
+            BinaryOp::Ne => &["cmpl %ecx, %eax", "setne %al", "movzbl %al, %eax"],
+        };
+        for line in lines {
+            self.emit(line);
+        }
+    }
+
+    fn emit_instruction(&mut self, instr: ValueRef) {
+        let module = self.module;
+        match &module.get_value(instr).kind {
+            ValueKind::Binary(inner) => {
+                self.load_operand(RAX, inner.lhs);
+                self.load_operand(RCX, inner.rhs);
+                self.emit_binary(&inner.op);
+                self.store_result(RAX, instr);
+            },
+            ValueKind::Offset(inner) => {
+                // byte offset of each index is the product of the following bounds and the element size.
+                self.load_operand(RAX, inner.base_addr);
+                let mut stride = size_of(&inner.elem_type) as i64;
+                let mut constant_offset = 0;
+                for (position, index) in inner.index.iter().cloned().enumerate().rev() {
+                    match &module.get_value(index).kind {
+                        ValueKind::ConstantInt(constant) => constant_offset += constant.value as i64 * stride,
+                        _ => {
+                            self.load_operand(RCX, index);
+                            self.emit("movslq %ecx, %rcx");
+                            self.emit(&format!("imulq ${}, %rcx, %rcx", stride));
+                            self.emit("addq %rcx, %rax");
+                        }
+                    }
+                    if position > 0 {
+                        stride *= inner.bounds[position].expect("only the first dimension of an offset may be unbounded") as i64;
+                    }
+                }
+                if constant_offset != 0 {
+                    self.emit(&format!("addq ${}, %rax", constant_offset));
+                }
+                self.store_result(RAX, instr);
+            },
+            ValueKind::FnCall(inner) => {
+                for (index, arg) in inner.args.iter().cloned().enumerate().skip(ARG_REGISTERS.len()) {
+                    self.load_operand(RAX, arg);
+                    self.emit(&format!("movq %rax, {}(%rsp)", (index - ARG_REGISTERS.len()) * 8));
+                }
+                for (index, arg) in inner.args.iter().cloned().enumerate().take(ARG_REGISTERS.len()) {
+                    self.load_operand(ARG_REGISTERS[index], arg);
+                }
+                self.emit(&format!("call {}", inner.callee));
+                self.store_result(RAX, instr);
+            },
+            ValueKind::Load(inner) => {
+                self.load_operand(RAX, inner.addr);
+                let ty = module.get_value_type(instr);
+                self.emit(&format!("{} (%rax), {}", mov_instr(&ty), RAX.sized(&ty)));
+                self.store_result(RAX, instr);
+            },
+            ValueKind::Store(inner) => {
+                let ty = module.get_value_type(inner.value);
+                if !ty.is_unit_type() {
+                    self.load_operand(RAX, inner.value);
+                    self.load_operand(RCX, inner.addr);
+                    self.emit(&format!("{} {}, (%rcx)", mov_instr(&ty), RAX.sized(&ty)));
+                }
+            },
+            // allocas are addressed from the frame pointer, phis are copied on edges.
+            ValueKind::Alloca(_) | ValueKind::Phi(_) => (),
+            _ => panic!("unexpected value in instruction position")
+        }
+    }
+
+    /// Copy the values flowing along the edge `from -> to` into the staging slots of the phis of `to`.
+    fn emit_phi_copies(&mut self, from: BlockRef, to: BlockRef) {
+        for phi in self.function.get_basic_block(to).instrs.iter().cloned() {
+            let incoming = match &self.module.get_value(phi).kind {
+                ValueKind::Phi(inner) => inner.incoming_value(from),
+                _ => break
+            };
+            let value = incoming.expect("phi has no incoming value for a predecessor");
+            let ty = self.module.get_value_type(phi);
+            self.load_operand(RAX, value);
+            let offset = self.frame.staging[&phi];
+            self.emit(&format!("{} {}, {}(%rbp)", mov_instr(&ty), RAX.sized(&ty), offset));
+        }
+    }
+
+    fn emit_terminator(&mut self, bb: BlockRef) {
+        match &self.function.get_basic_block(bb).terminator {
+            Terminator::Jump(inner) => {
+                self.emit_phi_copies(bb, inner.dest);
+                let dest = self.label(inner.dest);
+                self.emit(&format!("jmp {}", dest));
+            },
+            Terminator::Branch(inner) => {
+                let false_edge = format!("{}_false", self.label(bb));
+                self.load_operand(RAX, inner.cond);
+                self.emit("testl %eax, %eax");
+                self.emit(&format!("je {}", false_edge));
+                self.emit_phi_copies(bb, inner.true_label);
+                let true_label = self.label(inner.true_label);
+                self.emit(&format!("jmp {}", true_label));
+                self.out.push_str(&format!("{}:\n", false_edge));
+                self.emit_phi_copies(bb, inner.false_label);
+                let false_label = self.label(inner.false_label);
+                self.emit(&format!("jmp {}", false_label));
+            },
+            Terminator::Return(inner) => {
+                if !self.module.get_value_type(inner.value).is_unit_type() {
+                    self.load_operand(RAX, inner.value);
+                } else if self.function.name == "main" {
+                    // the exit status of a `main` returning unit.
+                    self.emit("xorl %eax, %eax");
+                }
+                self.emit("leave");
+                self.emit("ret");
+            },
+            Terminator::Panic => self.emit("ud2"),
+        }
+    }
+
+    fn emit_function(&mut self) {
+        let name = self.function.name.clone();
+        self.out.push_str(&format!("  .globl {}\n  .p2align 4\n  .type {}, @function\n{}:\n", name, name, name));
+        self.emit_prologue();
+        for bb in self.function.blocks.iter().cloned() {
+            let block = self.function.get_basic_block(bb);
+            let comment = block.name.as_ref().map_or(String::new(), | name | format!("  # %{}", name));
+            self.out.push_str(&format!("{}:{}\n", self.label(bb), comment));
+            for phi in block.instrs.iter().cloned().take_while(| instr | self.module.get_value(*instr).is_phi()) {
+                let ty = self.module.get_value_type(phi);
+                let (staging, slot) = (self.frame.staging[&phi], self.frame.slots[&phi]);
+                self.emit(&format!("{} {}(%rbp), {}", mov_instr(&ty), staging, RAX.sized(&ty)));
+                self.emit(&format!("{} {}, {}(%rbp)", mov_instr(&ty), RAX.sized(&ty), slot));
+            }
+            for instr in block.instrs.iter().cloned() {
+                self.emit_instruction(instr);
+            }
+            self.emit_terminator(bb);
+        }
+        self.out.push_str(&format!("  .size {}, .-{}\n\n", name, name));
+    }
+}
+
+/// Lower a verified module to x86-64 assembly for the System V ABI.
+///
+/// Function declarations are expected to be provided by the SysY runtime or other objects,
+/// global regions are zero initialized in `.bss`.
+pub fn emit_module(module: &Module) -> String {
+    let mut out = String::new();
+    let globals = module.globals
+        .iter()
+        .filter_map(| global | match &module.get_value(*global).kind {
+            ValueKind::GlobalVar(inner) => Some((module.get_value(*global).name.clone().expect("global variables are named"), inner)),
+            _ => None
+        })
+        .collect::<Vec<_>>();
+    if !globals.is_empty() {
+        out.push_str("  .bss\n");
+        for (name, global) in globals {
+            let size = (size_of(&global.elem_ty) * global.size).max(1);
+            out.push_str(&format!("  .globl {}\n  .p2align 3\n  .type {}, @object\n  .size {}, {}\n{}:\n  .zero {}\n\n", name, name, name, size, name, size));
+        }
+    }
+    out.push_str("  .text\n");
+    for function in module.funcs.iter().cloned() {
+        let function = module.get_function(function);
+        if function.is_external {
+            continue;
+        }
+        let mut emitter = FunctionEmitter { module, function, frame: Frame::new(module, function), out: &mut out };
+        emitter.emit_function();
+    }
+    // no executable stack is needed.
+    out.push_str("  .section .note.GNU-stack,\"\",@progbits\n");
+    out
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontend::new_parser::parse_module;
+
+    #[test]
+    fn test_emit_module() {
+        let module = parse_module(r"
+            @matrix: region i32, 12
+            fn @sum(#a: i32, #b: i32, #c: i32, #d: i32, #e: i32, #f: i32, #g: i32, #h: i32) -> i32 {
+            %entry:
+                let %0 = rem #a, #h
+                ret %0
+            }
+            fn @main() -> () {
+            %entry:
+                let %i = call @getint
+                let %addr = offset i32, @matrix, [%i < 3], [2 < 4]
+                let %0 = store 7, %addr
+                let %1 = call @sum, 1, 2, 3, 4, 5, 6, 7, 8
+                let %2 = lt %1, 2
+                ret ()
+            }
+        ").unwrap();
+        let asm = emit_module(&module);
+        let lines = asm.lines().map(str::trim).collect::<Vec<_>>();
+        let contains = | fragment: &[&str] | lines.windows(fragment.len()).any(| window | window == fragment);
+
+        assert!(contains(&[".bss", ".globl matrix"]));
+        assert!(contains(&[".size matrix, 48", "matrix:", ".zero 48"]));
+        // the seventh and eighth arguments are passed on the stack, read above the saved frame pointer by the callee.
+        assert!(contains(&["movq 16(%rbp), %rax", "movq %rax, -56(%rbp)", "movq 24(%rbp), %rax", "movq %rax, -64(%rbp)"]));
+        assert!(contains(&["movl -8(%rbp), %eax", "movl -64(%rbp), %ecx", "cltd", "idivl %ecx", "movl %edx, %eax", "movl %eax, -72(%rbp)"]));
+        // the row stride is 4 elements of 4 bytes, the constant column is folded into one addition.
+        assert!(contains(&["leaq matrix(%rip), %rax", "movl -8(%rbp), %ecx", "movslq %ecx, %rcx", "imulq $16, %rcx, %rcx", "addq %rcx, %rax", "addq $8, %rax"]));
+        assert!(contains(&["movl $7, %eax", "movq %rax, 0(%rsp)", "movl $8, %eax", "movq %rax, 8(%rsp)", "movl $1, %edi"]));
+        assert!(contains(&["cmpl %ecx, %eax", "setl %al", "movzbl %al, %eax"]));
+        assert!(contains(&["xorl %eax, %eax", "leave", "ret"]));
+    }
+}
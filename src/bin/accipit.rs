@@ -1,76 +1,265 @@
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::str::FromStr;
 use nom::*;
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use ariadne::{Color, Label, Report, ReportKind, Source};
 
 use accipit::{
     frontend::{
         token::Tokens,
         lexer,
         parser,
+        new_parser,
+        sysy,
+        SourceFile,
     },
-    ir::builders::IRBuilder,
+    ir::{builders::IRBuilder, structures::Module, verify::verify_module},
+    ir::transforms::pass_manager::PassManager,
+    ir::analysis::call_graph::CallGraph,
     apps::executor::*,
+    apps::debugger::Debugger,
+    apps::rvsim::{self, Machine},
+    backend::{c, llvm, riscv, regalloc::{self, AllocationStrategy}, wasm, x86_64},
 };
 
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Frontend {
+    /// Chumsky based parser, reports as many errors as possible
+    Chumsky,
+    /// Nom based parser, stops at the first error
+    Nom,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Emit {
+    /// RV64IM assembly for the LP64 ABI
+    Riscv,
+    /// C99 source, linked with the SysY runtime in `src/backend/sysy_runtime.c`
+    C,
+    /// LLVM textual IR with opaque pointers
+    Llvm,
+    /// WebAssembly text format, importing the SysY runtime from `env`
+    Wasm,
+    /// x86-64 assembly in AT&T syntax for the System V ABI, linked with the SysY runtime in `src/backend/sysy_runtime.c`
+    X86_64,
+}
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 #[command(name = "accipit")]
 #[command(bin_name = "accipit")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Specify the input file
-    #[clap(value_parser=clap::value_parser!(PathBuf))]
-    file: PathBuf,
+    #[clap(value_parser=clap::value_parser!(PathBuf), required = true)]
+    file: Option<PathBuf>,
 
     /// Specify the argument passes to the entry function
     #[clap(value_parser=clap::value_parser!(String), allow_hyphen_values(true))]
     args: Vec<String>,
 
-    /// Specify the output file (unused option)
+    /// Compile to the target selected by `--emit` written to the output file instead of running the program
     #[clap(short, long, value_parser=clap::value_parser!(PathBuf))]
     output: Option<PathBuf>,
 
+    /// Compile to the target instead of running the program, written to the output file or printed to stdout,
+    /// RISC-V if only `--output` is given
+    #[clap(long, value_enum)]
+    emit: Option<Emit>,
+
     /// Dump parsed module, producing explicit type annotation and different symbol prefix
     #[clap(long)]
     dump_module: bool,
 
+    /// Skip the static verification of the parsed module
+    #[clap(long)]
+    no_verify: bool,
+
+    /// Run the program in the interactive step debugger
+    #[clap(short, long)]
+    debug: bool,
+
+    /// Specify the frontend used to parse the input file
+    #[clap(long, value_enum, default_value_t = Frontend::Chumsky)]
+    frontend: Frontend,
+
+    /// Specify the certain function as the entry function
+    #[clap(short, long = "entry", default_value = "main")]
+    entry: String,
+
+    /// Assign values to K registers, annotated by `--dump-module` and simulated by the interpreter
+    #[clap(long, value_name = "K")]
+    regalloc: Option<usize>,
+
+    /// Spill every value instead of linear scan allocation with `--regalloc`
+    #[clap(long, requires = "regalloc")]
+    spill_all: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run a pipeline of passes on the input file and print the transformed module
+    Opt(OptArgs),
+    /// Report calls, recursion, undeclared callees and unreachable functions of the input file
+    Callgraph(CallGraphArgs),
+    /// Assemble and run RV64IM assembly in the built-in simulator, reading and writing standard streams
+    Rvsim(RvsimArgs),
+    /// Compile a SysY source file to IR, then run it in the interpreter or print the IR
+    Sysy(SysyArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SysyArgs {
+    /// Specify the input SysY source file
+    #[clap(value_parser=clap::value_parser!(PathBuf))]
+    file: PathBuf,
+
+    /// Print the generated IR instead of running it
+    #[clap(long)]
+    emit_ir: bool,
+
+    /// Specify the output file of `--emit-ir`, print to stdout if not given
+    #[clap(short, long, value_parser=clap::value_parser!(PathBuf), requires = "emit_ir")]
+    output: Option<PathBuf>,
+
+    /// Specify the comma separated pass pipeline run on the generated IR, such as `--passes=mem2reg,dce`
+    #[clap(long, default_value = "")]
+    passes: String,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct RvsimArgs {
+    /// Specify the input assembly file
+    #[clap(value_parser=clap::value_parser!(PathBuf))]
+    file: PathBuf,
+
     /// Specify the certain function as the entry function
     #[clap(short, long = "entry", default_value = "main")]
     entry: String,
 }
 
+#[derive(clap::Args, Debug)]
+pub struct CallGraphArgs {
+    /// Specify the input file
+    #[clap(value_parser=clap::value_parser!(PathBuf))]
+    file: PathBuf,
+
+    /// Specify the entry function used to find unreachable functions
+    #[clap(short, long = "entry", default_value = "main")]
+    entry: String,
+
+    /// Specify the frontend used to parse the input file
+    #[clap(long, value_enum, default_value_t = Frontend::Chumsky)]
+    frontend: Frontend,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct OptArgs {
+    /// Specify the input file
+    #[clap(value_parser=clap::value_parser!(PathBuf))]
+    file: PathBuf,
+
+    /// Specify the comma separated pass pipeline, such as `-passes=dce,constfold`
+    #[clap(long, default_value = "")]
+    passes: String,
+
+    /// Specify the output file, print to stdout if not given
+    #[clap(short, long, value_parser=clap::value_parser!(PathBuf))]
+    output: Option<PathBuf>,
+
+    /// Skip the static verification of the input and transformed module
+    #[clap(long)]
+    no_verify: bool,
+
+    /// Verify the module after each pass instead of only at the end
+    #[clap(long)]
+    verify_each: bool,
+
+    /// Specify the frontend used to parse the input file
+    #[clap(long, value_enum, default_value_t = Frontend::Chumsky)]
+    frontend: Frontend,
+}
+
 fn main() -> Result<(), ()>{
-    let args = Args::parse();
-    let input = args.file;
-    let src = std::fs::read_to_string(input)
+    // accept the single dash `-passes=` spelling of pass pipelines.
+    let args = Args::parse_from(std::env::args().map(| arg | match arg.strip_prefix("-passes=") {
+        Some(pipeline) => format!("--passes={}", pipeline),
+        None => arg
+    }));
+    match args.command {
+        Some(Command::Opt(opt_args)) => return run_opt(opt_args),
+        Some(Command::Callgraph(call_graph_args)) => return run_call_graph(call_graph_args),
+        Some(Command::Rvsim(rvsim_args)) => return run_rvsim(rvsim_args),
+        Some(Command::Sysy(sysy_args)) => return run_sysy(sysy_args),
+        None => ()
+    }
+    let input = args.file.expect("input file is required");
+    let filename = input.display().to_string();
+    let src = std::fs::read_to_string(&input)
         .expect("failed to read input file");
 
-    // let (tokens, lex_errs) = lexer().parse(src.as_str()).into_output_errors();
-    // println!("{:?}", tokens);
-    let (_, tokens) = lexer::Lexer::lex(&src)
-        .finish()
-        .inspect_err(| lex_err | {
-            println!("Unrecognized token:\n{}", nom::error::convert_error(src.as_str(), lex_err.clone())) 
-        })
-        .map_err(| _ | () )?;
-    // println!("{:?}", tokens);
-    let token_wrapper = Tokens::new(&tokens);
-    let builder = Rc::new(RefCell::new(IRBuilder::new()));
-    let (_, module) = parser::Parser::parse_from_complete_input(token_wrapper, builder)
-        .finish()
-        .inspect_err(| parser_err | {
-            println!("Parser Error:\n{:?}", parser_err) 
-        })
-        .map_err(| _ | () )?;
+    let mut module = match args.frontend {
+        Frontend::Chumsky => parse_with_chumsky(&filename, &src)?,
+        Frontend::Nom => parse_with_nom(&filename, &src)?,
+    };
+    // keep the source text for runtime error reporting.
+    module.source = Some(Rc::new(SourceFile::new(filename.clone(), src.clone())));
+
+    let allocations = args.regalloc.map(| num_registers | {
+        let strategy = if args.spill_all { AllocationStrategy::SpillEverything } else { AllocationStrategy::LinearScan };
+        regalloc::allocate_module(&module, num_registers, strategy)
+    });
 
     // dump module
     if args.dump_module {
-        println!("Module:\n{}", module);
+        match &allocations {
+            Some(allocations) => {
+                let locations = allocations
+                    .values()
+                    .flat_map(| allocation | allocation.locations().iter())
+                    .collect::<HashMap<_, _>>();
+                println!("Module:\n{}", module.annotated(| value | locations.get(&value).map(ToString::to_string)));
+            },
+            None => println!("Module:\n{}", module),
+        }
+    }
+
+    if !args.no_verify {
+        verify_module(&module)
+            .inspect_err( | errors | {
+                errors.iter().for_each(| err | println!("{}", err));
+            })
+            .map_err(| _ | ())?;
+    }
+
+    if args.emit.is_some() || args.output.is_some() {
+        let emitted = match args.emit.unwrap_or(Emit::Riscv) {
+            Emit::Riscv => riscv::emit_module(&module),
+            Emit::C => c::emit_module(&module),
+            Emit::Llvm => llvm::emit_module(&module),
+            Emit::Wasm => wasm::emit_module(&module),
+            Emit::X86_64 => x86_64::emit_module(&module),
+        };
+        match args.output {
+            Some(output) => std::fs::write(output, emitted)
+                .expect("failed to write output file"),
+            None => print!("{}", emitted),
+        }
+        return Ok(());
     }
 
     let mut prog_env = ProgramEnv::new();
+    prog_env.allocations = allocations
+        .iter()
+        .flatten()
+        .map(| (function, allocation) | (*function, Rc::new(allocation.clone())))
+        .collect();
     let entry_fn = args.entry;
     let input_args: Vec<Val> = args.args
         .iter()
@@ -82,12 +271,191 @@ fn main() -> Result<(), ()>{
             println!("{}", err)
         })
         .map_err( | _ | ())?;
+    if args.debug {
+        let mut debugger = Debugger::new(&module, &entry_fn, input_args)
+            .inspect_err( | err | {
+                println!("{}", err);
+            })
+            .map_err( | _ | ())?;
+        let stdin = std::io::stdin();
+        return debugger.run_repl(stdin.lock(), std::io::stdout())
+            .map_err( | _ | ());
+    }
     let interpreted = run_on_module(&mut prog_env, &module, &entry_fn, input_args)
         .inspect_err( | interpreted_err | {
             println!("{}", interpreted_err);
         })
         .map_err(| _ | ())?;
     eprintln!("\n\ninterpreter: {}", interpreted);
+    if let Some(allocations) = &allocations {
+        let num_values: usize = allocations.values().map(| allocation | allocation.intervals.len()).sum();
+        let num_spilled: usize = allocations.values().map(| allocation | allocation.num_spill_slots).sum();
+        eprintln!("register allocation: {} of {} values spilled, every read found its value in place",
+                  num_spilled, num_values);
+    }
+    Ok(())
+
+}
+
+fn run_opt(args: OptArgs) -> Result<(), ()> {
+    let mut pass_manager = PassManager::parse(&args.passes)
+        .inspect_err(| err | eprintln!("{}", err))
+        .map_err(| _ | ())?;
+    pass_manager.verify_each = args.verify_each;
+
+    let filename = args.file.display().to_string();
+    let src = std::fs::read_to_string(&args.file)
+        .expect("failed to read input file");
+    let mut module = match args.frontend {
+        Frontend::Chumsky => parse_with_chumsky(&filename, &src)?,
+        Frontend::Nom => parse_with_nom(&filename, &src)?,
+    };
+
+    let verify = | module: &Module | if args.no_verify {
+        Ok(())
+    } else {
+        verify_module(module)
+            .inspect_err( | errors | {
+                errors.iter().for_each(| err | eprintln!("{}", err));
+            })
+            .map_err(| _ | ())
+    };
+    verify(&module)?;
+    pass_manager.run(&mut module)
+        .inspect_err(| err | eprintln!("{}", err))
+        .map_err(| _ | ())?;
+    verify(&module)?;
+
+    match args.output {
+        Some(output) => std::fs::write(output, module.to_string())
+            .expect("failed to write output file"),
+        None => print!("{}", module),
+    }
     Ok(())
+}
 
+fn run_call_graph(args: CallGraphArgs) -> Result<(), ()> {
+    let filename = args.file.display().to_string();
+    let src = std::fs::read_to_string(&args.file)
+        .expect("failed to read input file");
+    let module = match args.frontend {
+        Frontend::Chumsky => parse_with_chumsky(&filename, &src)?,
+        Frontend::Nom => parse_with_nom(&filename, &src)?,
+    };
+    let call_graph = CallGraph::new(&module);
+    print!("{}", call_graph.report(&module, Some(&args.entry)));
+    if call_graph.undeclared_calls().is_empty() {
+        Ok(())
+    } else {
+        Err(())
+    }
+}
+
+fn run_rvsim(args: RvsimArgs) -> Result<(), ()> {
+    let src = std::fs::read_to_string(&args.file)
+        .expect("failed to read input file");
+    let program = rvsim::assemble(&src)
+        .inspect_err(| err | eprintln!("{}", err))
+        .map_err(| _ | ())?;
+    let mut machine = Machine::new(&program);
+    let stdout = std::io::stdout();
+    let result = machine.run(&args.entry, &mut std::io::stdin().lock(), &mut stdout.lock());
+    use std::io::Write;
+    stdout.lock().flush().expect("unable to flush output stream");
+    let returned = result
+        .inspect_err(| err | eprintln!("\n{}", err))
+        .map_err(| _ | ())?;
+    eprintln!("\n\nrvsim: {}, {} instructions executed", returned, machine.steps);
+    Ok(())
+}
+
+fn run_sysy(args: SysyArgs) -> Result<(), ()> {
+    let mut pass_manager = PassManager::parse(&args.passes)
+        .inspect_err(| err | eprintln!("{}", err))
+        .map_err(| _ | ())?;
+
+    let filename = args.file.display().to_string();
+    let src = std::fs::read_to_string(&args.file)
+        .expect("failed to read input file");
+    let mut module = sysy::compile(&src)
+        .inspect_err(| errors | {
+            errors.iter().for_each(| error | {
+                error.report(&filename)
+                    .print((filename.to_string(), Source::from(&src)))
+                    .expect("failed to print compilation error");
+            });
+        })
+        .map_err(| _ | ())?;
+    pass_manager.run(&mut module)
+        .inspect_err(| err | eprintln!("{}", err))
+        .map_err(| _ | ())?;
+    // the generated module is always well-formed, verification only catches bugs of the compiler.
+    verify_module(&module)
+        .inspect_err( | errors | {
+            errors.iter().for_each(| err | eprintln!("{}", err));
+        })
+        .map_err(| _ | ())?;
+
+    if args.emit_ir {
+        match args.output {
+            Some(output) => std::fs::write(output, module.to_string())
+                .expect("failed to write output file"),
+            None => print!("{}", module),
+        }
+        return Ok(());
+    }
+
+    // runtime errors are located in the SysY source.
+    module.source = Some(Rc::new(SourceFile::new(filename, src)));
+    let interpreted = run_on_module(&mut ProgramEnv::new(), &module, "main", Vec::new())
+        .inspect_err( | interpreted_err | {
+            println!("{}", interpreted_err);
+        })
+        .map_err(| _ | ())?;
+    eprintln!("\n\ninterpreter: {}", interpreted);
+    Ok(())
+}
+
+fn parse_with_chumsky(filename: &str, src: &str) -> Result<Module, ()> {
+    new_parser::parse_module(src)
+        .inspect_err(| errors | {
+            errors.iter().for_each(| error | {
+                new_parser::report(filename, error)
+                    .print((filename.to_string(), Source::from(src)))
+                    .expect("failed to print parsing error");
+            });
+        })
+        .map_err(| _ | ())
+}
+
+fn parse_with_nom(filename: &str, src: &str) -> Result<Module, ()> {
+    let (_, tokens) = lexer::Lexer::lex(src)
+        .finish()
+        .inspect_err(| lex_err | {
+            let offset = lexer::Lexer::error_offset(src, lex_err);
+            let found = src[offset..].chars().next()
+                .map_or("end of input".to_string(), | c | format!("`{}`", c));
+            Report::build(ReportKind::Error, filename.to_string(), offset)
+                .with_message("lexing error")
+                .with_label(Label::new((filename.to_string(), offset..offset + 1))
+                    .with_message(format!("unrecognized token starting with {}", found))
+                    .with_color(Color::Red))
+                .finish()
+                .print((filename.to_string(), Source::from(src)))
+                .expect("failed to print lexing error");
+        })
+        .map_err(| _ | () )?;
+    // println!("{:?}", tokens);
+    let token_wrapper = Tokens::new(&tokens);
+    let builder = Rc::new(RefCell::new(IRBuilder::new()));
+    let (_, module) = parser::Parser::parse_from_complete_input(token_wrapper, builder)
+        .finish()
+        .inspect_err(| parser_err | {
+            parser_err
+                .report(filename, src)
+                .print((filename.to_string(), Source::from(src)))
+                .expect("failed to print parsing error");
+        })
+        .map_err(| _ | () )?;
+    Ok(module)
 }
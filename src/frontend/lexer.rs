@@ -15,6 +15,7 @@ use nom::{
 
 
 use super::token::Token;
+use super::{Span, Spanned};
 
 pub type IResult<I, O, E=nom::error::VerboseError<I>> = Result<(I, O), nom::Err<E>>;
 
@@ -134,7 +135,8 @@ fn lex_memory_operator(input: &str) -> IResult<&str, Token> {
         alt((
             value(Token::TkAlloca, tag("alloca")),
             value(Token::TkLoad,   tag("load")),
-            value(Token::TkStore,  tag("store"))
+            value(Token::TkStore,  tag("store")),
+            value(Token::TkPhi,    tag("phi"))
         ))
     )(input)
 }
@@ -185,6 +187,33 @@ fn lex_primitive_type(input: &str) -> IResult<&str, Token> {
     )))(input)
 }
 
+fn lex_token(input: &str) -> IResult<&str, Token<'_>> {
+    alt((
+        // `let` `le` has name collision.
+        lex_keyword,
+        lex_literal,
+        lex_identifier,
+        lex_primitive_type,
+        lex_delimiter,
+        lex_binary_operator,
+        lex_offset_operator,
+        lex_memory_operator,
+        lex_function_cal_operator,
+        lex_terminator_operator,
+    ))(input)
+}
+
+/// Attach the byte span within `source` to the lexed token.
+fn lex_spanned_token<'a>(source: &'a str) -> impl Fn(&'a str) -> IResult<&'a str, Spanned<Token<'a>>> {
+    move | input: &'a str | {
+        let (input, _) = filter_whitespace_and_comment(input)?;
+        let start = source.len() - input.len();
+        let (input, token) = lex_token(input)?;
+        let end = source.len() - input.len();
+        Ok((input, Spanned::new(token, Span::from(start..end))))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Lexer;
 
@@ -193,22 +222,18 @@ impl Lexer {
         Lexer {}
     }
 
-    pub fn lex(input: &str) -> IResult<&str, Vec<Token>> {
+    pub fn lex(input: &str) -> IResult<&str, Vec<Spanned<Token<'_>>>> {
         all_consuming(
-            many1(terminated(alt((
-                // `let` `le` has name collision.
-                lex_keyword,
-                lex_literal,
-                lex_identifier,
-                lex_primitive_type,
-                lex_delimiter,
-                lex_binary_operator,
-                lex_offset_operator,
-                lex_memory_operator,
-                lex_function_cal_operator,
-                lex_terminator_operator,
-            )),
-            filter_whitespace_and_comment,
+            many1(terminated(
+                lex_spanned_token(input),
+                filter_whitespace_and_comment,
         )))(input)
     }
+
+    /// Byte offset where the lexer got stuck.
+    pub fn error_offset(input: &str, error: &nom::error::VerboseError<&str>) -> usize {
+        error.errors
+            .first()
+            .map_or(input.len(), | (rest, _) | input.len() - rest.len())
+    }
 }
\ No newline at end of file
@@ -7,7 +7,36 @@ use chumsky::prelude::{extra, Rich, SimpleSpan};
 
 pub type Span = SimpleSpan<usize>;
 pub type ParserError<'a, T> = extra::Err<Rich<'a, T, Span>>;
-#[derive(Debug, Clone, PartialEq)]
-pub struct Spanned<T>(T, Span);
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spanned<T>(pub T, pub Span);
 
-pub mod new_lexer;
\ No newline at end of file
+impl<T> Spanned<T> {
+    pub fn new(item: T, span: Span) -> Spanned<T> {
+        Spanned(item, span)
+    }
+
+    pub fn item(&self) -> &T {
+        &self.0
+    }
+
+    pub fn span(&self) -> Span {
+        self.1
+    }
+}
+
+/// Source text a module is parsed from, kept for diagnostics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    pub name: String,
+    pub text: String,
+}
+
+impl SourceFile {
+    pub fn new(name: String, text: String) -> SourceFile {
+        SourceFile { name, text }
+    }
+}
+
+pub mod new_lexer;
+pub mod new_parser;
+pub mod sysy;
\ No newline at end of file
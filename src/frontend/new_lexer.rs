@@ -24,7 +24,7 @@ pub fn accipit_named_ident<'a, I: ValueInput<'a> + StrInput<'a, C>, C: Char, E:
         .then(
             any()
                 // This error never appears due to `repeated` so can use `filter`
-                .filter(|c: &C| c.to_char().is_ascii_alphanumeric() || c.to_char() == '_' || c.to_char() == '.' || c.to_char() == '.')
+                .filter(|c: &C| c.to_char().is_ascii_alphanumeric() || c.to_char() == '_' || c.to_char() == '.' || c.to_char() == '-')
                 .repeated(),
         )
         .to_slice()
@@ -55,6 +55,7 @@ pub fn lexer<'a>() -> impl Parser<'a, &'a str, Vec<Spanned<Token<'a>>>, ParserEr
         keyword("alloca").to(Token::TkAlloca),
         keyword("load").to(Token::TkLoad),
         keyword("store").to(Token::TkStore),
+        keyword("phi").to(Token::TkPhi),
         keyword("call").to(Token::TkFnCall)
     ]).or(binop);
 
@@ -65,8 +66,8 @@ pub fn lexer<'a>() -> impl Parser<'a, &'a str, Vec<Spanned<Token<'a>>>, ParserEr
     ]);
 
     let opcode = instruction_opcode.or(terminator_opcode);
-    
-    let delimiter = 
+
+    let delimiter =
         just::<char, &'a str, ParserError<'a, char>>('-').then_ignore(just('>')).to(Token::Arrow)
     .or(choice([
         just::<char, &'a str, ParserError<'a, char>>('(').to(Token::LParen),
@@ -87,6 +88,7 @@ pub fn lexer<'a>() -> impl Parser<'a, &'a str, Vec<Spanned<Token<'a>>>, ParserEr
         keyword("fn").to(Token::KwFn),
         keyword("let").to(Token::KwLet),
         keyword("label").to(Token::KwLabel),
+        keyword("region").to(Token::KwRegion),
     ]);
 
     let primitive_type = choice([
@@ -95,10 +97,19 @@ pub fn lexer<'a>() -> impl Parser<'a, &'a str, Vec<Spanned<Token<'a>>>, ParserEr
         keyword("ptr").to(Token::TyPtr),
     ]);
 
-    let int_literal = int::<_, _, ParserError<'a, char>>(10)
-        .from_str::<i32>()
-        .unwrapped()
-        .map(Token::LtInt32);
+    // `-` followed by digits is a negative literal, `->` is left to `delimiter`.
+    let int_literal = just::<char, &'a str, ParserError<'a, char>>('-')
+        .or_not()
+        .then(digits(10))
+        .to_slice()
+        .validate(| lit: &str, extra, emitter | {
+            lit.parse::<i32>()
+                .map(Token::LtInt32)
+                .unwrap_or_else(| err | {
+                    emitter.emit(Rich::custom(extra.span(), format!("invalid integer literal `{}`: {}", lit, err)));
+                    Token::Unknown
+                })
+        });
 
     let literal = int_literal.or(choice([
         keyword("true").to(Token::LtInt1(true)),
@@ -113,19 +124,18 @@ pub fn lexer<'a>() -> impl Parser<'a, &'a str, Vec<Spanned<Token<'a>>>, ParserEr
             .or(digits(10).to_slice())
         )
         .map(Token::TkIdent);
-    
+
     let single_comment = just::<_, &str, ParserError<'a, char>>("//")
-        .ignore_then(none_of("\n").repeated())
-        .then_ignore(just("\n"))
+        .ignore_then(none_of("\n\r").repeated())
         .padded();
 
     let multi_comment = just::<_, &str, ParserError<'a, char>>("/*")
-        .ignore_then(none_of("*/").repeated())
+        .ignore_then(any().and_is(just("*/").not()).repeated())
         .then_ignore(just("*/"))
         .padded();
 
     let comment = single_comment.or(multi_comment);
-    
+
     // tokens
     let token = choice((
         keywords,
@@ -143,4 +153,7 @@ pub fn lexer<'a>() -> impl Parser<'a, &'a str, Vec<Spanned<Token<'a>>>, ParserEr
         .recover_with(skip_then_retry_until(any().ignored(), end()))
         .repeated()
         .collect::<Vec<Spanned<Token<'a>>>>()
-}
\ No newline at end of file
+        .then_ignore(comment.repeated())
+        .padded()
+        .then_ignore(end())
+}
@@ -0,0 +1,891 @@
+use std::ops::Range;
+
+use chumsky::prelude::*;
+use chumsky::error::{Error, RichPattern, RichReason};
+use chumsky::input::{SpannedInput, ValueInput};
+use chumsky::util::MaybeRef;
+use ariadne::{Color, Label, Report, ReportKind};
+use itertools::Itertools;
+
+use crate::ir::{
+    builders::IRBuilder, structures::*, types::Type, values
+};
+
+use super::{new_lexer::lexer, token::Token, ParserError, Span, Spanned};
+
+/// Diagnostic produced by the chumsky frontend, with tokens rendered as strings.
+pub type Diagnostic = Rich<'static, String, Span>;
+
+type TokenInput<'t, 'a> = SpannedInput<Token<'a>, Span, &'t [(Token<'a>, Span)]>;
+
+/// Operand of instructions and terminators.
+#[derive(Debug, Clone)]
+pub enum Operand<'a> {
+    Symbol(&'a str),
+    Literal(Value),
+}
+
+#[derive(Debug, Clone)]
+pub enum InstrKind<'a> {
+    Binary(values::BinaryOp, Spanned<Operand<'a>>, Spanned<Operand<'a>>),
+    Alloca(Type, usize),
+    Load(Spanned<Operand<'a>>),
+    Store(Spanned<Operand<'a>>, Spanned<Operand<'a>>),
+    Offset(Type, Spanned<Operand<'a>>, Vec<(Spanned<Operand<'a>>, Option<usize>)>),
+    FnCall(&'a str, Vec<Spanned<Operand<'a>>>),
+    Phi(Vec<(Spanned<Operand<'a>>, Spanned<&'a str>)>),
+}
+
+/// `let <name>[: <type>] = <instruction>`
+#[derive(Debug, Clone)]
+pub struct Instr<'a> {
+    pub name: Spanned<&'a str>,
+    pub annotated_type: Option<Type>,
+    pub kind: InstrKind<'a>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum Term<'a> {
+    Jump(Spanned<&'a str>),
+    Branch(Spanned<Operand<'a>>, Spanned<&'a str>, Spanned<&'a str>),
+    Return(Spanned<Operand<'a>>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Block<'a> {
+    pub label: Spanned<&'a str>,
+    pub instrs: Vec<Instr<'a>>,
+    pub terminator: Spanned<Term<'a>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Item<'a> {
+    GlobalVar {
+        name: Spanned<&'a str>,
+        elem_ty: Type,
+        region_size: usize,
+    },
+    Function {
+        name: Spanned<&'a str>,
+        params: Vec<(&'a str, Type)>,
+        ret: Type,
+        /// `None` for function declaration.
+        body: Option<Vec<Block<'a>>>,
+    },
+}
+
+/// Match a token by `select`, like `select!`,
+/// but the error is located at the unexpected token rather than after it,
+/// so labels and errors of alternatives merge properly.
+fn select_token<'a, I, O>(
+    select: impl Fn(&Token<'a>) -> Option<O> + Clone
+) -> impl Parser<'a, I, O, ParserError<'a, Token<'a>>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = Span>
+{
+    custom(move | inp | {
+        let before = inp.offset();
+        let found = inp.next();
+        match found.as_ref().and_then(&select) {
+            Some(out) => Ok(out),
+            None => Err(<Rich<_, _> as Error<I>>::expected_found([], found.map(MaybeRef::Val), inp.span_since(before)))
+        }
+    })
+}
+
+fn identifier<'a, I>() -> impl Parser<'a, I, Spanned<&'a str>, ParserError<'a, Token<'a>>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = Span>
+{
+    select_token(| token | match token {
+        Token::TkIdent(name) => Some(*name),
+        _ => None
+    })
+        .map_with(| name, extra | Spanned(name, extra.span()))
+        .labelled("identifier")
+}
+
+fn non_negative_literal<'a, I>(what: &'static str) -> impl Parser<'a, I, usize, ParserError<'a, Token<'a>>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = Span>
+{
+    select_token(| token | match token {
+        Token::LtInt32(lit) => Some(*lit),
+        _ => None
+    })
+        .try_map(move | lit, span | usize::try_from(lit)
+            .map_err(| _ | Rich::custom(span, format!("expect non-negative integer literal in {}", what))))
+        .labelled("integer literal")
+}
+
+fn parse_type<'a, I>() -> impl Parser<'a, I, Type, ParserError<'a, Token<'a>>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = Span>
+{
+    recursive(| ty | {
+        let base = choice((
+            just(Token::TyInt32).to(Type::get_i32()),
+            just(Token::TyInt1).to(Type::get_i1()),
+            just(Token::LParen).then(just(Token::RParen)).to(Type::get_unit()),
+        ));
+
+        // `i32`, `i32*`, `()**`, ...
+        let pointer = base.foldl(
+            just(Token::Asterisk).repeated(),
+            | ty, _ | Type::get_pointer(ty)
+        );
+
+        // fn(param_ty1, param_ty2, ...) -> return_ty
+        let function = just(Token::KwFn)
+            .ignore_then(ty.clone()
+                .separated_by(just(Token::Comma))
+                .collect::<Vec<_>>()
+                .delimited_by(just(Token::LParen), just(Token::RParen)))
+            .then_ignore(just(Token::Arrow))
+            .then(ty)
+            .map(| (params, ret) | Type::get_function(params, ret));
+
+        choice((
+            pointer,
+            /* opaque pointer type */
+            just(Token::TyPtr).to(Type::get_opaque_pointer()),
+            function,
+        )).labelled("type")
+    })
+}
+
+fn parse_binop<'a, I>() -> impl Parser<'a, I, values::BinaryOp, ParserError<'a, Token<'a>>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = Span>
+{
+    select_token(| token | match token {
+        Token::TkAdd => Some(values::BinaryOp::Add),
+        Token::TkSub => Some(values::BinaryOp::Sub),
+        Token::TkMul => Some(values::BinaryOp::Mul),
+        Token::TkDiv => Some(values::BinaryOp::Div),
+        Token::TkRem => Some(values::BinaryOp::Rem),
+        Token::TkAnd => Some(values::BinaryOp::And),
+        Token::TkOr  => Some(values::BinaryOp::Or),
+        Token::TkXor => Some(values::BinaryOp::Xor),
+        Token::TkLt  => Some(values::BinaryOp::Lt),
+        Token::TkGt  => Some(values::BinaryOp::Gt),
+        Token::TkLe  => Some(values::BinaryOp::Le),
+        Token::TkGe  => Some(values::BinaryOp::Ge),
+        Token::TkEq  => Some(values::BinaryOp::Eq),
+        Token::TkNe  => Some(values::BinaryOp::Ne),
+        _ => None
+    }).labelled("binary operator")
+}
+
+fn parse_operand<'a, I>() -> impl Parser<'a, I, Spanned<Operand<'a>>, ParserError<'a, Token<'a>>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = Span>
+{
+    let symbol = identifier()
+        .then_ignore(just(Token::Colon).then(parse_type()).or_not())
+        .map(| name | Operand::Symbol(name.0));
+
+    let literal = select_token(| token | match token {
+        Token::LtInt32(lit) => Some(values::ConstantInt::new_value(*lit)),
+        Token::LtInt1(lit) => Some(values::ConstantBool::new_bool_value(*lit)),
+        _ => None
+    }).or(just(Token::LParen)
+        .then(just(Token::RParen))
+        .map(| _ | values::ConstantUnit::new_value()))
+    .map(Operand::Literal);
+
+    symbol
+        .or(literal)
+        .map_with(| operand, extra | Spanned(operand, extra.span()))
+        .labelled("value")
+}
+
+fn parse_instruction<'a, I>() -> impl Parser<'a, I, Option<Instr<'a>>, ParserError<'a, Token<'a>>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = Span>
+{
+    let operand = parse_operand();
+
+    let binary = parse_binop()
+        .then(operand.clone())
+        .then_ignore(just(Token::Comma))
+        .then(operand.clone())
+        .map(| ((op, lhs), rhs) | InstrKind::Binary(op, lhs, rhs));
+
+    let alloca = just(Token::TkAlloca)
+        .ignore_then(parse_type())
+        .then_ignore(just(Token::Comma))
+        .then(non_negative_literal("alloca region size"))
+        .map(| (ty, size) | InstrKind::Alloca(ty, size));
+
+    let load = just(Token::TkLoad)
+        .ignore_then(operand.clone())
+        .map(InstrKind::Load);
+
+    let store = just(Token::TkStore)
+        .ignore_then(operand.clone())
+        .then_ignore(just(Token::Comma))
+        .then(operand.clone())
+        .map(| (stored, addr) | InstrKind::Store(stored, addr));
+
+    let bound = just(Token::LtNone)
+        .to(None)
+        .or(non_negative_literal("offset bound").map(Some));
+
+    let offset = just(Token::TkOffset)
+        .ignore_then(parse_type())
+        .then(just(Token::Comma).ignore_then(operand.clone()))
+        .then(just(Token::Comma)
+            .ignore_then(operand.clone()
+                .then_ignore(just(Token::Less))
+                .then(bound)
+                .delimited_by(just(Token::LBracket), just(Token::RBracket)))
+            .repeated()
+            .at_least(1)
+            .collect::<Vec<_>>())
+        .map(| ((ty, addr), indices) | InstrKind::Offset(ty, addr, indices));
+
+    let fncall = just(Token::TkFnCall)
+        .ignore_then(identifier().then_ignore(just(Token::Colon).then(parse_type()).or_not()))
+        .then(just(Token::Comma)
+            .ignore_then(operand.clone())
+            .repeated()
+            .collect::<Vec<_>>())
+        .map(| (callee, args) | InstrKind::FnCall(callee.0, args));
+
+    // phi [<value>, label <label>], ...
+    let phi = just(Token::TkPhi)
+        .ignore_then(operand
+            .then_ignore(just(Token::Comma))
+            .then(just(Token::KwLabel).ignore_then(identifier()))
+            .delimited_by(just(Token::LBracket), just(Token::RBracket))
+            .separated_by(just(Token::Comma))
+            .at_least(1)
+            .collect::<Vec<_>>())
+        .map(InstrKind::Phi);
+
+    // skip a malformed instruction up to the next instruction or terminator.
+    let recovery = just(Token::KwLet)
+        .then(none_of([
+            Token::KwLet, Token::TkJmp, Token::TKBranch, Token::TKRet, Token::RBrace, Token::KwFn
+        ]).repeated())
+        .to(None);
+
+    just(Token::KwLet)
+        .ignore_then(identifier())
+        .then(just(Token::Colon).ignore_then(parse_type()).or_not())
+        .then_ignore(just(Token::Equal))
+        .then(choice((binary, alloca, load, store, offset, fncall, phi)))
+        .map_with(| ((name, annotated_type), kind), extra | Some(Instr { name, annotated_type, kind, span: extra.span() }))
+        .recover_with(via_parser(recovery))
+        .labelled("instruction")
+}
+
+fn parse_terminator<'a, I>() -> impl Parser<'a, I, Option<Spanned<Term<'a>>>, ParserError<'a, Token<'a>>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = Span>
+{
+    let label = just(Token::KwLabel).ignore_then(identifier());
+
+    let jump = just(Token::TkJmp)
+        .ignore_then(label.clone())
+        .map(Term::Jump);
+
+    let branch = just(Token::TKBranch)
+        .ignore_then(parse_operand())
+        .then(just(Token::Comma).ignore_then(label.clone()))
+        .then(just(Token::Comma).ignore_then(label))
+        .map(| ((cond, true_label), false_label) | Term::Branch(cond, true_label, false_label));
+
+    let ret = just(Token::TKRet)
+        .ignore_then(parse_operand())
+        .map(Term::Return);
+
+    // skip a malformed terminator up to the next basic block or the end of function.
+    let block_start = identifier().then(just(Token::Colon));
+    let recovery = one_of([Token::TkJmp, Token::TKBranch, Token::TKRet])
+        .then(none_of([Token::KwLet, Token::RBrace, Token::KwFn])
+            .and_is(block_start.not())
+            .repeated())
+        .to(None);
+
+    choice((jump, branch, ret))
+        .map_with(| terminator, extra | Some(Spanned(terminator, extra.span())))
+        .recover_with(via_parser(recovery))
+        .labelled("terminator")
+}
+
+fn parse_function<'a, I>() -> impl Parser<'a, I, Item<'a>, ParserError<'a, Token<'a>>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = Span>
+{
+    let basic_block = identifier()
+        .then_ignore(just(Token::Colon))
+        .then(parse_instruction()
+            .repeated()
+            .collect::<Vec<_>>())
+        .then(parse_terminator())
+        .map(| ((label, instrs), terminator) | {
+            terminator.map(| terminator | Block {
+                label,
+                instrs: instrs.into_iter().flatten().collect(),
+                terminator
+            })
+        })
+        .labelled("basic block");
+
+    let body = basic_block
+        .repeated()
+        .at_least(1)
+        .collect::<Vec<_>>()
+        .map(| blocks | Some(blocks.into_iter().flatten().collect::<Vec<_>>()))
+        .delimited_by(just(Token::LBrace), just(Token::RBrace))
+        .recover_with(via_parser(nested_delimiters(
+            Token::LBrace,
+            Token::RBrace,
+            [(Token::LParen, Token::RParen), (Token::LBracket, Token::RBracket)],
+            | _ | Some(Vec::new())
+        )));
+
+    let params = identifier()
+        .then_ignore(just(Token::Colon))
+        .then(parse_type())
+        .map(| (name, ty) | (name.0, ty))
+        .separated_by(just(Token::Comma))
+        .collect::<Vec<_>>()
+        .delimited_by(just(Token::LParen), just(Token::RParen));
+
+    just(Token::KwFn)
+        .ignore_then(identifier())
+        .then(params)
+        .then_ignore(just(Token::Arrow))
+        .then(parse_type())
+        .then(just(Token::SemiColon).to(None).or(body))
+        .map(| (((name, params), ret), body) | Item::Function { name, params, ret, body })
+        .labelled("function")
+}
+
+fn parse_global_variable<'a, I>() -> impl Parser<'a, I, Item<'a>, ParserError<'a, Token<'a>>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = Span>
+{
+    // global variable: @<identifier> : region <type>, <size>
+    identifier()
+        .then_ignore(just(Token::Colon))
+        .then_ignore(just(Token::KwRegion))
+        .then(parse_type())
+        .then_ignore(just(Token::Comma))
+        .then(non_negative_literal("global variable region size"))
+        .map(| ((name, elem_ty), region_size) | Item::GlobalVar { name, elem_ty, region_size })
+        .labelled("global variable")
+}
+
+pub fn parser<'a, I>() -> impl Parser<'a, I, Vec<Item<'a>>, ParserError<'a, Token<'a>>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = Span>
+{
+    // skip a malformed item up to the next function or global variable.
+    let item_start = just(Token::KwFn)
+        .ignored()
+        .or(identifier().then(just(Token::Colon)).then(just(Token::KwRegion)).ignored());
+    let recovery = any()
+        .then(any().and_is(item_start.not()).repeated())
+        .to(None);
+
+    choice((parse_global_variable(), parse_function()))
+        .map(Some)
+        .recover_with(via_parser(recovery))
+        .repeated()
+        .at_least(1)
+        .collect::<Vec<_>>()
+        .map(| items | items.into_iter().flatten().collect())
+        .then_ignore(end())
+}
+
+/// Drive the IR builder over parsed items in source order,
+/// so that the resulting module is identical to the one built by the nom parser.
+struct Lowering {
+    builder: IRBuilder,
+    errors: Vec<Diagnostic>,
+    /// Symbols used by phi before definition in the working function, with the span of first use.
+    forward_symbols: Vec<(String, Span)>,
+    /// Return type of the working function.
+    ret_type: Type,
+}
+
+impl Lowering {
+    fn lower_operand(&mut self, operand: &Spanned<Operand>) -> Option<ValueRef> {
+        match operand.item() {
+            Operand::Symbol(name) => {
+                let value = self.builder.get_value_ref(name);
+                if value.is_none() {
+                    self.errors.push(Rich::custom(operand.span(), format!("undefined symbol `{}`", name)));
+                }
+                value
+            },
+            Operand::Literal(value) => Some(self.builder.insert_literal_value(value.clone())),
+        }
+    }
+
+    /// Report a type error at `span` unless `well_typed`,
+    /// since the IR builder asserts types rather than reporting them.
+    fn check_type(&mut self, well_typed: bool, span: Span, message: impl FnOnce() -> String) -> Option<()> {
+        if !well_typed {
+            self.errors.push(Rich::custom(span, message()));
+            return None;
+        }
+        Some(())
+    }
+
+    /// Check the type annotation of `instr`, if any, against the type of the instruction.
+    fn check_annotation(&mut self, instr: &Instr, expected: &Type) -> Option<()> {
+        match &instr.annotated_type {
+            Some(annotated) => self.check_type(annotated == expected, instr.span, ||
+                format!("expected type `{}` for `%{}`, found annotation `{}`", expected, instr.name.0, annotated)),
+            None => Some(())
+        }
+    }
+
+    fn value_type(&self, value: ValueRef) -> Type {
+        self.builder.module.get_value_type(value)
+    }
+
+    /// Parameter and return types of `callee`, `None` for undeclared callees left to the verifier.
+    fn callee_type(&self, callee: &str) -> Option<(Option<Vec<Type>>, Type)> {
+        match callee {
+            // arguments of runtime IO are checked by the executor.
+            "getint" | "getch" | "getarray" => Some((None, Type::get_i32())),
+            "putint" | "putch" | "putarray" | "starttime" | "stoptime" => Some((None, Type::get_unit())),
+            _ => self.builder.module.string_func_map.get(callee).map(| func | {
+                let ty = &self.builder.module.get_function(*func).ty;
+                (ty.get_function_params_type(), ty.get_function_ret_type().unwrap())
+            })
+        }
+    }
+
+    fn lower_instruction(&mut self, instr: &Instr) -> Option<ValueRef> {
+        let name = Some(String::from(instr.name.0));
+        let anno_ty = instr.annotated_type.clone();
+        let span = instr.span;
+        let value = match &instr.kind {
+            InstrKind::Binary(op, lhs, rhs) => {
+                let lhs = self.lower_operand(lhs)?;
+                let rhs = self.lower_operand(rhs)?;
+                let (lhs_ty, rhs_ty) = (self.value_type(lhs), self.value_type(rhs));
+                self.check_type(lhs_ty.is_integer_type() && lhs_ty == rhs_ty, span, ||
+                    format!("operands of `{}` should be the same integer type, found `{}` and `{}`", op, lhs_ty, rhs_ty))?;
+                self.check_annotation(instr, &lhs_ty)?;
+                self.builder.emit_numeric_binary_expr(op.clone(), name, lhs, rhs, anno_ty)
+            },
+            InstrKind::Alloca(base_ty, region_size) => {
+                self.check_annotation(instr, &Type::get_pointer(base_ty.clone()))?;
+                self.builder.emit_alloca(name, base_ty.clone(), *region_size, anno_ty)
+            },
+            InstrKind::Load(addr) => {
+                let addr = self.lower_operand(addr)?;
+                let addr_ty = self.value_type(addr);
+                match &anno_ty {
+                    Some(anno_ty) => self.check_type(addr_ty.deref_matches(anno_ty), span, ||
+                        format!("cannot load `{}` from address of type `{}`", anno_ty, addr_ty))?,
+                    None => self.check_type(addr_ty.get_pointer_base_type().is_some(), span, ||
+                        format!("expected pointer address to load from, found `{}`, \
+                        annotate the result type for opaque pointers", addr_ty))?
+                }
+                self.builder.emit_load(name, addr, anno_ty)
+            },
+            InstrKind::Store(stored, addr) => {
+                let stored = self.lower_operand(stored)?;
+                let addr = self.lower_operand(addr)?;
+                let (stored_ty, addr_ty) = (self.value_type(stored), self.value_type(addr));
+                self.check_type(addr_ty.deref_matches(&stored_ty), span, ||
+                    format!("cannot store `{}` to address of type `{}`", stored_ty, addr_ty))?;
+                self.check_annotation(instr, &Type::get_unit())?;
+                self.builder.emit_store(name, stored, addr, anno_ty)
+            },
+            InstrKind::Offset(base_ty, addr, indices) => {
+                let addr = self.lower_operand(addr)?;
+                let indices_bounds = indices
+                    .iter()
+                    .map(| (index, bound) | Some((self.lower_operand(index)?, *bound)))
+                    .collect::<Option<Vec<_>>>()?;
+                let addr_ty = self.value_type(addr);
+                self.check_type(addr_ty.deref_matches(base_ty), span, ||
+                    format!("element type `{}` is not compatible with address of type `{}`", base_ty, addr_ty))?;
+                let index_types = indices_bounds
+                    .iter()
+                    .map(| (index, _) | self.value_type(*index))
+                    .collect::<Vec<_>>();
+                self.check_type(index_types.iter().all(Type::is_integer_type), span, ||
+                    format!("expected integer indices, found {}", index_types.iter().map(| ty | format!("`{}`", ty)).join(", ")))?;
+                self.check_annotation(instr, &addr_ty)?;
+                self.builder.emit_offset(name, base_ty.clone(), addr, indices_bounds, anno_ty)
+            },
+            InstrKind::FnCall(callee, args) => {
+                let args = args
+                    .iter()
+                    .map(| arg | self.lower_operand(arg))
+                    .collect::<Option<Vec<_>>>()?;
+                if let Some((params_ty, ret_ty)) = self.callee_type(callee) {
+                    if let Some(params_ty) = params_ty {
+                        let args_ty = args
+                            .iter()
+                            .map(| arg | self.value_type(*arg))
+                            .collect::<Vec<_>>();
+                        self.check_type(params_ty == args_ty, span, || format!(
+                            "expected arguments ({}) for `@{}`, found ({})",
+                            params_ty.iter().join(", "), callee, args_ty.iter().join(", ")
+                        ))?;
+                    }
+                    self.check_annotation(instr, &ret_ty)?;
+                }
+                self.builder.emit_function_call(name, String::from(*callee), args, anno_ty)
+            },
+            InstrKind::Phi(incoming) => {
+                let incoming = self.lower_phi_incoming(instr, incoming)?;
+                self.builder.emit_phi(name, incoming, anno_ty)
+            },
+        };
+        self.builder.set_span(value, instr.span);
+        Some(value)
+    }
+
+    /// Incoming values of phi may be defined later, which are lowered to placeholders of the phi type.
+    fn lower_phi_incoming(
+        &mut self,
+        instr: &Instr,
+        incoming: &[(Spanned<Operand>, Spanned<&str>)]
+    ) -> Option<Vec<(ValueRef, BlockRef)>> {
+        let ty = instr.annotated_type.clone()
+            .or_else(|| incoming.iter().find_map(| (operand, _) | match operand.item() {
+                Operand::Symbol(name) => self.builder
+                    .get_value_ref(name)
+                    .map(| value | self.builder.get_value(value).ty),
+                Operand::Literal(value) => Some(value.ty.clone())
+            }));
+        let ty = match ty {
+            Some(ty) => ty,
+            None => {
+                self.errors.push(Rich::custom(instr.span,
+                    format!("cannot infer the type of phi `{}`, annotate it explicitly", instr.name.0)));
+                return None;
+            }
+        };
+        let incoming = incoming
+            .iter()
+            .map(| (operand, label) | {
+                let value = match operand.item() {
+                    Operand::Symbol(name) => {
+                        if self.builder.get_value_ref(name).is_none()
+                            && !self.forward_symbols.iter().any(| (symbol, _) | symbol == name) {
+                            self.forward_symbols.push((String::from(*name), operand.span()));
+                        }
+                        self.builder.get_or_insert_placeholder_value_ref(name, ty.clone())
+                    },
+                    Operand::Literal(value) => self.builder.insert_literal_value(value.clone())
+                };
+                (value, self.builder.get_or_insert_placeholder_block_ref(label.0))
+            })
+            .collect::<Vec<_>>();
+        let incoming_ty = incoming
+            .iter()
+            .map(| (value, _) | self.value_type(*value))
+            .collect::<Vec<_>>();
+        self.check_type(incoming_ty.iter().all(| incoming_ty | incoming_ty == &ty), instr.span, || format!(
+            "incoming values of phi `%{}` should all be type `{}`, found {}",
+            instr.name.0, ty, incoming_ty.iter().map(| ty | format!("`{}`", ty)).join(", ")
+        ))?;
+        Some(incoming)
+    }
+
+    fn lower_terminator(&mut self, terminator: &Spanned<Term>) -> Option<()> {
+        match terminator.item() {
+            Term::Jump(dest) => {
+                let dest_ref = self.builder.get_or_insert_placeholder_block_ref(dest.0);
+                self.builder.fixup_terminator_jump(dest_ref);
+            },
+            Term::Branch(cond, true_label, false_label) => {
+                let span = cond.span();
+                let cond = self.lower_operand(cond)?;
+                let cond_ty = self.value_type(cond);
+                self.check_type(cond_ty.is_i32_type(), span, ||
+                    format!("expected condition of type `i32`, found `{}`", cond_ty))?;
+                let true_ref = self.builder.get_or_insert_placeholder_block_ref(true_label.0);
+                let false_ref = self.builder.get_or_insert_placeholder_block_ref(false_label.0);
+                self.builder.fixup_terminator_branch(cond, true_ref, false_ref);
+            },
+            Term::Return(ret) => {
+                let span = ret.span();
+                let ret = self.lower_operand(ret)?;
+                let (expected, found) = (self.ret_type.clone(), self.value_type(ret));
+                self.check_type(expected == found, span, ||
+                    format!("expected return value of type `{}`, found `{}`", expected, found))?;
+                self.builder.fixup_terminator_return(ret);
+            }
+        }
+        self.builder.set_terminator_span(terminator.span());
+        Some(())
+    }
+
+    fn lower_block(&mut self, block: &Block) -> Option<()> {
+        let handler = self.builder.emit_basic_block(Some(String::from(block.label.0)));
+        self.builder.set_insert_point(handler);
+        for instr in block.instrs.iter() {
+            self.lower_instruction(instr)?;
+        }
+        self.lower_terminator(&block.terminator)
+    }
+
+    fn lower_item(&mut self, item: &Item) {
+        match item {
+            Item::GlobalVar { name, elem_ty, region_size } => {
+                let mut new_gv = values::GlobalVar::new_value(elem_ty.clone(), *region_size);
+                new_gv.set_name(String::from(name.0));
+                self.builder.insert_global_symbol(new_gv);
+            },
+            Item::Function { name, params, ret, body } => {
+                let params = params
+                    .iter()
+                    .map(| (name, ty) | (Some(String::from(*name)), ty.clone()))
+                    .collect();
+                self.builder.emit_function(String::from(name.0), params, ret.clone(), body.is_none());
+                self.ret_type = ret.clone();
+                // stop lowering the function at the first error to avoid cascading errors.
+                let lowered = body
+                    .iter()
+                    .flatten()
+                    .try_for_each(| block | self.lower_block(block));
+                let forward_symbols = std::mem::take(&mut self.forward_symbols);
+                if lowered.is_some() {
+                    let unresolved = self.builder.unresolved_placeholder_values();
+                    self.errors.extend(forward_symbols
+                        .into_iter()
+                        .filter(| (name, _) | unresolved.contains(name))
+                        .map(| (name, span) | Rich::custom(span, format!("undefined symbol `{}`", name))));
+                }
+            }
+        }
+    }
+}
+
+pub fn lower_module(items: &[Item]) -> Result<Module, Vec<Diagnostic>> {
+    let mut lowering = Lowering {
+        builder: IRBuilder::new(),
+        errors: Vec::new(),
+        forward_symbols: Vec::new(),
+        ret_type: Type::get_unit()
+    };
+    items.iter().for_each(| item | lowering.lower_item(item));
+    if lowering.errors.is_empty() {
+        Ok(lowering.builder.module)
+    } else {
+        Err(lowering.errors)
+    }
+}
+
+/// Lex, parse and build the module from source text,
+/// collecting as many errors as possible on failure.
+pub fn parse_module(src: &str) -> Result<Module, Vec<Diagnostic>> {
+    let (tokens, lex_errs) = lexer().parse(src).into_output_errors();
+    let mut errors: Vec<Diagnostic> = lex_errs
+        .into_iter()
+        .map(| err | err.map_token(| c | c.to_string()).into_owned())
+        .collect();
+
+    let tokens = tokens
+        .unwrap_or_default()
+        .into_iter()
+        .map(| Spanned(token, span) | (token, span))
+        .collect::<Vec<_>>();
+    let eoi: Span = (src.len()..src.len()).into();
+    let input: TokenInput = tokens.as_slice().spanned(eoi);
+    let (items, parse_errs) = parser().parse(input).into_output_errors();
+    errors.extend(parse_errs
+        .into_iter()
+        // invalid tokens are already reported by the lexer.
+        .filter(| err | err.found() != Some(&Token::Unknown))
+        .map(| err | err.map_token(| token | token.to_string()).into_owned()));
+
+    match items {
+        Some(items) if errors.is_empty() => lower_module(&items),
+        _ => Err(errors)
+    }
+}
+
+fn reason_message(reason: &RichReason<'static, String>) -> String {
+    match reason {
+        RichReason::ExpectedFound { expected, found } => {
+            let found = found
+                .as_ref()
+                .map_or("end of input".to_string(), | token | format!("`{}`", &**token));
+            let expected = expected
+                .iter()
+                .map(| pattern | match pattern {
+                    RichPattern::Token(token) => format!("`{}`", &**token),
+                    RichPattern::Label(label) => label.to_string(),
+                    RichPattern::EndOfInput => "end of input".to_string(),
+                })
+                .collect::<Vec<_>>();
+            match expected.as_slice() {
+                [] => format!("unexpected {}", found),
+                [expected] => format!("expected {}, found {}", expected, found),
+                [init @ .., last] => format!("expected {} or {}, found {}", init.join(", "), last, found)
+            }
+        },
+        RichReason::Custom(message) => message.clone(),
+        RichReason::Many(reasons) => reasons.iter().map(reason_message).collect::<Vec<_>>().join("; "),
+    }
+}
+
+/// Message of the diagnostic, worded as `SyntaxError::message` of the nom parser.
+pub fn message(error: &Diagnostic) -> String {
+    reason_message(error.reason())
+}
+
+/// Build an ariadne report of the diagnostic.
+pub fn report(filename: &str, error: &Diagnostic) -> Report<'static, (String, Range<usize>)> {
+    let span = error.span().into_range();
+    let title = match error.reason() {
+        RichReason::Custom(_) => "invalid module",
+        _ => "syntax error",
+    };
+    Report::build(ReportKind::Error, filename.to_string(), span.start)
+        .with_message(title)
+        .with_label(Label::new((filename.to_string(), span))
+            .with_message(message(error))
+            .with_color(Color::Red))
+        .with_labels(error.contexts().map(| (label, span) | {
+            Label::new((filename.to_string(), span.into_range()))
+                .with_message(format!("while parsing this {}", label))
+                .with_color(Color::Yellow)
+        }))
+        .finish()
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use nom::Finish;
+
+    use crate::frontend::{lexer::Lexer, parser, token::Tokens};
+    use crate::ir::builders::IRBuilder;
+
+    use super::{message, parse_module};
+
+    #[test]
+    fn test_parity_with_nom_parser() {
+        let examples = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+        let mut checked = 0;
+        for entry in std::fs::read_dir(examples).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(| ext | ext != "acc") {
+                continue;
+            }
+            let src = std::fs::read_to_string(&path).unwrap();
+            let (_, tokens) = Lexer::lex(&src).finish().unwrap();
+            let builder = Rc::new(RefCell::new(IRBuilder::new()));
+            let (_, expected) = parser::Parser::parse_from_complete_input(Tokens::new(&tokens), builder)
+                .finish()
+                .unwrap();
+            let module = parse_module(&src)
+                .unwrap_or_else(| errs | panic!("{}: {:?}", path.display(), errs));
+            assert_eq!(module.to_string(), expected.to_string(), "{}", path.display());
+            checked += 1;
+        }
+        assert!(checked > 0);
+    }
+
+    #[test]
+    fn test_error_recovery() {
+        let src = r"
+            fn @f(#a: i32) -> i32 {
+            %entry:
+                let %0 = add #a 1
+                let %1 = mul #a, 2
+                ret %1
+            }
+
+            fn @g() -> i32 {
+            %entry:
+                ret #missing
+            }
+
+            fn @h( -> i32;
+            fn @main() -> () {
+            %entry:
+                let %0 = load
+                ret ()
+            }
+        ";
+        let errors = parse_module(src).unwrap_err();
+        // missing comma, malformed `@h` header and missing load operand
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+    }
+
+    #[test]
+    fn test_type_error_recovery() {
+        let src = r"
+            fn @f(#a: i32) -> i32 {
+            %entry:
+                let %0 = add 1, true
+                ret %0
+            }
+
+            fn @g(#p: i32*) -> () {
+            %entry:
+                let %0: i32 = call @f, #p
+                let %1 = store false, #p
+                br #p, label %entry, label %entry
+            }
+
+            fn @main() -> i32 {
+            %entry:
+                let %0 = load
+                ret 0
+            }
+        ";
+        // syntax errors stop lowering, so only the missing load operand is reported.
+        let errors = parse_module(src).unwrap_err();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+
+        let src = src.replace("let %0 = load", "let %0 = call @f, 1");
+        let errors = parse_module(&src).unwrap_err();
+        // lowering stops at the first error of each function.
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert_eq!(message(&errors[0]), "operands of `add` should be the same integer type, found `i32` and `i1`");
+        assert_eq!(&src[errors[0].span().into_range()], "let %0 = add 1, true");
+        assert_eq!(message(&errors[1]), "expected arguments (i32) for `@f`, found (i32*)");
+
+        let src = src.replace("call @f, #p", "call @f, 1");
+        let errors = parse_module(&src).unwrap_err();
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert_eq!(message(&errors[1]), "cannot store `i1` to address of type `i32*`");
+    }
+
+    #[test]
+    fn test_error_message_parity_with_nom_parser() {
+        let src = "fn @f(#a: i32) -> i32 {\n%entry:\n    let %0 = add #a 1\n    ret %0\n}\n";
+        let (_, tokens) = Lexer::lex(src).finish().unwrap();
+        let builder = Rc::new(RefCell::new(IRBuilder::new()));
+        let nom_error = parser::Parser::parse_from_complete_input(Tokens::new(&tokens), builder)
+            .finish()
+            .unwrap_err();
+        let errors = parse_module(src).unwrap_err();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        // chumsky also expects the optional type annotation of `#a`.
+        assert_eq!(nom_error.message(), "expected `,`, found `1`");
+        assert_eq!(message(&errors[0]), "expected `:` or `,`, found `1`");
+    }
+
+    #[test]
+    fn test_source_spans() {
+        let src = "fn @main() -> i32 {\n%entry:\n    let %0 = add 1, 2\n    ret %0\n}\n";
+        let module = parse_module(src).unwrap();
+        let function = module.get_function(module.get_function_ref("main"));
+        let block = function.get_basic_block(function.blocks[0]);
+        let instr = module.get_value(block.instrs[0]);
+        assert_eq!(&src[instr.span.unwrap().into_range()], "let %0 = add 1, 2");
+        assert_eq!(&src[block.terminator.span().unwrap().into_range()], "ret %0");
+    }
+}
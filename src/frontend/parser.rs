@@ -1,14 +1,17 @@
 use std::cell::RefCell;
+use std::ops::Range;
 use std::rc::Rc;
 use nom::{
     branch::alt,
     bytes::complete::take,
-    combinator::{all_consuming, map, opt, peek, value},
-    error::{ErrorKind, ParseError, VerboseError},
-    multi::{fold_many1, many0, many0_count, many1, many1_count, separated_list0},
+    combinator::{cut, map, opt, peek, value},
+    error::{ErrorKind, ParseError},
+    multi::{fold_many1, many0, many0_count, many1, many1_count, separated_list0, separated_list1},
     sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
     Compare, CompareResult, Err, InputIter, InputLength, InputTake
 };
+use ariadne::{Color, Label, Report, ReportKind};
+use itertools::Either;
 
 use crate::ir::{
     builders::IRBuilder, structures::*, types::Type, values
@@ -16,53 +19,119 @@ use crate::ir::{
 
 use super::token::{Token, Tokens};
 
-pub type IResult<I, O, E=nom::error::VerboseError<I>> = Result<(I, O), nom::Err<E>>;
+pub type IResult<I, O, E=SyntaxError<I>> = Result<(I, O), nom::Err<E>>;
 
+/// Parse error which remembers what are expected at the furthest position reached.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError<I> {
+    /// Remaining input where error occurs.
+    pub input: I,
+    /// Descriptions of expected tokens.
+    pub expected: Vec<String>,
+}
+
+impl<I> SyntaxError<I> {
+    pub fn expected(input: I, expected: &str) -> Self {
+        SyntaxError { input, expected: vec![expected.to_string()] }
+    }
+}
 
-fn token<'a, Input, Error: ParseError<Input>>(
+impl<I: InputLength> ParseError<I> for SyntaxError<I> {
+    fn from_error_kind(input: I, _kind: ErrorKind) -> Self {
+        SyntaxError { input, expected: vec![] }
+    }
+
+    fn append(_input: I, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    fn or(mut self, other: Self) -> Self {
+        // keep the error that goes further, merge expectations at the same position.
+        match self.input.input_len().cmp(&other.input.input_len()) {
+            std::cmp::Ordering::Less => self,
+            std::cmp::Ordering::Greater => other,
+            std::cmp::Ordering::Equal => {
+                for expected in other.expected {
+                    if !self.expected.contains(&expected) {
+                        self.expected.push(expected);
+                    }
+                }
+                self
+            }
+        }
+    }
+}
+
+impl<'a> SyntaxError<Tokens<'a>> {
+    pub fn message(&self) -> String {
+        let found = self.input
+            .peek_token()
+            .map_or("end of input".to_string(), | token | format!("`{}`", token.item()));
+        match self.expected.as_slice() {
+            [] => format!("unexpected {}", found),
+            [expected] => format!("expected {}, found {}", expected, found),
+            [init @ .., last] => format!("expected {} or {}, found {}", init.join(", "), last, found)
+        }
+    }
+
+    /// Build an ariadne report labelling the offending token in `src`.
+    pub fn report(&self, filename: &str, src: &str) -> Report<'static, (String, Range<usize>)> {
+        let span = self.input
+            .first_span()
+            .map_or(src.len()..src.len(), | span | span.into_range());
+        Report::build(ReportKind::Error, filename.to_string(), span.start)
+            .with_message("syntax error")
+            .with_label(Label::new((filename.to_string(), span))
+                .with_message(self.message())
+                .with_color(Color::Red))
+            .finish()
+    }
+}
+
+fn token<'a, Input>(
     t: Token<'a>
-) -> impl Fn(Input) -> IResult<Input, Input, Error> + 'a
+) -> impl Fn(Input) -> IResult<Input, Input> + 'a
 where
     Input: InputTake + Compare<Token<'a>>
 {
     move | i: Input | {
         let token_len = t.input_len();
         let t = t.clone();
-        let res: IResult<_, _, Error> = match i.compare(t) {
+        let res: IResult<_, _> = match i.compare(t.clone()) {
             CompareResult::Ok => Ok(i.take_split(token_len)),
-            _ => {
-                let e: ErrorKind = ErrorKind::Tag;
-                Err(Err::Error(Error::from_error_kind(i, e)))
-            }
+            _ => Err(Err::Error(SyntaxError::expected(i, &format!("`{}`", t))))
         };
         res
     }
 }
 
 fn identifier(input: Tokens) -> IResult<Tokens, &str> {
-    let (input, tk) = take(1usize)(input)?;
-    match tk.iter_elements().next().unwrap() {
+    let (rest, tk) = take(1usize)(input.clone())
+        .map_err(| _: Err<SyntaxError<Tokens>> | Err::Error(SyntaxError::expected(input.clone(), "identifier")))?;
+    match tk.iter_elements().next().unwrap().item() {
         Token::TkIdent(id) => { 
             // println!("identifier {}, now token: {:?}", id, input);
-            Ok((input, id))
+            Ok((rest, id))
         },
-        _ => Err(Err::Error(VerboseError::from_error_kind(input, ErrorKind::Tag)))
+        _ => Err(Err::Error(SyntaxError::expected(input, "identifier")))
     }
 }
 
 fn i32_literal(input: Tokens) -> IResult<Tokens, i32> {
-    let (input, tk) = take(1usize)(input)?;
-    match tk.iter_elements().next().unwrap() {
-        Token::LtInt32(value) => Ok((input, value.clone())),
-        _ => Err(Err::Error(VerboseError::from_error_kind(input, ErrorKind::Tag)))
+    let (rest, tk) = take(1usize)(input.clone())
+        .map_err(| _: Err<SyntaxError<Tokens>> | Err::Error(SyntaxError::expected(input.clone(), "integer literal")))?;
+    match tk.iter_elements().next().unwrap().item() {
+        Token::LtInt32(value) => Ok((rest, *value)),
+        _ => Err(Err::Error(SyntaxError::expected(input, "integer literal")))
     }
 }
 
 fn i1_literal(input: Tokens) -> IResult<Tokens, bool> {
-    let (input, tk) = take(1usize)(input)?;
-    match tk.iter_elements().next().unwrap() {
-        Token::LtInt1(value) => Ok((input, value.clone())),
-        _ => Err(Err::Error(VerboseError::from_error_kind(input, ErrorKind::Tag)))
+    let (rest, tk) = take(1usize)(input.clone())
+        .map_err(| _: Err<SyntaxError<Tokens>> | Err::Error(SyntaxError::expected(input.clone(), "boolean literal")))?;
+    match tk.iter_elements().next().unwrap().item() {
+        Token::LtInt1(value) => Ok((rest, *value)),
+        _ => Err(Err::Error(SyntaxError::expected(input, "boolean literal")))
     }
 }
 
@@ -338,18 +407,86 @@ impl<'a, 'b: 'a> Parser {
         )))
     }
 
+    fn parse_phi(
+        input: Tokens<'a>,
+        builder: Rc<RefCell<IRBuilder>>
+    ) -> IResult<Tokens<'a>, ValueRef> {
+        let (input,(name, anno_ty)) =
+            delimited(token(Token::KwLet), parse_symbol, token(Token::Equal))(input)?;
+
+        // incoming values may be defined later, resolve them after the type is known.
+        let (input, incoming) = preceded(
+            token(Token::TkPhi),
+            separated_list1(
+                token(Token::Comma),
+                delimited(
+                    token(Token::LBracket),
+                    separated_pair(
+                        alt((
+                            map(parse_symbol, | (name, _) | Either::Left(name)),
+                            map(parse_literal, Either::Right)
+                        )),
+                        token(Token::Comma),
+                        preceded(token(Token::KwLabel), identifier)
+                    ),
+                    token(Token::RBracket)
+                )
+            )
+        )(input)?;
+
+        let ty = anno_ty.clone()
+            .or_else(|| incoming.iter().find_map(| (operand, _) | match operand {
+                Either::Left(symbol) => builder
+                    .borrow()
+                    .get_value_ref(symbol)
+                    .map(| value_ref | builder.borrow().get_value(value_ref).ty),
+                Either::Right(literal) => Some(literal.ty.clone())
+            }))
+            .unwrap_or_else(|| panic!("cannot infer the type of phi '%{}', annotate it explicitly", name));
+        let incoming = incoming
+            .into_iter()
+            .map(| (operand, label) | {
+                let value_ref = match operand {
+                    Either::Left(symbol) => builder
+                        .borrow_mut()
+                        .get_or_insert_placeholder_value_ref(symbol, ty.clone()),
+                    Either::Right(literal) => builder
+                        .borrow_mut()
+                        .insert_literal_value(literal)
+                };
+                let bb_ref = builder
+                    .borrow_mut()
+                    .get_or_insert_placeholder_block_ref(label);
+                (value_ref, bb_ref)
+            })
+            .collect();
+
+        Ok((input, builder.borrow_mut().emit_phi(
+            Some(String::from(name)),
+            incoming,
+            anno_ty
+        )))
+    }
+
     fn parse_instruction(
         input: Tokens<'a>,
         builder: Rc<RefCell<IRBuilder>>,
     ) -> IResult<Tokens<'a>, ValueRef> {
-        alt((
+        // commit to an instruction once `let` is seen.
+        let (input, _) = peek(token(Token::KwLet))(input)?;
+        let (rest, value) = cut(alt((
             | input: Tokens<'a> | Parser::parse_binary_expr(input, builder.clone()),
             | input: Tokens<'a> | Parser::parse_alloca(input, builder.clone()),
             | input: Tokens<'a> | Parser::parse_load(input, builder.clone()),
             | input: Tokens<'a> | Parser::parse_store(input, builder.clone()),
             | input: Tokens<'a> | Parser::parse_offset(input, builder.clone()),
             | input: Tokens<'a> | Parser::parse_fncall(input, builder.clone()),
-        ))(input)
+            | input: Tokens<'a> | Parser::parse_phi(input, builder.clone()),
+        )))(input.clone())?;
+        if let Some(span) = input.span_until(&rest) {
+            builder.borrow_mut().set_span(value, span);
+        }
+        Ok((rest, value))
     }
 
     fn parse_terminator_jump(
@@ -439,9 +576,12 @@ impl<'a, 'b: 'a> Parser {
             | input: Tokens<'a> | Parser::parse_instruction(input, builder.clone()))(input)?;
         // fixup terminator
         // println!("build terminator start");
-        let (input, _) = Parser::parse_terminator(input, builder.clone())?;
+        let (rest, _) = cut(| input: Tokens<'a> | Parser::parse_terminator(input, builder.clone()))(input.clone())?;
+        if let Some(span) = input.span_until(&rest) {
+            builder.borrow_mut().set_terminator_span(span);
+        }
         // println!("build terminator finish");
-        Ok((input, handler))
+        Ok((rest, handler))
     }
 
     fn parse_function_body(
@@ -486,19 +626,25 @@ impl<'a, 'b: 'a> Parser {
         input: Tokens<'a>,
         builder: Rc<RefCell<IRBuilder>>
     ) -> IResult<Tokens<'a>, ()> {
-        let (input, (name, params, ret, external)) = Parser::parse_function_header(input)?;
+        // commit to a function once `fn` is seen.
+        let (input, _) = peek(token(Token::KwFn))(input)?;
+        let (input, (name, params, ret, external)) = cut(Parser::parse_function_header)(input)?;
         builder
             .borrow_mut()
             .emit_function(name, params, ret, external);
         // println!("build function body");
-        alt((
+        let (input, _) = cut(alt((
             value((), token(Token::SemiColon)),
             delimited(
                 token(Token::LBrace),
                 | input: Tokens<'a> | Parser::parse_function_body(input, builder.clone()),
                 token(Token::RBrace)
             )
-        ))(input)
+        )))(input)?;
+        if let Some(name) = builder.borrow().unresolved_placeholder_values().first() {
+            panic!("undefined symbol '{}'", name);
+        }
+        Ok((input, ()))
     }
 
     fn parse_global_variable(
@@ -508,9 +654,9 @@ impl<'a, 'b: 'a> Parser {
         // global variable: @<identifier> : region, <size>
         let (input, (name, (elem_ty, region_size))) = pair(
             identifier,
-            preceded(token(Token::Colon), 
+            cut(preceded(token(Token::Colon), 
                 preceded(token(Token::KwRegion), 
-                            separated_pair(parse_type, token(Token::Comma), map(i32_literal, | lit | usize::try_from(lit).expect("expect non-negative global variable region size"))))))(input)?;
+                            separated_pair(parse_type, token(Token::Comma), map(i32_literal, | lit | usize::try_from(lit).expect("expect non-negative global variable region size")))))))(input)?;
         let mut new_gv = values::GlobalVar::new_value(
             elem_ty,
             region_size
@@ -524,10 +670,19 @@ impl<'a, 'b: 'a> Parser {
         input: Tokens<'a>,
         builder: Rc<RefCell<IRBuilder>>
     ) -> IResult<Tokens<'a>, Module> {
-        let (input, _) = all_consuming(many1(alt((
-            | input: Tokens<'a> | Parser::parse_global_variable(input, builder.clone()),
-            | input: Tokens<'a> | Parser::parse_function(input, builder.clone())
-        ))))(input)?;
+        let (input, _) = terminated(
+            many1(alt((
+                | input: Tokens<'a> | Parser::parse_global_variable(input, builder.clone()),
+                | input: Tokens<'a> | Parser::parse_function(input, builder.clone())
+            ))),
+            | input: Tokens<'a> | match input.peek_token() {
+                None => Ok((input, ())),
+                Some(_) => Err(Err::Error(SyntaxError {
+                    input,
+                    expected: vec!["`fn`".to_string(), "global variable".to_string()]
+                }))
+            }
+        )(input)?;
 
         Ok((input, builder.borrow().module.clone()))
     }
@@ -611,6 +766,20 @@ mod test {
         )
     }
 
+    #[test]
+    fn test_syntax_error() {
+        let input = "fn @f() -> i32 {\n%entry:\n  let %x = 1, 2\n  ret %x\n}";
+        let (_, tokens) = Lexer::lex(input).expect("failed to lex");
+        let builder = Rc::new(RefCell::new(IRBuilder::new()));
+        let error = match Parser::parse_from_complete_input(Tokens::new(&tokens), builder) {
+            Err(Err::Failure(error)) => error,
+            _ => panic!("expect a syntax error")
+        };
+        assert_eq!(error.input.first_span().map(| span | &input[span.into_range()]), Some("1"));
+        assert!(error.message().starts_with("expected `add`, `sub`"));
+        assert!(error.message().ends_with("`call` or `phi`, found `1`"));
+    }
+
 }
 
 
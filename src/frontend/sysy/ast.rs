@@ -0,0 +1,118 @@
+use crate::frontend::{Span, Spanned};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FuncType {
+    Int,
+    Void,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Plus,
+    Minus,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+/// `IDENT {"[" Exp "]"}`
+#[derive(Debug, Clone)]
+pub struct LVal<'a> {
+    pub name: Spanned<&'a str>,
+    pub indices: Vec<Expr<'a>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind<'a> {
+    Number(i32),
+    LVal(LVal<'a>),
+    Call(Spanned<&'a str>, Vec<Expr<'a>>),
+    Unary(UnaryOp, Box<Expr<'a>>),
+    Binary(BinaryOp, Box<Expr<'a>>, Box<Expr<'a>>),
+}
+
+pub type Expr<'a> = Spanned<ExprKind<'a>>;
+
+#[derive(Debug, Clone)]
+pub enum InitVal<'a> {
+    Expr(Expr<'a>),
+    /// Braced initializer list, nested lists initialize sub-arrays.
+    List(Vec<InitVal<'a>>, Span),
+}
+
+impl<'a> InitVal<'a> {
+    pub fn span(&self) -> Span {
+        match self {
+            InitVal::Expr(expr) => expr.span(),
+            InitVal::List(_, span) => *span,
+        }
+    }
+}
+
+/// `IDENT {"[" INT_CONST "]"} ["=" InitVal]`
+#[derive(Debug, Clone)]
+pub struct VarDef<'a> {
+    pub name: Spanned<&'a str>,
+    pub dims: Vec<Spanned<i32>>,
+    pub init: Option<InitVal<'a>>,
+}
+
+/// `"int" IDENT ["[" "]" {"[" INT_CONST "]"}]`
+#[derive(Debug, Clone)]
+pub struct Param<'a> {
+    pub name: Spanned<&'a str>,
+    /// `None` for an `int` parameter, otherwise dimensions after the omitted first one.
+    pub dims: Option<Vec<Spanned<i32>>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum BlockItem<'a> {
+    Decl(Vec<VarDef<'a>>),
+    Stmt(Stmt<'a>),
+}
+
+pub type Block<'a> = Vec<BlockItem<'a>>;
+
+#[derive(Debug, Clone)]
+pub enum StmtKind<'a> {
+    Assign(LVal<'a>, Expr<'a>),
+    /// Expression statement, `None` for the empty statement `;`.
+    Expr(Option<Expr<'a>>),
+    Block(Block<'a>),
+    If(Expr<'a>, Box<Stmt<'a>>, Option<Box<Stmt<'a>>>),
+    While(Expr<'a>, Box<Stmt<'a>>),
+    Break,
+    Continue,
+    Return(Option<Expr<'a>>),
+}
+
+pub type Stmt<'a> = Spanned<StmtKind<'a>>;
+
+#[derive(Debug, Clone)]
+pub struct FuncDef<'a> {
+    pub ret: FuncType,
+    pub name: Spanned<&'a str>,
+    pub params: Vec<Param<'a>>,
+    pub body: Block<'a>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Item<'a> {
+    Decl(Vec<VarDef<'a>>),
+    Func(FuncDef<'a>),
+}
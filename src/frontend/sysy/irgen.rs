@@ -0,0 +1,457 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::frontend::Span;
+use crate::ir::{
+    builders::IRBuilder, structures::*, types::Type, values
+};
+
+use super::ast::*;
+use super::semantic::{array_dims, evaluate_constant, flatten_initializer};
+
+/// Address of a variable, with the dimensions for array variables.
+#[derive(Debug, Clone)]
+struct Variable {
+    addr: ValueRef,
+    /// Empty for `int` variables, the first dimension is `None` for array parameters.
+    dims: Vec<Option<usize>>,
+}
+
+/// Lower the checked AST to IR, where every local `int` variable and parameter lives in an `alloca`
+/// in the entry block, following `docs/appendix/sysy-accipit-mapping.md`.
+struct Generator<'a> {
+    builder: IRBuilder,
+    /// Variable scopes from the global scope to the innermost block.
+    scopes: Vec<HashMap<&'a str, Variable>>,
+    /// Nonzero initial values of global variables, stored at the beginning of `main`.
+    global_inits: Vec<(ValueRef, Vec<(usize, i32)>)>,
+
+    entry: Option<BlockRef>,
+    position: Option<BlockRef>,
+    /// Whether the working basic block already has a terminator, so following statements are unreachable.
+    terminated: bool,
+    /// Basic blocks in the order they are worked on, which becomes the layout of the function.
+    layout: Vec<BlockRef>,
+    /// `continue` and `break` destinations of enclosing loops.
+    loops: Vec<(BlockRef, BlockRef)>,
+}
+
+impl<'a> Generator<'a> {
+    fn lookup(&self, name: &str) -> &Variable {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(| scope | scope.get(name))
+            .unwrap_or_else(|| panic!("undefined variable `{}` passes semantic check", name))
+    }
+
+    fn constant(&mut self, value: i32) -> ValueRef {
+        self.builder.insert_literal_value(values::ConstantInt::new_value(value))
+    }
+
+    fn new_block(&mut self, name: &str) -> BlockRef {
+        self.builder.emit_basic_block(Some(name.to_string()))
+    }
+
+    fn switch_to(&mut self, bb: BlockRef) {
+        self.builder.set_insert_point(bb);
+        self.position = Some(bb);
+        self.terminated = false;
+        self.layout.push(bb);
+    }
+
+    fn jump(&mut self, dest: BlockRef) {
+        if !self.terminated {
+            self.builder.fixup_terminator_jump(dest);
+            self.terminated = true;
+        }
+    }
+
+    fn branch(&mut self, cond: ValueRef, true_label: BlockRef, false_label: BlockRef) {
+        self.builder.fixup_terminator_branch(cond, true_label, false_label);
+        self.terminated = true;
+    }
+
+    /// Allocate the variable in the entry block, so that loops do not allocate it repeatedly.
+    fn alloca(&mut self, name: &str, size: usize) -> ValueRef {
+        self.builder.set_insert_point(self.entry.unwrap());
+        let addr = self.builder.emit_alloca(Some(format!("{}.addr", name)), Type::get_i32(), size, None);
+        self.builder.set_insert_point(self.position.unwrap());
+        addr
+    }
+
+    /// Address of the element or sub-array, indices are padded with zeros for sub-arrays.
+    fn element_addr(&mut self, variable: &Variable, indices: Vec<ValueRef>, span: Span) -> ValueRef {
+        if indices.is_empty() {
+            return variable.addr;
+        }
+        let zero = self.constant(0);
+        let indices_bounds = indices
+            .into_iter()
+            .chain(std::iter::repeat(zero))
+            .zip(variable.dims.iter().cloned())
+            .collect();
+        let addr = self.builder.emit_offset(None, Type::get_i32(), variable.addr, indices_bounds, None);
+        self.builder.set_span(addr, span);
+        addr
+    }
+
+    fn lval_addr(&mut self, lval: &LVal<'a>, span: Span) -> (ValueRef, bool) {
+        let variable = self.lookup(lval.name.0).clone();
+        let indices = lval.indices
+            .iter()
+            .map(| index | self.expr(index))
+            .collect::<Vec<_>>();
+        let is_element = indices.len() == variable.dims.len();
+        (self.element_addr(&variable, indices, span), is_element)
+    }
+
+    fn expr(&mut self, expr: &Expr<'a>) -> ValueRef {
+        let value = match expr.item() {
+            ExprKind::Number(lit) => return self.constant(*lit),
+            ExprKind::LVal(lval) => {
+                let (addr, is_element) = self.lval_addr(lval, expr.span());
+                if !is_element {
+                    // arrays decay to the address of the first element.
+                    return addr;
+                }
+                self.builder.emit_load(None, addr, None)
+            },
+            ExprKind::Call(callee, args) => {
+                let args = args
+                    .iter()
+                    .map(| arg | self.expr(arg))
+                    .collect();
+                self.builder.emit_function_call(None, callee.0.to_string(), args, None)
+            },
+            ExprKind::Unary(UnaryOp::Plus, operand) => return self.expr(operand),
+            ExprKind::Unary(UnaryOp::Minus, operand) => {
+                let zero = self.constant(0);
+                let operand = self.expr(operand);
+                self.builder.emit_numeric_binary_expr(values::BinaryOp::Sub, None, zero, operand, None)
+            },
+            ExprKind::Unary(UnaryOp::Not, operand) => {
+                let operand = self.expr(operand);
+                let zero = self.constant(0);
+                self.builder.emit_numeric_binary_expr(values::BinaryOp::Eq, None, operand, zero, None)
+            },
+            ExprKind::Binary(BinaryOp::And | BinaryOp::Or, ..) => return self.logical(expr),
+            ExprKind::Binary(op, lhs, rhs) => {
+                let lhs = self.expr(lhs);
+                let rhs = self.expr(rhs);
+                let op = match op {
+                    BinaryOp::Mul => values::BinaryOp::Mul,
+                    BinaryOp::Div => values::BinaryOp::Div,
+                    BinaryOp::Rem => values::BinaryOp::Rem,
+                    BinaryOp::Add => values::BinaryOp::Add,
+                    BinaryOp::Sub => values::BinaryOp::Sub,
+                    BinaryOp::Lt => values::BinaryOp::Lt,
+                    BinaryOp::Gt => values::BinaryOp::Gt,
+                    BinaryOp::Le => values::BinaryOp::Le,
+                    BinaryOp::Ge => values::BinaryOp::Ge,
+                    BinaryOp::Eq => values::BinaryOp::Eq,
+                    BinaryOp::Ne => values::BinaryOp::Ne,
+                    BinaryOp::And | BinaryOp::Or => unreachable!(),
+                };
+                self.builder.emit_numeric_binary_expr(op, None, lhs, rhs, None)
+            }
+        };
+        self.builder.set_span(value, expr.span());
+        value
+    }
+
+    /// Value of `&&` and `||`, merged by phi from the short-circuit branches.
+    fn logical(&mut self, expr: &Expr<'a>) -> ValueRef {
+        let true_bb = self.new_block("logic.true");
+        let false_bb = self.new_block("logic.false");
+        let end_bb = self.new_block("logic.end");
+        self.condition(expr, true_bb, false_bb);
+        self.switch_to(true_bb);
+        self.jump(end_bb);
+        self.switch_to(false_bb);
+        self.jump(end_bb);
+        self.switch_to(end_bb);
+        let one = self.constant(1);
+        let zero = self.constant(0);
+        let value = self.builder.emit_phi(None, vec![(one, true_bb), (zero, false_bb)], None);
+        self.builder.set_span(value, expr.span());
+        value
+    }
+
+    /// Branch to `true_label` if the expression is nonzero, evaluating `&&` and `||` by short circuit.
+    fn condition(&mut self, expr: &Expr<'a>, true_label: BlockRef, false_label: BlockRef) {
+        match expr.item() {
+            ExprKind::Binary(BinaryOp::And, lhs, rhs) => {
+                let rhs_bb = self.new_block("land.rhs");
+                self.condition(lhs, rhs_bb, false_label);
+                self.switch_to(rhs_bb);
+                self.condition(rhs, true_label, false_label);
+            },
+            ExprKind::Binary(BinaryOp::Or, lhs, rhs) => {
+                let rhs_bb = self.new_block("lor.rhs");
+                self.condition(lhs, true_label, rhs_bb);
+                self.switch_to(rhs_bb);
+                self.condition(rhs, true_label, false_label);
+            },
+            ExprKind::Unary(UnaryOp::Not, operand) =>
+                self.condition(operand, false_label, true_label),
+            _ => {
+                let cond = self.expr(expr);
+                self.branch(cond, true_label, false_label);
+                self.builder.set_terminator_span(expr.span());
+            }
+        }
+    }
+
+    fn local_var_def(&mut self, def: &VarDef<'a>) {
+        let dims = array_dims(&def.dims);
+        let addr = self.alloca(def.name.0, dims.iter().product());
+        let variable = Variable { addr, dims: dims.iter().cloned().map(Some).collect() };
+        // the initializer refers to the shadowed variable of the same name.
+        match &def.init {
+            None => (),
+            Some(InitVal::Expr(expr)) => {
+                let value = self.expr(expr);
+                self.builder.emit_store(None, value, addr, None);
+            },
+            Some(InitVal::List(list, span)) => {
+                let flattened = flatten_initializer(&dims, list, *span)
+                    .expect("malformed initializer passes semantic check");
+                // elements without initializer are zero.
+                let mut elements = vec![None; dims.iter().product()];
+                flattened.into_iter().for_each(| (index, expr) | elements[index] = Some(expr));
+                for (index, element) in elements.into_iter().enumerate() {
+                    let value = match element {
+                        Some(expr) => self.expr(expr),
+                        None => self.constant(0),
+                    };
+                    let indices = dims
+                        .iter()
+                        .enumerate()
+                        .map(| (k, dim) | {
+                            let stride: usize = dims[k + 1..].iter().product();
+                            self.constant((index / stride % dim) as i32)
+                        })
+                        .collect();
+                    let element_addr = self.element_addr(&variable, indices, def.name.span());
+                    self.builder.emit_store(None, value, element_addr, None);
+                }
+            }
+        }
+        self.scopes.last_mut().unwrap().insert(def.name.0, variable);
+    }
+
+    fn global_var_def(&mut self, def: &VarDef<'a>, functions: &HashSet<&str>) {
+        let dims = array_dims(&def.dims);
+        let mut global = values::GlobalVar::new_value(Type::get_i32(), dims.iter().product());
+        // avoid the name of function, which is in the same namespace for backends.
+        if functions.contains(def.name.0) {
+            global.set_name(format!("{}.var", def.name.0));
+        } else {
+            global.set_name(def.name.0.to_string());
+        }
+        let addr = self.builder.insert_global_symbol(global);
+        let inits = match &def.init {
+            None => Vec::new(),
+            Some(InitVal::Expr(expr)) => vec![(0, expr)],
+            Some(InitVal::List(list, span)) => flatten_initializer(&dims, list, *span)
+                .expect("malformed initializer passes semantic check"),
+        };
+        let inits = inits
+            .into_iter()
+            .map(| (index, expr) | (index, evaluate_constant(expr).expect("non-constant global initializer passes semantic check")))
+            .filter(| (_, value) | *value != 0)
+            .collect::<Vec<_>>();
+        if !inits.is_empty() {
+            self.global_inits.push((addr, inits));
+        }
+        self.scopes[0].insert(def.name.0, Variable { addr, dims: dims.into_iter().map(Some).collect() });
+    }
+
+    fn block(&mut self, block: &Block<'a>) {
+        for item in block {
+            // skip unreachable code after `return`, `break` and `continue`.
+            if self.terminated {
+                break;
+            }
+            match item {
+                BlockItem::Decl(defs) => defs.iter().for_each(| def | self.local_var_def(def)),
+                BlockItem::Stmt(stmt) => self.stmt(stmt),
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt<'a>) {
+        match stmt.item() {
+            StmtKind::Assign(lval, expr) => {
+                let value = self.expr(expr);
+                let (addr, _) = self.lval_addr(lval, stmt.span());
+                let store = self.builder.emit_store(None, value, addr, None);
+                self.builder.set_span(store, stmt.span());
+            },
+            StmtKind::Expr(expr) => {
+                if let Some(expr) = expr {
+                    self.expr(expr);
+                }
+            },
+            StmtKind::Block(block) => {
+                self.scopes.push(HashMap::new());
+                self.block(block);
+                self.scopes.pop();
+            },
+            StmtKind::If(cond, then, otherwise) => {
+                let then_bb = self.new_block("if.then");
+                let else_bb = otherwise.as_ref().map(| _ | self.new_block("if.else"));
+                let end_bb = self.new_block("if.end");
+                self.condition(cond, then_bb, else_bb.unwrap_or(end_bb));
+                self.switch_to(then_bb);
+                self.stmt(then);
+                self.jump(end_bb);
+                if let (Some(else_bb), Some(otherwise)) = (else_bb, otherwise) {
+                    self.switch_to(else_bb);
+                    self.stmt(otherwise);
+                    self.jump(end_bb);
+                }
+                self.switch_to(end_bb);
+            },
+            StmtKind::While(cond, body) => {
+                let cond_bb = self.new_block("while.cond");
+                let body_bb = self.new_block("while.body");
+                let end_bb = self.new_block("while.end");
+                self.jump(cond_bb);
+                self.switch_to(cond_bb);
+                self.condition(cond, body_bb, end_bb);
+                self.switch_to(body_bb);
+                self.loops.push((cond_bb, end_bb));
+                self.stmt(body);
+                self.loops.pop();
+                self.jump(cond_bb);
+                self.switch_to(end_bb);
+            },
+            StmtKind::Break => {
+                let (_, break_bb) = *self.loops.last().expect("`break` outside of loop passes semantic check");
+                self.jump(break_bb);
+                self.builder.set_terminator_span(stmt.span());
+            },
+            StmtKind::Continue => {
+                let (continue_bb, _) = *self.loops.last().expect("`continue` outside of loop passes semantic check");
+                self.jump(continue_bb);
+                self.builder.set_terminator_span(stmt.span());
+            },
+            StmtKind::Return(expr) => {
+                let value = match expr {
+                    Some(expr) => self.expr(expr),
+                    None => self.builder.insert_literal_value(values::ConstantUnit::new_value()),
+                };
+                self.builder.fixup_terminator_return(value);
+                self.builder.set_terminator_span(stmt.span());
+                self.terminated = true;
+            }
+        }
+    }
+
+    fn function(&mut self, func: &FuncDef<'a>) {
+        let params = func.params
+            .iter()
+            .map(| param | {
+                let ty = match param.dims {
+                    None => Type::get_i32(),
+                    Some(_) => Type::get_pointer(Type::get_i32()),
+                };
+                (Some(param.name.0.to_string()), ty)
+            })
+            .collect();
+        let ret_ty = match func.ret {
+            FuncType::Int => Type::get_i32(),
+            FuncType::Void => Type::get_unit(),
+        };
+        self.builder.emit_function(func.name.0.to_string(), params, ret_ty, false);
+        let func_ref = self.builder.module.get_function_ref(func.name.0);
+        let args = self.builder.module.get_function(func_ref).args.clone();
+
+        let entry = self.new_block("entry");
+        self.entry = Some(entry);
+        self.layout.clear();
+        self.switch_to(entry);
+
+        if func.name.0 == "main" {
+            for (addr, inits) in std::mem::take(&mut self.global_inits) {
+                for (index, value) in inits {
+                    let value = self.constant(value);
+                    let element_addr = if index == 0 {
+                        addr
+                    } else {
+                        let index = self.constant(index as i32);
+                        self.builder.emit_offset(None, Type::get_i32(), addr, vec![(index, None)], None)
+                    };
+                    self.builder.emit_store(None, value, element_addr, None);
+                }
+            }
+        }
+
+        // array parameters are pointers never assigned, so only `int` parameters are spilled.
+        let mut scope = HashMap::new();
+        for (param, arg) in func.params.iter().zip(args) {
+            let variable = match &param.dims {
+                None => {
+                    let addr = self.alloca(param.name.0, 1);
+                    self.builder.emit_store(None, arg, addr, None);
+                    Variable { addr, dims: Vec::new() }
+                },
+                Some(dims) => Variable {
+                    addr: arg,
+                    dims: std::iter::once(None)
+                        .chain(array_dims(dims).into_iter().map(Some))
+                        .collect(),
+                }
+            };
+            scope.insert(param.name.0, variable);
+        }
+
+        self.scopes.push(scope);
+        self.block(&func.body);
+        self.scopes.pop();
+
+        // falling off the end returns zero for `int` functions.
+        if !self.terminated {
+            let value = match func.ret {
+                FuncType::Int => self.constant(0),
+                FuncType::Void => self.builder.insert_literal_value(values::ConstantUnit::new_value()),
+            };
+            self.builder.fixup_terminator_return(value);
+        }
+        self.builder.module.get_function_mut(func_ref).blocks = std::mem::take(&mut self.layout);
+    }
+}
+
+/// Generate the module of a compile unit which passes `semantic::check`.
+pub fn generate(items: &[Item]) -> Module {
+    let mut generator = Generator {
+        builder: IRBuilder::new(),
+        scopes: vec![HashMap::new()],
+        global_inits: Vec::new(),
+        entry: None,
+        position: None,
+        terminated: false,
+        layout: Vec::new(),
+        loops: Vec::new(),
+    };
+    let functions = items
+        .iter()
+        .filter_map(| item | match item {
+            Item::Func(func) => Some(func.name.0),
+            Item::Decl(_) => None
+        })
+        .collect::<HashSet<_>>();
+    // global variables are all defined ahead, as `main` initializes those defined after it.
+    for item in items {
+        if let Item::Decl(defs) = item {
+            defs.iter().for_each(| def | generator.global_var_def(def, &functions));
+        }
+    }
+    for item in items {
+        if let Item::Func(func) = item {
+            generator.function(func);
+        }
+    }
+    generator.builder.module
+}
@@ -0,0 +1,176 @@
+use std::fmt;
+
+use chumsky::prelude::*;
+
+use crate::frontend::{ParserError, Spanned};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token<'a> {
+    Ident(&'a str),
+    IntConst(i32),
+    // keywords
+    KwInt,
+    KwVoid,
+    KwIf,
+    KwElse,
+    KwWhile,
+    KwBreak,
+    KwContinue,
+    KwReturn,
+    // operators
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Not,
+    AndAnd,
+    OrOr,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    EqEq,
+    NotEq,
+    Assign,
+    // delimiters
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Comma,
+    SemiColon,
+    // malformed token, already reported by the lexer
+    Unknown,
+}
+
+impl<'a> fmt::Display for Token<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Token::*;
+        match self {
+            Ident(ident) => write!(f, "{}", ident),
+            IntConst(lit) => write!(f, "{}", lit),
+            KwInt => write!(f, "int"),
+            KwVoid => write!(f, "void"),
+            KwIf => write!(f, "if"),
+            KwElse => write!(f, "else"),
+            KwWhile => write!(f, "while"),
+            KwBreak => write!(f, "break"),
+            KwContinue => write!(f, "continue"),
+            KwReturn => write!(f, "return"),
+            Plus => write!(f, "+"),
+            Minus => write!(f, "-"),
+            Star => write!(f, "*"),
+            Slash => write!(f, "/"),
+            Percent => write!(f, "%"),
+            Not => write!(f, "!"),
+            AndAnd => write!(f, "&&"),
+            OrOr => write!(f, "||"),
+            Lt => write!(f, "<"),
+            Gt => write!(f, ">"),
+            Le => write!(f, "<="),
+            Ge => write!(f, ">="),
+            EqEq => write!(f, "=="),
+            NotEq => write!(f, "!="),
+            Assign => write!(f, "="),
+            LParen => write!(f, "("),
+            RParen => write!(f, ")"),
+            LBracket => write!(f, "["),
+            RBracket => write!(f, "]"),
+            LBrace => write!(f, "{{"),
+            RBrace => write!(f, "}}"),
+            Comma => write!(f, ","),
+            SemiColon => write!(f, ";"),
+            Unknown => write!(f, "<unknown>"),
+        }
+    }
+}
+
+pub fn lexer<'a>() -> impl Parser<'a, &'a str, Vec<Spanned<Token<'a>>>, ParserError<'a, char>> {
+    let ident = text::ascii::ident().map(| ident | match ident {
+        "int" => Token::KwInt,
+        "void" => Token::KwVoid,
+        "if" => Token::KwIf,
+        "else" => Token::KwElse,
+        "while" => Token::KwWhile,
+        "break" => Token::KwBreak,
+        "continue" => Token::KwContinue,
+        "return" => Token::KwReturn,
+        _ => Token::Ident(ident),
+    });
+
+    // decimal literal only, digits followed by identifier characters such as `3c` are malformed.
+    let int_const = text::digits(10)
+        .then(any().filter(| c: &char | c.is_ascii_alphanumeric() || *c == '_').repeated())
+        .to_slice()
+        .validate(| lit: &str, extra, emitter | {
+            if lit.contains(| c: char | !c.is_ascii_digit()) {
+                emitter.emit(Rich::custom(extra.span(), format!("invalid identifier `{}`", lit)));
+                return Token::Unknown;
+            }
+            lit.parse::<i32>()
+                .map(Token::IntConst)
+                .unwrap_or_else(| err | {
+                    emitter.emit(Rich::custom(extra.span(), format!("invalid integer literal `{}`: {}", lit, err)));
+                    Token::Unknown
+                })
+        });
+
+    // two-character operators go first.
+    let operator = choice((
+        just("&&").to(Token::AndAnd),
+        just("||").to(Token::OrOr),
+        just("<=").to(Token::Le),
+        just(">=").to(Token::Ge),
+        just("==").to(Token::EqEq),
+        just("!=").to(Token::NotEq),
+        one_of("+-*/%!<>=").map(| c | match c {
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '/' => Token::Slash,
+            '%' => Token::Percent,
+            '!' => Token::Not,
+            '<' => Token::Lt,
+            '>' => Token::Gt,
+            _ => Token::Assign,
+        }),
+    ));
+
+    let delimiter = one_of("()[]{},;").map(| c | match c {
+        '(' => Token::LParen,
+        ')' => Token::RParen,
+        '[' => Token::LBracket,
+        ']' => Token::RBracket,
+        '{' => Token::LBrace,
+        '}' => Token::RBrace,
+        ',' => Token::Comma,
+        _ => Token::SemiColon,
+    });
+
+    let single_comment = just::<_, &str, ParserError<'a, char>>("//")
+        .ignore_then(none_of("\n\r").repeated())
+        .padded();
+
+    let multi_comment = just::<_, &str, ParserError<'a, char>>("/*")
+        .ignore_then(any().and_is(just("*/").not()).repeated())
+        .then_ignore(just("*/"))
+        .padded();
+
+    let comment = single_comment.or(multi_comment);
+
+    let token = choice((ident, int_const, operator, delimiter));
+
+    token
+        .map_with(| tok, extra | Spanned(tok, extra.span()))
+        .padded_by(comment.repeated())
+        .padded()
+        .recover_with(skip_then_retry_until(any().ignored(), end()))
+        .repeated()
+        .collect::<Vec<Spanned<Token<'a>>>>()
+        .then_ignore(comment.repeated())
+        .padded()
+        .then_ignore(end())
+}
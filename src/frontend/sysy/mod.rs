@@ -0,0 +1,152 @@
+//! Compiler frontend of SysY described in `docs/appendix/sysy-spec.md`,
+//! extended with nested array initializers.
+//!
+//! The source is lexed and parsed into an AST, checked against the semantic constraints,
+//! then translated into a module following `docs/appendix/sysy-accipit-mapping.md`.
+
+pub mod ast;
+pub mod lexer;
+pub mod parser;
+pub mod semantic;
+pub mod irgen;
+
+use std::ops::Range;
+
+use chumsky::prelude::*;
+use chumsky::input::SpannedInput;
+use ariadne::{Color, Label, Report, ReportKind};
+
+use crate::ir::structures::Module;
+
+use super::{new_parser::{self, Diagnostic}, Span, Spanned};
+use lexer::Token;
+
+type TokenInput<'t, 'a> = SpannedInput<Token<'a>, Span, &'t [(Token<'a>, Span)]>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Lexing and parsing error.
+    Syntax,
+    Semantic,
+}
+
+#[derive(Debug, Clone)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub diagnostic: Diagnostic,
+}
+
+impl Error {
+    /// Build an ariadne report of the error.
+    pub fn report(&self, filename: &str) -> Report<'static, (String, Range<usize>)> {
+        let span = self.diagnostic.span().into_range();
+        let message = match self.kind {
+            ErrorKind::Syntax => "syntax error",
+            ErrorKind::Semantic => "semantic error",
+        };
+        Report::build(ReportKind::Error, filename.to_string(), span.start)
+            .with_message(message)
+            .with_label(Label::new((filename.to_string(), span))
+                .with_message(new_parser::message(&self.diagnostic))
+                .with_color(Color::Red))
+            .with_labels(self.diagnostic.contexts().map(| (label, span) | {
+                Label::new((filename.to_string(), span.into_range()))
+                    .with_message(format!("while parsing this {}", label))
+                    .with_color(Color::Yellow)
+            }))
+            .finish()
+    }
+}
+
+/// Compile SysY source text into a module,
+/// collecting all syntax errors, or all semantic errors if the syntax is correct.
+pub fn compile(src: &str) -> Result<Module, Vec<Error>> {
+    let syntax_error = | diagnostic | Error { kind: ErrorKind::Syntax, diagnostic };
+    let (tokens, lex_errs) = lexer::lexer().parse(src).into_output_errors();
+    let mut errors: Vec<Error> = lex_errs
+        .into_iter()
+        .map(| err | syntax_error(err.map_token(| c | c.to_string()).into_owned()))
+        .collect();
+
+    let tokens = tokens
+        .unwrap_or_default()
+        .into_iter()
+        .map(| Spanned(token, span) | (token, span))
+        .collect::<Vec<_>>();
+    let eoi: Span = (src.len()..src.len()).into();
+    let input: TokenInput = tokens.as_slice().spanned(eoi);
+    let (items, parse_errs) = parser::parser().parse(input).into_output_errors();
+    errors.extend(parse_errs
+        .into_iter()
+        // invalid tokens are already reported by the lexer.
+        .filter(| err | err.found() != Some(&Token::Unknown))
+        .map(| err | syntax_error(err.map_token(| token | token.to_string()).into_owned())));
+
+    let items = match items {
+        Some(items) if errors.is_empty() => items,
+        _ => return Err(errors),
+    };
+    semantic::check(&items, eoi)
+        .map_err(| errors | errors
+            .into_iter()
+            .map(| diagnostic | Error { kind: ErrorKind::Semantic, diagnostic })
+            .collect::<Vec<_>>())?;
+    Ok(irgen::generate(&items))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::apps::executor::{run_on_module, ProgramEnv, Val};
+    use crate::ir::verify::verify_module;
+
+    use super::{compile, ErrorKind};
+
+    #[test]
+    fn test_compile_and_run() {
+        let src = r"
+            int g[2][3] = {1, 2, 3, {4}};
+            int count;
+            int visit() { count = count + 1; return 1; }
+            int sum(int a[][3], int n) {
+                int i = 0, s = 0;
+                while (1) {
+                    if (i >= n) break;
+                    s = s + a[i][0] * 10 + a[i][1];
+                    i = i + 1;
+                }
+                return s;
+            }
+            int main() {
+                int local[3] = {sum(g, 2)};
+                // the right operands are never evaluated.
+                if (0 && visit() || 1 || visit()) local[1] = count;
+                return local[0] * 100 + local[1] * 10 + !local[2];
+            }
+        ";
+        let module = compile(src).unwrap();
+        assert!(verify_module(&module).is_ok());
+        // (1 * 10 + 2) + (4 * 10 + 0) = 52
+        let result = run_on_module(&mut ProgramEnv::new(), &module, "main", vec![]).unwrap();
+        assert_eq!(result, Val::Integer(5201));
+    }
+
+    #[test]
+    fn test_errors() {
+        let syntax = compile("int main() { int 3c = 1; return 0 }").unwrap_err();
+        assert!(syntax.iter().all(| err | err.kind == ErrorKind::Syntax));
+        assert_eq!(syntax.len(), 2, "{:?}", syntax);
+
+        let src = r"
+            int f(int a[]) { return a; }
+            int main() {
+                int a[2] = {1, 2, 3};
+                break;
+                return f(a[0]) + b;
+            }
+        ";
+        let semantic = compile(src).unwrap_err();
+        assert!(semantic.iter().all(| err | err.kind == ErrorKind::Semantic));
+        // return type mismatch, excess element, `break` outside loop, argument mismatch and undefined `b`
+        assert_eq!(semantic.len(), 5, "{:?}", semantic);
+    }
+}
@@ -0,0 +1,276 @@
+use chumsky::prelude::*;
+use chumsky::input::ValueInput;
+
+use crate::frontend::{ParserError, Span, Spanned};
+
+use super::{ast::*, lexer::Token};
+
+fn identifier<'a, I>() -> impl Parser<'a, I, Spanned<&'a str>, ParserError<'a, Token<'a>>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = Span>
+{
+    select! { Token::Ident(name) => name }
+        .map_with(| name, extra | Spanned(name, extra.span()))
+        .labelled("identifier")
+}
+
+fn int_const<'a, I>() -> impl Parser<'a, I, Spanned<i32>, ParserError<'a, Token<'a>>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = Span>
+{
+    select! { Token::IntConst(lit) => lit }
+        .map_with(| lit, extra | Spanned(lit, extra.span()))
+        .labelled("integer literal")
+}
+
+/// Fold `lhs (op rhs)*` left associatively, spanning from the leftmost to the rightmost operand.
+fn binary<'a, I>(
+    operand: impl Parser<'a, I, Expr<'a>, ParserError<'a, Token<'a>>> + Clone + 'a,
+    op: impl Parser<'a, I, BinaryOp, ParserError<'a, Token<'a>>> + Clone + 'a,
+) -> Boxed<'a, 'a, I, Expr<'a>, ParserError<'a, Token<'a>>>
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = Span>
+{
+    operand.clone()
+        .foldl(op.then(operand).repeated(), | lhs, (op, rhs) | {
+            let span = Span::new(lhs.span().start, rhs.span().end);
+            Spanned(ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)), span)
+        })
+        .boxed()
+}
+
+pub fn expression<'a, I>() -> impl Parser<'a, I, Expr<'a>, ParserError<'a, Token<'a>>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = Span>
+{
+    recursive(| expr | {
+        let call = identifier()
+            .then(expr.clone()
+                .separated_by(just(Token::Comma))
+                .collect::<Vec<_>>()
+                .delimited_by(just(Token::LParen), just(Token::RParen)))
+            .map(| (callee, args) | ExprKind::Call(callee, args));
+
+        let primary = choice((
+            expr.clone().delimited_by(just(Token::LParen), just(Token::RParen)),
+            call
+                .or(lvalue(expr).map(ExprKind::LVal))
+                .or(int_const().map(| lit | ExprKind::Number(lit.0)))
+                .map_with(| kind, extra | Spanned(kind, extra.span())),
+        )).labelled("expression");
+
+        let unary_op = select! {
+            Token::Plus => UnaryOp::Plus,
+            Token::Minus => UnaryOp::Minus,
+            Token::Not => UnaryOp::Not,
+        }.map_with(| op, extra | Spanned(op, extra.span()));
+
+        let unary = unary_op
+            .repeated()
+            .foldr(primary, | Spanned(op, span), operand | {
+                let span = Span::new(span.start, operand.span().end);
+                Spanned(ExprKind::Unary(op, Box::new(operand)), span)
+            })
+            .boxed();
+
+        let mul = binary(unary, select! {
+            Token::Star => BinaryOp::Mul,
+            Token::Slash => BinaryOp::Div,
+            Token::Percent => BinaryOp::Rem,
+        });
+        let add = binary(mul, select! {
+            Token::Plus => BinaryOp::Add,
+            Token::Minus => BinaryOp::Sub,
+        });
+        let rel = binary(add, select! {
+            Token::Lt => BinaryOp::Lt,
+            Token::Gt => BinaryOp::Gt,
+            Token::Le => BinaryOp::Le,
+            Token::Ge => BinaryOp::Ge,
+        });
+        let eq = binary(rel, select! {
+            Token::EqEq => BinaryOp::Eq,
+            Token::NotEq => BinaryOp::Ne,
+        });
+        let and = binary(eq, just(Token::AndAnd).to(BinaryOp::And));
+        binary(and, just(Token::OrOr).to(BinaryOp::Or))
+    })
+}
+
+fn lvalue<'a, I>(
+    expr: impl Parser<'a, I, Expr<'a>, ParserError<'a, Token<'a>>> + Clone
+) -> impl Parser<'a, I, LVal<'a>, ParserError<'a, Token<'a>>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = Span>
+{
+    identifier()
+        .then(expr
+            .delimited_by(just(Token::LBracket), just(Token::RBracket))
+            .repeated()
+            .collect::<Vec<_>>())
+        .map(| (name, indices) | LVal { name, indices })
+}
+
+fn declaration<'a, I>() -> impl Parser<'a, I, Vec<VarDef<'a>>, ParserError<'a, Token<'a>>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = Span>
+{
+    let init_val = recursive(| init_val | {
+        init_val
+            .separated_by(just(Token::Comma))
+            .collect::<Vec<_>>()
+            .delimited_by(just(Token::LBrace), just(Token::RBrace))
+            .map_with(| list, extra | InitVal::List(list, extra.span()))
+            .or(expression().map(InitVal::Expr))
+    });
+
+    let var_def = identifier()
+        .then(int_const()
+            .delimited_by(just(Token::LBracket), just(Token::RBracket))
+            .repeated()
+            .collect::<Vec<_>>())
+        .then(just(Token::Assign).ignore_then(init_val).or_not())
+        .map(| ((name, dims), init) | VarDef { name, dims, init });
+
+    just(Token::KwInt)
+        .ignore_then(var_def
+            .separated_by(just(Token::Comma))
+            .at_least(1)
+            .collect::<Vec<_>>())
+        .then_ignore(just(Token::SemiColon))
+        .labelled("declaration")
+}
+
+fn block<'a, I>() -> impl Parser<'a, I, Block<'a>, ParserError<'a, Token<'a>>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = Span>
+{
+    recursive(| block | {
+        let stmt = recursive(| stmt | {
+            let assign = lvalue(expression())
+                .then_ignore(just(Token::Assign))
+                .then(expression())
+                .then_ignore(just(Token::SemiColon))
+                .map(| (lval, expr) | StmtKind::Assign(lval, expr));
+
+            let expr = expression()
+                .or_not()
+                .then_ignore(just(Token::SemiColon))
+                .map(StmtKind::Expr);
+
+            let condition = expression()
+                .delimited_by(just(Token::LParen), just(Token::RParen));
+
+            // `else` binds to the nearest `if`.
+            let if_stmt = just(Token::KwIf)
+                .ignore_then(condition.clone())
+                .then(stmt.clone().map(Box::new))
+                .then(just(Token::KwElse).ignore_then(stmt.clone().map(Box::new)).or_not())
+                .map(| ((cond, then), otherwise) | StmtKind::If(cond, then, otherwise));
+
+            let while_stmt = just(Token::KwWhile)
+                .ignore_then(condition)
+                .then(stmt.map(Box::new))
+                .map(| (cond, body) | StmtKind::While(cond, body));
+
+            let jump = choice((
+                just(Token::KwBreak).to(StmtKind::Break),
+                just(Token::KwContinue).to(StmtKind::Continue),
+                just(Token::KwReturn)
+                    .ignore_then(expression().or_not())
+                    .map(StmtKind::Return),
+            )).then_ignore(just(Token::SemiColon));
+
+            // skip a malformed statement up to the next `;`, except declarations.
+            let recovery = none_of([Token::SemiColon, Token::LBrace, Token::RBrace, Token::KwInt])
+                .then(none_of([Token::SemiColon, Token::LBrace, Token::RBrace]).repeated())
+                .then(just(Token::SemiColon))
+                .to(StmtKind::Expr(None));
+
+            choice((
+                block.clone().map(StmtKind::Block),
+                if_stmt,
+                while_stmt,
+                jump,
+                assign,
+                expr,
+            ))
+                .recover_with(via_parser(recovery))
+                .map_with(| kind, extra | Spanned(kind, extra.span()))
+                .labelled("statement")
+        });
+
+        // skip a malformed declaration up to the next `;`.
+        let recovery = just(Token::KwInt)
+            .then(none_of([Token::SemiColon, Token::LBrace, Token::RBrace]).repeated())
+            .then(just(Token::SemiColon))
+            .to(Vec::new());
+
+        declaration()
+            .recover_with(via_parser(recovery))
+            .map(BlockItem::Decl)
+            .or(stmt.map(BlockItem::Stmt))
+            .repeated()
+            .collect::<Vec<_>>()
+            .delimited_by(just(Token::LBrace), just(Token::RBrace))
+            .recover_with(via_parser(nested_delimiters(
+                Token::LBrace,
+                Token::RBrace,
+                [(Token::LParen, Token::RParen), (Token::LBracket, Token::RBracket)],
+                | _ | Vec::new()
+            )))
+            .boxed()
+    })
+}
+
+fn function<'a, I>() -> impl Parser<'a, I, FuncDef<'a>, ParserError<'a, Token<'a>>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = Span>
+{
+    // the first dimension of array parameter is omitted.
+    let param_dims = just(Token::LBracket)
+        .then(just(Token::RBracket))
+        .ignore_then(int_const()
+            .delimited_by(just(Token::LBracket), just(Token::RBracket))
+            .repeated()
+            .collect::<Vec<_>>());
+
+    let param = just(Token::KwInt)
+        .ignore_then(identifier())
+        .then(param_dims.or_not())
+        .map(| (name, dims) | Param { name, dims })
+        .labelled("parameter");
+
+    let ret = select! {
+        Token::KwInt => FuncType::Int,
+        Token::KwVoid => FuncType::Void,
+    };
+
+    ret
+        .then(identifier())
+        .then(param
+            .separated_by(just(Token::Comma))
+            .collect::<Vec<_>>()
+            .delimited_by(just(Token::LParen), just(Token::RParen)))
+        .then(block())
+        .map(| (((ret, name), params), body) | FuncDef { ret, name, params, body })
+        .labelled("function")
+}
+
+pub fn parser<'a, I>() -> impl Parser<'a, I, Vec<Item<'a>>, ParserError<'a, Token<'a>>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = Span>
+{
+    // skip a malformed item up to the next `int` or `void`.
+    let recovery = any()
+        .then(none_of([Token::KwInt, Token::KwVoid]).repeated())
+        .to(None);
+
+    choice((function().map(Item::Func), declaration().map(Item::Decl)))
+        .map(Some)
+        .recover_with(via_parser(recovery))
+        .repeated()
+        .collect::<Vec<_>>()
+        .map(| items | items.into_iter().flatten().collect())
+        .then_ignore(end())
+}
@@ -0,0 +1,493 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use chumsky::prelude::Rich;
+
+use crate::frontend::{new_parser::Diagnostic, Span, Spanned};
+
+use super::ast::*;
+
+/// Type of SysY expressions and variables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ty {
+    Int,
+    Void,
+    /// Array of `int`, the first dimension is `None` for array parameters.
+    Array(Vec<Option<usize>>),
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ty::Int => write!(f, "int"),
+            Ty::Void => write!(f, "void"),
+            Ty::Array(dims) => {
+                write!(f, "int")?;
+                dims.iter().try_for_each(| dim | match dim {
+                    Some(dim) => write!(f, "[{}]", dim),
+                    None => write!(f, "[]"),
+                })
+            }
+        }
+    }
+}
+
+impl From<FuncType> for Ty {
+    fn from(ty: FuncType) -> Ty {
+        match ty {
+            FuncType::Int => Ty::Int,
+            FuncType::Void => Ty::Void,
+        }
+    }
+}
+
+/// Signatures of the SysY runtime functions, callable without definition.
+pub fn runtime_functions() -> Vec<(&'static str, FuncType, Vec<Ty>)> {
+    let int_array = Ty::Array(vec![None]);
+    vec![
+        ("getint", FuncType::Int, vec![]),
+        ("getch", FuncType::Int, vec![]),
+        ("getarray", FuncType::Int, vec![int_array.clone()]),
+        ("putint", FuncType::Void, vec![Ty::Int]),
+        ("putch", FuncType::Void, vec![Ty::Int]),
+        ("putarray", FuncType::Void, vec![Ty::Int, int_array]),
+        ("starttime", FuncType::Void, vec![]),
+        ("stoptime", FuncType::Void, vec![]),
+    ]
+}
+
+/// Array dimensions, checked to be positive by the semantic checker.
+pub fn array_dims(dims: &[Spanned<i32>]) -> Vec<usize> {
+    dims.iter().map(| dim | dim.0.max(0) as usize).collect()
+}
+
+/// Flatten the initializer list of an array with dimensions `dims`,
+/// giving each expression its index in the row-major layout of the array.
+///
+/// Braces may be elided as in C: scalars fill elements in order,
+/// and a nested list initializes the largest sub-array aligned at the current element.
+pub fn flatten_initializer<'e, 'a>(
+    dims: &[usize],
+    list: &'e [InitVal<'a>],
+    span: Span,
+) -> Result<Vec<(usize, &'e Expr<'a>)>, Diagnostic> {
+    fn flatten<'e, 'a>(
+        dims: &[usize],
+        list: &'e [InitVal<'a>],
+        base: usize,
+        flattened: &mut Vec<(usize, &'e Expr<'a>)>,
+    ) -> Result<(), Diagnostic> {
+        let size: usize = dims.iter().product();
+        let mut position = 0;
+        for init in list {
+            if position >= size {
+                return Err(Rich::custom(init.span(), "excess elements in array initializer".to_string()));
+            }
+            match init {
+                InitVal::Expr(expr) => {
+                    flattened.push((base + position, expr));
+                    position += 1;
+                },
+                InitVal::List(sublist, span) => {
+                    let sub_dims = (1..dims.len())
+                        .map(| k | &dims[k..])
+                        .find(| sub_dims | position % sub_dims.iter().product::<usize>() == 0)
+                        .ok_or_else(|| Rich::custom(*span, "braces around scalar initializer".to_string()))?;
+                    flatten(sub_dims, sublist, base + position, flattened)?;
+                    position += sub_dims.iter().product::<usize>();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    if dims.is_empty() {
+        return Err(Rich::custom(span, "braces around scalar initializer".to_string()));
+    }
+    let mut flattened = Vec::new();
+    flatten(dims, list, 0, &mut flattened)?;
+    Ok(flattened)
+}
+
+/// Evaluate the constant expression, `None` if it reads variables, calls functions or divides by zero.
+pub fn evaluate_constant(expr: &Expr) -> Option<i32> {
+    match expr.item() {
+        ExprKind::Number(lit) => Some(*lit),
+        ExprKind::LVal(_) | ExprKind::Call(..) => None,
+        ExprKind::Unary(op, operand) => {
+            let operand = evaluate_constant(operand)?;
+            Some(match op {
+                UnaryOp::Plus => operand,
+                UnaryOp::Minus => operand.wrapping_neg(),
+                UnaryOp::Not => (operand == 0) as i32,
+            })
+        },
+        ExprKind::Binary(op, lhs, rhs) => {
+            let lhs = evaluate_constant(lhs)?;
+            let rhs = evaluate_constant(rhs)?;
+            Some(match op {
+                BinaryOp::Mul => lhs.wrapping_mul(rhs),
+                BinaryOp::Div => lhs.checked_div(rhs)?,
+                BinaryOp::Rem => lhs.checked_rem(rhs)?,
+                BinaryOp::Add => lhs.wrapping_add(rhs),
+                BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                BinaryOp::Lt => (lhs < rhs) as i32,
+                BinaryOp::Gt => (lhs > rhs) as i32,
+                BinaryOp::Le => (lhs <= rhs) as i32,
+                BinaryOp::Ge => (lhs >= rhs) as i32,
+                BinaryOp::Eq => (lhs == rhs) as i32,
+                BinaryOp::Ne => (lhs != rhs) as i32,
+                BinaryOp::And => (lhs != 0 && rhs != 0) as i32,
+                BinaryOp::Or => (lhs != 0 || rhs != 0) as i32,
+            })
+        }
+    }
+}
+
+fn binary_op_str(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::Rem => "%",
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Lt => "<",
+        BinaryOp::Gt => ">",
+        BinaryOp::Le => "<=",
+        BinaryOp::Ge => ">=",
+        BinaryOp::Eq => "==",
+        BinaryOp::Ne => "!=",
+        BinaryOp::And => "&&",
+        BinaryOp::Or => "||",
+    }
+}
+
+/// Functions and variables live in separate namespaces,
+/// so a global variable may share its name with a function as `tests/lab2/redef_fun_var.sy` expects.
+struct Checker<'a> {
+    functions: HashMap<&'a str, (FuncType, Vec<Ty>)>,
+    /// Variable scopes from the global scope to the innermost block.
+    scopes: Vec<HashMap<&'a str, Ty>>,
+    ret: FuncType,
+    loop_depth: usize,
+    errors: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    fn error(&mut self, span: Span, message: String) {
+        self.errors.push(Rich::custom(span, message));
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Ty> {
+        self.scopes.iter().rev().find_map(| scope | scope.get(name))
+    }
+
+    /// Define a variable in the innermost scope.
+    fn define(&mut self, name: Spanned<&'a str>, ty: Ty) {
+        let scope = self.scopes.last_mut().unwrap();
+        if scope.contains_key(name.0) {
+            self.error(name.span(), format!("redefinition of `{}`", name.0));
+        } else {
+            scope.insert(name.0, ty);
+        }
+    }
+
+    fn check_dims(&mut self, dims: &[Spanned<i32>]) -> bool {
+        let mut valid = true;
+        for dim in dims {
+            if dim.0 <= 0 {
+                self.error(dim.span(), format!("array dimension `{}` is not positive", dim.0));
+                valid = false;
+            }
+        }
+        valid
+    }
+
+    /// Check an expression used as an `int` operand.
+    fn check_int(&mut self, expr: &Expr<'a>, what: impl FnOnce(&Ty) -> String) {
+        if let Some(ty) = self.check_expr(expr) {
+            if ty != Ty::Int {
+                let message = what(&ty);
+                self.error(expr.span(), message);
+            }
+        }
+    }
+
+    fn check_lval(&mut self, lval: &LVal<'a>) -> Option<Ty> {
+        let ty = self.lookup(lval.name.0).cloned();
+        for index in lval.indices.iter() {
+            self.check_int(index, | ty | format!("array subscript is not an integer, but `{}`", ty));
+        }
+        let ty = match ty {
+            Some(ty) => ty,
+            None => {
+                let message = if self.functions.contains_key(lval.name.0) {
+                    format!("function `{}` is used as a variable", lval.name.0)
+                } else {
+                    format!("variable `{}` is not defined", lval.name.0)
+                };
+                self.error(lval.name.span(), message);
+                return None;
+            }
+        };
+        match ty {
+            Ty::Array(dims) if lval.indices.len() <= dims.len() =>
+                if lval.indices.len() == dims.len() {
+                    Some(Ty::Int)
+                } else {
+                    Some(Ty::Array(dims[lval.indices.len()..].to_vec()))
+                },
+            Ty::Array(dims) => {
+                self.error(lval.indices[dims.len()].span(),
+                    format!("too many subscripts for `{}` of type `{}`", lval.name.0, Ty::Array(dims.clone())));
+                None
+            },
+            _ if !lval.indices.is_empty() => {
+                self.error(lval.indices[0].span(), format!("subscripted value `{}` is not an array", lval.name.0));
+                None
+            },
+            ty => Some(ty),
+        }
+    }
+
+    fn check_call(&mut self, callee: &Spanned<&'a str>, args: &[Expr<'a>], span: Span) -> Option<Ty> {
+        let args_ty = args
+            .iter()
+            .map(| arg | self.check_expr(arg))
+            .collect::<Vec<_>>();
+        // global variables do not hide functions of the same name.
+        if self.scopes[1..].iter().any(| scope | scope.contains_key(callee.0)) {
+            self.error(callee.span(), format!("called object `{}` is not a function", callee.0));
+            return None;
+        }
+        let (ret, params) = match self.functions.get(callee.0) {
+            Some(function) => function.clone(),
+            None => {
+                self.error(callee.span(), format!("function `{}` is not defined", callee.0));
+                return None;
+            }
+        };
+        if params.len() != args.len() {
+            self.error(span, format!("function `{}` expects {} arguments, but {} given", callee.0, params.len(), args.len()));
+            return Some(ret.into());
+        }
+        for ((param, arg_ty), arg) in params.iter().zip(args_ty).zip(args) {
+            // an array argument matches if all but the first dimension are the same.
+            let matched = match (param, &arg_ty) {
+                (_, None) => true,
+                (Ty::Array(param_dims), Some(Ty::Array(arg_dims))) =>
+                    param_dims.len() == arg_dims.len() && param_dims[1..] == arg_dims[1..],
+                (param, Some(arg_ty)) => param == arg_ty,
+            };
+            if !matched {
+                self.error(arg.span(), format!("no matching function for call to `{}`, cannot convert `{}` to `{}`",
+                    callee.0, arg_ty.unwrap(), param));
+            }
+        }
+        Some(ret.into())
+    }
+
+    /// Type of the expression, `None` if it is ill-typed and the error is already reported.
+    fn check_expr(&mut self, expr: &Expr<'a>) -> Option<Ty> {
+        match expr.item() {
+            ExprKind::Number(_) => Some(Ty::Int),
+            ExprKind::LVal(lval) => self.check_lval(lval),
+            ExprKind::Call(callee, args) => self.check_call(callee, args, expr.span()),
+            ExprKind::Unary(_, operand) => {
+                let ty = self.check_expr(operand)?;
+                if ty != Ty::Int {
+                    self.error(operand.span(), format!("invalid operand of type `{}` to unary operator", ty));
+                    return None;
+                }
+                Some(Ty::Int)
+            },
+            ExprKind::Binary(op, lhs, rhs) => {
+                let lhs_ty = self.check_expr(lhs);
+                let rhs_ty = self.check_expr(rhs);
+                match (lhs_ty?, rhs_ty?) {
+                    (Ty::Int, Ty::Int) => Some(Ty::Int),
+                    (lhs_ty, rhs_ty) => {
+                        self.error(expr.span(), format!("invalid operands to binary `{}` (`{}` and `{}`)",
+                            binary_op_str(*op), lhs_ty, rhs_ty));
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_var_def(&mut self, def: &VarDef<'a>) {
+        let is_global = self.scopes.len() == 1;
+        let valid_dims = self.check_dims(&def.dims);
+        match &def.init {
+            None => (),
+            Some(InitVal::Expr(expr)) if def.dims.is_empty() => {
+                self.check_int(expr, | ty | format!("initializing `int` with incompatible type `{}`", ty));
+                if is_global && evaluate_constant(expr).is_none() {
+                    self.error(expr.span(), "initializer element is not a compile-time constant".to_string());
+                }
+            },
+            Some(InitVal::Expr(expr)) => {
+                self.error(expr.span(), format!("array `{}` must be initialized by an initializer list", def.name.0));
+            },
+            Some(InitVal::List(list, span)) => {
+                let flattened = if valid_dims {
+                    flatten_initializer(&array_dims(&def.dims), list, *span)
+                        .inspect_err(| err | self.errors.push(err.clone()))
+                        .unwrap_or_default()
+                } else {
+                    Vec::new()
+                };
+                for (_, expr) in flattened {
+                    self.check_int(expr, | ty | format!("initializing `int` with incompatible type `{}`", ty));
+                    if is_global && evaluate_constant(expr).is_none() {
+                        self.error(expr.span(), "initializer element is not a compile-time constant".to_string());
+                    }
+                }
+            }
+        }
+        // the variable is visible after its initializer.
+        let ty = if def.dims.is_empty() {
+            Ty::Int
+        } else {
+            Ty::Array(array_dims(&def.dims).into_iter().map(Some).collect())
+        };
+        self.define(def.name.clone(), ty);
+    }
+
+    fn check_block(&mut self, block: &Block<'a>) {
+        for item in block {
+            match item {
+                BlockItem::Decl(defs) => defs.iter().for_each(| def | self.check_var_def(def)),
+                BlockItem::Stmt(stmt) => self.check_stmt(stmt),
+            }
+        }
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt<'a>) {
+        match stmt.item() {
+            StmtKind::Assign(lval, expr) => {
+                let lhs_ty = self.check_lval(lval);
+                let rhs_ty = self.check_expr(expr);
+                match lhs_ty {
+                    Some(Ty::Int) | None => (),
+                    Some(ty) => self.error(lval.name.span(), format!("array type `{}` is not assignable", ty)),
+                }
+                match rhs_ty {
+                    Some(Ty::Int) | None => (),
+                    Some(ty) => self.error(expr.span(), format!("assigning to `int` from incompatible type `{}`", ty)),
+                }
+            },
+            StmtKind::Expr(expr) => {
+                if let Some(expr) = expr {
+                    self.check_expr(expr);
+                }
+            },
+            StmtKind::Block(block) => {
+                self.scopes.push(HashMap::new());
+                self.check_block(block);
+                self.scopes.pop();
+            },
+            StmtKind::If(cond, then, otherwise) => {
+                self.check_int(cond, | ty | format!("condition of type `{}` is not an integer", ty));
+                self.check_stmt(then);
+                if let Some(otherwise) = otherwise {
+                    self.check_stmt(otherwise);
+                }
+            },
+            StmtKind::While(cond, body) => {
+                self.check_int(cond, | ty | format!("condition of type `{}` is not an integer", ty));
+                self.loop_depth += 1;
+                self.check_stmt(body);
+                self.loop_depth -= 1;
+            },
+            StmtKind::Break | StmtKind::Continue => {
+                if self.loop_depth == 0 {
+                    self.error(stmt.span(), "`break` or `continue` statement not within a loop".to_string());
+                }
+            },
+            StmtKind::Return(expr) => match (self.ret, expr) {
+                (FuncType::Int, Some(expr)) =>
+                    self.check_int(expr, | ty | format!("return type mismatch, expect `int` but found `{}`", ty)),
+                (FuncType::Void, None) => (),
+                (FuncType::Int, None) =>
+                    self.error(stmt.span(), "return type mismatch, non-void function should return a value".to_string()),
+                (FuncType::Void, Some(expr)) => {
+                    self.check_expr(expr);
+                    self.error(stmt.span(), "return type mismatch, void function should not return a value".to_string());
+                }
+            },
+        }
+    }
+
+    fn check_function(&mut self, func: &FuncDef<'a>) {
+        let mut params_ty = Vec::new();
+        let mut scope = HashMap::new();
+        for param in func.params.iter() {
+            let ty = match &param.dims {
+                None => Ty::Int,
+                Some(dims) => {
+                    self.check_dims(dims);
+                    Ty::Array(std::iter::once(None)
+                        .chain(array_dims(dims).into_iter().map(Some))
+                        .collect())
+                }
+            };
+            if scope.insert(param.name.0, ty.clone()).is_some() {
+                self.error(param.name.span(), format!("redefinition of parameter `{}`", param.name.0));
+            }
+            params_ty.push(ty);
+        }
+
+        // define the function before its body for recursion.
+        if self.functions.contains_key(func.name.0) {
+            self.error(func.name.span(), format!("redefinition of `{}`", func.name.0));
+        } else {
+            self.functions.insert(func.name.0, (func.ret, params_ty));
+        }
+
+        // parameters share the scope with the outermost block of function body.
+        self.ret = func.ret;
+        self.scopes.push(scope);
+        self.check_block(&func.body);
+        self.scopes.pop();
+    }
+}
+
+/// Check the compile unit against the semantic constraints of SysY,
+/// collecting all errors found.
+pub fn check(items: &[Item], eoi: Span) -> Result<(), Vec<Diagnostic>> {
+    let mut checker = Checker {
+        functions: runtime_functions()
+            .into_iter()
+            .map(| (name, ret, params) | (name, (ret, params)))
+            .collect(),
+        scopes: vec![HashMap::new()],
+        ret: FuncType::Void,
+        loop_depth: 0,
+        errors: Vec::new(),
+    };
+    for item in items {
+        match item {
+            Item::Decl(defs) => defs.iter().for_each(| def | checker.check_var_def(def)),
+            Item::Func(func) => checker.check_function(func),
+        }
+    }
+
+    let main = items.iter().find_map(| item | match item {
+        Item::Func(func) if func.name.0 == "main" => Some(func),
+        _ => None
+    });
+    match main {
+        Some(main) if main.ret != FuncType::Int || !main.params.is_empty() =>
+            checker.error(main.name.span(), "`main` should be defined as `int main()`".to_string()),
+        Some(_) => (),
+        None => checker.error(eoi, "function `int main()` is not defined".to_string()),
+    }
+
+    if checker.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(checker.errors)
+    }
+}
@@ -5,6 +5,8 @@ use std::slice::Iter;
 use std::iter::Enumerate;
 use std::ops::Index;
 
+use super::{Span, Spanned};
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Token<'a> {
     // Identifier
@@ -40,6 +42,8 @@ pub enum Token<'a> {
     TkAlloca,
     TkLoad,
     TkStore,
+    // SSA
+    TkPhi,
     // Function Call
     TkFnCall,
     // Terminators
@@ -100,6 +104,7 @@ impl<'a> fmt::Display for Token<'a> {
             TkAlloca => write!(f, "alloca"),
             TkLoad => write!(f, "load"),
             TkStore => write!(f, "store"),
+            TkPhi => write!(f, "phi"),
             TkFnCall => write!(f, "call"),
             TkJmp => write!(f, "jmp"),
             TKBranch => write!(f, "br"),
@@ -128,19 +133,39 @@ impl<'a> fmt::Display for Token<'a> {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Tokens<'a> {
-    tokens: &'a [Token<'a>],
+    tokens: &'a [Spanned<Token<'a>>],
     start: usize,
     end: usize,
 }
 
 impl<'a> Tokens<'a> {
-    pub fn new(tokens: &'a [Token<'a>]) -> Self {
+    pub fn new(tokens: &'a [Spanned<Token<'a>>]) -> Self {
         Self {
             tokens,
             start: 0,
             end: tokens.len(),
         }
     }
+
+    /// The first remaining token, `None` at the end of input.
+    pub fn peek_token(&self) -> Option<&'a Spanned<Token<'a>>> {
+        self.tokens.first()
+    }
+
+    /// Source span of the first remaining token, `None` at the end of input.
+    pub fn first_span(&self) -> Option<Span> {
+        self.tokens.first().map(Spanned::span)
+    }
+
+    /// Source span covering the tokens consumed from `self` to `rest`,
+    /// `None` if nothing is consumed.
+    pub fn span_until(&self, rest: &Tokens<'a>) -> Option<Span> {
+        let consumed = &self.tokens[..self.tokens.len() - rest.tokens.len()];
+        match (consumed.first(), consumed.last()) {
+            (Some(first), Some(last)) => Some(Span::from(first.span().start..last.span().end)),
+            _ => None
+        }
+    }
 }
 
 
@@ -149,7 +174,7 @@ impl<'a, 'b> Compare<Token<'b>> for Tokens<'a> {
     fn compare(&self, t: Token<'b>) -> nom::CompareResult {
         match self.iter_elements()
             .next()
-            .map(| elem | elem.eq(&t)) 
+            .map(| elem | elem.item().eq(&t)) 
         {
             Some(true) => CompareResult::Ok,
             Some(false) => CompareResult::Error,
@@ -164,7 +189,7 @@ impl<'a, 'b> Compare<Token<'b>> for Tokens<'a> {
 }
 
 impl<'a> Index<usize> for Tokens<'a> {
-    type Output = Token<'a>;
+    type Output = Spanned<Token<'a>>;
 
     fn index(&self, idx: usize) -> &Self::Output {
         &self.tokens[idx]
@@ -209,9 +234,9 @@ impl<'a> InputTake for Tokens<'a> {
 }
 
 impl<'a> InputIter for Tokens<'a> {
-    type Item = &'a Token<'a>;
-    type Iter = Enumerate<Iter<'a, Token<'a>>>;
-    type IterElem = Iter<'a, Token<'a>>;
+    type Item = &'a Spanned<Token<'a>>;
+    type Iter = Enumerate<Iter<'a, Spanned<Token<'a>>>>;
+    type IterElem = Iter<'a, Spanned<Token<'a>>>;
 
     fn iter_indices(&self) -> Self::Iter {
         self.tokens.iter().enumerate()
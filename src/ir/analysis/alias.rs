@@ -0,0 +1,48 @@
+use crate::ir::def_use::{UseMap, User};
+use crate::ir::structures::*;
+
+/// Whether the address computed by `ptr`, possibly through offsets, may be observed
+/// other than by loads and stores through it, e.g. stored to memory, passed to calls or returned.
+pub fn pointer_escapes(module: &Module, uses: &UseMap, ptr: ValueRef) -> bool {
+    uses.users(ptr).iter().any(| user | match user {
+        User::Instruction(instr) => match &module.get_value(*instr).kind {
+            ValueKind::Load(_) => false,
+            ValueKind::Store(inner) => inner.value == ptr,
+            ValueKind::Offset(inner) => inner.base_addr != ptr || pointer_escapes(module, uses, *instr),
+            _ => true
+        },
+        User::Terminator(_) => true
+    })
+}
+
+/// Object an address points into, looking through offsets.
+pub fn underlying_object(module: &Module, mut ptr: ValueRef) -> ValueRef {
+    while let ValueKind::Offset(inner) = &module.get_value(ptr).kind {
+        ptr = inner.base_addr;
+    }
+    ptr
+}
+
+/// Whether the addresses may refer to the same memory, distinct allocas and global variables never do,
+/// neither does an alloca whose address does not escape with any other pointer.
+pub fn may_alias(module: &Module, uses: &UseMap, lhs: ValueRef, rhs: ValueRef) -> bool {
+    if lhs == rhs {
+        return true;
+    }
+    let (lhs_object, rhs_object) = (underlying_object(module, lhs), underlying_object(module, rhs));
+    if lhs_object == rhs_object {
+        return true;
+    }
+    let is_identified = | object | matches!(
+        module.get_value(object).kind, ValueKind::Alloca(..) | ValueKind::GlobalVar(..));
+    let is_local = | object | matches!(module.get_value(object).kind, ValueKind::Alloca(..))
+        && !pointer_escapes(module, uses, object);
+    let distinct = (is_identified(lhs_object) && is_identified(rhs_object)) || is_local(lhs_object) || is_local(rhs_object);
+    !distinct
+}
+
+/// Whether a call to `callee` leaves memory visible to the program unchanged,
+/// only runtime functions other than `getarray` are known to.
+pub fn call_preserves_memory(callee: &str) -> bool {
+    matches!(callee, "getint" | "getch" | "putint" | "putch" | "putarray" | "starttime" | "stoptime")
+}
@@ -0,0 +1,298 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::apps::executor::is_runtime_function;
+use crate::ir::structures::*;
+
+/// Call to a name which is neither a function of the module nor a runtime function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UndeclaredCall {
+    pub caller: FunctionRef,
+    pub call: ValueRef,
+    pub callee: String,
+}
+
+/// Calls between functions of a module, with strongly connected components.
+///
+/// Nodes are all functions of the module, including declarations.
+/// Calls to names without a function in the module are not edges,
+/// those not naming runtime functions are recorded as undeclared calls.
+#[derive(Debug, Clone)]
+pub struct CallGraph {
+    functions: Vec<FunctionRef>,
+    undeclared_calls: Vec<UndeclaredCall>,
+    callees: HashMap<FunctionRef, Vec<FunctionRef>>,
+    callers: HashMap<FunctionRef, Vec<FunctionRef>>,
+    /// Strongly connected components, callees before callers.
+    sccs: Vec<Vec<FunctionRef>>,
+    scc_index: HashMap<FunctionRef, usize>,
+}
+
+/// Tarjan's algorithm, components are found in reverse topological order.
+struct SccBuilder<'a> {
+    callees: &'a HashMap<FunctionRef, Vec<FunctionRef>>,
+    index: HashMap<FunctionRef, usize>,
+    lowlink: HashMap<FunctionRef, usize>,
+    stack: Vec<FunctionRef>,
+    sccs: Vec<Vec<FunctionRef>>,
+}
+
+impl<'a> SccBuilder<'a> {
+    fn visit(&mut self, function: FunctionRef) {
+        let index = self.index.len();
+        self.index.insert(function, index);
+        self.lowlink.insert(function, index);
+        self.stack.push(function);
+        for callee in self.callees[&function].iter().cloned() {
+            if !self.index.contains_key(&callee) {
+                self.visit(callee);
+                let lowlink = self.lowlink[&function].min(self.lowlink[&callee]);
+                self.lowlink.insert(function, lowlink);
+            } else if self.stack.contains(&callee) {
+                let lowlink = self.lowlink[&function].min(self.index[&callee]);
+                self.lowlink.insert(function, lowlink);
+            }
+        }
+        if self.lowlink[&function] == index {
+            let position = self.stack.iter().position(| f | *f == function).unwrap();
+            self.sccs.push(self.stack.split_off(position));
+        }
+    }
+}
+
+impl CallGraph {
+    pub fn new(module: &Module) -> CallGraph {
+        let mut callees: HashMap<FunctionRef, Vec<FunctionRef>> = HashMap::new();
+        let mut callers: HashMap<FunctionRef, Vec<FunctionRef>> = HashMap::new();
+        for function in module.funcs.iter().cloned() {
+            callees.entry(function).or_default();
+            callers.entry(function).or_default();
+        }
+        let mut undeclared_calls = Vec::new();
+        for function in module.funcs.iter().cloned() {
+            for (call, callee) in Self::calls(module, function) {
+                let callee = match module.string_func_map.get(&callee) {
+                    Some(callee) => *callee,
+                    None => {
+                        if !is_runtime_function(&callee) {
+                            undeclared_calls.push(UndeclaredCall { caller: function, call, callee });
+                        }
+                        continue
+                    }
+                };
+                if !callees[&function].contains(&callee) {
+                    callees.get_mut(&function).unwrap().push(callee);
+                    callers.get_mut(&callee).unwrap().push(function);
+                }
+            }
+        }
+
+        let mut builder = SccBuilder {
+            callees: &callees,
+            index: HashMap::new(),
+            lowlink: HashMap::new(),
+            stack: Vec::new(),
+            sccs: Vec::new(),
+        };
+        for function in module.funcs.iter().cloned() {
+            if !builder.index.contains_key(&function) {
+                builder.visit(function);
+            }
+        }
+        let sccs = builder.sccs;
+        let scc_index = sccs
+            .iter()
+            .enumerate()
+            .flat_map(| (index, scc) | scc.iter().map(move | function | (*function, index)))
+            .collect();
+        CallGraph { functions: module.funcs.clone(), undeclared_calls, callees, callers, sccs, scc_index }
+    }
+
+    /// Calls in a function with their callee names, in program order.
+    pub fn calls(module: &Module, function: FunctionRef) -> Vec<(ValueRef, String)> {
+        let function = module.get_function(function);
+        function.blocks
+            .iter()
+            .flat_map(| bb | function.get_basic_block(*bb).instrs.iter())
+            .filter_map(| instr | match &module.get_value(*instr).kind {
+                ValueKind::FnCall(inner) => Some((*instr, inner.callee.clone())),
+                _ => None
+            })
+            .collect()
+    }
+
+    /// Callee names of calls in a function, in program order with duplicates.
+    pub fn called_names(module: &Module, function: FunctionRef) -> Vec<String> {
+        Self::calls(module, function)
+            .into_iter()
+            .map(| (_, callee) | callee)
+            .collect()
+    }
+
+    pub fn functions(&self) -> &[FunctionRef] {
+        &self.functions
+    }
+
+    pub fn callees(&self, function: FunctionRef) -> &[FunctionRef] {
+        self.callees
+            .get(&function)
+            .map_or(&[], Vec::as_slice)
+    }
+
+    pub fn callers(&self, function: FunctionRef) -> &[FunctionRef] {
+        self.callers
+            .get(&function)
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Strongly connected components in bottom-up order, callees come before their callers.
+    pub fn sccs(&self) -> &[Vec<FunctionRef>] {
+        &self.sccs
+    }
+
+    pub fn scc_of(&self, function: FunctionRef) -> &[FunctionRef] {
+        &self.sccs[self.scc_index[&function]]
+    }
+
+    /// Whether the function may call itself, directly or through other functions.
+    pub fn is_recursive(&self, function: FunctionRef) -> bool {
+        self.scc_of(function).len() > 1 || self.callees(function).contains(&function)
+    }
+
+    /// Recursive functions in module order.
+    pub fn recursive_functions(&self) -> Vec<FunctionRef> {
+        self.functions
+            .iter()
+            .cloned()
+            .filter(| function | self.is_recursive(*function))
+            .collect()
+    }
+
+    /// Calls which would fail at runtime since the callee does not exist.
+    pub fn undeclared_calls(&self) -> &[UndeclaredCall] {
+        &self.undeclared_calls
+    }
+
+    /// Functions that may be called, directly or indirectly, starting from `entry`, including `entry`.
+    pub fn reachable_from(&self, entry: FunctionRef) -> HashSet<FunctionRef> {
+        let mut reachable = HashSet::new();
+        let mut worklist = vec![entry];
+        while let Some(function) = worklist.pop() {
+            if reachable.insert(function) {
+                worklist.extend(self.callees(function).iter().cloned());
+            }
+        }
+        reachable
+    }
+
+    /// Functions never called starting from `entry`, in module order.
+    pub fn unreachable_from(&self, entry: FunctionRef) -> Vec<FunctionRef> {
+        let reachable = self.reachable_from(entry);
+        self.functions
+            .iter()
+            .cloned()
+            .filter(| function | !reachable.contains(function))
+            .collect()
+    }
+
+    /// Human readable summary of the call graph, see `CallGraphReport`.
+    pub fn report<'a>(&'a self, module: &'a Module, entry: Option<&'a str>) -> CallGraphReport<'a> {
+        CallGraphReport { call_graph: self, module, entry }
+    }
+}
+
+/// Summary of a call graph listing calls, components, recursive functions, undeclared calls
+/// and functions unreachable from the entry, if the entry function exists.
+pub struct CallGraphReport<'a> {
+    call_graph: &'a CallGraph,
+    module: &'a Module,
+    entry: Option<&'a str>,
+}
+
+impl<'a> fmt::Display for CallGraphReport<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = | function: &FunctionRef | format!("@{}", self.module.get_function(*function).name);
+        let names = | functions: &[FunctionRef] | functions.iter().map(name).collect::<Vec<_>>().join(", ");
+        let graph = self.call_graph;
+
+        writeln!(f, "calls:")?;
+        for function in graph.functions() {
+            writeln!(f, "  {} -> [{}]", name(function), names(graph.callees(*function)))?;
+        }
+        writeln!(f, "strongly connected components (callees first):")?;
+        for scc in graph.sccs() {
+            writeln!(f, "  [{}]", names(scc))?;
+        }
+        writeln!(f, "recursive functions: [{}]", names(&graph.recursive_functions()))?;
+        writeln!(f, "undeclared calls:")?;
+        for call in graph.undeclared_calls() {
+            let call_name = self.module.get_value(call.call).name.clone().unwrap_or(String::from("<anonymous>"));
+            writeln!(f, "  %{} in {} calls undeclared function @{}", call_name, name(&call.caller), call.callee)?;
+        }
+        match self.entry.and_then(| entry | self.module.string_func_map.get(entry).map(| func | (entry, func))) {
+            Some((entry, function)) =>
+                writeln!(f, "unreachable from @{}: [{}]", entry, names(&graph.unreachable_from(*function))),
+            None =>
+                writeln!(f, "unreachable from @{}: entry function not found", self.entry.unwrap_or("<none>")),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontend::new_parser::parse_module;
+
+    #[test]
+    fn test_call_graph() {
+        let module = parse_module(r"
+            fn @putint(#x: i32) -> ();
+            fn @unused() -> () {
+            %entry:
+                let %0 = call @missing, 0
+                ret ()
+            }
+            fn @odd(#n: i32) -> i32 {
+            %entry:
+                let %0 = call @even, #n
+                ret %0
+            }
+            fn @even(#n: i32) -> i32 {
+            %entry:
+                let %0 = call @odd, #n
+                ret %0
+            }
+            fn @fact(#n: i32) -> i32 {
+            %entry:
+                let %0 = call @fact, #n
+                ret %0
+            }
+            fn @main() -> () {
+            %entry:
+                let %0 = call @even, 1
+                let %1 = call @putint, %0
+                ret ()
+            }
+        ").unwrap();
+        let function = | name | module.get_function_ref(name);
+        let call_graph = CallGraph::new(&module);
+
+        assert_eq!(call_graph.callees(function("main")), [function("even"), function("putint")]);
+        assert_eq!(call_graph.callers(function("even")), [function("odd"), function("main")]);
+        assert_eq!(call_graph.scc_of(function("even")).len(), 2);
+        assert_eq!(call_graph.recursive_functions(), vec![function("odd"), function("even"), function("fact")]);
+        let sccs = call_graph.sccs();
+        let position = | f | sccs.iter().position(| scc | scc.contains(&f)).unwrap();
+        assert!(position(function("even")) < position(function("main")));
+
+        let undeclared = call_graph.undeclared_calls();
+        assert_eq!(undeclared.len(), 1);
+        assert_eq!((undeclared[0].caller, undeclared[0].callee.as_str()), (function("unused"), "missing"));
+        assert_eq!(call_graph.unreachable_from(function("main")), vec![function("unused"), function("fact")]);
+
+        let report = call_graph.report(&module, Some("main")).to_string();
+        assert!(report.contains("  %0 in @unused calls undeclared function @missing\n"));
+        assert!(report.contains("unreachable from @main: [@unused, @fact]\n"));
+    }
+}
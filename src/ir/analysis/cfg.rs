@@ -0,0 +1,119 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::structures::*;
+
+/// Predecessor and successor maps over the basic blocks of a function.
+///
+/// Only blocks appended to `Function::blocks` are nodes of the graph,
+/// edges to dangling placeholder blocks are ignored.
+/// Parallel edges (a branch with both labels the same) are merged.
+#[derive(Debug, Clone)]
+pub struct ControlFlowGraph {
+    blocks: Vec<BlockRef>,
+    successors: HashMap<BlockRef, Vec<BlockRef>>,
+    predecessors: HashMap<BlockRef, Vec<BlockRef>>,
+}
+
+impl ControlFlowGraph {
+    pub fn new(function: &Function) -> ControlFlowGraph {
+        let appended = function.blocks.iter().cloned().collect::<HashSet<_>>();
+        let mut successors: HashMap<BlockRef, Vec<BlockRef>> = HashMap::new();
+        let mut predecessors: HashMap<BlockRef, Vec<BlockRef>> = HashMap::new();
+        for bb in function.blocks.iter().cloned() {
+            successors.entry(bb).or_default();
+            predecessors.entry(bb).or_default();
+        }
+        for bb in function.blocks.iter().cloned() {
+            for succ in function.get_basic_block(bb).terminator.successors() {
+                if !appended.contains(&succ) || successors[&bb].contains(&succ) {
+                    continue;
+                }
+                successors.get_mut(&bb).unwrap().push(succ);
+                predecessors.get_mut(&succ).unwrap().push(bb);
+            }
+        }
+        ControlFlowGraph { blocks: function.blocks.clone(), successors, predecessors }
+    }
+
+    /// Entry block, `None` for function declarations.
+    pub fn entry(&self) -> Option<BlockRef> {
+        self.blocks.first().cloned()
+    }
+
+    /// All blocks in function order.
+    pub fn blocks(&self) -> &[BlockRef] {
+        &self.blocks
+    }
+
+    pub fn successors(&self, bb: BlockRef) -> &[BlockRef] {
+        self.successors
+            .get(&bb)
+            .map_or(&[], Vec::as_slice)
+    }
+
+    pub fn predecessors(&self, bb: BlockRef) -> &[BlockRef] {
+        self.predecessors
+            .get(&bb)
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Blocks without successors, i.e. returning or panicking blocks.
+    pub fn exits(&self) -> Vec<BlockRef> {
+        self.blocks
+            .iter().cloned()
+            .filter(| bb | self.successors(*bb).is_empty())
+            .collect()
+    }
+
+    /// Postorder of blocks reachable from the entry block.
+    pub fn postorder(&self) -> Vec<BlockRef> {
+        let entry = match self.entry() {
+            Some(entry) => entry,
+            None => return Vec::new()
+        };
+        depth_first_postorder(&[entry], | bb | self.successors(bb))
+    }
+
+    /// Reverse postorder of blocks reachable from the entry block.
+    pub fn reverse_postorder(&self) -> Vec<BlockRef> {
+        let mut order = self.postorder();
+        order.reverse();
+        order
+    }
+
+    pub fn reachable_blocks(&self) -> HashSet<BlockRef> {
+        self.postorder().into_iter().collect()
+    }
+}
+
+/// Iterative depth-first search from `roots`, producing nodes in postorder.
+pub(super) fn depth_first_postorder<'a>(
+    roots: &[BlockRef],
+    successors: impl Fn(BlockRef) -> &'a [BlockRef]
+) -> Vec<BlockRef> {
+    let mut visited = HashSet::new();
+    let mut order = Vec::new();
+    for root in roots.iter().cloned() {
+        if !visited.insert(root) {
+            continue;
+        }
+        // stack of (node, index of next successor to visit)
+        let mut stack = vec![(root, 0)];
+        while let Some((bb, next)) = stack.last_mut() {
+            let bb = *bb;
+            match successors(bb).get(*next) {
+                Some(succ) => {
+                    *next += 1;
+                    if visited.insert(*succ) {
+                        stack.push((*succ, 0));
+                    }
+                },
+                None => {
+                    order.push(bb);
+                    stack.pop();
+                }
+            }
+        }
+    }
+    order
+}
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+
+use crate::ir::structures::BlockRef;
+use super::cfg::{depth_first_postorder, ControlFlowGraph};
+
+/// Dominator tree, or post-dominator tree when built by `DominatorTree::new_post`.
+///
+/// Only blocks reachable from the root(s) are in the tree.
+/// The post-dominator tree is rooted at a virtual exit succeeding all exit blocks,
+/// so exit blocks have no immediate post-dominator.
+#[derive(Debug, Clone)]
+pub struct DominatorTree {
+    /// Immediate dominator of each block in the tree, `None` for roots.
+    idom: HashMap<BlockRef, Option<BlockRef>>,
+    children: HashMap<BlockRef, Vec<BlockRef>>,
+    roots: Vec<BlockRef>,
+    /// Whether this is a post-dominator tree.
+    post: bool,
+    /// Preorder and postorder numbers of the tree walk, for constant time dominance queries.
+    numbering: HashMap<BlockRef, (usize, usize)>,
+}
+
+/// Immediate dominators by "A Simple, Fast Dominance Algorithm" (Cooper, Harvey and Kennedy).
+/// Nodes are numbered in reverse postorder with the root at 0,
+/// `preds[i]` are the predecessors of node `i`. The root is its own immediate dominator.
+fn compute_idoms(preds: &[Vec<usize>]) -> Vec<usize> {
+    let undefined = usize::MAX;
+    let mut idom = vec![undefined; preds.len()];
+    if idom.is_empty() {
+        return idom;
+    }
+    idom[0] = 0;
+    let intersect = | idom: &[usize], mut lhs: usize, mut rhs: usize | {
+        while lhs != rhs {
+            while lhs > rhs { lhs = idom[lhs]; }
+            while rhs > lhs { rhs = idom[rhs]; }
+        }
+        lhs
+    };
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for node in 1..preds.len() {
+            let new_idom = preds[node]
+                .iter().cloned()
+                .filter(| pred | idom[*pred] != undefined)
+                .reduce(| acc, pred | intersect(&idom, acc, pred))
+                .unwrap_or(undefined);
+            if idom[node] != new_idom {
+                idom[node] = new_idom;
+                changed = true;
+            }
+        }
+    }
+    idom
+}
+
+impl DominatorTree {
+    /// Dominator tree of blocks reachable from the entry block.
+    pub fn new(cfg: &ControlFlowGraph) -> DominatorTree {
+        let order = cfg.reverse_postorder();
+        let index = order
+            .iter().cloned()
+            .enumerate()
+            .map(| (index, bb) | (bb, index))
+            .collect::<HashMap<_, _>>();
+        let preds = order
+            .iter()
+            .map(| bb | cfg.predecessors(*bb)
+                .iter()
+                .filter_map(| pred | index.get(pred).cloned())
+                .collect())
+            .collect::<Vec<_>>();
+        let idom = compute_idoms(&preds)
+            .into_iter()
+            .enumerate()
+            .map(| (node, idom) | (order[node], if node == 0 { None } else { Some(order[idom]) }))
+            .collect();
+        DominatorTree::from_idoms(idom, order, false)
+    }
+
+    /// Post-dominator tree of blocks reaching an exit block.
+    pub fn new_post(cfg: &ControlFlowGraph) -> DominatorTree {
+        // reverse postorder on the reversed graph, the virtual exit comes first.
+        let exits = cfg.exits();
+        let mut order = depth_first_postorder(&exits, | bb | cfg.predecessors(bb));
+        order.reverse();
+        // nodes are shifted by one, node 0 is the virtual exit.
+        let index = order
+            .iter().cloned()
+            .enumerate()
+            .map(| (index, bb) | (bb, index + 1))
+            .collect::<HashMap<_, _>>();
+        let preds = std::iter::once(Vec::new())
+            .chain(order
+                .iter()
+                .map(| bb | {
+                    let mut preds = cfg.successors(*bb)
+                        .iter()
+                        .filter_map(| succ | index.get(succ).cloned())
+                        .collect::<Vec<_>>();
+                    if cfg.successors(*bb).is_empty() {
+                        preds.push(0);
+                    }
+                    preds
+                }))
+            .collect::<Vec<_>>();
+        let idom = compute_idoms(&preds)
+            .into_iter()
+            .enumerate()
+            .skip(1)
+            .map(| (node, idom) | (order[node - 1], if idom == 0 { None } else { Some(order[idom - 1]) }))
+            .collect();
+        DominatorTree::from_idoms(idom, order, true)
+    }
+
+    /// Build the tree from immediate dominators, `order` lists the nodes with parents before children.
+    fn from_idoms(idom: HashMap<BlockRef, Option<BlockRef>>, order: Vec<BlockRef>, post: bool) -> DominatorTree {
+        let mut children: HashMap<BlockRef, Vec<BlockRef>> = HashMap::new();
+        let mut roots = Vec::new();
+        for bb in order.iter().cloned() {
+            children.entry(bb).or_default();
+            match idom[&bb] {
+                Some(parent) => children.entry(parent).or_default().push(bb),
+                None => roots.push(bb)
+            }
+        }
+        let mut tree = DominatorTree { idom, children, roots, post, numbering: HashMap::new() };
+        let mut counter = 0;
+        for root in tree.roots.clone() {
+            // (node, entered)
+            let mut stack = vec![(root, false)];
+            while let Some((bb, entered)) = stack.pop() {
+                if entered {
+                    tree.numbering.get_mut(&bb).unwrap().1 = counter;
+                } else {
+                    tree.numbering.insert(bb, (counter, 0));
+                    stack.push((bb, true));
+                    stack.extend(tree.children[&bb].iter().rev().map(| child | (*child, false)));
+                }
+                counter += 1;
+            }
+        }
+        tree
+    }
+
+    /// Whether the block is in the tree, i.e. reachable (or reaching an exit for post-dominators).
+    pub fn contains(&self, bb: BlockRef) -> bool {
+        self.idom.contains_key(&bb)
+    }
+
+    pub fn roots(&self) -> &[BlockRef] {
+        &self.roots
+    }
+
+    pub fn is_post_dominator_tree(&self) -> bool {
+        self.post
+    }
+
+    pub fn immediate_dominator(&self, bb: BlockRef) -> Option<BlockRef> {
+        self.idom.get(&bb).cloned().flatten()
+    }
+
+    pub fn children(&self, bb: BlockRef) -> &[BlockRef] {
+        self.children
+            .get(&bb)
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Whether `lhs` dominates `rhs`, every block dominates itself.
+    /// Blocks not in the tree dominate nothing and are dominated by nothing.
+    pub fn dominates(&self, lhs: BlockRef, rhs: BlockRef) -> bool {
+        match (self.numbering.get(&lhs), self.numbering.get(&rhs)) {
+            (Some((lhs_pre, lhs_post)), Some((rhs_pre, rhs_post))) =>
+                lhs_pre <= rhs_pre && rhs_post <= lhs_post,
+            _ => false
+        }
+    }
+
+    pub fn strictly_dominates(&self, lhs: BlockRef, rhs: BlockRef) -> bool {
+        lhs != rhs && self.dominates(lhs, rhs)
+    }
+
+    /// Blocks in preorder of the tree, parents before children.
+    pub fn preorder(&self) -> Vec<BlockRef> {
+        let mut order = self.numbering.keys().cloned().collect::<Vec<_>>();
+        order.sort_by_key(| bb | self.numbering[bb].0);
+        order
+    }
+
+    /// Dominance frontier of every block in the tree, built over `cfg`.
+    /// For a post-dominator tree this gives the reverse dominance frontiers (control dependence).
+    pub fn dominance_frontiers(&self, cfg: &ControlFlowGraph) -> HashMap<BlockRef, Vec<BlockRef>> {
+        let mut frontiers: HashMap<BlockRef, Vec<BlockRef>> = self.idom
+            .keys()
+            .map(| bb | (*bb, Vec::new()))
+            .collect();
+        for bb in self.preorder() {
+            let preds = if self.post { cfg.successors(bb) } else { cfg.predecessors(bb) };
+            let preds = preds
+                .iter().cloned()
+                .filter(| pred | self.contains(*pred))
+                .collect::<Vec<_>>();
+            if preds.len() < 2 {
+                continue;
+            }
+            let idom = self.immediate_dominator(bb);
+            for pred in preds {
+                let mut runner = Some(pred);
+                while runner.is_some() && runner != idom {
+                    let node = runner.unwrap();
+                    let frontier = frontiers.get_mut(&node).unwrap();
+                    if !frontier.contains(&bb) {
+                        frontier.push(bb);
+                    }
+                    runner = self.immediate_dominator(node);
+                }
+            }
+        }
+        frontiers
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontend::new_parser::parse_module;
+
+    #[test]
+    fn test_dominators_and_frontiers() {
+        let module = parse_module(r"
+            fn @f(#a: i32) -> i32 {
+            %entry:
+                br #a, label %then, label %else
+            %then:
+                jmp label %merge
+            %else:
+                br #a, label %merge, label %exit
+            %merge:
+                jmp label %exit
+            %exit:
+                ret #a
+            }
+        ").unwrap();
+        let function = module.get_function(module.get_function_ref("f"));
+        let block = | name: &str | function.blocks
+            .iter().cloned()
+            .find(| bb | function.get_basic_block(*bb).name.as_deref() == Some(name))
+            .unwrap();
+        let (entry, then, else_, merge, exit) = (block("entry"), block("then"), block("else"), block("merge"), block("exit"));
+
+        let cfg = ControlFlowGraph::new(function);
+        assert_eq!(cfg.predecessors(merge), &[then, else_]);
+        let rpo = cfg.reverse_postorder();
+        assert_eq!(rpo[0], entry);
+        assert_eq!(rpo[4], exit);
+
+        let domtree = DominatorTree::new(&cfg);
+        assert_eq!(domtree.immediate_dominator(merge), Some(entry));
+        assert_eq!(domtree.immediate_dominator(exit), Some(entry));
+        assert!(domtree.dominates(entry, exit));
+        assert!(!domtree.dominates(then, merge));
+        let frontiers = domtree.dominance_frontiers(&cfg);
+        assert_eq!(frontiers[&then], vec![merge]);
+        assert_eq!(frontiers[&else_], vec![merge, exit]);
+        assert!(frontiers[&entry].is_empty());
+
+        let postdom = DominatorTree::new_post(&cfg);
+        assert_eq!(postdom.roots(), &[exit]);
+        assert_eq!(postdom.immediate_dominator(then), Some(merge));
+        assert_eq!(postdom.immediate_dominator(else_), Some(exit));
+        assert_eq!(postdom.immediate_dominator(entry), Some(exit));
+        let control_dependence = postdom.dominance_frontiers(&cfg);
+        assert_eq!(control_dependence[&then], vec![entry]);
+        assert_eq!(control_dependence[&merge], vec![else_, entry]);
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::structures::BlockRef;
+use super::cfg::ControlFlowGraph;
+use super::dominance::DominatorTree;
+
+/// A natural loop, identified by its header block.
+#[derive(Debug, Clone)]
+pub struct Loop {
+    pub header: BlockRef,
+    /// Sources of back edges to the header.
+    pub latches: Vec<BlockRef>,
+    /// All blocks of the loop including the header and nested loops, in function order.
+    pub blocks: Vec<BlockRef>,
+    /// Index of the innermost enclosing loop in `LoopInfo::loops`.
+    pub parent: Option<usize>,
+    /// Indices of immediately nested loops.
+    pub children: Vec<usize>,
+    /// Nesting depth, outermost loops have depth 1.
+    pub depth: usize,
+}
+
+impl Loop {
+    pub fn contains(&self, bb: BlockRef) -> bool {
+        self.blocks.contains(&bb)
+    }
+
+    /// Blocks outside the loop with a predecessor inside the loop.
+    pub fn exit_blocks(&self, cfg: &ControlFlowGraph) -> Vec<BlockRef> {
+        let mut exits = Vec::new();
+        for bb in self.blocks.iter().cloned() {
+            for succ in cfg.successors(bb).iter().cloned() {
+                if !self.contains(succ) && !exits.contains(&succ) {
+                    exits.push(succ);
+                }
+            }
+        }
+        exits
+    }
+
+    /// The only predecessor of the header outside the loop, if it has the header as its only successor.
+    pub fn preheader(&self, cfg: &ControlFlowGraph) -> Option<BlockRef> {
+        let outside = cfg.predecessors(self.header)
+            .iter().cloned()
+            .filter(| pred | !self.contains(*pred))
+            .collect::<Vec<_>>();
+        match outside.as_slice() {
+            [pred] if cfg.successors(*pred) == [self.header] => Some(*pred),
+            _ => None
+        }
+    }
+}
+
+/// Natural loops of a function and their nesting.
+///
+/// Back edges are edges whose destination dominates their source,
+/// back edges sharing a header form a single loop.
+/// Irreducible cycles have no back edge and are not reported as loops.
+#[derive(Debug, Clone, Default)]
+pub struct LoopInfo {
+    /// Loops with outer loops before inner loops.
+    loops: Vec<Loop>,
+    /// Innermost loop of each block in some loop.
+    block_loop: HashMap<BlockRef, usize>,
+}
+
+impl LoopInfo {
+    pub fn new(cfg: &ControlFlowGraph, domtree: &DominatorTree) -> LoopInfo {
+        // headers in reverse postorder, so enclosing loops come before nested ones.
+        let mut loops = Vec::new();
+        for header in cfg.reverse_postorder() {
+            let latches = cfg.predecessors(header)
+                .iter().cloned()
+                .filter(| pred | domtree.dominates(header, *pred))
+                .collect::<Vec<_>>();
+            if latches.is_empty() {
+                continue;
+            }
+            // walk backwards from latches until the header.
+            let mut body = HashSet::from([header]);
+            let mut worklist = latches.clone();
+            while let Some(bb) = worklist.pop() {
+                if domtree.contains(bb) && body.insert(bb) {
+                    worklist.extend(cfg.predecessors(bb).iter().cloned());
+                }
+            }
+            let blocks = cfg.blocks()
+                .iter().cloned()
+                .filter(| bb | body.contains(bb))
+                .collect();
+            loops.push(Loop { header, latches, blocks, parent: None, children: Vec::new(), depth: 1 });
+        }
+
+        let mut block_loop = HashMap::new();
+        for index in 0..loops.len() {
+            // the innermost loop seen so far containing the header encloses this loop.
+            if let Some(parent) = block_loop.get(&loops[index].header).cloned() {
+                loops[index].parent = Some(parent);
+                loops[index].depth = loops[parent].depth + 1;
+                loops[parent].children.push(index);
+            }
+            for bb in loops[index].blocks.iter().cloned() {
+                block_loop.insert(bb, index);
+            }
+        }
+        LoopInfo { loops, block_loop }
+    }
+
+    pub fn loops(&self) -> &[Loop] {
+        &self.loops
+    }
+
+    pub fn get_loop(&self, index: usize) -> &Loop {
+        &self.loops[index]
+    }
+
+    /// Index of the innermost loop containing the block.
+    pub fn loop_of(&self, bb: BlockRef) -> Option<usize> {
+        self.block_loop.get(&bb).cloned()
+    }
+
+    /// Loop nesting depth of the block, 0 if not in any loop.
+    pub fn depth(&self, bb: BlockRef) -> usize {
+        self.loop_of(bb).map_or(0, | index | self.loops[index].depth)
+    }
+
+    pub fn is_header(&self, bb: BlockRef) -> bool {
+        self.loop_of(bb).is_some_and(| index | self.loops[index].header == bb)
+    }
+
+    pub fn top_level_loops(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.loops.len()).filter(| index | self.loops[*index].parent.is_none())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontend::new_parser::parse_module;
+
+    #[test]
+    fn test_nested_loops() {
+        let module = parse_module(r"
+            fn @f(#n: i32) -> i32 {
+            %entry:
+                jmp label %outer
+            %outer:
+                br #n, label %inner, label %exit
+            %inner:
+                br #n, label %inner.body, label %outer.latch
+            %inner.body:
+                jmp label %inner
+            %outer.latch:
+                jmp label %outer
+            %exit:
+                ret #n
+            }
+        ").unwrap();
+        let function = module.get_function(module.get_function_ref("f"));
+        let block = | name: &str | function.blocks
+            .iter().cloned()
+            .find(| bb | function.get_basic_block(*bb).name.as_deref() == Some(name))
+            .unwrap();
+        let cfg = ControlFlowGraph::new(function);
+        let domtree = DominatorTree::new(&cfg);
+        let loop_info = LoopInfo::new(&cfg, &domtree);
+
+        assert_eq!(loop_info.loops().len(), 2);
+        let outer = loop_info.get_loop(loop_info.loop_of(block("outer")).unwrap());
+        assert_eq!(outer.latches, vec![block("outer.latch")]);
+        assert_eq!(outer.blocks.len(), 4);
+        assert_eq!(outer.depth, 1);
+        assert_eq!(outer.exit_blocks(&cfg), vec![block("exit")]);
+        assert_eq!(outer.preheader(&cfg), Some(block("entry")));
+
+        let inner = loop_info.get_loop(loop_info.loop_of(block("inner.body")).unwrap());
+        assert_eq!(inner.header, block("inner"));
+        assert_eq!(inner.depth, 2);
+        assert!(outer.contains(block("inner.body")));
+        assert_eq!(inner.preheader(&cfg), None);
+        assert!(loop_info.is_header(block("inner")));
+        assert_eq!(loop_info.depth(block("exit")), 0);
+        assert_eq!(loop_info.top_level_loops().count(), 1);
+    }
+}
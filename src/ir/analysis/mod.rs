@@ -0,0 +1,7 @@
+//! Analyses over the control flow of functions and calls between them.
+
+pub mod alias;
+pub mod call_graph;
+pub mod cfg;
+pub mod dominance;
+pub mod loops;
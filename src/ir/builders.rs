@@ -2,7 +2,9 @@
 use std::collections::HashMap;
 
 use slotmap::SlotMap;
+use itertools::Itertools;
 
+use crate::frontend::Span;
 use crate::utils::unique_name::UniqueName;
 use super::types::{Type, TypeKind};
 use super::{structures::*, values};
@@ -12,6 +14,8 @@ struct FunctionEmitState {
     /* local variables */
     local_string_value_map: HashMap<String, ValueRef>,
     local_string_bb_map: HashMap<String, BlockRef>,
+    /* placeholders of values used before definition */
+    forward_value_map: HashMap<String, ValueRef>,
 
     current_function: FunctionRef,
     position: Option<BlockRef>,
@@ -23,6 +27,7 @@ impl FunctionEmitState {
         FunctionEmitState {
             local_string_bb_map: HashMap::new(),
             local_string_value_map: HashMap::new(),
+            forward_value_map: HashMap::new(),
             current_function: func,
             position: None,
             namer: UniqueName::new(),
@@ -144,6 +149,44 @@ impl IRBuilder {
 
     }
 
+    /// This function is only for create a placeholder value,
+    /// which is used as the incoming value of phi before its definition.
+    /// The placeholder is replaced by the actual instruction with the same name once it is emitted,
+    /// see `unresolved_placeholder_values` for placeholders never defined.
+    pub fn get_or_insert_placeholder_value_ref(&mut self, name: &str, ty: Type) -> ValueRef {
+        if let Some(value_ref) = self.get_value_ref(name) {
+            return value_ref;
+        }
+        let possible_value = self.func
+            .as_ref()
+            .unwrap()
+            .forward_value_map
+            .get(name)
+            .cloned();
+        match possible_value {
+            Some(value_ref) => value_ref,
+            None => {
+                // the kind does not matter, it never survives until the end of function.
+                let placeholder = Value::new(ty, Some(name.into()), ValueKind::ConstantUnit(values::ConstantUnit));
+                let handler = self.insert_value(placeholder);
+                self.func
+                    .as_mut()
+                    .expect("builder has no working function")
+                    .forward_value_map
+                    .insert(name.into(), handler);
+                handler
+            }
+        }
+    }
+
+    /// Names of placeholder values in the working function that are still not defined.
+    pub fn unresolved_placeholder_values(&self) -> Vec<String> {
+        self.func
+            .as_ref()
+            .map(| state | state.forward_value_map.keys().cloned().sorted().collect())
+            .unwrap_or_default()
+    }
+
     pub fn insert_literal_value(&mut self, value: Value) -> ValueRef {
         self.module.insert_value(value)
     }
@@ -178,9 +221,33 @@ impl IRBuilder {
         let handler = self.insert_local_value_symbol(instr);
         let working_bb = self.get_current_block_data_mut();
         working_bb.insert_instr_before_terminator(handler);
+        self.resolve_placeholder_value(handler);
         handler
     }
 
+    /// Replace the placeholder with the same name as the newly defined instruction.
+    fn resolve_placeholder_value(&mut self, instr: ValueRef) {
+        let value = self.module.get_value(instr);
+        let name = match value.name.as_ref() {
+            Some(name) => name.clone(),
+            None => return
+        };
+        let state = self.func
+            .as_mut()
+            .expect("builder has no working function");
+        if let Some(placeholder) = state.forward_value_map.remove(&name) {
+            let placeholder_ty = self.module.get_value_type(placeholder);
+            assert!(
+                placeholder_ty.eq(&value.ty),
+                "'%{}' is used as type `{}` before definition, but defined as type `{}`",
+                name, placeholder_ty, value.ty
+            );
+            let current_function = state.current_function;
+            self.module.replace_uses_in_function(current_function, placeholder, instr);
+            self.module.value_ctx.remove(placeholder);
+        }
+    }
+
     pub fn insert_global_symbol(&mut self, global_variable: Value) -> ValueRef {
         let global_name = global_variable.name.as_ref().unwrap().clone();
         let handler = self.insert_global_value(global_variable);
@@ -243,6 +310,7 @@ impl IRBuilder {
         self.func = Some(FunctionEmitState {
             local_string_value_map: local_name_ctx,
             local_string_bb_map: HashMap::new(),
+            forward_value_map: HashMap::new(),
             current_function: func_ref,
 
             position: None,
@@ -471,6 +539,9 @@ impl IRBuilder {
             // do nothing for runtime IO, postphone to executor.
             "getint" |  "getch" |  "getarray" => Type::get_i32(),
             "putint" | "putch" | "putarray" | "starttime" | "stoptime" => Type::get_unit(),
+            // undeclared callees are left to the verifier and the call graph, returning the annotated type or `i32`.
+            _ if !self.module.string_func_map.contains_key(&callee) =>
+                annotated_type.clone().unwrap_or_else(Type::get_i32),
             _ => {
                 let funcref = self.module.get_function_ref(&callee);
                 let function = self.module.get_function(funcref);
@@ -516,6 +587,31 @@ impl IRBuilder {
         self.insert_instruction_symbol(call)
     }
 
+    pub fn emit_phi(
+        &mut self,
+        name: Option<String>,
+        incoming: Vec<(ValueRef, BlockRef)>,
+        annotated_type: Option<Type>
+    ) -> ValueRef {
+        let inner_name = self.get_unique_name(&name);
+        let incoming_ty = incoming
+            .iter()
+            .map(| (value, _) | self.module.get_value_type(*value))
+            .collect::<Vec<_>>();
+        let result_ty = annotated_type
+            .or_else(|| incoming_ty.first().cloned())
+            .unwrap_or_else(|| panic!("cannot infer the type of phi '%{}' without incoming values", inner_name));
+        assert!(
+            incoming_ty.iter().all(| ty | ty.eq(&result_ty)),
+            "incoming values of phi '%{}' should all be type `{}`, but found {:?}",
+            inner_name, result_ty, incoming_ty
+        );
+
+        let mut phi = values::Phi::new_value(result_ty, incoming);
+        phi.set_name(inner_name);
+        self.insert_instruction_symbol(phi)
+    }
+
     pub fn fixup_terminator_jump(&mut self, dest: BlockRef) {
         let state = self.func
             .as_mut()
@@ -601,4 +697,18 @@ impl IRBuilder {
                 values::Return::new_value(return_value)
             )
     }
-}
\ No newline at end of file
+
+    /// Record the source span of a value.
+    pub fn set_span(&mut self, value: ValueRef, span: Span) {
+        self.module
+            .get_value_mut(value)
+            .span = Some(span);
+    }
+
+    /// Record the source span of the terminator of the working basic block.
+    pub fn set_terminator_span(&mut self, span: Span) {
+        self.get_current_block_data_mut()
+            .terminator
+            .set_span(span);
+    }
+}
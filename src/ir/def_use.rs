@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+
+use super::structures::*;
+
+/// Something that uses a value as its operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum User {
+    /// An instruction in the function.
+    Instruction(ValueRef),
+    /// The terminator of a basic block.
+    Terminator(BlockRef),
+}
+
+/// Users of values in a function, computed on demand.
+///
+/// The map is a snapshot and is not updated by mutations of the module,
+/// recompute it after transforming the function.
+#[derive(Debug, Clone, Default)]
+pub struct UseMap {
+    users: HashMap<ValueRef, Vec<User>>,
+}
+
+impl UseMap {
+    pub fn new(module: &Module, function: &Function) -> UseMap {
+        let mut users: HashMap<ValueRef, Vec<User>> = HashMap::new();
+        for bb in function.blocks.iter().cloned() {
+            let block = function.get_basic_block(bb);
+            for instr in block.instrs.iter().cloned() {
+                for operand in module.get_value(instr).kind.operands() {
+                    users.entry(operand).or_default().push(User::Instruction(instr));
+                }
+            }
+            for operand in block.terminator.operands() {
+                users.entry(operand).or_default().push(User::Terminator(bb));
+            }
+        }
+        UseMap { users }
+    }
+
+    /// Users of `value`, a user occurs once for each of its operands referring to `value`.
+    pub fn users(&self, value: ValueRef) -> &[User] {
+        self.users
+            .get(&value)
+            .map_or(&[], Vec::as_slice)
+    }
+
+    pub fn has_users(&self, value: ValueRef) -> bool {
+        !self.users(value).is_empty()
+    }
+
+    pub fn num_uses(&self, value: ValueRef) -> usize {
+        self.users(value).len()
+    }
+}
+
+impl Function {
+    /// Basic block and index of an instruction in the function.
+    pub fn find_instruction(&self, instr: ValueRef) -> Option<(BlockRef, usize)> {
+        self.blocks
+            .iter().cloned()
+            .find_map(| bb | {
+                self.get_basic_block(bb)
+                    .instrs
+                    .iter()
+                    .position(| value | *value == instr)
+                    .map(| index | (bb, index))
+            })
+    }
+}
+
+impl Module {
+    /// Users of `value` within a function.
+    pub fn get_users(&self, function: FunctionRef, value: ValueRef) -> Vec<User> {
+        UseMap::new(self, self.get_function(function))
+            .users(value)
+            .to_vec()
+    }
+
+    /// Replace uses of `from` by `to` in instructions and terminators of a function,
+    /// returns the number of replaced uses.
+    pub fn replace_uses_in_function(&mut self, function: FunctionRef, from: ValueRef, to: ValueRef) -> usize {
+        let function = self.func_ctx
+            .get_mut(function)
+            .unwrap();
+        let mut replaced = 0;
+        for bb in function.blocks.iter().cloned() {
+            let block = function.blocks_ctx.get_mut(bb).unwrap();
+            for instr in block.instrs.iter().cloned() {
+                for operand in self.value_ctx[instr].kind.operands_mut() {
+                    if *operand == from {
+                        *operand = to;
+                        replaced += 1;
+                    }
+                }
+            }
+            for operand in block.terminator.operands_mut() {
+                if *operand == from {
+                    *operand = to;
+                    replaced += 1;
+                }
+            }
+        }
+        replaced
+    }
+
+    /// Replace all uses of `from` by `to` in every function of the module,
+    /// returns the number of replaced uses.
+    pub fn replace_all_uses_with(&mut self, from: ValueRef, to: ValueRef) -> usize {
+        self.funcs
+            .clone()
+            .into_iter()
+            .map(| function | self.replace_uses_in_function(function, from, to))
+            .sum()
+    }
+
+    /// Remove an instruction from its basic block and from the module.
+    /// The instruction must have no users left.
+    pub fn erase_instruction(&mut self, function: FunctionRef, instr: ValueRef) {
+        debug_assert!(
+            self.get_users(function, instr).is_empty(),
+            "try to erase instruction `{}` which still has users",
+            self.get_value(instr)
+        );
+        let function = self.get_function_mut(function);
+        let (bb, index) = function
+            .find_instruction(instr)
+            .expect("try to erase an instruction not in the function");
+        function.get_basic_block_mut(bb).instrs.remove(index);
+        self.value_ctx.remove(instr);
+    }
+
+    /// Detach an instruction from its basic block, keeping it in the module.
+    fn detach_instruction(&mut self, function: FunctionRef, instr: ValueRef) {
+        let function = self.get_function_mut(function);
+        let (bb, index) = function
+            .find_instruction(instr)
+            .expect("try to move an instruction not in the function");
+        function.get_basic_block_mut(bb).instrs.remove(index);
+    }
+
+    /// Move `instr` right before the instruction `before`, possibly into another basic block.
+    pub fn move_before(&mut self, function: FunctionRef, instr: ValueRef, before: ValueRef) {
+        assert!(instr != before, "try to move an instruction before itself");
+        self.detach_instruction(function, instr);
+        let function = self.get_function_mut(function);
+        let (bb, index) = function
+            .find_instruction(before)
+            .expect("anchor instruction is not in the function");
+        function.get_basic_block_mut(bb).instrs.insert(index, instr);
+    }
+
+    /// Move `instr` right after the instruction `after`, possibly into another basic block.
+    pub fn move_after(&mut self, function: FunctionRef, instr: ValueRef, after: ValueRef) {
+        assert!(instr != after, "try to move an instruction after itself");
+        self.detach_instruction(function, instr);
+        let function = self.get_function_mut(function);
+        let (bb, index) = function
+            .find_instruction(after)
+            .expect("anchor instruction is not in the function");
+        function.get_basic_block_mut(bb).instrs.insert(index + 1, instr);
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontend::new_parser::parse_module;
+
+    #[test]
+    fn test_replace_erase_and_move() {
+        let mut module = parse_module(r"
+            fn @f(#a: i32) -> i32 {
+            %entry:
+                let %0 = add #a, 1
+                let %1 = mul %0, %0
+                let %2 = add #a, 1
+                let %3 = sub %2, %1
+                ret %3
+            }
+        ").unwrap();
+        let function = module.get_function_ref("f");
+        let instrs = module.get_function(function)
+            .get_basic_block(module.get_function(function).blocks[0])
+            .instrs
+            .clone();
+        let (v0, v1, v2, v3) = (instrs[0], instrs[1], instrs[2], instrs[3]);
+
+        assert_eq!(module.get_users(function, v0), vec![User::Instruction(v1), User::Instruction(v1)]);
+        assert_eq!(module.get_users(function, v3).len(), 1);
+
+        // `%2` is redundant with `%0`.
+        assert_eq!(module.replace_all_uses_with(v2, v0), 1);
+        module.erase_instruction(function, v2);
+        assert_eq!(module.get_users(function, v0).len(), 3);
+
+        module.move_before(function, v3, v1);
+        module.move_after(function, v1, v0);
+        let function = module.get_function(function);
+        assert_eq!(function.get_basic_block(function.blocks[0]).instrs, vec![v0, v1, v3]);
+        assert_eq!(function.find_instruction(v3), Some((function.blocks[0], 2)));
+    }
+}
@@ -1,4 +1,8 @@
 pub mod types;
 pub mod values;
 pub mod structures;
-pub mod builders;
\ No newline at end of file
+pub mod builders;
+pub mod verify;
+pub mod def_use;
+pub mod analysis;
+pub mod transforms;
\ No newline at end of file
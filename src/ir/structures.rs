@@ -1,4 +1,5 @@
 use std::fmt;
+use std::rc::Rc;
 use std::collections::HashMap;
 
 use slotmap::{new_key_type, SlotMap};
@@ -6,6 +7,7 @@ use itertools::Itertools;
 
 use super::values;
 use super::types::Type;
+use crate::frontend::{SourceFile, Span};
 use crate::utils::display_helper::*;
 
 new_key_type! {
@@ -27,9 +29,42 @@ pub enum ValueKind {
     Alloca(values::Alloca),
     Load(values::Load),
     Store(values::Store),
+    Phi(values::Phi),
     GlobalVar(values::GlobalVar)
 }
 
+impl ValueKind {
+    /// Values used by this value as operands, in order.
+    pub fn operands(&self) -> impl Iterator<Item = ValueRef> {
+        let operands = match self {
+            ValueKind::Binary(inner) => vec![inner.lhs, inner.rhs],
+            ValueKind::Offset(inner) =>
+                std::iter::once(inner.base_addr).chain(inner.index.iter().cloned()).collect(),
+            ValueKind::FnCall(inner) => inner.args.clone(),
+            ValueKind::Load(inner) => vec![inner.addr],
+            ValueKind::Store(inner) => vec![inner.value, inner.addr],
+            ValueKind::Phi(inner) => inner.incoming.iter().map(| (value, _) | *value).collect(),
+            _ => vec![]
+        };
+        operands.into_iter()
+    }
+
+    /// Mutable references to operands, in the same order as `operands`.
+    pub fn operands_mut(&mut self) -> impl Iterator<Item = &mut ValueRef> {
+        let operands = match self {
+            ValueKind::Binary(inner) => vec![&mut inner.lhs, &mut inner.rhs],
+            ValueKind::Offset(inner) =>
+                std::iter::once(&mut inner.base_addr).chain(inner.index.iter_mut()).collect(),
+            ValueKind::FnCall(inner) => inner.args.iter_mut().collect(),
+            ValueKind::Load(inner) => vec![&mut inner.addr],
+            ValueKind::Store(inner) => vec![&mut inner.value, &mut inner.addr],
+            ValueKind::Phi(inner) => inner.incoming.iter_mut().map(| (value, _) | value).collect(),
+            _ => vec![]
+        };
+        operands.into_iter()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Terminator {
     Branch(values::Branch),
@@ -39,18 +74,79 @@ pub enum Terminator {
     Panic
 }
 
+impl Terminator {
+    /// Values used by the terminator as operands.
+    pub fn operands(&self) -> impl Iterator<Item = ValueRef> {
+        let operands = match self {
+            Terminator::Branch(inner) => vec![inner.cond],
+            Terminator::Return(inner) => vec![inner.value],
+            Terminator::Jump(_) | Terminator::Panic => vec![]
+        };
+        operands.into_iter()
+    }
+
+    pub fn operands_mut(&mut self) -> impl Iterator<Item = &mut ValueRef> {
+        let operands = match self {
+            Terminator::Branch(inner) => vec![&mut inner.cond],
+            Terminator::Return(inner) => vec![&mut inner.value],
+            Terminator::Jump(_) | Terminator::Panic => vec![]
+        };
+        operands.into_iter()
+    }
+
+    /// Destination blocks of the terminator, a block may occur twice in a branch.
+    pub fn successors(&self) -> impl Iterator<Item = BlockRef> {
+        let successors = match self {
+            Terminator::Branch(inner) => vec![inner.true_label, inner.false_label],
+            Terminator::Jump(inner) => vec![inner.dest],
+            Terminator::Return(_) | Terminator::Panic => vec![]
+        };
+        successors.into_iter()
+    }
+
+    pub fn successors_mut(&mut self) -> impl Iterator<Item = &mut BlockRef> {
+        let successors = match self {
+            Terminator::Branch(inner) => vec![&mut inner.true_label, &mut inner.false_label],
+            Terminator::Jump(inner) => vec![&mut inner.dest],
+            Terminator::Return(_) | Terminator::Panic => vec![]
+        };
+        successors.into_iter()
+    }
+
+    /// Source span of the terminator, if it is parsed from text.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Terminator::Branch(inner) => inner.span,
+            Terminator::Jump(inner) => inner.span,
+            Terminator::Return(inner) => inner.span,
+            Terminator::Panic => None
+        }
+    }
+
+    pub fn set_span(&mut self, span: Span) {
+        match self {
+            Terminator::Branch(inner) => inner.span = Some(span),
+            Terminator::Jump(inner) => inner.span = Some(span),
+            Terminator::Return(inner) => inner.span = Some(span),
+            Terminator::Panic => ()
+        }
+    }
+}
+
 /* In Accipit, the class used to mean a variable (symbol) and the statement that assigns to it is the `Value`.
  */
 #[derive(Debug, Clone)]
 pub struct Value {
     pub ty: Type,
     pub name: Option<String>,
-    pub kind: ValueKind
+    pub kind: ValueKind,
+    /// Source span of the value, if it is parsed from text.
+    pub span: Option<Span>
 }
 
 impl Value {
     pub fn new(ty: Type, name: Option<String>, kind: ValueKind) -> Value {
-        Value { ty, name, kind }
+        Value { ty, name, kind, span: None }
     }
 
     pub fn set_name(&mut self, name: String) {
@@ -60,7 +156,12 @@ impl Value {
     pub fn isa_instruction(&self) -> bool {
         matches!(self.kind, 
                 ValueKind::Binary(..) | ValueKind::Offset(..) | ValueKind::FnCall(..) | 
-                ValueKind::Alloca(..) | ValueKind::Load(..) | ValueKind::Store(..))
+                ValueKind::Alloca(..) | ValueKind::Load(..) | ValueKind::Store(..) |
+                ValueKind::Phi(..))
+    }
+
+    pub fn is_phi(&self) -> bool {
+        matches!(self.kind, ValueKind::Phi(..))
     }
 
     pub fn is_constant_value(&self) -> bool {
@@ -76,7 +177,8 @@ impl fmt::Display for Value {
             ValueKind::ConstantNullPtr(_) => write!(f, "null: ptr"),
             ValueKind::ConstantUnit(_) => write!(f, "()"),
             ValueKind::Binary(..) | ValueKind::Offset(..) | ValueKind::FnCall(..) |
-            ValueKind::Alloca(..) | ValueKind::Load(..) | ValueKind::Store(..) =>
+            ValueKind::Alloca(..) | ValueKind::Load(..) | ValueKind::Store(..) |
+            ValueKind::Phi(..) =>
                 write!(f, "%{}: {}", self.name.clone().unwrap_or(String::from("<anonymous>")), self.ty),
             ValueKind::Argument(..) =>
                 write!(f, "#{}: {}", self.name.clone().unwrap_or(String::from("<anonymous>")), self.ty),
@@ -168,7 +270,10 @@ pub struct Module {
     pub func_ctx: SlotMap<FunctionRef, Function>,
     pub string_func_map: HashMap<String, FunctionRef>,
 
-    pub globals: Vec<ValueRef>
+    pub globals: Vec<ValueRef>,
+
+    /// Source text the module is parsed from, used by diagnostics.
+    pub source: Option<Rc<SourceFile>>
 }
 
 impl Module {
@@ -179,7 +284,8 @@ impl Module {
             funcs: Vec::new(),
             func_ctx: SlotMap::with_key(),
             string_func_map: HashMap::new(),
-            globals: Vec::new()
+            globals: Vec::new(),
+            source: None
         }
     }
 
@@ -235,10 +341,10 @@ impl Module {
 }
 
 
-impl<'a> fmt::Display for DisplayWithContext<'a, Value, Module> {
+impl<'a> fmt::Display for DisplayWithContext<'a, Value, (&'a Module, &'a Function)> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let value = self.item;
-        let module = self.context;
+        let (module, function) = *self.context;
         match &value.kind {
             ValueKind::Alloca(inner) => {
                 write!(f, "  let {} = alloca {}, {}\n",
@@ -288,6 +394,15 @@ impl<'a> fmt::Display for DisplayWithContext<'a, Value, Module> {
                         })
                     )
             },
+            ValueKind::Phi(inner) => {
+                writeln!(f, "  let {} = phi {}",
+                        value,
+                        inner.incoming.iter().format_with(", ", | (incoming, bb), f | {
+                            let label = function.get_basic_block(*bb).name.clone().unwrap_or(String::from("%<unknown_label>"));
+                            f(&format_args!("[{}, label %{}]", module.get_value(*incoming), label))
+                        })
+                    )
+            },
             ValueKind::GlobalVar(inner) => {
                 write!(f, "  {} : region {}, {}\n",
                         value, inner.elem_ty, inner.size)
@@ -298,8 +413,62 @@ impl<'a> fmt::Display for DisplayWithContext<'a, Value, Module> {
 }
 
 
+impl<'a> fmt::Display for DisplayWithContext<'a, Terminator, (&'a Module, &'a Function)> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (module, function) = self.context;
+        match self.item {
+            Terminator::Panic => write!(f, "  panic!\n"),
+            Terminator::Branch(inner) => {
+                let cond = module.get_value(inner.cond);
+                let true_bb = function.get_basic_block(inner.true_label);
+                let false_bb = function.get_basic_block(inner.false_label);
+                write!(f, "  br {}, label %{}, label %{}\n",
+                        cond,
+                        true_bb.name.clone().unwrap_or(String::from("%<unknown_label>")),
+                        false_bb.name.clone().unwrap_or(String::from("%<unknown_label>"))
+                )
+            },
+            Terminator::Jump(inner) => {
+                let bb = function.get_basic_block(inner.dest);
+                write!(f, "  jmp label %{}\n",
+                        bb.name.clone().unwrap_or(String::from("%<unknown_label>"))
+                )
+            },
+            Terminator::Return(inner) => {
+                let ret = module.get_value(inner.value);
+                write!(f, "  ret {}\n",
+                        ret
+                )
+            }
+        }
+    }
+}
+
 impl fmt::Display for Module {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_annotated(f, &| _ | None)
+    }
+}
+
+/// Module printed with a line comment after annotated instructions, see `Module::annotated`.
+pub struct AnnotatedModule<'a, F> {
+    module: &'a Module,
+    annotate: F,
+}
+
+impl<'a, F: Fn(ValueRef) -> Option<String>> fmt::Display for AnnotatedModule<'a, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.module.fmt_annotated(f, &self.annotate)
+    }
+}
+
+impl Module {
+    /// Print the module with the annotation of each instruction as a comment, such as its register.
+    pub fn annotated<F: Fn(ValueRef) -> Option<String>>(&self, annotate: F) -> AnnotatedModule<'_, F> {
+        AnnotatedModule { module: self, annotate }
+    }
+
+    fn fmt_annotated(&self, f: &mut fmt::Formatter, annotate: &dyn Fn(ValueRef) -> Option<String>) -> fmt::Result {
         for gvref in self.globals.iter() {
             let global_var = self.get_value(gvref.clone());
             let name = global_var.name.clone().unwrap();
@@ -338,34 +507,14 @@ impl fmt::Display for Module {
                         .unwrap_or(String::from("%<unknown_label>")))?;
                     for value_ref in basic_block.instrs.iter() {
                         let value = self.get_value(value_ref.clone());
-                        write!(f, "{}", value.wrap_context(self))?;
+                        match annotate(*value_ref) {
+                            Some(annotation) => writeln!(f, "{}  // {}",
+                                value.wrap_context(&(self, function)).to_string().trim_end(), annotation)?,
+                            None => write!(f, "{}", value.wrap_context(&(self, function)))?
+                        }
                     };
 
-                    match &basic_block.terminator {
-                        Terminator::Panic => write!(f, "  panic!\n"),
-                        Terminator::Branch(inner) => {
-                            let cond = self.get_value(inner.cond);
-                            let true_bb = function.get_basic_block(inner.true_label);
-                            let false_bb = function.get_basic_block(inner.false_label);
-                            write!(f, "  br {}, label %{}, label %{}\n",
-                                    cond,
-                                    true_bb.name.clone().unwrap_or(String::from("%<unknown_label>")),
-                                    false_bb.name.clone().unwrap_or(String::from("%<unknown_label>"))
-                            )
-                        },
-                        Terminator::Jump(inner) => {
-                            let bb = function.get_basic_block(inner.dest);
-                            write!(f, "  jmp label %{}\n",
-                                    bb.name.clone().unwrap_or(String::from("%<unknown_label>"))
-                            )
-                        },
-                        Terminator::Return(inner) => {
-                            let ret = self.get_value(inner.value);
-                            write!(f, "  ret {}\n",
-                                    ret
-                            )
-                        }
-                    }?;
+                    write!(f, "{}", basic_block.terminator.wrap_context(&(self, function)))?;
                 }
                 write!(f, "}}\n\n")?
             }
@@ -0,0 +1,129 @@
+use crate::apps::executor::Val;
+use crate::ir::structures::*;
+use crate::ir::types::Type;
+use crate::ir::values::{self, BinaryOp};
+
+use super::pass_manager::{AnalysisManager, Pass, Preserved};
+
+/// Compile time constant of the integer or boolean type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Constant {
+    Int(i32),
+    Bool(bool),
+}
+
+impl Constant {
+    pub fn from_value(value: &Value) -> Option<Constant> {
+        match &value.kind {
+            ValueKind::ConstantInt(inner) => Some(Constant::Int(inner.value)),
+            ValueKind::ConstantBool(inner) => Some(Constant::Bool(inner.value)),
+            _ => None
+        }
+    }
+
+    pub fn into_value(self) -> Value {
+        match self {
+            Constant::Int(value) => values::ConstantInt::new_value(value),
+            Constant::Bool(value) => values::ConstantBool::new_bool_value(value),
+        }
+    }
+
+    /// Whether the constant is of type `ty`, comparisons produce integers for example.
+    pub fn has_type(&self, ty: &Type) -> bool {
+        match self {
+            Constant::Int(_) => ty.is_i32_type(),
+            Constant::Bool(_) => ty.is_i1_type(),
+        }
+    }
+
+    /// Whether a branch on the constant takes the true label, following the executor.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Constant::Int(value) => *value != 0,
+            Constant::Bool(value) => *value,
+        }
+    }
+}
+
+impl From<Constant> for Val {
+    fn from(constant: Constant) -> Val {
+        match constant {
+            Constant::Int(value) => Val::Integer(value),
+            Constant::Bool(value) => Val::Bool(value),
+        }
+    }
+}
+
+/// Evaluate a binary operation with `Val::compute_binary`, as the executor does.
+/// Returns `None` when evaluating would trap at runtime, such as division by zero and overflow,
+/// and for operand types the executor rejects.
+pub fn fold_binary(op: &BinaryOp, lhs: Constant, rhs: Constant) -> Option<Constant> {
+    if let (Constant::Int(lhs), Constant::Int(rhs)) = (lhs, rhs) {
+        let checked = match op {
+            BinaryOp::Add => lhs.checked_add(rhs),
+            BinaryOp::Sub => lhs.checked_sub(rhs),
+            BinaryOp::Mul => lhs.checked_mul(rhs),
+            BinaryOp::Div => lhs.checked_div(rhs),
+            BinaryOp::Rem => lhs.checked_rem(rhs),
+            _ => Some(0)
+        };
+        checked?;
+    }
+    match Val::compute_binary(op.clone(), &lhs.into(), &rhs.into()).ok()? {
+        Val::Integer(value) => Some(Constant::Int(value)),
+        Val::Bool(value) => Some(Constant::Bool(value)),
+        _ => None
+    }
+}
+
+/// Fold an instruction whose operands are all constants.
+pub fn fold_instruction(module: &Module, instr: ValueRef) -> Option<Constant> {
+    match &module.get_value(instr).kind {
+        ValueKind::Binary(inner) => {
+            let lhs = Constant::from_value(module.get_value(inner.lhs))?;
+            let rhs = Constant::from_value(module.get_value(inner.rhs))?;
+            fold_binary(&inner.op, lhs, rhs)
+                .filter(| folded | folded.has_type(&module.get_value(instr).ty))
+        },
+        _ => None
+    }
+}
+
+/// Replace instructions computing constants by the constants, in program order,
+/// so that folded results propagate to later instructions.
+/// Returns the number of folded instructions.
+pub fn fold_constants(module: &mut Module, function: FunctionRef) -> usize {
+    let instrs = module
+        .get_function(function)
+        .blocks
+        .iter()
+        .flat_map(| bb | module.get_function(function).get_basic_block(*bb).instrs.clone())
+        .collect::<Vec<_>>();
+    let mut folded = 0;
+    for instr in instrs {
+        if let Some(constant) = fold_instruction(module, instr) {
+            let constant = module.insert_value(constant.into_value());
+            module.replace_uses_in_function(function, instr, constant);
+            module.erase_instruction(function, instr);
+            folded += 1;
+        }
+    }
+    folded
+}
+
+/// Constant folding pass, named `constfold` in pipelines.
+pub struct ConstantFolding;
+
+impl Pass for ConstantFolding {
+    fn name(&self) -> &'static str {
+        "constfold"
+    }
+
+    fn run_on_function(&mut self, module: &mut Module, function: FunctionRef, _analyses: &mut AnalysisManager) -> Preserved {
+        if fold_constants(module, function) > 0 {
+            Preserved::ControlFlow
+        } else {
+            Preserved::All
+        }
+    }
+}
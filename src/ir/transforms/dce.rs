@@ -0,0 +1,128 @@
+use std::collections::HashSet;
+
+use crate::ir::analysis::alias::{pointer_escapes, underlying_object};
+use crate::ir::def_use::UseMap;
+use crate::ir::structures::*;
+use crate::ir::values::BinaryOp;
+
+use super::pass_manager::{AnalysisManager, Pass, Preserved};
+
+/// Whether an instruction can be removed without changing the behavior of the program once it is unused.
+///
+/// Stores and function calls, including calls to runtime functions, are always kept,
+/// so are divisions which may trap and loads from memory visible outside the function.
+pub fn is_removable_if_unused(module: &Module, uses: &UseMap, instr: ValueRef) -> bool {
+    match &module.get_value(instr).kind {
+        ValueKind::Binary(inner) => match inner.op {
+            BinaryOp::Div | BinaryOp::Rem =>
+                matches!(module.get_value(inner.rhs).kind, ValueKind::ConstantInt(ref rhs) if rhs.value != 0 && rhs.value != -1),
+            _ => true
+        },
+        ValueKind::Load(inner) => {
+            let object = underlying_object(module, inner.addr);
+            matches!(module.get_value(object).kind, ValueKind::Alloca(..)) && !pointer_escapes(module, uses, object)
+        },
+        ValueKind::Offset(..) | ValueKind::Alloca(..) | ValueKind::Phi(..) => true,
+        _ => false
+    }
+}
+
+/// Erase instructions whose results never reach an instruction with side effects or a terminator,
+/// including cycles of phis only used by each other.
+/// Returns the number of erased instructions.
+pub fn eliminate_dead_instructions(module: &mut Module, function: FunctionRef) -> usize {
+    let func = module.get_function(function);
+    let uses = UseMap::new(module, func);
+    let instrs = func.blocks
+        .iter()
+        .flat_map(| bb | func.get_basic_block(*bb).instrs.iter().cloned())
+        .collect::<Vec<_>>();
+
+    // mark instructions needed by side effects and control flow.
+    let mut worklist = instrs
+        .iter()
+        .cloned()
+        .filter(| instr | !is_removable_if_unused(module, &uses, *instr))
+        .chain(func.blocks.iter().flat_map(| bb | func.get_basic_block(*bb).terminator.operands()))
+        .collect::<Vec<_>>();
+    let mut live = HashSet::new();
+    while let Some(value) = worklist.pop() {
+        if live.insert(value) {
+            worklist.extend(module.get_value(value).kind.operands());
+        }
+    }
+
+    let dead = instrs
+        .into_iter()
+        .filter(| instr | !live.contains(instr))
+        .collect::<HashSet<_>>();
+    // dead instructions may use each other, detach all of them before removing.
+    let func = module.get_function_mut(function);
+    for bb in func.blocks.clone() {
+        func.get_basic_block_mut(bb).instrs.retain(| instr | !dead.contains(instr));
+    }
+    for instr in dead.iter() {
+        module.value_ctx.remove(*instr);
+    }
+    dead.len()
+}
+
+/// Dead code elimination pass, named `dce` in pipelines.
+pub struct DeadCodeElimination;
+
+impl Pass for DeadCodeElimination {
+    fn name(&self) -> &'static str {
+        "dce"
+    }
+
+    fn run_on_function(&mut self, module: &mut Module, function: FunctionRef, _analyses: &mut AnalysisManager) -> Preserved {
+        if eliminate_dead_instructions(module, function) > 0 {
+            Preserved::ControlFlow
+        } else {
+            Preserved::All
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontend::new_parser::parse_module;
+
+    #[test]
+    fn test_dce_keeps_side_effects() {
+        let mut module = parse_module(r"
+            fn @getint() -> i32;
+            fn @f(#p: i32*) -> i32 {
+            %entry:
+                let %x = alloca i32, 4
+                let %y = alloca i32, 1
+                let %0 = offset i32, %x, [1 < 4]
+                let %1 = store 1, %0
+                let %2 = load %0
+                let %3 = load #p
+                let %4 = call @getint
+                let %5 = div %4, 0
+                let %6 = add %4, 1
+                jmp label %loop
+            %loop:
+                let %i = phi [0, label %entry], [%i.next, label %loop]
+                let %i.next = add %i, 1
+                br %4, label %loop, label %exit
+            %exit:
+                ret 0
+            }
+        ").unwrap();
+        let function = module.get_function_ref("f");
+        // %y, %2, %6 and the phi cycle are dead, %3 may read memory of the caller.
+        assert_eq!(eliminate_dead_instructions(&mut module, function), 5);
+        let function = module.get_function(function);
+        let names = function.blocks
+            .iter()
+            .flat_map(| bb | function.get_basic_block(*bb).instrs.iter())
+            .map(| instr | module.get_value(*instr).name.clone().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["x", "0", "1", "3", "4", "5"]);
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::analysis::{alias::{call_preserves_memory, may_alias}, dominance::DominatorTree};
+use crate::ir::def_use::UseMap;
+use crate::ir::structures::*;
+use crate::ir::types::Type;
+use crate::ir::values::BinaryOp;
+
+use super::constfold::Constant;
+use super::pass_manager::{AnalysisManager, Pass, Preserved};
+
+/// Operand of an expression, constants are compared by value since each literal is a distinct value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Operand {
+    Value(ValueRef),
+    Constant(ConstantKey),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum ConstantKey {
+    Int(i32),
+    Bool(bool),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Expression {
+    Binary(BinaryOp, Operand, Operand),
+    Offset(Type, Operand, Vec<Operand>, Vec<Option<usize>>),
+}
+
+fn is_commutative(op: &BinaryOp) -> bool {
+    matches!(op, BinaryOp::Add | BinaryOp::Mul | BinaryOp::And | BinaryOp::Or | BinaryOp::Xor | BinaryOp::Eq | BinaryOp::Ne)
+}
+
+struct ValueNumbering<'a> {
+    module: &'a Module,
+    function: &'a Function,
+    uses: UseMap,
+    /// Available expressions along the dominator tree path to the current block.
+    available: HashMap<Expression, ValueRef>,
+    /// Redundant instruction and the value replacing it.
+    replaced: HashMap<ValueRef, ValueRef>,
+}
+
+impl<'a> ValueNumbering<'a> {
+    fn leader(&self, value: ValueRef) -> ValueRef {
+        self.replaced.get(&value).cloned().unwrap_or(value)
+    }
+
+    fn operand(&self, value: ValueRef) -> Operand {
+        let value = self.leader(value);
+        match Constant::from_value(self.module.get_value(value)) {
+            Some(Constant::Int(value)) => Operand::Constant(ConstantKey::Int(value)),
+            Some(Constant::Bool(value)) => Operand::Constant(ConstantKey::Bool(value)),
+            None => Operand::Value(value)
+        }
+    }
+
+    fn expression(&self, instr: ValueRef) -> Option<Expression> {
+        match &self.module.get_value(instr).kind {
+            ValueKind::Binary(inner) => {
+                let (mut lhs, mut rhs) = (self.operand(inner.lhs), self.operand(inner.rhs));
+                if is_commutative(&inner.op) && rhs < lhs {
+                    std::mem::swap(&mut lhs, &mut rhs);
+                }
+                Some(Expression::Binary(inner.op.clone(), lhs, rhs))
+            },
+            ValueKind::Offset(inner) => Some(Expression::Offset(
+                inner.elem_type.clone(),
+                self.operand(inner.base_addr),
+                inner.index.iter().map(| index | self.operand(*index)).collect(),
+                inner.bounds.clone()
+            )),
+            _ => None
+        }
+    }
+
+    /// Forward a load from an earlier load or store of the same address in the block,
+    /// `memory` holds addresses with the value known to be stored there.
+    fn visit_memory_access(&mut self, instr: ValueRef, memory: &mut Vec<(ValueRef, ValueRef)>) {
+        match &self.module.get_value(instr).kind {
+            ValueKind::Load(inner) => {
+                let addr = self.leader(inner.addr);
+                match memory.iter().find(| (known, _) | *known == addr) {
+                    Some((_, value)) if self.module.get_value_type(*value) == self.module.get_value_type(instr) => {
+                        self.replaced.insert(instr, *value);
+                    },
+                    _ => memory.push((addr, instr))
+                }
+            },
+            ValueKind::Store(inner) => {
+                let addr = self.leader(inner.addr);
+                let value = self.leader(inner.value);
+                memory.retain(| (known, _) | !may_alias(self.module, &self.uses, *known, addr));
+                memory.push((addr, value));
+            },
+            ValueKind::FnCall(inner) if !call_preserves_memory(&inner.callee) => memory.clear(),
+            _ => ()
+        }
+    }
+
+    fn visit(&mut self, domtree: &DominatorTree, bb: BlockRef) {
+        let mut inserted = Vec::new();
+        let mut memory = Vec::new();
+        for instr in self.function.get_basic_block(bb).instrs.iter().cloned() {
+            let expression = match self.expression(instr) {
+                Some(expression) => expression,
+                None => {
+                    self.visit_memory_access(instr, &mut memory);
+                    continue
+                }
+            };
+            match self.available.get(&expression) {
+                Some(leader) => {
+                    self.replaced.insert(instr, *leader);
+                },
+                None => {
+                    self.available.insert(expression.clone(), instr);
+                    inserted.push(expression);
+                }
+            }
+        }
+        for child in domtree.children(bb) {
+            self.visit(domtree, *child);
+        }
+        for expression in inserted {
+            self.available.remove(&expression);
+        }
+    }
+}
+
+/// Replace `Binary` and `Offset` instructions computing the same value as a dominating instruction,
+/// and loads of addresses whose content is known from an earlier load or store in the same block.
+/// Returns the number of removed instructions.
+pub fn number_values(module: &mut Module, function: FunctionRef, domtree: &DominatorTree) -> usize {
+    let func = module.get_function(function);
+    if func.blocks.is_empty() {
+        return 0;
+    }
+    let mut numbering = ValueNumbering {
+        module,
+        function: func,
+        uses: UseMap::new(module, func),
+        available: HashMap::new(),
+        replaced: HashMap::new(),
+    };
+    numbering.visit(domtree, func.blocks[0]);
+    let replaced = numbering.replaced;
+
+    for (instr, leader) in replaced.iter() {
+        module.replace_uses_in_function(function, *instr, *leader);
+    }
+    // redundant instructions may use each other, detach all of them before removing.
+    let func = module.get_function_mut(function);
+    for bb in func.blocks.clone() {
+        func.get_basic_block_mut(bb).instrs.retain(| instr | !replaced.contains_key(instr));
+    }
+    let removed = replaced.keys().cloned().collect::<HashSet<_>>();
+    for instr in removed.iter() {
+        module.value_ctx.remove(*instr);
+    }
+    removed.len()
+}
+
+/// Global value numbering pass, named `gvn` in pipelines.
+pub struct GlobalValueNumbering;
+
+impl Pass for GlobalValueNumbering {
+    fn name(&self) -> &'static str {
+        "gvn"
+    }
+
+    fn run_on_function(&mut self, module: &mut Module, function: FunctionRef, analyses: &mut AnalysisManager) -> Preserved {
+        let domtree = analyses.get::<DominatorTree>(module, function);
+        if number_values(module, function, &domtree) > 0 {
+            Preserved::ControlFlow
+        } else {
+            Preserved::All
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::apps::executor::{run_on_module, ProgramEnv, Val};
+    use crate::frontend::new_parser::parse_module;
+    use crate::ir::analysis::cfg::ControlFlowGraph;
+    use crate::ir::verify::verify_module;
+
+    #[test]
+    fn test_gvn() {
+        let mut module = parse_module(r"
+            fn @f(#a: i32, #b: i32, #p: i32*) -> i32 {
+            %entry:
+                let %x = alloca i32, 4
+                let %0 = add #a, #b
+                let %1 = add #b, #a
+                let %2 = sub #a, #b
+                let %3 = sub #b, #a
+                let %4 = offset i32, %x, [1 < 4]
+                let %5 = offset i32, %x, [1 < 4]
+                let %6 = store %0, %4
+                let %7 = store 3, #p
+                let %8 = load %5
+                let %9 = lt %8, 10
+                br %9, label %then, label %exit
+            %then:
+                let %10 = add #a, #b
+                let %11 = load #p
+                let %12 = call @putint, %11
+                let %13 = load #p
+                let %14 = add %10, %13
+                jmp label %exit
+            %exit:
+                let %15 = add #b, #a
+                let %16 = add %15, %1
+                let %17 = add %16, %2
+                let %18 = add %17, %3
+                ret %18
+            }
+            fn @main() -> i32 {
+            %entry:
+                let %p = alloca i32, 1
+                let %0 = call @f, 1, 2, %p
+                ret %0
+            }
+        ").unwrap();
+        let expected = run_on_module(&mut ProgramEnv::new(), &module, "main", vec![]).unwrap();
+        let function = module.get_function_ref("f");
+        let domtree = DominatorTree::new(&ControlFlowGraph::new(module.get_function(function)));
+        // %1, %5, %10, %15 are redundant, %8 reads the stored %0 since `#p` cannot alias the local `%x`,
+        // and %13 reads %11 since `putint` writes no memory.
+        assert_eq!(number_values(&mut module, function, &domtree), 6);
+        assert!(verify_module(&module).is_ok());
+        assert_eq!(run_on_module(&mut ProgramEnv::new(), &module, "main", vec![]).unwrap(), expected);
+        assert_eq!(expected, Val::Integer(6));
+    }
+}
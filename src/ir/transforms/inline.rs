@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+
+use crate::apps::executor::is_runtime_function;
+use crate::ir::analysis::call_graph::CallGraph;
+use crate::ir::structures::*;
+use crate::ir::values::{ConstantUnit, Jump, Phi};
+use crate::utils::unique_name::UniqueName;
+
+use super::pass_manager::{AnalysisManager, Pass, Preserved};
+
+/// Callees with more instructions than this are not inlined by default.
+pub const DEFAULT_INLINE_THRESHOLD: usize = 64;
+
+/// Number of instructions and terminators of a function, the size used by the inline heuristic.
+pub fn function_size(function: &Function) -> usize {
+    function.blocks
+        .iter()
+        .map(| bb | function.get_basic_block(*bb).instrs.len() + 1)
+        .sum()
+}
+
+/// Names of all values and blocks in a function, so that new ones can be named apart.
+pub fn used_names(module: &Module, function: &Function) -> UniqueName {
+    let mut names = UniqueName::new();
+    let values = function.args
+        .iter()
+        .chain(function.blocks.iter().flat_map(| bb | function.get_basic_block(*bb).instrs.iter()))
+        .filter_map(| value | module.get_value(*value).name.clone());
+    let blocks = function.blocks
+        .iter()
+        .filter_map(| bb | function.get_basic_block(*bb).name.clone());
+    for name in values.chain(blocks) {
+        names.next_name(&name);
+    }
+    names
+}
+
+/// Whether calls to `callee` may be inlined, regardless of their size.
+///
+/// Runtime functions are handled by the executor by name and have no body to inline.
+pub fn is_inlinable(module: &Module, call_graph: &CallGraph, callee: &str) -> bool {
+    if is_runtime_function(callee) {
+        return false;
+    }
+    let callee = match module.string_func_map.get(callee) {
+        Some(callee) => *callee,
+        None => return false
+    };
+    let function = module.get_function(callee);
+    // phis in the entry block would need incoming values from the call site.
+    let entry_has_phis = function.blocks
+        .first()
+        .and_then(| entry | function.get_basic_block(*entry).instrs.first())
+        .is_some_and(| instr | module.get_value(*instr).is_phi());
+    !function.is_external && !function.blocks.is_empty() && !entry_has_phis && !call_graph.is_recursive(callee)
+}
+
+/// Inline the function call `call` in `function`, the callee must satisfy `is_inlinable`.
+///
+/// The block containing the call is split after the call into a continuation block,
+/// the cloned blocks of the callee are placed in between, and returns jump to the continuation.
+/// Arguments are replaced by the call operands, and the result of the call by the returned value,
+/// merged by a phi in the continuation block if the callee returns in several blocks.
+/// Allocas of the callee are hoisted into the entry block of the caller.
+pub fn inline_call(module: &mut Module, function: FunctionRef, call: ValueRef) {
+    let (callee, call_args) = match &module.get_value(call).kind {
+        ValueKind::FnCall(inner) => (module.get_function_ref(&inner.callee), inner.args.clone()),
+        _ => panic!("try to inline an instruction which is not a call")
+    };
+    let func = module.get_function(function);
+    let (call_bb, call_index) = func.find_instruction(call).expect("call not in the function");
+    let mut names = used_names(module, func);
+    let callee_func = module.get_function(callee).clone();
+
+    // clone blocks and instructions of the callee, operands are remapped afterwards.
+    let mut value_map: HashMap<ValueRef, ValueRef> = callee_func.args
+        .iter()
+        .cloned()
+        .zip(call_args)
+        .collect();
+    let mut block_map: HashMap<BlockRef, BlockRef> = HashMap::new();
+    let mut cloned_blocks = Vec::new();
+    for bb in callee_func.blocks.iter().cloned() {
+        let block = callee_func.get_basic_block(bb);
+        let mut cloned_block = BasicBlock::new();
+        cloned_block.set_name(block.name.as_ref().map(| name | names.next_fresh_name(name)));
+        cloned_block.terminator = block.terminator.clone();
+        for instr in block.instrs.iter().cloned() {
+            let mut value = module.get_value(instr).clone();
+            value.name = value.name.map(| name | names.next_fresh_name(&name));
+            let cloned = module.insert_value(value);
+            value_map.insert(instr, cloned);
+            cloned_block.instrs.push(cloned);
+        }
+        let cloned_bb = module.get_function_mut(function).insert_dangling_basic_block(cloned_block);
+        block_map.insert(bb, cloned_bb);
+        cloned_blocks.push(cloned_bb);
+    }
+
+    // split the calling block after the call.
+    let func = module.get_function_mut(function);
+    let call_block = func.get_basic_block_mut(call_bb);
+    let mut continuation = BasicBlock::new();
+    continuation.instrs = call_block.instrs.split_off(call_index + 1);
+    call_block.instrs.pop();
+    let cloned_entry = block_map[&callee_func.blocks[0]];
+    continuation.terminator = std::mem::replace(
+        &mut call_block.terminator,
+        Terminator::Jump(Jump { dest: cloned_entry, span: None }));
+    let continuation_name = call_block.name.as_ref().map(| name | names.next_fresh_name(&format!("{}.cont", name)));
+    continuation.set_name(continuation_name);
+    let successors = continuation.terminator.successors().collect::<Vec<_>>();
+    let continuation = func.insert_dangling_basic_block(continuation);
+    for succ in successors {
+        let phis = module.get_function(function).get_basic_block(succ).instrs.clone();
+        for phi in phis {
+            match &mut module.get_value_mut(phi).kind {
+                ValueKind::Phi(inner) => inner.incoming
+                    .iter_mut()
+                    .filter(| (_, pred) | *pred == call_bb)
+                    .for_each(| (_, pred) | *pred = continuation),
+                _ => break
+            }
+        }
+    }
+
+    // remap operands and labels, turn returns into jumps to the continuation.
+    let mut returned = Vec::new();
+    for cloned_bb in cloned_blocks.iter().cloned() {
+        let instrs = module.get_function(function).get_basic_block(cloned_bb).instrs.clone();
+        for instr in instrs {
+            let value = module.get_value_mut(instr);
+            value.kind
+                .operands_mut()
+                .for_each(| operand | *operand = value_map.get(operand).cloned().unwrap_or(*operand));
+            if let ValueKind::Phi(inner) = &mut value.kind {
+                inner.incoming
+                    .iter_mut()
+                    .for_each(| (_, pred) | *pred = block_map[pred]);
+            }
+        }
+        let block = module.get_function_mut(function).get_basic_block_mut(cloned_bb);
+        block.terminator
+            .operands_mut()
+            .for_each(| operand | *operand = value_map.get(operand).cloned().unwrap_or(*operand));
+        block.terminator
+            .successors_mut()
+            .for_each(| succ | *succ = block_map[succ]);
+        if let Terminator::Return(inner) = &block.terminator {
+            returned.push((inner.value, cloned_bb));
+            block.terminator = Terminator::Jump(Jump { dest: continuation, span: inner.span });
+        }
+    }
+
+    // lay out the cloned blocks right after the calling block, and hoist allocas.
+    let func = &mut module.func_ctx[function];
+    let position = func.blocks.iter().position(| bb | *bb == call_bb).unwrap() + 1;
+    func.blocks.splice(position..position, cloned_blocks.iter().cloned().chain(std::iter::once(continuation)));
+    let allocas = cloned_blocks
+        .iter()
+        .flat_map(| bb | func.get_basic_block(*bb).instrs.iter().cloned())
+        .filter(| instr | matches!(module.value_ctx[*instr].kind, ValueKind::Alloca(..)))
+        .collect::<Vec<_>>();
+    for bb in cloned_blocks {
+        func.get_basic_block_mut(bb).instrs.retain(| instr | !allocas.contains(instr));
+    }
+    let entry = func.blocks[0];
+    func.get_basic_block_mut(entry).instrs.splice(0..0, allocas);
+
+    // replace the result of the call.
+    let result = if module.get_value_type(call).is_unit_type() {
+        module.insert_value(ConstantUnit::new_value())
+    } else if let [(value, _)] = returned.as_slice() {
+        *value
+    } else {
+        let mut phi = Phi::new_value(module.get_value_type(call), returned);
+        phi.name = module.get_value(call).name.as_ref().map(| name | names.next_fresh_name(name));
+        let phi = module.insert_value(phi);
+        module.get_function_mut(function).get_basic_block_mut(continuation).instrs.insert(0, phi);
+        phi
+    };
+    module.replace_uses_in_function(function, call, result);
+    module.value_ctx.remove(call);
+}
+
+/// Function inlining pass, named `inline` in pipelines.
+///
+/// Calls to inlinable callees no larger than `threshold` are inlined,
+/// visiting functions bottom-up in the call graph so that callees are inlined into first.
+pub struct Inliner {
+    pub threshold: usize,
+}
+
+impl Default for Inliner {
+    fn default() -> Self {
+        Inliner { threshold: DEFAULT_INLINE_THRESHOLD }
+    }
+}
+
+impl Inliner {
+    /// Inline calls in `function`, returns the number of inlined calls.
+    pub fn inline_calls_in(&self, module: &mut Module, call_graph: &CallGraph, function: FunctionRef) -> usize {
+        let func = module.get_function(function);
+        let calls = func.blocks
+            .iter()
+            .flat_map(| bb | func.get_basic_block(*bb).instrs.iter().cloned())
+            .filter(| instr | match &module.get_value(*instr).kind {
+                ValueKind::FnCall(inner) => is_inlinable(module, call_graph, &inner.callee)
+                    && module.get_function_ref(&inner.callee) != function,
+                _ => false
+            })
+            .collect::<Vec<_>>();
+        let mut inlined = 0;
+        for call in calls {
+            let callee = match &module.get_value(call).kind {
+                ValueKind::FnCall(inner) => module.get_function_ref(&inner.callee),
+                _ => unreachable!()
+            };
+            if function_size(module.get_function(callee)) <= self.threshold {
+                inline_call(module, function, call);
+                inlined += 1;
+            }
+        }
+        inlined
+    }
+}
+
+impl Pass for Inliner {
+    fn name(&self) -> &'static str {
+        "inline"
+    }
+
+    fn run_on_module(&mut self, module: &mut Module, analyses: &mut AnalysisManager) -> Preserved {
+        let call_graph = CallGraph::new(module);
+        let mut preserved = Preserved::All;
+        for function in call_graph.sccs().iter().flatten().cloned() {
+            if module.get_function(function).is_external {
+                continue;
+            }
+            if self.inline_calls_in(module, &call_graph, function) > 0 {
+                analyses.invalidate(function, Preserved::None);
+                preserved = Preserved::None;
+            }
+        }
+        preserved
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::apps::executor::{run_on_module, ProgramEnv, Val};
+    use crate::frontend::new_parser::parse_module;
+    use crate::ir::verify::verify_module;
+
+    #[test]
+    fn test_inline_calls() {
+        let mut module = parse_module(r"
+            fn @putint(#x: i32) -> ();
+            fn @abs(#x: i32) -> i32 {
+            %entry:
+                let %neg = lt #x, 0
+                br %neg, label %then, label %else
+            %then:
+                let %y = sub 0, #x
+                ret %y
+            %else:
+                ret #x
+            }
+            fn @fact(#n: i32) -> i32 {
+            %entry:
+                let %c = le #n, 1
+                br %c, label %base, label %rec
+            %base:
+                ret 1
+            %rec:
+                let %m = sub #n, 1
+                let %r = call @fact, %m
+                let %y = mul #n, %r
+                ret %y
+            }
+            fn @main() -> i32 {
+            %entry:
+                let %y = call @abs, -3
+                let %f = call @fact, %y
+                let %0 = call @putint, %f
+                let %1 = add %f, %y
+                ret %1
+            }
+        ").unwrap();
+        let expected = run_on_module(&mut ProgramEnv::new(), &module, "main", vec![]).unwrap();
+        let mut analyses = AnalysisManager::new();
+        assert_eq!(Inliner::default().run_on_module(&mut module, &mut analyses), Preserved::None);
+        assert!(verify_module(&module).is_ok());
+
+        // only `abs` is inlined, `fact` is recursive and `putint` is a runtime function.
+        let main = module.get_function(module.get_function_ref("main"));
+        let calls = CallGraph::called_names(&module, module.get_function_ref("main"));
+        assert_eq!(calls, vec!["fact", "putint"]);
+        let block_names = main.blocks
+            .iter()
+            .map(| bb | main.get_basic_block(*bb).name.clone().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(block_names, vec!["entry", "entry.1", "then", "else", "entry.cont"]);
+        assert_eq!(run_on_module(&mut ProgramEnv::new(), &module, "main", vec![]).unwrap(), expected);
+        assert_eq!(expected, Val::Integer(9));
+    }
+
+    #[test]
+    fn test_inline_into_numeric_block() {
+        let mut module = parse_module(r"
+            fn @inc(#x: i32) -> i32 {
+            %entry:
+                let %y = add #x, 1
+                ret %y
+            }
+            fn @main() -> i32 {
+            %0:
+                let %1 = call @inc, 1
+                ret %1
+            }
+        ").unwrap();
+        let main = module.get_function_ref("main");
+        let func = module.get_function(main);
+        let call = func.get_basic_block(func.blocks[0]).instrs[0];
+        inline_call(&mut module, main, call);
+        assert!(verify_module(&module).is_ok());
+
+        // `0.cont` is not a name, the continuation takes a fresh number instead.
+        let func = module.get_function(main);
+        let continuation = func.get_basic_block(*func.blocks.last().unwrap()).name.clone().unwrap();
+        assert!(continuation.chars().all(| c | c.is_ascii_digit()), "{}", continuation);
+        let reparsed = parse_module(&module.to_string()).unwrap();
+        assert_eq!(run_on_module(&mut ProgramEnv::new(), &reparsed, "main", vec![]).unwrap(), Val::Integer(2));
+    }
+}
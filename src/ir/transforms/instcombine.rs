@@ -0,0 +1,280 @@
+use crate::ir::structures::*;
+use crate::ir::values::{Binary, BinaryOp, ConstantInt};
+
+use super::constfold::Constant;
+use super::inline::used_names;
+use super::pass_manager::{AnalysisManager, Pass, Preserved};
+
+/// Multiplications by `2^k` are turned into `k` doublings up to this `k`.
+pub const MAX_DOUBLINGS: u32 = 3;
+
+/// Value an instruction simplifies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Simplified {
+    Value(ValueRef),
+    Constant(Constant),
+}
+
+fn constant_of(module: &Module, value: ValueRef) -> Option<Constant> {
+    Constant::from_value(module.get_value(value))
+}
+
+/// Operation computing the same result with operands swapped.
+fn swapped(op: &BinaryOp) -> Option<BinaryOp> {
+    match op {
+        BinaryOp::Add | BinaryOp::Mul | BinaryOp::And | BinaryOp::Or | BinaryOp::Xor
+            | BinaryOp::Eq | BinaryOp::Ne => Some(op.clone()),
+        BinaryOp::Lt => Some(BinaryOp::Gt),
+        BinaryOp::Gt => Some(BinaryOp::Lt),
+        BinaryOp::Le => Some(BinaryOp::Ge),
+        BinaryOp::Ge => Some(BinaryOp::Le),
+        BinaryOp::Sub | BinaryOp::Div | BinaryOp::Rem => None
+    }
+}
+
+/// Move a constant left operand to the right, flipping comparisons, returns whether the instruction changed.
+pub fn canonicalize_operands(module: &mut Module, instr: ValueRef) -> bool {
+    let swap = match &module.get_value(instr).kind {
+        ValueKind::Binary(inner) => constant_of(module, inner.lhs).is_some()
+            && constant_of(module, inner.rhs).is_none()
+            && swapped(&inner.op).is_some(),
+        _ => false
+    };
+    if let (true, ValueKind::Binary(inner)) = (swap, &mut module.get_value_mut(instr).kind) {
+        inner.op = swapped(&inner.op).unwrap();
+        std::mem::swap(&mut inner.lhs, &mut inner.rhs);
+    }
+    swap
+}
+
+/// Whether `value` is `op lhs, rhs` for a constant `rhs`, returning `lhs`.
+fn match_constant_rhs(module: &Module, value: ValueRef, op: &BinaryOp, rhs: Constant) -> Option<ValueRef> {
+    match &module.get_value(value).kind {
+        ValueKind::Binary(inner) if inner.op == *op && constant_of(module, inner.rhs) == Some(rhs) => Some(inner.lhs),
+        _ => None
+    }
+}
+
+/// Algebraic identities of a binary instruction, whose constant operand is on the right.
+///
+/// Only identities holding for every operand under wrapping arithmetic are applied,
+/// and no instruction is simplified away if it may trap in the executor,
+/// `div x, x` and `rem x, -1` for example are kept since they fail for zero and `i32::MIN`.
+/// Comparisons are only simplified on integers, the executor rejects comparing booleans.
+pub fn simplify_binary(module: &Module, instr: ValueRef) -> Option<Simplified> {
+    let value = module.get_value(instr);
+    let inner = match &value.kind {
+        ValueKind::Binary(inner) => inner,
+        _ => return None
+    };
+    let is_bool = value.ty.is_i1_type();
+    let truth = | b: bool | Simplified::Constant(if is_bool { Constant::Bool(b) } else { Constant::Int(b as i32) });
+    let zero = truth(false);
+    let is_int_operand = module.get_value_type(inner.lhs).is_i32_type();
+    let (lhs, rhs) = (inner.lhs, inner.rhs);
+
+    if lhs == rhs {
+        return match inner.op {
+            BinaryOp::Sub | BinaryOp::Xor => Some(zero),
+            BinaryOp::And | BinaryOp::Or => Some(Simplified::Value(lhs)),
+            BinaryOp::Eq | BinaryOp::Le | BinaryOp::Ge if is_int_operand => Some(truth(true)),
+            BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Gt if is_int_operand => Some(truth(false)),
+            _ => None
+        };
+    }
+    // `sub 0, (sub 0, x)` is `x`.
+    if inner.op == BinaryOp::Sub && constant_of(module, lhs) == Some(Constant::Int(0)) {
+        let negated = match &module.get_value(rhs).kind {
+            ValueKind::Binary(neg) if neg.op == BinaryOp::Sub && constant_of(module, neg.lhs) == Some(Constant::Int(0)) => Some(neg.rhs),
+            _ => None
+        };
+        return negated.map(Simplified::Value);
+    }
+    let constant = constant_of(module, rhs)?;
+    match (&inner.op, constant) {
+        (BinaryOp::Add | BinaryOp::Sub | BinaryOp::Or | BinaryOp::Xor, Constant::Int(0) | Constant::Bool(false))
+            | (BinaryOp::Mul | BinaryOp::Div, Constant::Int(1))
+            | (BinaryOp::And, Constant::Int(-1) | Constant::Bool(true)) => Some(Simplified::Value(lhs)),
+        (BinaryOp::Mul | BinaryOp::And, Constant::Int(0) | Constant::Bool(false))
+            | (BinaryOp::Or, Constant::Int(-1) | Constant::Bool(true)) => Some(Simplified::Constant(constant)),
+        (BinaryOp::Rem, Constant::Int(1)) => Some(Simplified::Constant(Constant::Int(0))),
+        // `xor (xor x, c), c` is `x`, bitwise and logical not are `xor x, -1` and `xor x, true`.
+        (BinaryOp::Xor, _) => match_constant_rhs(module, lhs, &BinaryOp::Xor, constant).map(Simplified::Value),
+        _ => None
+    }
+}
+
+/// Replace multiplications by small powers of two by doublings, and multiplications
+/// by `-1` by negations, in place. Returns whether the instruction changed.
+///
+/// Both overflow for the same operands as the original multiplication, so wrapping and trapping agree.
+/// `div x, -1` is kept, it traps for `i32::MIN` while the negation wraps.
+pub fn reduce_strength(module: &mut Module, function: FunctionRef, instr: ValueRef) -> bool {
+    let (op, lhs, rhs) = match &module.get_value(instr).kind {
+        ValueKind::Binary(inner) => match constant_of(module, inner.rhs) {
+            Some(Constant::Int(rhs)) => (inner.op.clone(), inner.lhs, rhs),
+            _ => return false
+        },
+        _ => return false
+    };
+    match (op, rhs) {
+        (BinaryOp::Mul, -1) => {
+            let zero = module.insert_value(ConstantInt::new_value(0));
+            module.get_value_mut(instr).kind = ValueKind::Binary(Binary { op: BinaryOp::Sub, lhs: zero, rhs: lhs });
+            true
+        },
+        (BinaryOp::Mul, rhs) if rhs > 1 && rhs.count_ones() == 1 && rhs.trailing_zeros() <= MAX_DOUBLINGS => {
+            let mut names = used_names(module, module.get_function(function));
+            let base = module.get_value(instr).name.clone();
+            let ty = module.get_value_type(instr);
+            let mut doubled = lhs;
+            for _ in 1..rhs.trailing_zeros() {
+                let mut double = Binary::new(ty.clone(), BinaryOp::Add, doubled, doubled);
+                double.name = base.as_ref().map(| name | names.next_fresh_name(name));
+                let double = module.insert_value(double);
+                let func = module.get_function_mut(function);
+                let (bb, index) = func.find_instruction(instr).expect("instruction not in the function");
+                func.get_basic_block_mut(bb).instrs.insert(index, double);
+                doubled = double;
+            }
+            module.get_value_mut(instr).kind = ValueKind::Binary(Binary { op: BinaryOp::Add, lhs: doubled, rhs: doubled });
+            true
+        },
+        _ => false
+    }
+}
+
+/// Apply peephole rewrites to the binary instructions of a function until none applies.
+/// Returns the number of rewrites.
+pub fn combine_instructions(module: &mut Module, function: FunctionRef) -> usize {
+    let mut rewrites = 0;
+    loop {
+        let func = module.get_function(function);
+        let instrs = func.blocks
+            .iter()
+            .flat_map(| bb | func.get_basic_block(*bb).instrs.iter().cloned())
+            .filter(| instr | matches!(module.get_value(*instr).kind, ValueKind::Binary(..)))
+            .collect::<Vec<_>>();
+        let mut changed = false;
+        for instr in instrs {
+            if canonicalize_operands(module, instr) {
+                rewrites += 1;
+                changed = true;
+            }
+            if let Some(simplified) = simplify_binary(module, instr) {
+                let replacement = match simplified {
+                    Simplified::Value(value) => value,
+                    Simplified::Constant(constant) => module.insert_value(constant.into_value())
+                };
+                module.replace_uses_in_function(function, instr, replacement);
+                module.erase_instruction(function, instr);
+                rewrites += 1;
+                changed = true;
+            } else if reduce_strength(module, function, instr) {
+                rewrites += 1;
+                changed = true;
+            }
+        }
+        if !changed {
+            return rewrites;
+        }
+    }
+}
+
+/// Peephole simplification pass, named `instcombine` in pipelines.
+pub struct InstructionCombining;
+
+impl Pass for InstructionCombining {
+    fn name(&self) -> &'static str {
+        "instcombine"
+    }
+
+    fn run_on_function(&mut self, module: &mut Module, function: FunctionRef, _analyses: &mut AnalysisManager) -> Preserved {
+        if combine_instructions(module, function) > 0 {
+            Preserved::ControlFlow
+        } else {
+            Preserved::All
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::apps::executor::{run_on_module, ProgramEnv, Val};
+    use crate::frontend::new_parser::parse_module;
+    use crate::ir::verify::verify_module;
+
+    #[test]
+    fn test_instcombine() {
+        let mut module = parse_module(r"
+            fn @f(#x: i32, #y: i32) -> i32 {
+            %entry:
+                let %a = mul #x, 8
+                let %b = add 0, %a
+                let %c = sub #y, #y
+                let %d = xor #x, #x
+                let %e = lt 3, #x
+                let %f = le #y, #y
+                let %g = sub 0, #y
+                let %h = sub 0, %g
+                let %i = xor #x, -1
+                let %j = xor %i, -1
+                let %k = rem %j, 1
+                let %l = div #y, -1
+                let %m = rem #x, -1
+                let %0 = add %b, %c
+                let %1 = add %0, %d
+                let %2 = add %1, %e
+                let %3 = add %2, %f
+                let %4 = add %3, %h
+                let %5 = add %4, %k
+                let %6 = add %5, %l
+                let %7 = add %6, %m
+                ret %7
+            }
+            fn @main() -> i32 {
+            %entry:
+                let %0 = call @f, 5, -7
+                ret %0
+            }
+        ").unwrap();
+        let expected = run_on_module(&mut ProgramEnv::new(), &module, "main", vec![]).unwrap();
+        let function = module.get_function_ref("f");
+        assert!(combine_instructions(&mut module, function) > 0);
+        assert!(verify_module(&module).is_ok());
+        assert_eq!(run_on_module(&mut ProgramEnv::new(), &module, "main", vec![]).unwrap(), expected);
+        assert_eq!(expected, Val::Integer(40 + 1 + 1 - 7 + 7));
+
+        // `%a` is three doublings, `%e` is flipped, `%l` and `%m` may trap and are kept,
+        // `%g` and `%i` are left for dead code elimination.
+        let func = module.get_function(function);
+        let ops = func.blocks
+            .iter()
+            .flat_map(| bb | func.get_basic_block(*bb).instrs.iter())
+            .filter_map(| instr | match &module.get_value(*instr).kind {
+                ValueKind::Binary(inner) => Some(inner.op.to_string()),
+                _ => None
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(&ops[..8], ["add", "add", "add", "gt", "sub", "xor", "div", "rem"]);
+    }
+
+    #[test]
+    fn test_division_by_minus_one_traps() {
+        let mut module = parse_module(r"
+            fn @f(#x: i32) -> i32 {
+            %entry:
+                let %a = div #x, -1
+                ret %a
+            }
+        ").unwrap();
+        let function = module.get_function_ref("f");
+        assert_eq!(combine_instructions(&mut module, function), 0);
+        let trapped = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            run_on_module(&mut ProgramEnv::new(), &module, "f", vec![Val::Integer(i32::MIN)]).ok()
+        }));
+        assert!(trapped.is_err());
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+
+use crate::ir::analysis::{
+    alias::{call_preserves_memory, may_alias},
+    cfg::ControlFlowGraph,
+    dominance::DominatorTree,
+    loops::{Loop, LoopInfo},
+};
+use crate::ir::def_use::UseMap;
+use crate::ir::structures::*;
+use crate::ir::values::{BinaryOp, Jump, Phi};
+
+use super::constfold::{fold_binary, Constant};
+use super::inline::used_names;
+use super::pass_manager::{AnalysisManager, Pass, Preserved};
+
+/// Insert a block jumping to the loop header, through which all edges entering the loop pass.
+/// Phis of the header get their values from outside the loop merged in the preheader.
+fn insert_preheader(module: &mut Module, function: FunctionRef, cfg: &ControlFlowGraph, lp: &Loop) -> BlockRef {
+    let header = lp.header;
+    let outside = cfg.predecessors(header)
+        .iter()
+        .cloned()
+        .filter(| pred | !lp.contains(*pred))
+        .collect::<Vec<_>>();
+    let mut names = used_names(module, module.get_function(function));
+
+    let func = module.get_function_mut(function);
+    let mut block = BasicBlock::new();
+    block.set_name(func.get_basic_block(header).name.as_ref().map(| name | names.next_fresh_name(&format!("{}.preheader", name))));
+    block.set_terminator(Terminator::Jump(Jump { dest: header, span: None }));
+    let preheader = func.insert_dangling_basic_block(block);
+    let position = func.blocks.iter().position(| bb | *bb == header).unwrap();
+    func.blocks.insert(position, preheader);
+    for pred in outside.iter().cloned() {
+        func.get_basic_block_mut(pred)
+            .terminator
+            .successors_mut()
+            .filter(| succ | **succ == header)
+            .for_each(| succ | *succ = preheader);
+    }
+
+    let phis = func.get_basic_block(header).instrs.clone();
+    for phi in phis {
+        let (entering, mut incoming): (Vec<_>, Vec<_>) = match &module.get_value(phi).kind {
+            ValueKind::Phi(inner) => inner.incoming
+                .iter()
+                .cloned()
+                .partition(| (_, pred) | outside.contains(pred)),
+            _ => break
+        };
+        let entering_value = match entering.as_slice() {
+            [] => continue,
+            [(value, _)] => *value,
+            [(value, _), rest @ ..] if rest.iter().all(| (other, _) | other == value) => *value,
+            _ => {
+                let mut merged = Phi::new_value(module.get_value_type(phi), entering);
+                merged.name = module.get_value(phi).name.as_ref().map(| name | names.next_fresh_name(&format!("{}.ph", name)));
+                let merged = module.insert_value(merged);
+                module.get_function_mut(function).get_basic_block_mut(preheader).instrs.push(merged);
+                merged
+            }
+        };
+        incoming.push((entering_value, preheader));
+        if let ValueKind::Phi(inner) = &mut module.get_value_mut(phi).kind {
+            inner.incoming = incoming;
+        }
+    }
+    preheader
+}
+
+/// Give every loop a preheader, returns the number of inserted blocks.
+pub fn insert_preheaders(module: &mut Module, function: FunctionRef) -> usize {
+    let mut inserted = 0;
+    loop {
+        let func = module.get_function(function);
+        let cfg = ControlFlowGraph::new(func);
+        let loop_info = LoopInfo::new(&cfg, &DominatorTree::new(&cfg));
+        match loop_info.loops().iter().find(| lp | lp.preheader(&cfg).is_none()) {
+            Some(lp) => {
+                let lp = lp.clone();
+                insert_preheader(module, function, &cfg, &lp);
+                inserted += 1;
+            },
+            None => return inserted
+        }
+    }
+}
+
+/// Whether evaluating the instruction has no effect but its result and never fails.
+/// Arithmetic traps on overflow in the executor, so it is only speculatable
+/// on constant operands which `fold_binary` accepts, or for divisions by a constant other than `0` and `-1`.
+fn is_speculatable(module: &Module, instr: ValueRef) -> bool {
+    match &module.get_value(instr).kind {
+        ValueKind::Binary(inner) => {
+            let constants = (
+                Constant::from_value(module.get_value(inner.lhs)),
+                Constant::from_value(module.get_value(inner.rhs))
+            );
+            if let (Some(lhs), Some(rhs)) = constants {
+                return fold_binary(&inner.op, lhs, rhs).is_some();
+            }
+            match inner.op {
+                BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul => false,
+                BinaryOp::Div | BinaryOp::Rem =>
+                    matches!(module.get_value(inner.rhs).kind, ValueKind::ConstantInt(ref rhs) if rhs.value != 0 && rhs.value != -1),
+                _ => true
+            }
+        },
+        _ => false
+    }
+}
+
+struct LoopHoister<'a> {
+    cfg: &'a ControlFlowGraph,
+    domtree: &'a DominatorTree,
+    uses: &'a UseMap,
+    /// Block of each instruction, updated when instructions are hoisted.
+    def_block: HashMap<ValueRef, BlockRef>,
+}
+
+impl<'a> LoopHoister<'a> {
+    fn is_invariant(&self, module: &Module, lp: &Loop, instr: ValueRef) -> bool {
+        module.get_value(instr).kind
+            .operands()
+            .all(| operand | self.def_block.get(&operand).is_none_or(| bb | !lp.contains(*bb)))
+    }
+
+    /// Whether the block is executed in every iteration which does not leave the loop early,
+    /// so that hoisting instructions which may fail does not introduce new failures.
+    fn is_guaranteed_to_execute(&self, lp: &Loop, bb: BlockRef) -> bool {
+        lp.blocks
+            .iter()
+            .filter(| exiting | self.cfg.successors(**exiting).iter().any(| succ | !lp.contains(*succ)))
+            .all(| exiting | self.domtree.dominates(bb, *exiting))
+    }
+
+    /// Hoist invariant instructions of a loop into its preheader, returns the number of hoisted instructions.
+    fn hoist_loop(&mut self, module: &mut Module, function: FunctionRef, lp: &Loop) -> usize {
+        let preheader = lp.preheader(self.cfg).expect("preheaders are inserted before hoisting");
+        let func = module.get_function(function);
+        let loop_instrs = lp.blocks
+            .iter()
+            .flat_map(| bb | func.get_basic_block(*bb).instrs.iter().cloned())
+            .collect::<Vec<_>>();
+        let stored_addrs = loop_instrs
+            .iter()
+            .filter_map(| instr | match &module.get_value(*instr).kind {
+                ValueKind::Store(inner) => Some(inner.addr),
+                _ => None
+            })
+            .collect::<Vec<_>>();
+        let calls_clobber = loop_instrs
+            .iter()
+            .any(| instr | matches!(&module.get_value(*instr).kind, ValueKind::FnCall(inner) if !call_preserves_memory(&inner.callee)));
+
+        let mut hoisted = 0;
+        loop {
+            let mut changed = false;
+            for bb in lp.blocks.iter().cloned() {
+                for instr in module.get_function(function).get_basic_block(bb).instrs.clone() {
+                    if self.def_block[&instr] != bb || !self.is_invariant(module, lp, instr) {
+                        continue;
+                    }
+                    let hoistable = match &module.get_value(instr).kind {
+                        ValueKind::Binary(..) if is_speculatable(module, instr) => true,
+                        ValueKind::Binary(..) | ValueKind::Offset(..) => self.is_guaranteed_to_execute(lp, bb),
+                        ValueKind::Load(inner) => !calls_clobber
+                            && stored_addrs.iter().all(| addr | !may_alias(module, self.uses, *addr, inner.addr))
+                            && self.is_guaranteed_to_execute(lp, bb),
+                        _ => false
+                    };
+                    if hoistable {
+                        let func = module.get_function_mut(function);
+                        func.get_basic_block_mut(bb).instrs.retain(| other | *other != instr);
+                        func.get_basic_block_mut(preheader).instrs.push(instr);
+                        self.def_block.insert(instr, preheader);
+                        hoisted += 1;
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                return hoisted;
+            }
+        }
+    }
+}
+
+/// Hoist loop invariant `Binary`, `Offset` and `Load` instructions into loop preheaders, inner loops first.
+/// Instructions which may fail, including arithmetic which may overflow, and loads,
+/// are only hoisted from blocks executed in every iteration,
+/// and loads only if no store in the loop may write the address and no call may write memory.
+/// Returns the number of hoisted instructions.
+pub fn hoist_invariants(module: &mut Module, function: FunctionRef) -> usize {
+    let func = module.get_function(function);
+    let cfg = ControlFlowGraph::new(func);
+    let domtree = DominatorTree::new(&cfg);
+    let loop_info = LoopInfo::new(&cfg, &domtree);
+    let uses = UseMap::new(module, func);
+    let def_block = func.blocks
+        .iter()
+        .flat_map(| bb | func.get_basic_block(*bb).instrs.iter().map(| instr | (*instr, *bb)))
+        .collect();
+    let mut hoister = LoopHoister { cfg: &cfg, domtree: &domtree, uses: &uses, def_block };
+
+    let mut loops = loop_info.loops().iter().collect::<Vec<_>>();
+    loops.sort_by_key(| lp | std::cmp::Reverse(lp.depth));
+    loops
+        .into_iter()
+        .map(| lp | hoister.hoist_loop(module, function, lp))
+        .sum()
+}
+
+/// Loop invariant code motion pass, named `licm` in pipelines.
+pub struct LoopInvariantCodeMotion;
+
+impl Pass for LoopInvariantCodeMotion {
+    fn name(&self) -> &'static str {
+        "licm"
+    }
+
+    fn run_on_function(&mut self, module: &mut Module, function: FunctionRef, _analyses: &mut AnalysisManager) -> Preserved {
+        let inserted = insert_preheaders(module, function);
+        let hoisted = hoist_invariants(module, function);
+        if inserted > 0 {
+            Preserved::None
+        } else if hoisted > 0 {
+            Preserved::ControlFlow
+        } else {
+            Preserved::All
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::apps::executor::{run_on_module, ProgramEnv, Val};
+    use crate::frontend::new_parser::parse_module;
+    use crate::ir::verify::verify_module;
+
+    #[test]
+    fn test_licm() {
+        let mut module = parse_module(r"
+            @g: region i32, 4
+            fn @f(#n: i32, #k: i32) -> i32 {
+            %entry:
+                let %a = alloca i32, 8
+                let %c = gt #n, 0
+                br %c, label %loop, label %exit
+            %loop:
+                let %i = phi [0, label %entry], [%i.next, label %body]
+                let %sum = phi [0, label %entry], [%sum.next, label %body]
+                let %k2 = mul #k, 2
+                let %addr = offset i32, %a, [#k < 8]
+                let %0 = store %i, %addr
+                let %gaddr = offset i32, @g, [1 < 4]
+                let %gv = load %gaddr
+                let %1 = call @putint, %gv
+                let %cmp = lt %i, #n
+                br %cmp, label %body, label %exit
+            %body:
+                let %x = load %addr
+                let %q = div #n, #k
+                let %y = add %k2, %gv
+                let %sum.next = add %sum, %y
+                let %i.next = add %i, 1
+                jmp label %loop
+            %exit:
+                let %r = phi [0, label %entry], [%sum, label %loop]
+                ret %r
+            }
+            fn @main() -> i32 {
+            %entry:
+                let %0 = call @f, 3, 2
+                ret %0
+            }
+        ").unwrap();
+        let expected = run_on_module(&mut ProgramEnv::new(), &module, "main", vec![]).unwrap();
+        let function = module.get_function_ref("f");
+        // the header has two predecessors outside the loop.
+        assert_eq!(insert_preheaders(&mut module, function), 1);
+        // %k2, %addr, %gaddr and %gv, the store through %addr keeps %x in the loop,
+        // and %q and %y may trap while only executed when the loop does not exit.
+        assert_eq!(hoist_invariants(&mut module, function), 4);
+        assert!(verify_module(&module).is_ok());
+        assert_eq!(run_on_module(&mut ProgramEnv::new(), &module, "main", vec![]).unwrap(), expected);
+        assert_eq!(expected, Val::Integer(12));
+    }
+
+    #[test]
+    fn test_licm_keeps_overflow_in_cold_block() {
+        let mut module = parse_module(r"
+            fn @f(#n: i32, #k: i32) -> i32 {
+            %entry:
+                jmp label %loop
+            %loop:
+                let %i = phi [0, label %entry], [%i.next, label %latch]
+                let %c = eq %i, 100
+                br %c, label %rare, label %latch
+            %rare:
+                let %big = add #k, 2147483647
+                let %folded = add 1, 2
+                let %wrapped = add 2147483647, 1
+                let %0 = call @putint, %big
+                jmp label %latch
+            %latch:
+                let %i.next = add %i, 1
+                let %cmp = lt %i.next, #n
+                br %cmp, label %loop, label %exit
+            %exit:
+                ret %i.next
+            }
+            fn @main() -> i32 {
+            %entry:
+                let %0 = call @f, 3, 1
+                ret %0
+            }
+        ").unwrap();
+        let function = module.get_function_ref("f");
+        insert_preheaders(&mut module, function);
+        // only %folded, %big overflows for the given `#k` and %wrapped always does.
+        assert_eq!(hoist_invariants(&mut module, function), 1);
+        assert!(verify_module(&module).is_ok());
+        assert_eq!(run_on_module(&mut ProgramEnv::new(), &module, "main", vec![]).unwrap(), Val::Integer(3));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::ir::analysis::{cfg::ControlFlowGraph, dominance::DominatorTree};
+use crate::ir::def_use::{UseMap, User};
+use crate::ir::structures::*;
+
+use super::pass_manager::{AnalysisManager, Pass, Preserved};
+
+/// Loads and stores of a promotable alloca, with their positions.
+struct AllocaAccesses {
+    alloca: ValueRef,
+    /// (basic block, index in block, instruction)
+    loads: Vec<(BlockRef, usize, ValueRef)>,
+    stores: Vec<(BlockRef, usize, ValueRef)>,
+}
+
+/// Collect accesses of a scalar alloca used only as the address of loads and stores.
+fn collect_accesses(module: &Module, function: &Function, uses: &UseMap, alloca: ValueRef) -> Option<AllocaAccesses> {
+    match &module.get_value(alloca).kind {
+        ValueKind::Alloca(inner) if inner.num_elements == 1 => (),
+        _ => return None
+    }
+    let mut accesses = AllocaAccesses { alloca, loads: Vec::new(), stores: Vec::new() };
+    for user in uses.users(alloca).iter().cloned() {
+        let instr = match user {
+            User::Instruction(instr) => instr,
+            User::Terminator(_) => return None
+        };
+        let (bb, index) = function.find_instruction(instr)?;
+        match &module.get_value(instr).kind {
+            ValueKind::Load(_) => accesses.loads.push((bb, index, instr)),
+            // storing the address itself lets it escape.
+            ValueKind::Store(inner) if inner.addr == alloca && inner.value != alloca && !uses.has_users(instr) =>
+                accesses.stores.push((bb, index, instr)),
+            _ => return None
+        }
+    }
+    Some(accesses)
+}
+
+fn stored_value(module: &Module, store: ValueRef) -> ValueRef {
+    match &module.get_value(store).kind {
+        ValueKind::Store(inner) => inner.value,
+        _ => unreachable!("expect a store instruction")
+    }
+}
+
+/// Value each load reads, if every load can be resolved without merging values from different paths.
+fn resolve_loads(module: &Module, domtree: &DominatorTree, accesses: &AllocaAccesses) -> Option<Vec<(ValueRef, ValueRef)>> {
+    let mut blocks = accesses.loads
+        .iter()
+        .chain(accesses.stores.iter())
+        .map(| (bb, _, _) | *bb);
+    let first_block = blocks.next();
+
+    if let [(store_bb, store_index, store)] = accesses.stores.as_slice() {
+        // a single store dominating all loads.
+        let dominated = accesses.loads
+            .iter()
+            .all(| (bb, index, _) | if bb == store_bb {
+                store_index < index
+            } else {
+                domtree.strictly_dominates(*store_bb, *bb)
+            });
+        if dominated {
+            let value = stored_value(module, *store);
+            return Some(accesses.loads.iter().map(| (_, _, load) | (*load, value)).collect());
+        }
+    }
+
+    if blocks.all(| bb | Some(bb) == first_block) {
+        // all accesses in one block, each load reads the last store before it.
+        let mut instrs = accesses.loads
+            .iter()
+            .chain(accesses.stores.iter())
+            .map(| (_, index, instr) | (*index, *instr))
+            .collect::<Vec<_>>();
+        instrs.sort();
+        let mut current = None;
+        let mut resolved = Vec::new();
+        for (_, instr) in instrs {
+            match &module.get_value(instr).kind {
+                ValueKind::Store(inner) => {
+                    // the stored value may be an earlier load of the same alloca, which is erased as well.
+                    current = Some(resolved
+                        .iter()
+                        .find(| (load, _) | *load == inner.value)
+                        .map_or(inner.value, | (_, value) | *value));
+                },
+                // loading before any store reads memory from another iteration or uninitialized memory.
+                _ => resolved.push((instr, current?))
+            }
+        }
+        return Some(resolved);
+    }
+    None
+}
+
+/// Promote scalar allocas to the values stored into them when no phi is needed,
+/// that is, a single store dominating all loads, or all loads and stores in one basic block.
+/// Loads are replaced by the stored values, then the loads, the stores and the alloca are erased.
+/// Returns the number of promoted allocas.
+pub fn promote_allocas(module: &mut Module, function: FunctionRef) -> usize {
+    let func = module.get_function(function);
+    if func.is_external || func.blocks.is_empty() {
+        return 0;
+    }
+    let domtree = DominatorTree::new(&ControlFlowGraph::new(func));
+    let uses = UseMap::new(module, func);
+    let candidates = func.blocks
+        .iter()
+        .flat_map(| bb | func.get_basic_block(*bb).instrs.iter().cloned())
+        .filter_map(| instr | collect_accesses(module, func, &uses, instr))
+        .collect::<Vec<_>>();
+
+    let mut promoted = 0;
+    for accesses in candidates {
+        // resolve against the current module, since loads of allocas promoted earlier
+        // may have been replaced in the stored values.
+        let resolved = match resolve_loads(module, &domtree, &accesses) {
+            Some(resolved) => resolved,
+            None => continue
+        };
+        let type_matches = resolved
+            .iter()
+            .all(| (load, value) | module.get_value_type(*load) == module.get_value_type(*value));
+        if !type_matches {
+            continue;
+        }
+        for (load, value) in resolved {
+            module.replace_uses_in_function(function, load, value);
+            module.erase_instruction(function, load);
+        }
+        for (_, _, store) in accesses.stores {
+            module.erase_instruction(function, store);
+        }
+        module.erase_instruction(function, accesses.alloca);
+        promoted += 1;
+    }
+    promoted
+}
+
+/// Promote allocas in every function of the module, see `promote_allocas`.
+pub fn promote_module_allocas(module: &mut Module) -> usize {
+    module.funcs
+        .clone()
+        .into_iter()
+        .map(| function | promote_allocas(module, function))
+        .sum()
+}
+
+/// Alloca promotion pass, named `mem2reg` in pipelines.
+pub struct PromoteAllocas;
+
+impl Pass for PromoteAllocas {
+    fn name(&self) -> &'static str {
+        "mem2reg"
+    }
+
+    fn run_on_function(&mut self, module: &mut Module, function: FunctionRef, _analyses: &mut AnalysisManager) -> Preserved {
+        if promote_allocas(module, function) > 0 {
+            Preserved::ControlFlow
+        } else {
+            Preserved::All
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::apps::executor::{run_on_module, ProgramEnv, Val};
+    use crate::frontend::new_parser::parse_module;
+    use crate::ir::verify::verify_module;
+
+    #[test]
+    fn test_promote_allocas() {
+        let src = std::fs::read_to_string(
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/if.acc")).unwrap();
+        let mut module = parse_module(&src).unwrap();
+        let expected = ["if_ifElse_", "if_if_Else"]
+            .map(| entry | run_on_module(&mut ProgramEnv::new(), &module, entry, vec![]).unwrap());
+
+        // `b` and the return slot have a single dominating store, `a` is stored in several branches.
+        let function = module.get_function_ref("if_ifElse_");
+        assert_eq!(promote_allocas(&mut module, function), 2);
+        promote_module_allocas(&mut module);
+        assert!(verify_module(&module).is_ok());
+        let function = module.get_function(function);
+        let num_loads = function.blocks
+            .iter()
+            .flat_map(| bb | function.get_basic_block(*bb).instrs.iter())
+            .filter(| instr | matches!(module.get_value(**instr).kind, ValueKind::Load(..)))
+            .count();
+        assert_eq!(num_loads, 3);
+
+        let results = ["if_ifElse_", "if_if_Else"]
+            .map(| entry | run_on_module(&mut ProgramEnv::new(), &module, entry, vec![]).unwrap());
+        assert_eq!(results, expected);
+        assert_eq!(results[0], Val::Integer(25));
+
+        let mut module = parse_module(r"
+            fn @f(#a: i32) -> i32 {
+            %entry:
+                let %x = alloca i32, 1
+                let %0 = store #a, %x
+                let %1 = load %x
+                let %2 = add %1, 1
+                let %3 = store %2, %x
+                let %4 = load %x
+                ret %4
+            }
+        ").unwrap();
+        let function = module.get_function_ref("f");
+        assert_eq!(promote_allocas(&mut module, function), 1);
+        let function = module.get_function(function);
+        assert_eq!(function.get_basic_block(function.blocks[0]).instrs.len(), 1);
+        assert!(verify_module(&module).is_ok());
+    }
+}
@@ -0,0 +1,12 @@
+//! Transformations over the IR.
+
+pub mod pass_manager;
+pub mod mem2reg;
+pub mod constfold;
+pub mod sccp;
+pub mod dce;
+pub mod simplify_cfg;
+pub mod inline;
+pub mod gvn;
+pub mod licm;
+pub mod instcombine;
@@ -0,0 +1,300 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::ir::analysis::{cfg::ControlFlowGraph, dominance::DominatorTree, loops::LoopInfo};
+use crate::ir::structures::*;
+use crate::ir::verify::{verify_module, VerifyError};
+
+/// Analyses kept valid by a pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preserved {
+    /// Nothing changed.
+    All,
+    /// Instructions changed, but basic blocks and terminator edges are kept.
+    ControlFlow,
+    /// The control flow graph may have changed.
+    None,
+}
+
+impl Preserved {
+    /// Analyses preserved by both.
+    pub fn intersect(self, other: Preserved) -> Preserved {
+        match (self, other) {
+            (Preserved::All, other) | (other, Preserved::All) => other,
+            (Preserved::ControlFlow, Preserved::ControlFlow) => Preserved::ControlFlow,
+            _ => Preserved::None
+        }
+    }
+
+    pub fn changed(self) -> bool {
+        self != Preserved::All
+    }
+}
+
+/// Analysis result computed on demand for a function and cached by `AnalysisManager`.
+pub trait FunctionAnalysis: Any + Sized {
+    /// Whether the result only depends on the control flow graph,
+    /// so that it survives passes preserving `Preserved::ControlFlow`.
+    const CONTROL_FLOW_ONLY: bool;
+
+    fn compute(module: &Module, function: FunctionRef, analyses: &mut AnalysisManager) -> Self;
+}
+
+impl FunctionAnalysis for ControlFlowGraph {
+    const CONTROL_FLOW_ONLY: bool = true;
+
+    fn compute(module: &Module, function: FunctionRef, _: &mut AnalysisManager) -> Self {
+        ControlFlowGraph::new(module.get_function(function))
+    }
+}
+
+impl FunctionAnalysis for DominatorTree {
+    const CONTROL_FLOW_ONLY: bool = true;
+
+    fn compute(module: &Module, function: FunctionRef, analyses: &mut AnalysisManager) -> Self {
+        DominatorTree::new(&analyses.get::<ControlFlowGraph>(module, function))
+    }
+}
+
+/// Post-dominator tree, see `DominatorTree::new_post`.
+pub struct PostDominatorTree(pub DominatorTree);
+
+impl FunctionAnalysis for PostDominatorTree {
+    const CONTROL_FLOW_ONLY: bool = true;
+
+    fn compute(module: &Module, function: FunctionRef, analyses: &mut AnalysisManager) -> Self {
+        PostDominatorTree(DominatorTree::new_post(&analyses.get::<ControlFlowGraph>(module, function)))
+    }
+}
+
+impl FunctionAnalysis for LoopInfo {
+    const CONTROL_FLOW_ONLY: bool = true;
+
+    fn compute(module: &Module, function: FunctionRef, analyses: &mut AnalysisManager) -> Self {
+        let cfg = analyses.get::<ControlFlowGraph>(module, function);
+        let domtree = analyses.get::<DominatorTree>(module, function);
+        LoopInfo::new(&cfg, &domtree)
+    }
+}
+
+struct CachedAnalysis {
+    result: Rc<dyn Any>,
+    control_flow_only: bool,
+}
+
+/// Cache of function analyses, invalidated according to what passes preserve.
+#[derive(Default)]
+pub struct AnalysisManager {
+    cache: HashMap<(FunctionRef, TypeId), CachedAnalysis>,
+}
+
+impl AnalysisManager {
+    pub fn new() -> AnalysisManager {
+        AnalysisManager::default()
+    }
+
+    /// Get the cached result, or compute and cache it.
+    pub fn get<A: FunctionAnalysis>(&mut self, module: &Module, function: FunctionRef) -> Rc<A> {
+        let key = (function, TypeId::of::<A>());
+        if let Some(cached) = self.cache.get(&key) {
+            return cached.result
+                .clone()
+                .downcast::<A>()
+                .expect("analysis cached with a mismatched type");
+        }
+        let result = Rc::new(A::compute(module, function, self));
+        self.cache.insert(key, CachedAnalysis { result: result.clone(), control_flow_only: A::CONTROL_FLOW_ONLY });
+        result
+    }
+
+    /// Drop results of a function not kept valid by `preserved`.
+    pub fn invalidate(&mut self, function: FunctionRef, preserved: Preserved) {
+        match preserved {
+            Preserved::All => (),
+            Preserved::ControlFlow =>
+                self.cache.retain(| (func, _), cached | *func != function || cached.control_flow_only),
+            Preserved::None =>
+                self.cache.retain(| (func, _), _ | *func != function),
+        }
+    }
+
+    /// Drop results of every function not kept valid by `preserved`.
+    pub fn invalidate_all(&mut self, preserved: Preserved) {
+        match preserved {
+            Preserved::All => (),
+            Preserved::ControlFlow => self.cache.retain(| _, cached | cached.control_flow_only),
+            Preserved::None => self.cache.clear(),
+        }
+    }
+
+    pub fn is_cached<A: FunctionAnalysis>(&self, function: FunctionRef) -> bool {
+        self.cache.contains_key(&(function, TypeId::of::<A>()))
+    }
+}
+
+/// A transformation over the module.
+///
+/// Function passes implement `run_on_function` only,
+/// module passes override `run_on_module`.
+pub trait Pass {
+    /// Name used in pass pipelines.
+    fn name(&self) -> &'static str;
+
+    fn run_on_function(&mut self, _module: &mut Module, _function: FunctionRef, _analyses: &mut AnalysisManager) -> Preserved {
+        Preserved::All
+    }
+
+    /// Run on every function definition by default, invalidating analyses after each function.
+    fn run_on_module(&mut self, module: &mut Module, analyses: &mut AnalysisManager) -> Preserved {
+        let mut preserved = Preserved::All;
+        for function in module.funcs.clone() {
+            if module.get_function(function).is_external {
+                continue;
+            }
+            let function_preserved = self.run_on_function(module, function, analyses);
+            analyses.invalidate(function, function_preserved);
+            preserved = preserved.intersect(function_preserved);
+        }
+        preserved
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum PassError {
+    /// The pipeline names a pass which does not exist.
+    UnknownPass(String),
+    /// The module is broken after running the pass.
+    VerifyFailed(String, Vec<VerifyError>),
+}
+
+impl fmt::Display for PassError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PassError::UnknownPass(name) =>
+                write!(f, "unknown pass '{}', available passes are: {}", name, available_passes().join(", ")),
+            PassError::VerifyFailed(name, errors) => {
+                write!(f, "module is broken after pass '{}'", name)?;
+                errors.iter().try_for_each(| err | write!(f, "\n{}", err))
+            }
+        }
+    }
+}
+
+/// Names of passes that can appear in pipelines.
+pub fn available_passes() -> Vec<&'static str> {
+    vec!["mem2reg", "constfold", "sccp", "dce", "simplifycfg", "inline", "gvn", "licm", "instcombine"]
+}
+
+/// Create a pass from its name in pipelines.
+pub fn create_pass(name: &str) -> Option<Box<dyn Pass>> {
+    use super::{constfold::ConstantFolding, dce::DeadCodeElimination, mem2reg::PromoteAllocas};
+    use super::{sccp::SparseConditionalConstantPropagation, simplify_cfg::SimplifyControlFlow, inline::Inliner};
+    use super::{gvn::GlobalValueNumbering, licm::LoopInvariantCodeMotion, instcombine::InstructionCombining};
+    match name {
+        "mem2reg" => Some(Box::new(PromoteAllocas)),
+        "constfold" => Some(Box::new(ConstantFolding)),
+        "sccp" => Some(Box::new(SparseConditionalConstantPropagation)),
+        "dce" => Some(Box::new(DeadCodeElimination)),
+        "simplifycfg" => Some(Box::new(SimplifyControlFlow)),
+        "inline" => Some(Box::new(Inliner::default())),
+        "gvn" => Some(Box::new(GlobalValueNumbering)),
+        "licm" => Some(Box::new(LoopInvariantCodeMotion)),
+        "instcombine" => Some(Box::new(InstructionCombining)),
+        _ => None
+    }
+}
+
+/// Run a sequence of passes, sharing one analysis cache.
+#[derive(Default)]
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+    analyses: AnalysisManager,
+    /// Verify the module after each pass.
+    pub verify_each: bool,
+}
+
+impl PassManager {
+    pub fn new() -> PassManager {
+        PassManager::default()
+    }
+
+    /// Build from a comma separated pipeline such as `dce,constfold`,
+    /// the `-passes=` prefix of command line options is accepted as well.
+    pub fn parse(pipeline: &str) -> Result<PassManager, PassError> {
+        let pipeline = pipeline.strip_prefix("-passes=").unwrap_or(pipeline);
+        let mut manager = PassManager::new();
+        for name in pipeline.split(',').map(str::trim).filter(| name | !name.is_empty()) {
+            let pass = create_pass(name).ok_or_else(|| PassError::UnknownPass(name.to_string()))?;
+            manager.add_pass(pass);
+        }
+        Ok(manager)
+    }
+
+    pub fn add_pass(&mut self, pass: Box<dyn Pass>) {
+        self.passes.push(pass);
+    }
+
+    pub fn pass_names(&self) -> Vec<&'static str> {
+        self.passes.iter().map(| pass | pass.name()).collect()
+    }
+
+    /// Run passes in order, returns whether the module changed.
+    pub fn run(&mut self, module: &mut Module) -> Result<bool, PassError> {
+        let mut changed = false;
+        for pass in self.passes.iter_mut() {
+            let preserved = pass.run_on_module(module, &mut self.analyses);
+            self.analyses.invalidate_all(preserved);
+            changed |= preserved.changed();
+            if self.verify_each {
+                verify_module(module)
+                    .map_err(| errors | PassError::VerifyFailed(pass.name().to_string(), errors))?;
+            }
+        }
+        Ok(changed)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frontend::new_parser::parse_module;
+
+    #[test]
+    fn test_pipeline_and_analysis_cache() {
+        assert!(matches!(PassManager::parse("-passes=dce,nope"), Err(PassError::UnknownPass(name)) if name == "nope"));
+        let mut manager = PassManager::parse("-passes=constfold,dce").unwrap();
+        assert_eq!(manager.pass_names(), vec!["constfold", "dce"]);
+        manager.verify_each = true;
+
+        let mut module = parse_module(r"
+            fn @f() -> i32 {
+            %entry:
+                let %0 = add 1, 2
+                let %1 = mul %0, 3
+                let %2 = sub %1, %0
+                ret %1
+            }
+        ").unwrap();
+        let function = module.get_function_ref("f");
+        let cfg = manager.analyses.get::<ControlFlowGraph>(&module, function);
+        manager.analyses.get::<DominatorTree>(&module, function);
+        assert!(Rc::ptr_eq(&cfg, &manager.analyses.get::<ControlFlowGraph>(&module, function)));
+
+        assert!(manager.run(&mut module).unwrap());
+        // instruction changes keep the control flow analyses.
+        assert!(manager.analyses.is_cached::<DominatorTree>(function));
+        let function = module.get_function(function);
+        let block = function.get_basic_block(function.blocks[0]);
+        assert!(block.instrs.is_empty());
+        match &block.terminator {
+            Terminator::Return(inner) =>
+                assert!(matches!(module.get_value(inner.value).kind, ValueKind::ConstantInt(ref c) if c.value == 9)),
+            _ => panic!("expect return")
+        }
+        manager.analyses.invalidate_all(Preserved::None);
+        assert!(!manager.analyses.is_cached::<ControlFlowGraph>(module.get_function_ref("f")));
+    }
+}
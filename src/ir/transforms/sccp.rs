@@ -0,0 +1,324 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::ir::def_use::{UseMap, User};
+use crate::ir::structures::*;
+use crate::ir::values::{Jump, Phi};
+
+use super::constfold::{fold_binary, Constant};
+use super::pass_manager::{AnalysisManager, Pass, Preserved};
+
+/// Lattice of values in sparse conditional constant propagation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatticeValue {
+    /// No executable definition reaches the value yet.
+    Undefined,
+    Constant(Constant),
+    /// The value is not a compile time constant.
+    Overdefined,
+}
+
+impl LatticeValue {
+    fn meet(self, other: LatticeValue) -> LatticeValue {
+        match (self, other) {
+            (LatticeValue::Undefined, other) | (other, LatticeValue::Undefined) => other,
+            (LatticeValue::Constant(lhs), LatticeValue::Constant(rhs)) if lhs == rhs => self,
+            _ => LatticeValue::Overdefined
+        }
+    }
+}
+
+/// Result of sparse conditional constant propagation over a function.
+pub struct ConstantPropagation {
+    values: HashMap<ValueRef, LatticeValue>,
+    executable_blocks: HashSet<BlockRef>,
+    executable_edges: HashSet<(BlockRef, BlockRef)>,
+}
+
+struct Solver<'a> {
+    module: &'a Module,
+    function: &'a Function,
+    uses: UseMap,
+    result: ConstantPropagation,
+    block_worklist: VecDeque<BlockRef>,
+    value_worklist: VecDeque<ValueRef>,
+}
+
+impl<'a> Solver<'a> {
+    fn lattice(&self, value: ValueRef) -> LatticeValue {
+        let value_data = self.module.get_value(value);
+        match Constant::from_value(value_data) {
+            Some(constant) => LatticeValue::Constant(constant),
+            None if value_data.isa_instruction() =>
+                self.result.values.get(&value).cloned().unwrap_or(LatticeValue::Undefined),
+            // arguments, global variables and null pointers.
+            None => LatticeValue::Overdefined
+        }
+    }
+
+    fn update(&mut self, instr: ValueRef, lattice: LatticeValue) {
+        let old = self.lattice(instr);
+        // values only move down the lattice.
+        let new = old.meet(lattice);
+        if new != old {
+            self.result.values.insert(instr, new);
+            self.value_worklist.push_back(instr);
+        }
+    }
+
+    fn mark_edge(&mut self, from: BlockRef, to: BlockRef) {
+        if !self.result.executable_edges.insert((from, to)) {
+            return;
+        }
+        if self.result.executable_blocks.insert(to) {
+            self.block_worklist.push_back(to);
+        } else {
+            // a new incoming edge changes phis only.
+            let phis = self.function
+                .get_basic_block(to)
+                .instrs
+                .iter()
+                .cloned()
+                .take_while(| instr | self.module.get_value(*instr).is_phi())
+                .collect::<Vec<_>>();
+            phis.into_iter().for_each(| phi | self.visit_instruction(phi, to));
+        }
+    }
+
+    fn visit_instruction(&mut self, instr: ValueRef, bb: BlockRef) {
+        let lattice = match &self.module.get_value(instr).kind {
+            ValueKind::Phi(inner) => inner.incoming
+                .iter()
+                .filter(| (_, pred) | self.result.executable_edges.contains(&(*pred, bb)))
+                .fold(LatticeValue::Undefined, | acc, (value, _) | acc.meet(self.lattice(*value))),
+            ValueKind::Binary(inner) => match (self.lattice(inner.lhs), self.lattice(inner.rhs)) {
+                (LatticeValue::Constant(lhs), LatticeValue::Constant(rhs)) => fold_binary(&inner.op, lhs, rhs)
+                    .filter(| folded | folded.has_type(&self.module.get_value(instr).ty))
+                    .map_or(LatticeValue::Overdefined, LatticeValue::Constant),
+                (LatticeValue::Overdefined, _) | (_, LatticeValue::Overdefined) => LatticeValue::Overdefined,
+                _ => LatticeValue::Undefined
+            },
+            _ => LatticeValue::Overdefined
+        };
+        self.update(instr, lattice);
+    }
+
+    fn visit_terminator(&mut self, bb: BlockRef) {
+        match &self.function.get_basic_block(bb).terminator {
+            Terminator::Jump(inner) => self.mark_edge(bb, inner.dest),
+            Terminator::Branch(inner) => match self.lattice(inner.cond) {
+                LatticeValue::Undefined => (),
+                LatticeValue::Constant(cond) =>
+                    self.mark_edge(bb, if cond.is_truthy() { inner.true_label } else { inner.false_label }),
+                LatticeValue::Overdefined => {
+                    self.mark_edge(bb, inner.true_label);
+                    self.mark_edge(bb, inner.false_label);
+                }
+            },
+            Terminator::Return(_) | Terminator::Panic => ()
+        }
+    }
+
+    fn solve(mut self) -> ConstantPropagation {
+        let entry = match self.function.blocks.first() {
+            Some(entry) => *entry,
+            None => return self.result
+        };
+        self.result.executable_blocks.insert(entry);
+        self.block_worklist.push_back(entry);
+        loop {
+            if let Some(bb) = self.block_worklist.pop_front() {
+                for instr in self.function.get_basic_block(bb).instrs.clone() {
+                    self.visit_instruction(instr, bb);
+                }
+                self.visit_terminator(bb);
+            } else if let Some(value) = self.value_worklist.pop_front() {
+                for user in self.uses.users(value).to_vec() {
+                    match user {
+                        User::Instruction(instr) => {
+                            let (bb, _) = self.function.find_instruction(instr).expect("user not in the function");
+                            if self.result.executable_blocks.contains(&bb) {
+                                self.visit_instruction(instr, bb);
+                            }
+                        },
+                        User::Terminator(bb) if self.result.executable_blocks.contains(&bb) => self.visit_terminator(bb),
+                        User::Terminator(_) => ()
+                    }
+                }
+            } else {
+                return self.result;
+            }
+        }
+    }
+}
+
+impl ConstantPropagation {
+    /// Propagate constants along executable control flow paths of `function`.
+    pub fn new(module: &Module, function: &Function) -> ConstantPropagation {
+        let solver = Solver {
+            module,
+            function,
+            uses: UseMap::new(module, function),
+            result: ConstantPropagation {
+                values: HashMap::new(),
+                executable_blocks: HashSet::new(),
+                executable_edges: HashSet::new(),
+            },
+            block_worklist: VecDeque::new(),
+            value_worklist: VecDeque::new(),
+        };
+        solver.solve()
+    }
+
+    /// Lattice value of an instruction, constants and other values are not recorded.
+    pub fn lattice_value(&self, instr: ValueRef) -> LatticeValue {
+        self.values.get(&instr).cloned().unwrap_or(LatticeValue::Undefined)
+    }
+
+    pub fn is_executable(&self, bb: BlockRef) -> bool {
+        self.executable_blocks.contains(&bb)
+    }
+
+    pub fn is_edge_executable(&self, from: BlockRef, to: BlockRef) -> bool {
+        self.executable_edges.contains(&(from, to))
+    }
+
+    /// Blocks never executed on any input, in layout order.
+    pub fn unreachable_blocks(&self, function: &Function) -> Vec<BlockRef> {
+        function.blocks
+            .iter()
+            .cloned()
+            .filter(| bb | !self.is_executable(*bb))
+            .collect()
+    }
+}
+
+/// Statistics of `propagate_constants`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SccpStats {
+    /// Number of instructions replaced by constants.
+    pub folded_instructions: usize,
+    /// Number of branches turned into jumps.
+    pub folded_branches: usize,
+    /// Blocks found unreachable, left for unreachable block elimination.
+    pub unreachable_blocks: Vec<BlockRef>,
+}
+
+/// Replace instructions evaluating to constants by the constants,
+/// and branches on constant conditions by jumps to the taken label.
+pub fn propagate_constants(module: &mut Module, function: FunctionRef) -> SccpStats {
+    let func = module.get_function(function);
+    if func.is_external {
+        return SccpStats::default();
+    }
+    let propagation = ConstantPropagation::new(module, func);
+    let mut stats = SccpStats {
+        unreachable_blocks: propagation.unreachable_blocks(func),
+        ..SccpStats::default()
+    };
+
+    let mut constant_instrs = Vec::new();
+    let mut constant_branches = Vec::new();
+    for bb in func.blocks.iter().cloned().filter(| bb | propagation.is_executable(*bb)) {
+        let block = func.get_basic_block(bb);
+        for instr in block.instrs.iter().cloned() {
+            if let LatticeValue::Constant(constant) = propagation.lattice_value(instr) {
+                constant_instrs.push((instr, constant));
+            }
+        }
+        if let Terminator::Branch(inner) = &block.terminator {
+            let executable = | label | propagation.is_edge_executable(bb, label);
+            match (executable(inner.true_label), executable(inner.false_label)) {
+                (true, false) => constant_branches.push((bb, inner.true_label, inner.false_label, inner.span)),
+                (false, true) => constant_branches.push((bb, inner.false_label, inner.true_label, inner.span)),
+                _ => ()
+            }
+        }
+    }
+
+    for (instr, constant) in constant_instrs {
+        let constant = module.insert_value(constant.into_value());
+        module.replace_uses_in_function(function, instr, constant);
+        module.erase_instruction(function, instr);
+        stats.folded_instructions += 1;
+    }
+    for (bb, taken, not_taken, span) in constant_branches {
+        let func = module.get_function_mut(function);
+        func.get_basic_block_mut(bb).terminator = Terminator::Jump(Jump { dest: taken, span });
+        if taken != not_taken {
+            remove_phi_incoming(module, function, not_taken, bb);
+        }
+        stats.folded_branches += 1;
+    }
+    stats
+}
+
+/// Remove the incoming values from `pred` of phis in `bb`, after the edge is removed.
+pub fn remove_phi_incoming(module: &mut Module, function: FunctionRef, bb: BlockRef, pred: BlockRef) {
+    let phis = module.get_function(function).get_basic_block(bb).instrs.clone();
+    for phi in phis {
+        match &mut module.get_value_mut(phi).kind {
+            ValueKind::Phi(Phi { incoming }) => incoming.retain(| (_, incoming_bb) | *incoming_bb != pred),
+            _ => break
+        }
+    }
+}
+
+/// Sparse conditional constant propagation pass, named `sccp` in pipelines.
+pub struct SparseConditionalConstantPropagation;
+
+impl Pass for SparseConditionalConstantPropagation {
+    fn name(&self) -> &'static str {
+        "sccp"
+    }
+
+    fn run_on_function(&mut self, module: &mut Module, function: FunctionRef, _analyses: &mut AnalysisManager) -> Preserved {
+        let stats = propagate_constants(module, function);
+        if stats.folded_branches > 0 {
+            Preserved::None
+        } else if stats.folded_instructions > 0 {
+            Preserved::ControlFlow
+        } else {
+            Preserved::All
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::apps::executor::{run_on_module, ProgramEnv, Val};
+    use crate::frontend::new_parser::parse_module;
+    use crate::ir::verify::verify_module;
+
+    #[test]
+    fn test_sccp_folds_branches() {
+        let mut module = parse_module(r"
+            fn @f(#n: i32) -> i32 {
+            %entry:
+                let %0 = add 0, 5
+                let %1 = gt %0, 3
+                br %1, label %then, label %else
+            %then:
+                let %2 = mul %0, 2
+                jmp label %exit
+            %else:
+                let %3 = add #n, 1
+                jmp label %exit
+            %exit:
+                let %4 = phi [%2, label %then], [%3, label %else]
+                let %5 = add %4, #n
+                ret %5
+            }
+        ").unwrap();
+        let function = module.get_function_ref("f");
+        let stats = propagate_constants(&mut module, function);
+        assert_eq!(stats.folded_instructions, 4);
+        assert_eq!(stats.folded_branches, 1);
+        let func = module.get_function(function);
+        let else_ = func.blocks[2];
+        assert_eq!(stats.unreachable_blocks, vec![else_]);
+        assert!(verify_module(&module).is_ok());
+        assert_eq!(run_on_module(&mut ProgramEnv::new(), &module, "f", vec![Val::Integer(7)]).unwrap(), Val::Integer(17));
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashSet;
+
+use crate::ir::analysis::cfg::ControlFlowGraph;
+use crate::ir::structures::*;
+
+use super::pass_manager::{AnalysisManager, Pass, Preserved};
+use super::sccp::remove_phi_incoming;
+
+/// Delete blocks unreachable from the entry block, together with their instructions.
+/// Returns the number of deleted blocks.
+pub fn remove_unreachable_blocks(module: &mut Module, function: FunctionRef) -> usize {
+    let func = module.get_function(function);
+    let cfg = ControlFlowGraph::new(func);
+    let reachable = cfg.reachable_blocks();
+    let unreachable = func.blocks
+        .iter()
+        .cloned()
+        .filter(| bb | !reachable.contains(bb))
+        .collect::<Vec<_>>();
+
+    for bb in unreachable.iter().cloned() {
+        for succ in cfg.successors(bb).iter().cloned().filter(| succ | reachable.contains(succ)) {
+            remove_phi_incoming(module, function, succ, bb);
+        }
+    }
+    // values defined in unreachable blocks are only used by unreachable blocks.
+    for bb in unreachable.iter().cloned() {
+        let block = module.get_function_mut(function).blocks_ctx.remove(bb).unwrap();
+        for instr in block.instrs {
+            module.value_ctx.remove(instr);
+        }
+    }
+    module.get_function_mut(function).blocks.retain(| bb | reachable.contains(bb));
+    unreachable.len()
+}
+
+/// Replace phis whose incoming values are all the same, ignoring the phi itself, by that value.
+fn remove_trivial_phis(module: &mut Module, function: FunctionRef, bb: BlockRef) {
+    let instrs = module.get_function(function).get_basic_block(bb).instrs.clone();
+    for phi in instrs {
+        let incoming = match &module.get_value(phi).kind {
+            ValueKind::Phi(inner) => inner.incoming
+                .iter()
+                .map(| (value, _) | *value)
+                .filter(| value | *value != phi)
+                .collect::<HashSet<_>>(),
+            _ => break
+        };
+        if let [value] = incoming.into_iter().collect::<Vec<_>>().as_slice() {
+            module.replace_uses_in_function(function, phi, *value);
+            module.erase_instruction(function, phi);
+        }
+    }
+}
+
+/// Merge a block into its only predecessor jumping to it.
+fn merge_into_predecessor(module: &mut Module, function: FunctionRef, pred: BlockRef, bb: BlockRef) {
+    remove_trivial_phis(module, function, bb);
+    let func = module.get_function_mut(function);
+    let block = func.blocks_ctx.remove(bb).unwrap();
+    func.blocks.retain(| block_ref | *block_ref != bb);
+    let successors = block.terminator.successors().collect::<HashSet<_>>();
+    let pred_block = func.get_basic_block_mut(pred);
+    pred_block.instrs.extend(block.instrs);
+    pred_block.terminator = block.terminator;
+    // successors now receive control from the predecessor.
+    for succ in successors {
+        let phis = module.get_function(function).get_basic_block(succ).instrs.clone();
+        for phi in phis {
+            match &mut module.get_value_mut(phi).kind {
+                ValueKind::Phi(inner) => inner.incoming
+                    .iter_mut()
+                    .filter(| (_, incoming_bb) | *incoming_bb == bb)
+                    .for_each(| (_, incoming_bb) | *incoming_bb = pred),
+                _ => break
+            }
+        }
+    }
+}
+
+/// Whether `bb` only forwards control to its successor, without phis to resolve there.
+fn forwarding_target(module: &Module, function: &Function, bb: BlockRef) -> Option<BlockRef> {
+    let block = function.get_basic_block(bb);
+    match &block.terminator {
+        Terminator::Jump(inner) if block.instrs.is_empty() && inner.dest != bb => {
+            let dest = function.get_basic_block(inner.dest);
+            let has_phis = dest.instrs
+                .first()
+                .is_some_and(| instr | module.get_value(*instr).is_phi());
+            (!has_phis).then_some(inner.dest)
+        },
+        _ => None
+    }
+}
+
+/// Merge chains of blocks connected by jumps, and bypass empty blocks only jumping to another block.
+/// The entry block is never merged into a predecessor nor bypassed.
+/// Returns the number of removed blocks.
+pub fn merge_jump_chains(module: &mut Module, function: FunctionRef) -> usize {
+    let mut removed = 0;
+    'restart: loop {
+        let func = module.get_function(function);
+        let cfg = ControlFlowGraph::new(func);
+        let entry = match cfg.entry() {
+            Some(entry) => entry,
+            None => return removed
+        };
+        for bb in func.blocks.iter().cloned() {
+            if bb == entry {
+                continue;
+            }
+            if let [pred] = cfg.predecessors(bb) {
+                let pred = *pred;
+                if pred != bb && matches!(func.get_basic_block(pred).terminator, Terminator::Jump(..)) {
+                    merge_into_predecessor(module, function, pred, bb);
+                    removed += 1;
+                    continue 'restart;
+                }
+            }
+            if let Some(dest) = forwarding_target(module, func, bb) {
+                let func = module.get_function_mut(function);
+                for pred in cfg.predecessors(bb) {
+                    func.get_basic_block_mut(*pred)
+                        .terminator
+                        .successors_mut()
+                        .filter(| succ | **succ == bb)
+                        .for_each(| succ | *succ = dest);
+                }
+                func.blocks_ctx.remove(bb);
+                func.blocks.retain(| block_ref | *block_ref != bb);
+                removed += 1;
+                continue 'restart;
+            }
+        }
+        return removed;
+    }
+}
+
+/// Control flow simplification pass, named `simplifycfg` in pipelines.
+/// Removes unreachable blocks, then merges jump chains.
+pub struct SimplifyControlFlow;
+
+impl Pass for SimplifyControlFlow {
+    fn name(&self) -> &'static str {
+        "simplifycfg"
+    }
+
+    fn run_on_function(&mut self, module: &mut Module, function: FunctionRef, _analyses: &mut AnalysisManager) -> Preserved {
+        let removed = remove_unreachable_blocks(module, function) + merge_jump_chains(module, function);
+        if removed > 0 {
+            Preserved::None
+        } else {
+            Preserved::All
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::frontend::new_parser::parse_module;
+    use crate::ir::transforms::pass_manager::PassManager;
+    use crate::ir::verify::verify_module;
+
+    #[test]
+    fn test_simplify_cfg_round_trip() {
+        let mut module = parse_module(r"
+            fn @f(#n: i32) -> i32 {
+            %entry:
+                let %0 = lt #n, 0
+                br %0, label %neg, label %forward
+            %forward:
+                jmp label %pos
+            %pos:
+                let %1 = add #n, 0
+                jmp label %join
+            %neg:
+                let %2 = sub 0, #n
+                jmp label %join
+            %dead:
+                let %3 = add #n, 2
+                jmp label %join
+            %join:
+                let %4 = phi [%1, label %pos], [%2, label %neg], [%3, label %dead]
+                jmp label %exit
+            %exit:
+                ret %4
+            }
+        ").unwrap();
+        let mut manager = PassManager::parse("simplifycfg,dce").unwrap();
+        manager.verify_each = true;
+        manager.run(&mut module).unwrap();
+
+        // `dead` is deleted, `forward` is bypassed and `exit` is merged into `join`.
+        let function = module.get_function(module.get_function_ref("f"));
+        let names = function.blocks
+            .iter()
+            .map(| bb | function.get_basic_block(*bb).name.clone().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["entry", "pos", "neg", "join"]);
+
+        let printed = module.to_string();
+        let reparsed = parse_module(&printed).unwrap();
+        assert!(verify_module(&reparsed).is_ok());
+        assert_eq!(reparsed.to_string(), printed);
+    }
+}
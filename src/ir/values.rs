@@ -2,13 +2,14 @@ use std::fmt;
 
 use super::structures::{Terminator, Value, ValueKind};
 use super::types::Type;
+use crate::frontend::Span;
 
 use super::structures::{
     ValueRef, BlockRef
 };
 
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum BinaryOp {
     /* Numeric Operations */
     Add,
@@ -203,14 +204,37 @@ impl Store {
     }
 }
 
+/* SSA phi node, choosing the incoming value from the predecessor control reaches the block from.
+ * Phi nodes only appear at the beginning of a basic block.
+ */
+#[derive(Debug, Clone)]
+pub struct Phi {
+    pub incoming: Vec<(ValueRef, BlockRef)>
+}
+
+impl Phi {
+    pub fn new_value(ty: Type, incoming: Vec<(ValueRef, BlockRef)>) -> Value {
+        Value::new(ty, None, ValueKind::Phi(Self { incoming }))
+    }
+
+    /// Incoming value from the predecessor `bb`.
+    pub fn incoming_value(&self, bb: BlockRef) -> Option<ValueRef> {
+        self.incoming
+            .iter()
+            .find(| (_, pred) | *pred == bb)
+            .map(| (value, _) | *value)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Jump {
-    pub dest: BlockRef
+    pub dest: BlockRef,
+    pub span: Option<Span>,
 }
 
 impl Jump {
     pub fn new_value(dest: BlockRef) -> Terminator {
-        Terminator::Jump(Self { dest })
+        Terminator::Jump(Self { dest, span: None })
     }
 }
 
@@ -219,6 +243,7 @@ pub struct Branch {
     pub cond: ValueRef,
     pub true_label: BlockRef,
     pub false_label: BlockRef,
+    pub span: Option<Span>,
 }
 
 impl Branch {
@@ -227,17 +252,18 @@ impl Branch {
         true_label: BlockRef,
         false_label: BlockRef
     ) -> Terminator {
-        Terminator::Branch(Self { cond, true_label, false_label})
+        Terminator::Branch(Self { cond, true_label, false_label, span: None })
     } 
 }
 
 #[derive(Debug, Clone)]
 pub struct Return {
-    pub value: ValueRef
+    pub value: ValueRef,
+    pub span: Option<Span>,
 }
 
 impl Return {
     pub fn new_value(value: ValueRef) -> Terminator {
-        Terminator::Return(Self { value })
+        Terminator::Return(Self { value, span: None })
     }
 }
\ No newline at end of file
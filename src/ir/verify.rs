@@ -0,0 +1,515 @@
+use std::fmt;
+use std::collections::HashSet;
+
+use slotmap::SecondaryMap;
+use colored::Colorize;
+
+use super::structures::*;
+use super::types::Type;
+use super::values::BinaryOp;
+use super::analysis::{cfg::ControlFlowGraph, dominance::DominatorTree};
+use crate::apps::executor::is_runtime_function;
+
+#[derive(Debug, Clone)]
+pub enum VerifyErrorInternal {
+    MissingTerminator(String),
+    MissingFunctionBody,
+    NotAnInstruction,
+    DuplicatedInstruction(String),
+    OperandTypeMismatch(String),
+    ResultTypeMismatch(Type, Type),
+    InvalidOffsetBounds(String),
+    UndefinedCallee(String),
+    CallArgumentMismatch(String),
+    ForeignValue(String),
+    UseBeforeDefinition(String),
+    DanglingBlock(String),
+    ReturnTypeMismatch(Type, Type),
+    MisplacedPhi(String),
+    PhiIncomingMismatch(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct VerifyError {
+    // context of verification error.
+    pub function: String,
+    pub value: String,
+    // error internal.
+    pub error: VerifyErrorInternal
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} in function '{}' with value '{}'",
+                    "verify error: ".red().bold(), self.function.bold(), self.value.bold())?;
+        use VerifyErrorInternal::*;
+        match &self.error {
+            MissingTerminator(label) =>
+                write!(f, "basic block '%{}' has no terminator", label.bold()),
+            MissingFunctionBody =>
+                write!(f, "function definition has no basic block"),
+            NotAnInstruction =>
+                write!(f, "non-instruction value found in basic block"),
+            DuplicatedInstruction(label) =>
+                write!(f, "instruction appears more than once, again in '%{}'", label.bold()),
+            OperandTypeMismatch(s) =>
+                write!(f, "operand type mismatch, {}", s),
+            ResultTypeMismatch(expected, found) =>
+                write!(f, "expect result type '{}', but found '{}'",
+                        expected.to_string().bold(), found.to_string().bold()),
+            InvalidOffsetBounds(s) =>
+                write!(f, "invalid offset bounds, {}", s),
+            UndefinedCallee(callee) =>
+                write!(f, "call to undefined function '@{}'", callee.bold()),
+            CallArgumentMismatch(s) =>
+                write!(f, "call arguments mismatch, {}", s),
+            ForeignValue(operand) =>
+                write!(f, "operand '{}' does not belong to this function", operand.bold()),
+            UseBeforeDefinition(operand) =>
+                write!(f, "operand '{}' does not dominate its use", operand.bold()),
+            DanglingBlock(label) =>
+                write!(f, "jump to basic block '%{}' that is never defined", label.bold()),
+            ReturnTypeMismatch(expected, found) =>
+                write!(f, "expect return type '{}', but found '{}'",
+                        expected.to_string().bold(), found.to_string().bold()),
+            MisplacedPhi(label) =>
+                write!(f, "phi is not at the beginning of basic block '%{}'", label.bold()),
+            PhiIncomingMismatch(s) =>
+                write!(f, "phi incoming blocks mismatch, {}", s),
+        }
+    }
+}
+
+fn value_name(value: &Value) -> String {
+    value.to_string()
+}
+
+fn block_label(block: &BasicBlock) -> String {
+    block.name.clone().unwrap_or("<unknown_label>".to_string())
+}
+
+struct FunctionVerifier<'a> {
+    module: &'a Module,
+    function: &'a Function,
+    errors: Vec<VerifyError>,
+}
+
+impl<'a> FunctionVerifier<'a> {
+    fn report(&mut self, value: String, error: VerifyErrorInternal) {
+        self.errors.push(VerifyError { function: self.function.name.clone(), value, error });
+    }
+
+    fn value(&self, value: ValueRef) -> &'a Value {
+        self.module.get_value(value)
+    }
+
+    fn check_instruction(&mut self, instr: ValueRef) {
+        let value = self.value(instr);
+        let name = value_name(value);
+        let expect_result = | verifier: &mut Self, expected: Type | {
+            if value.ty != expected {
+                verifier.report(name.clone(), VerifyErrorInternal::ResultTypeMismatch(expected, value.ty.clone()));
+            }
+        };
+        match &value.kind {
+            ValueKind::Binary(inner) => {
+                let lhs = self.value(inner.lhs);
+                let rhs = self.value(inner.rhs);
+                let operand_ok = match inner.op {
+                    BinaryOp::And | BinaryOp::Or | BinaryOp::Xor => lhs.ty.is_integer_type(),
+                    _ => lhs.ty.is_i32_type()
+                };
+                if !operand_ok || lhs.ty != rhs.ty {
+                    self.report(name.clone(), VerifyErrorInternal::OperandTypeMismatch(
+                        format!("'{}' is not defined on '{}' and '{}'", inner.op, lhs, rhs)));
+                }
+                expect_result(self, lhs.ty.clone());
+            },
+            ValueKind::Offset(inner) => {
+                let base = self.value(inner.base_addr);
+                if !base.ty.deref_matches(&inner.elem_type) {
+                    self.report(name.clone(), VerifyErrorInternal::OperandTypeMismatch(
+                        format!("base address '{}' is incompatible with element type '{}'", base, inner.elem_type)));
+                }
+                for index in inner.index.iter() {
+                    let index = self.value(*index);
+                    if !index.ty.is_integer_type() {
+                        self.report(name.clone(), VerifyErrorInternal::OperandTypeMismatch(
+                            format!("index '{}' is not an integer", index)));
+                    }
+                }
+                if inner.index.is_empty() || inner.index.len() != inner.bounds.len() {
+                    self.report(name.clone(), VerifyErrorInternal::InvalidOffsetBounds(
+                        format!("{} indices but {} bounds", inner.index.len(), inner.bounds.len())));
+                } else if inner.bounds.iter().skip(1).any(Option::is_none) {
+                    self.report(name.clone(), VerifyErrorInternal::InvalidOffsetBounds(
+                        "only the first dimension can be 'none'".to_string()));
+                }
+                expect_result(self, Type::get_pointer(inner.elem_type.clone()));
+            },
+            ValueKind::FnCall(inner) => {
+                let args = inner.args.iter().map(| arg | self.value(*arg)).collect::<Vec<_>>();
+                if is_runtime_function(&inner.callee) {
+                    let (params, ret) = match inner.callee.as_str() {
+                        "getint" | "getch" => (vec![], Type::get_i32()),
+                        "getarray" => (vec![Type::get_pointer(Type::get_i32())], Type::get_i32()),
+                        "putint" | "putch" => (vec![Type::get_i32()], Type::get_unit()),
+                        "putarray" => (vec![Type::get_i32(), Type::get_pointer(Type::get_i32())], Type::get_unit()),
+                        _ => (vec![], Type::get_unit())
+                    };
+                    // runtime functions accept opaque pointers as well.
+                    let matches = params.len() == args.len() && params.iter().zip(args.iter())
+                        .all(| (param, arg) | param == &arg.ty || (param.is_pointer_type() && arg.ty.is_pointer_type()));
+                    if !matches {
+                        self.report(name.clone(), VerifyErrorInternal::CallArgumentMismatch(
+                            format!("runtime function '@{}' expects ({})", inner.callee,
+                                    params.iter().map(Type::to_string).collect::<Vec<_>>().join(", "))));
+                    }
+                    expect_result(self, ret);
+                } else {
+                    match self.module.string_func_map.get(&inner.callee) {
+                        Some(callee) => {
+                            let callee_ty = self.module.get_function(*callee).ty.clone();
+                            let params = callee_ty.get_function_params_type().unwrap();
+                            if params.len() != args.len() {
+                                self.report(name.clone(), VerifyErrorInternal::CallArgumentMismatch(
+                                    format!("'@{}' expects {} arguments, but {} given", inner.callee, params.len(), args.len())));
+                            }
+                            for (param, arg) in params.iter().zip(args.iter()) {
+                                if param != &arg.ty {
+                                    self.report(name.clone(), VerifyErrorInternal::CallArgumentMismatch(
+                                        format!("argument '{}' is incompatible with parameter type '{}'", arg, param)));
+                                }
+                            }
+                            expect_result(self, callee_ty.get_function_ret_type().unwrap());
+                        },
+                        None => self.report(name.clone(), VerifyErrorInternal::UndefinedCallee(inner.callee.clone()))
+                    }
+                }
+            },
+            ValueKind::Alloca(inner) => {
+                expect_result(self, Type::get_pointer(inner.elem_type.clone()));
+            },
+            ValueKind::Load(inner) => {
+                let addr = self.value(inner.addr);
+                if !addr.ty.deref_matches(&value.ty) {
+                    self.report(name.clone(), VerifyErrorInternal::OperandTypeMismatch(
+                        format!("cannot load '{}' from address '{}'", value.ty, addr)));
+                }
+            },
+            ValueKind::Store(inner) => {
+                let stored = self.value(inner.value);
+                let addr = self.value(inner.addr);
+                if !addr.ty.deref_matches(&stored.ty) {
+                    self.report(name.clone(), VerifyErrorInternal::OperandTypeMismatch(
+                        format!("cannot store '{}' to address '{}'", stored, addr)));
+                }
+                expect_result(self, Type::get_unit());
+            },
+            ValueKind::Phi(inner) => {
+                for (incoming, _) in inner.incoming.iter() {
+                    let incoming = self.value(*incoming);
+                    if incoming.ty != value.ty {
+                        self.report(name.clone(), VerifyErrorInternal::OperandTypeMismatch(
+                            format!("incoming value '{}' is incompatible with phi type '{}'", incoming, value.ty)));
+                    }
+                }
+            },
+            _ => self.report(name, VerifyErrorInternal::NotAnInstruction)
+        }
+    }
+
+    fn check_terminator(&mut self, block: &BasicBlock) {
+        let context = format!("<terminator of %{}>", block_label(block));
+        let appended = self.function.blocks.iter().cloned().collect::<HashSet<_>>();
+        for succ in block.terminator.successors() {
+            if !appended.contains(&succ) {
+                let label = block_label(self.function.get_basic_block(succ));
+                self.report(context.clone(), VerifyErrorInternal::DanglingBlock(label));
+            }
+        }
+        match &block.terminator {
+            Terminator::Panic =>
+                self.report(context, VerifyErrorInternal::MissingTerminator(block_label(block))),
+            Terminator::Branch(inner) => {
+                let cond = self.value(inner.cond);
+                if !cond.ty.is_integer_type() {
+                    self.report(context, VerifyErrorInternal::OperandTypeMismatch(
+                        format!("branch condition '{}' is not an integer", cond)));
+                }
+            },
+            Terminator::Return(inner) => {
+                let expected = self.function.ty.get_function_ret_type().unwrap();
+                let ret = self.value(inner.value);
+                if ret.ty != expected {
+                    self.report(context, VerifyErrorInternal::ReturnTypeMismatch(expected, ret.ty.clone()));
+                }
+            },
+            Terminator::Jump(_) => ()
+        }
+    }
+
+    /// Check phi only appear at the beginning of basic blocks,
+    /// with exactly one incoming value for each predecessor.
+    fn check_phi(&mut self, cfg: &ControlFlowGraph) {
+        let function = self.function;
+        for bb in function.blocks.iter().cloned() {
+            let block = function.get_basic_block(bb);
+            let num_phis = block.instrs
+                .iter()
+                .take_while(| instr | self.value(**instr).is_phi())
+                .count();
+            for instr in block.instrs.iter().skip(num_phis) {
+                if self.value(*instr).is_phi() {
+                    self.report(value_name(self.value(*instr)), VerifyErrorInternal::MisplacedPhi(block_label(block)));
+                }
+            }
+            let predecessors = cfg.predecessors(bb);
+            for instr in block.instrs.iter().take(num_phis) {
+                let phi = match &self.value(*instr).kind {
+                    ValueKind::Phi(inner) => inner,
+                    _ => unreachable!()
+                };
+                let name = value_name(self.value(*instr));
+                let mut seen = HashSet::new();
+                for (_, incoming_bb) in phi.incoming.iter().cloned() {
+                    let label = block_label(function.get_basic_block(incoming_bb));
+                    if !predecessors.contains(&incoming_bb) {
+                        self.report(name.clone(), VerifyErrorInternal::PhiIncomingMismatch(
+                            format!("'%{}' is not a predecessor", label)));
+                    } else if !seen.insert(incoming_bb) {
+                        self.report(name.clone(), VerifyErrorInternal::PhiIncomingMismatch(
+                            format!("more than one incoming value from '%{}'", label)));
+                    }
+                }
+                for pred in predecessors.iter().filter(| pred | !seen.contains(pred)) {
+                    self.report(name.clone(), VerifyErrorInternal::PhiIncomingMismatch(
+                        format!("no incoming value from predecessor '%{}'", block_label(function.get_basic_block(*pred)))));
+                }
+            }
+        }
+    }
+
+    /// Check every operand is an argument of this function, a global, a constant,
+    /// or an instruction whose definition dominates the use.
+    /// Incoming values of phi are used at the end of the corresponding predecessor.
+    fn check_dominance(&mut self, cfg: &ControlFlowGraph) {
+        let function = self.function;
+        // definition position of instructions.
+        let mut definitions: SecondaryMap<ValueRef, (BlockRef, usize)> = SecondaryMap::new();
+        for bb in function.blocks.iter().cloned() {
+            let block = function.get_basic_block(bb);
+            for (index, instr) in block.instrs.iter().cloned().enumerate() {
+                if definitions.insert(instr, (bb, index)).is_some() {
+                    self.report(value_name(self.value(instr)), VerifyErrorInternal::DuplicatedInstruction(block_label(block)));
+                }
+            }
+        }
+        let domtree = DominatorTree::new(cfg);
+
+        for bb in function.blocks.iter().cloned() {
+            let block = function.get_basic_block(bb);
+            let uses = block.instrs
+                .iter().cloned()
+                .enumerate()
+                .flat_map(| (index, instr) | {
+                    let user = value_name(self.value(instr));
+                    let uses: Vec<(BlockRef, usize, String, ValueRef)> = match &self.value(instr).kind {
+                        ValueKind::Phi(inner) => inner.incoming
+                            .iter().cloned()
+                            .map(| (operand, pred) | (pred, function.get_basic_block(pred).instrs.len(), user.clone(), operand))
+                            .collect(),
+                        kind => kind.operands()
+                            .map(| operand | (bb, index, user.clone(), operand))
+                            .collect()
+                    };
+                    uses
+                })
+                .chain(block.terminator.operands()
+                    .map(| operand | (bb, block.instrs.len(), format!("<terminator of %{}>", block_label(block)), operand)))
+                .collect::<Vec<_>>();
+            for (bb, index, user, operand) in uses {
+                let operand_value = self.value(operand);
+                match &operand_value.kind {
+                    ValueKind::Argument(_) if !function.args.contains(&operand) =>
+                        self.report(user, VerifyErrorInternal::ForeignValue(value_name(operand_value))),
+                    _ if operand_value.isa_instruction() => {
+                        let dominated = match definitions.get(operand) {
+                            None => {
+                                self.report(user, VerifyErrorInternal::ForeignValue(value_name(operand_value)));
+                                continue;
+                            },
+                            // uses in unreachable blocks are trivially dominated.
+                            Some(_) if !domtree.contains(bb) => true,
+                            Some((def_bb, def_index)) if *def_bb == bb => *def_index < index,
+                            Some((def_bb, _)) => domtree.dominates(*def_bb, bb)
+                        };
+                        if !dominated {
+                            self.report(user, VerifyErrorInternal::UseBeforeDefinition(value_name(operand_value)));
+                        }
+                    },
+                    _ => ()
+                }
+            }
+        }
+    }
+
+    fn run(mut self) -> Vec<VerifyError> {
+        let function = self.function;
+        if function.is_external {
+            return self.errors;
+        }
+        if function.blocks.is_empty() {
+            self.report("<function body>".to_string(), VerifyErrorInternal::MissingFunctionBody);
+            return self.errors;
+        }
+        for bb in function.blocks.iter().cloned() {
+            let block = function.get_basic_block(bb);
+            for instr in block.instrs.iter().cloned() {
+                self.check_instruction(instr);
+            }
+            self.check_terminator(block);
+        }
+        let cfg = ControlFlowGraph::new(function);
+        self.check_phi(&cfg);
+        self.check_dominance(&cfg);
+        self.errors
+    }
+}
+
+pub fn verify_function(module: &Module, function: &Function) -> Result<(), Vec<VerifyError>> {
+    let errors = FunctionVerifier { module, function, errors: Vec::new() }.run();
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
+
+/// Check the whole module statically, collecting all errors found.
+pub fn verify_module(module: &Module) -> Result<(), Vec<VerifyError>> {
+    let errors = module.funcs
+        .iter()
+        .flat_map(| func_ref | {
+            verify_function(module, module.get_function(*func_ref))
+                .err()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::frontend::{lexer::Lexer, parser::Parser, token::Tokens};
+    use crate::ir::builders::IRBuilder;
+
+    fn parse(input: &str) -> Module {
+        let (_, tokens) = Lexer::lex(input).expect("failed to lex");
+        let builder = Rc::new(RefCell::new(IRBuilder::new()));
+        let (_, module) = Parser::parse_from_complete_input(Tokens::new(&tokens), builder)
+            .expect("failed to parse");
+        module
+    }
+
+    #[test]
+    fn test_verify_well_formed() {
+        let module = parse("
+            fn @max(#a: i32, #b: i32) -> i32 {
+            %entry:
+                let %cmp = gt #a, #b
+                br %cmp, label %lhs, label %rhs
+            %lhs:
+                ret #a
+            %rhs:
+                ret #b
+            }
+        ");
+        assert!(verify_module(&module).is_ok());
+    }
+
+    #[test]
+    fn test_verify_dominance_and_dangling_block() {
+        let module = parse("
+            fn @f(#a: i32) -> i32 {
+            %entry:
+                br #a, label %then, label %else
+            %then:
+                let %x = add #a, 1
+                jmp label %exit
+            %exit:
+                let %y = add %x, 1
+                ret %y
+            }
+        ");
+        let errors = verify_module(&module).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0].error, VerifyErrorInternal::DanglingBlock(..)));
+
+        let module = parse("
+            fn @f(#a: i32) -> i32 {
+            %entry:
+                br #a, label %then, label %exit
+            %then:
+                let %x = add #a, 1
+                jmp label %exit
+            %exit:
+                let %y = add %x, 1
+                ret %y
+            }
+        ");
+        let errors = verify_module(&module).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0].error, VerifyErrorInternal::UseBeforeDefinition(..)));
+    }
+
+    #[test]
+    fn test_verify_phi() {
+        let module = parse("
+            fn @f(#a: i32) -> i32 {
+            %entry:
+                br #a, label %then, label %exit
+            %then:
+                let %x = add #a, 1
+                jmp label %exit
+            %exit:
+                let %y = phi [%x, label %then], [0, label %entry]
+                ret %y
+            }
+        ");
+        assert!(verify_module(&module).is_ok());
+
+        let module = parse("
+            fn @f(#a: i32) -> i32 {
+            %entry:
+                br #a, label %then, label %exit
+            %then:
+                let %x = add #a, 1
+                jmp label %exit
+            %exit:
+                let %y = phi [%x, label %then], [%x, label %entry]
+                ret %y
+            }
+        ");
+        let errors = verify_module(&module).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0].error, VerifyErrorInternal::UseBeforeDefinition(..)));
+
+        let module = parse("
+            fn @f(#a: i32) -> i32 {
+            %entry:
+                br #a, label %then, label %exit
+            %then:
+                jmp label %exit
+            %exit:
+                let %y = phi [1, label %then], [2, label %then]
+                ret %y
+            }
+        ");
+        let errors = verify_module(&module).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().all(| error | matches!(error.error, VerifyErrorInternal::PhiIncomingMismatch(..))));
+    }
+}
@@ -1,4 +1,5 @@
 pub mod ir;
 pub mod frontend;
 pub mod utils;
-pub mod apps;
\ No newline at end of file
+pub mod apps;
+pub mod backend;
\ No newline at end of file
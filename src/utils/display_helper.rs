@@ -12,8 +12,9 @@ pub trait FromNotDisplayable<'a, T, C>: Sized {
     }
 }
 
-impl<'a> FromNotDisplayable<'a, Value, Module> for Value {}
+impl<'a> FromNotDisplayable<'a, Value, (&'a Module, &'a Function)> for Value {}
 impl<'a> FromNotDisplayable<'a, BasicBlock, Module> for BasicBlock {}
 impl<'a> FromNotDisplayable<'a, Function, Module> for Function {}
+impl<'a> FromNotDisplayable<'a, Terminator, (&'a Module, &'a Function)> for Terminator {}
 
 impl <'a> FromNotDisplayable<'a, Val, Module> for Val {}
\ No newline at end of file
@@ -1,9 +1,17 @@
 use std::collections::HashMap;
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct UniqueName {
     history: HashMap<String, usize>,
-    anonymous: usize
+    anonymous: usize,
+    /// Put between a repeated name and its counter, `.` unless the names are for another language.
+    separator: &'static str
+}
+
+impl Default for UniqueName {
+    fn default() -> Self {
+        Self { history: HashMap::new(), anonymous: 0, separator: "." }
+    }
 }
 
 impl UniqueName {
@@ -11,12 +19,16 @@ impl UniqueName {
         Self::default()
     }
 
+    pub fn with_separator(separator: &'static str) -> Self {
+        Self { separator, ..Self::default() }
+    }
+
     pub fn next_name(&mut self, base: &str) -> String {
         self.anonymous += 1;
         if self.history.contains_key(base) {
             let n = self.history.get_mut(base).unwrap();
             *n += 1;
-            format!("{}.{}", base, n)
+            format!("{}{}{}", base, self.separator, n)
         } else {
             self.history.insert(base.to_string(), 0);
             format!("{}", base)
@@ -31,4 +43,55 @@ impl UniqueName {
         self.anonymous += 1;
         format!("{}", self.anonymous)
     }
-}
\ No newline at end of file
+
+    /// Name based on `base` never returned nor registered before, registering it.
+    /// Names starting with a digit, including ones derived from numbers such as `0.cont`,
+    /// are replaced by fresh numbers, since suffixed numbers are not identifiers.
+    pub fn next_fresh_name(&mut self, base: &str) -> String {
+        let numeric = base.starts_with(| c: char | c.is_ascii_digit());
+        loop {
+            let name = if numeric { self.next_anonymous_name() } else { self.next_name(base) };
+            if name == base && !numeric {
+                // registered by `next_name` already.
+                return name;
+            }
+            if !self.contains_name(&name) {
+                self.next_name(&name);
+                return name;
+            }
+        }
+    }
+
+    /// Name based on `base` never returned nor registered before, registering it.
+    /// Unlike `next_fresh_name`, numeric names are suffixed as well, for targets accepting them.
+    pub fn next_unused_name(&mut self, base: &str) -> String {
+        loop {
+            let name = self.next_name(base);
+            if name == base {
+                return name;
+            }
+            if !self.contains_name(&name) {
+                self.next_name(&name);
+                return name;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::UniqueName;
+
+    #[test]
+    fn test_next_fresh_name() {
+        let mut names = UniqueName::new();
+        assert_eq!(names.next_fresh_name("x"), "x");
+        assert_eq!(names.next_fresh_name("x"), "x.1");
+        assert_eq!(names.next_fresh_name("x.1"), "x.1.1");
+        names.next_name("x.2");
+        assert_eq!(names.next_fresh_name("x"), "x.3");
+        let name = names.next_fresh_name("0.cont");
+        assert!(name.chars().all(| c | c.is_ascii_digit()), "{}", name);
+        assert_ne!(names.next_fresh_name(&name), name);
+    }
+}
@@ -0,0 +1,26 @@
+//! Run `accipit callgraph` on `tests/callgraph/undeclared.acc` with both frontends,
+//! which must build the module despite the undeclared callee and report it.
+
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn test_undeclared_call_report() {
+    let file = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/callgraph/undeclared.acc");
+    for frontend in ["chumsky", "nom"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_accipit"))
+            .arg("callgraph")
+            .arg(&file)
+            .args(["--frontend", frontend])
+            .output()
+            .unwrap();
+        let report = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "{}: undeclared calls should fail", frontend);
+        assert!(!stderr.contains("panicked"), "{}: {}", frontend, stderr);
+        assert!(report.contains("undeclared calls:\n  %x in @unused calls undeclared function @missing\n"), "{}: {}", frontend, report);
+        // `@even` is called by `@odd` before its declaration.
+        assert!(report.contains("recursive functions: [@odd, @even]\n"), "{}: {}", frontend, report);
+        assert!(report.contains("unreachable from @main: [@unused]\n"), "{}: {}", frontend, report);
+    }
+}
@@ -0,0 +1,109 @@
+//! Run the SysY corpus of `tests/lab*` end to end, following the header convention of `tests/test.py`:
+//! no heading comment means success, a single comment containing `Error` means failure,
+//! and a pair of `// Input:` and `// Output:` comments gives the standard input and expected output.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+struct Case {
+    path: PathBuf,
+    should_fail: bool,
+    io: Option<(Vec<String>, Vec<String>)>,
+}
+
+impl Case {
+    fn parse(path: PathBuf) -> Case {
+        let src = std::fs::read_to_string(&path).unwrap();
+        let comments = src
+            .lines()
+            .map_while(| line | line.strip_prefix("//"))
+            .collect::<Vec<_>>();
+        let tokens = | comment: &str, key: &str | comment
+            .replace(key, "")
+            .split_whitespace()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        match comments.as_slice() {
+            [] => Case { path, should_fail: false, io: None },
+            [comment] => Case { path, should_fail: comment.contains("Error"), io: None },
+            [input, output] => Case {
+                path,
+                should_fail: false,
+                io: Some((tokens(input, "Input:"), tokens(output, "Output:"))),
+            },
+            _ => panic!("{}: invalid heading comment", path.display()),
+        }
+    }
+}
+
+fn accipit(args: &[&Path], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_accipit"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // programs reading no input may exit before it is written.
+    if let Err(err) = child.stdin.take().unwrap().write_all(stdin.as_bytes()) {
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe, "{}", err);
+    }
+    child.wait_with_output().unwrap()
+}
+
+fn run_lab(lab: &str) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join(lab);
+    let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(lab);
+    std::fs::create_dir_all(&out_dir).unwrap();
+    let mut paths = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(| entry | entry.unwrap().path())
+        .filter(| path | path.extension().is_some_and(| ext | ext == "sy"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    let mut failures = Vec::new();
+    for case in paths.into_iter().map(Case::parse) {
+        let ir = out_dir.join(case.path.with_extension("acc").file_name().unwrap());
+        let compiled = accipit(&[Path::new("sysy"), &case.path, Path::new("--emit-ir"), Path::new("-o"), &ir], "");
+        if compiled.status.success() == case.should_fail {
+            failures.push(format!("{}: expect {} but compilation {}\n{}", case.path.display(),
+                if case.should_fail { "failure" } else { "success" },
+                if compiled.status.success() { "succeeded" } else { "failed" },
+                String::from_utf8_lossy(&compiled.stdout)));
+            continue;
+        }
+        // run the emitted IR by the interpreter, comparing concatenated output as `tests/test.py` does for lab4.
+        if let Some((input, expected)) = &case.io {
+            let run = accipit(&[&ir], &input.join("\n"));
+            let output = String::from_utf8_lossy(&run.stdout).split_whitespace().collect::<String>();
+            if !run.status.success() || output != expected.concat() {
+                failures.push(format!("{}: expect output `{}` but found `{}`\n{}", case.path.display(),
+                    expected.concat(), output, String::from_utf8_lossy(&run.stderr)));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn test_lab1_syntax() {
+    run_lab("lab1");
+}
+
+#[test]
+fn test_lab2_semantic() {
+    run_lab("lab2");
+}
+
+#[test]
+fn test_lab3_ir() {
+    run_lab("lab3");
+}
+
+#[test]
+fn test_lab4_init() {
+    run_lab("lab4");
+}
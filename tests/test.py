@@ -139,11 +139,41 @@ def run_one_test(compiler: str, test: Test, lab: str, local: bool) -> TestResult
             print(red(f"Error: {test.filename} timed out."))
             return TestResult(test, None, -1)
 
+    def run_with_asm(compiler: str, test: Test) -> TestResult:  # lab4
+        if not local:
+            asm_file = NamedTemporaryFile(suffix=".s")
+            asm_file_name = asm_file.name
+        else:
+            asm_file_name = test.filename.replace(
+                ".sy", ".s").split("/")[-1]
+            asm_file_name = f"{IR_PATH}/{asm_file_name}"
+        assert test.expected is not None, f"Error: {test.filename} has no expected output."
+        try:
+            result = subprocess.run(
+                [compiler, test.filename, asm_file_name],
+                capture_output=True,
+                timeout=TIMEOUT)
+            if result.returncode != 0:  # compile error
+                return TestResult(test, None, result.returncode)
+            # run the assembly in the built-in RV64IM simulator
+            result = subprocess.run(
+                [EXECUTOR_PATH, "rvsim", asm_file_name],
+                input="\n".join(test.inputs or []),
+                capture_output=True,
+                text=True,
+                timeout=TIMEOUT)
+            return TestResult(test, "".join(result.stdout.split()), result.returncode, concat_output=True)
+        except subprocess.TimeoutExpired:
+            print(red(f"Error: {test.filename} timed out."))
+            return TestResult(test, None, -1)
+
     match lab:
         case "lab1" | "lab2":
             return run_only_compiler(compiler, test)
         case "lab3":
             return run_with_ir(compiler, test)
+        case "lab4":
+            return run_with_asm(compiler, test)
         case _:
             raise NotImplemented
 